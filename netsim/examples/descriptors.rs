@@ -0,0 +1,114 @@
+//! Waits on a pipe, an eventfd, a timerfd and a native descriptor with `epoll` and `poll`, and
+//! reports what it observed. Run inside the simulation with
+//! `netsim netsim/examples/descriptors.toml` after `cargo build --examples`.
+
+use std::io;
+use std::mem;
+use std::time::Instant;
+
+fn check(result: libc::c_int) -> libc::c_int {
+    if result < 0 {
+        panic!("call failed: {}", io::Error::last_os_error());
+    }
+    result
+}
+
+fn errno_of(result: libc::c_int) -> String {
+    match result {
+        0.. => "succeeded".to_string(),
+        _ => io::Error::last_os_error().to_string(),
+    }
+}
+
+fn add(epoll: libc::c_int, fd: libc::c_int, data: u64) {
+    let mut event = libc::epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: data,
+    };
+    check(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, fd, &mut event) });
+}
+
+/// Waits for events and lists their data in order.
+fn wait(epoll: libc::c_int, timeout: libc::c_int) -> Vec<u64> {
+    let mut events = [libc::epoll_event { events: 0, u64: 0 }; 8];
+    let count = check(unsafe { libc::epoll_wait(epoll, events.as_mut_ptr(), 8, timeout) });
+    let mut ready: Vec<u64> = events[..count as usize]
+        .iter()
+        .map(|event| event.u64)
+        .collect();
+    ready.sort_unstable();
+    ready
+}
+
+fn main() {
+    let start = Instant::now();
+
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) });
+    let (reader, writer) = (fds[0], fds[1]);
+    let eventfd = check(unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) });
+    let timer = check(unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, 0) });
+    let inotify = check(unsafe { libc::inotify_init1(libc::IN_NONBLOCK) });
+
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    check(unsafe { libc::fstat(reader, &mut stat) });
+    println!(
+        "pipe is a fifo: {}",
+        stat.st_mode & libc::S_IFMT == libc::S_IFIFO
+    );
+
+    let epoll = check(unsafe { libc::epoll_create1(0) });
+    add(epoll, reader, 1);
+    add(epoll, eventfd, 2);
+    add(epoll, timer, 3);
+    add(epoll, inotify, 4);
+    println!("ready at first: {:?}", wait(epoll, 0));
+
+    let value = 1u64.to_ne_bytes();
+    unsafe {
+        libc::write(writer, b"x".as_ptr() as *const libc::c_void, 1);
+        libc::write(eventfd, value.as_ptr() as *const libc::c_void, 8);
+    }
+    println!("ready after writes: {:?}", wait(epoll, 0));
+
+    let mut buf = [0u8; 8];
+    unsafe {
+        libc::read(reader, buf.as_mut_ptr() as *mut libc::c_void, 8);
+        libc::read(eventfd, buf.as_mut_ptr() as *mut libc::c_void, 8);
+    }
+
+    let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+    spec.it_value.tv_sec = 2;
+    check(unsafe { libc::timerfd_settime(timer, 0, &spec, std::ptr::null_mut()) });
+    println!("ready after waiting: {:?}", wait(epoll, -1));
+    println!("timer expired after {} s", start.elapsed().as_secs());
+
+    let mut pollfds = [
+        libc::pollfd {
+            fd: inotify,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: reader,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    let count = check(unsafe { libc::poll(pollfds.as_mut_ptr(), 2, 1000) });
+    println!(
+        "polled {} ready until {} s",
+        count,
+        start.elapsed().as_secs()
+    );
+
+    unsafe { libc::close(writer) };
+    check(unsafe { libc::poll(pollfds.as_mut_ptr(), 2, 1000) });
+    println!("pipe hung up: {}", pollfds[1].revents & libc::POLLHUP != 0);
+
+    let mut pair = [0; 2];
+    let result =
+        unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, pair.as_mut_ptr()) };
+    println!("socketpair {}", errno_of(result));
+    println!("fork {}", errno_of(unsafe { libc::fork() }));
+}
@@ -0,0 +1,119 @@
+//! Runs a simulation with an echo server and a client written against the application API
+//! instead of as binaries: `cargo run --example echo_app -- netsim/examples/echo_app.toml`.
+
+use std::error;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use clap::Parser;
+
+use netsim::app::{AppBuilder, AppContext, SimApp, SocketId, TimerId};
+use netsim::cli::Args;
+
+const PORT: u16 = 7;
+
+/// Sends every byte it receives back to the sender.
+struct EchoServer;
+
+impl SimApp for EchoServer {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(PORT) {
+            log::error!("cannot listen on port {}: {}", PORT, err);
+            context.exit(1);
+        }
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let mut buf = [0; 4096];
+
+        loop {
+            match context.recv(socket, &mut buf) {
+                Ok(0) => {
+                    context.close(socket);
+                    return;
+                }
+                Ok(len) => {
+                    let _ = context.send(socket, &buf[..len]);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(_) => {
+                    context.close(socket);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Sends a message to the server given as its argument every second and logs the round trip.
+struct EchoClient {
+    server: String,
+    count: u32,
+    socket: Option<SocketId>,
+    sent_at: Duration,
+}
+
+impl SimApp for EchoClient {
+    fn on_start(&mut self, context: &mut AppContext) {
+        let ip = match context.resolve(&self.server) {
+            Some(ip) => ip,
+            None => return context.exit(1),
+        };
+
+        match context.connect(SocketAddr::new(ip, PORT)) {
+            Ok(socket) => self.socket = Some(socket),
+            Err(_) => context.exit(1),
+        }
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, _socket: SocketId) {
+        context.set_timer(Duration::ZERO);
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        if let Some(socket) = self.socket {
+            self.count += 1;
+            self.sent_at = context.now();
+            let _ = context.send(socket, format!("ping {}", self.count).as_bytes());
+        }
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let mut buf = [0; 4096];
+
+        match context.recv(socket, &mut buf) {
+            Ok(len) if len > 0 => {
+                log::info!(
+                    "{} after {:?}",
+                    String::from_utf8_lossy(&buf[..len]),
+                    context.now() - self.sent_at
+                );
+                context.set_timer(Duration::from_secs(1));
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            _ => context.exit(1),
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        context.exit(0);
+    }
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let app = AppBuilder::with_args(Args::parse())?
+        .register_app("echo_server", |_| Ok(Box::new(EchoServer)))
+        .register_app("echo_client", |args| {
+            let server = args.first().ok_or("missing the server name")?;
+            Ok(Box::new(EchoClient {
+                server: server.clone(),
+                count: 0,
+                socket: None,
+                sent_at: Duration::ZERO,
+            }))
+        })
+        .build()?;
+
+    app.run()
+}
@@ -0,0 +1,70 @@
+//! Sleeps for ten seconds, waits five more on a timer set to an absolute time and two and three
+//! more in `poll` and `select` without descriptors, and reports the time it observed. Run
+//! inside the simulation with `netsim netsim/examples/sleep.toml` after
+//! `cargo build --examples`, the waits then last twenty simulated seconds and return
+//! immediately in wall-clock time.
+
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Waits on a `timerfd` of the monotonic clock that expires `delay` from now.
+fn wait_until(delay: Duration) -> io::Result<()> {
+    unsafe {
+        let fd = libc::timerfd_create(libc::CLOCK_MONOTONIC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut now: libc::timespec = mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+
+        let mut spec: libc::itimerspec = mem::zeroed();
+        spec.it_value.tv_sec = now.tv_sec + delay.as_secs() as libc::time_t;
+        spec.it_value.tv_nsec = now.tv_nsec;
+
+        if libc::timerfd_settime(fd, libc::TFD_TIMER_ABSTIME, &spec, std::ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut expirations = 0u64;
+        let read = libc::read(fd, &mut expirations as *mut u64 as *mut libc::c_void, 8);
+        libc::close(fd);
+
+        match read {
+            8 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+fn main() {
+    let start = Instant::now();
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before unix epoch");
+
+    println!("started {} s after the unix epoch", since_epoch.as_secs());
+
+    thread::sleep(Duration::from_secs(10));
+
+    println!("slept for {} s", start.elapsed().as_secs());
+
+    wait_until(Duration::from_secs(5)).expect("failed to wait for timer");
+
+    println!("timer expired after {} s", start.elapsed().as_secs());
+
+    unsafe { libc::poll(std::ptr::null_mut(), 0, 2000) };
+
+    println!("polled until {} s", start.elapsed().as_secs());
+
+    let mut timeout = libc::timeval {
+        tv_sec: 3,
+        tv_usec: 0,
+    };
+    let null = std::ptr::null_mut();
+    unsafe { libc::select(0, null, null, null, &mut timeout) };
+
+    println!("selected until {} s", start.elapsed().as_secs());
+}
@@ -1,25 +1,58 @@
 use std::error;
 use std::io;
-use std::string::ParseError;
 
+use crate::apps;
 use crate::cli::Args;
+use crate::config::{Config, ConfigError};
+use crate::logger::Logger;
 use crate::sim::Driver;
+use crate::simapp::AppRegistry;
 
-pub struct App;
+pub use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+pub struct App {
+    config: Config,
+    registry: AppRegistry,
+}
 
 impl App {
     pub fn run(self) -> Result<(), Box<dyn error::Error>> {
-        Driver::new().run()
+        Logger::init(&self.config)?;
+        let result = Driver::new(self.config, self.registry)?.run();
+        log::logger().flush();
+        result
     }
 }
-pub struct AppBuilder;
+pub struct AppBuilder {
+    config: Config,
+    registry: AppRegistry,
+}
 
 impl AppBuilder {
-    pub fn with_args(cli_args: Args) -> Result<Self, ParseError> {
-        Ok(Self)
+    pub fn with_args(cli_args: Args) -> Result<Self, ConfigError> {
+        let mut config = Config::load(&cli_args.config)?;
+        config.apply(&cli_args);
+
+        let mut registry = AppRegistry::default();
+        apps::register(&mut registry);
+
+        Ok(Self { config, registry })
+    }
+
+    /// Makes an application available to processes configured with `app = "<name>"`. The
+    /// factory creates an instance from the arguments of each such process.
+    pub fn register_app<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&[String]) -> Result<Box<dyn SimApp>, String> + Send + Sync + 'static,
+    {
+        self.registry.register(name, factory);
+        self
     }
 
     pub fn build(self) -> io::Result<App> {
-        Ok(App)
+        Ok(App {
+            config: self.config,
+            registry: self.registry,
+        })
     }
 }
@@ -0,0 +1,301 @@
+//! An iperf-like bulk TCP transfer between a sender and a sink.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+use super::{
+    parse_bytes, parse_duration, parse_number, parse_string, rate, reason, seconds, Options,
+    Report, CHUNK_SIZE, ZEROS,
+};
+
+const DEFAULT_PORT: u16 = 5001;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends as fast as the connection allows, `size` bytes or until stopped.
+///
+/// Options: `server` (required), `port`, `size` and the report `interval`.
+pub(super) struct BulkSender {
+    server: String,
+    port: u16,
+    size: Option<u64>,
+    interval: Duration,
+    socket: Option<SocketId>,
+    connected_at: Duration,
+    sent: u64,
+    interval_sent: u64,
+}
+
+impl BulkSender {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["interval", "port", "server", "size"])?;
+
+        Ok(Self {
+            server: options.require("server", parse_string)?,
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            size: options.get("size", parse_bytes)?,
+            interval: options.get_or("interval", DEFAULT_INTERVAL, parse_duration)?,
+            socket: None,
+            connected_at: Duration::ZERO,
+            sent: 0,
+            interval_sent: 0,
+        })
+    }
+
+    /// Sends until the send buffer is full or everything is sent, then ends the stream.
+    fn fill(&mut self, context: &mut AppContext, socket: SocketId) {
+        loop {
+            let remaining = match self.size {
+                Some(size) => size - self.sent,
+                None => u64::MAX,
+            };
+            if remaining == 0 {
+                if let Err(err) = context.shutdown(socket) {
+                    self.fail(context, err);
+                }
+                return;
+            }
+
+            let len = remaining.min(CHUNK_SIZE as u64) as usize;
+            match context.send(socket, &ZEROS[..len]) {
+                Ok(sent) => {
+                    self.sent += sent as u64;
+                    self.interval_sent += sent as u64;
+                    if sent < len {
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => return self.fail(context, err),
+            }
+        }
+    }
+
+    fn finish(&mut self, context: &mut AppContext) {
+        let duration = context.now() - self.connected_at;
+
+        Report::new(context, "done")
+            .field("bytes", self.sent)
+            .field("duration", seconds(duration))
+            .field("rate", rate(self.sent, duration))
+            .write();
+        context.exit(0);
+    }
+
+    fn fail(&mut self, context: &mut AppContext, err: io::Error) {
+        Report::new(context, "error")
+            .field("bytes", self.sent)
+            .field("reason", reason(&err))
+            .write();
+        context.exit(1);
+    }
+}
+
+impl SimApp for BulkSender {
+    fn on_start(&mut self, context: &mut AppContext) {
+        let ip = match context.resolve(&self.server) {
+            Some(ip) => ip,
+            None => return self.fail(context, io::ErrorKind::NotFound.into()),
+        };
+
+        match context.connect(SocketAddr::new(ip, self.port)) {
+            Ok(socket) => self.socket = Some(socket),
+            Err(err) => self.fail(context, err),
+        }
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.connected_at = context.now();
+
+        let peer = context.peer_addr(socket).map(|peer| peer.to_string());
+        Report::new(context, "connected")
+            .field("peer", peer.unwrap_or_default())
+            .write();
+
+        context.set_timer(self.interval);
+        self.fill(context, socket);
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.fill(context, socket);
+    }
+
+    /// The sink closes the connection once it received everything.
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let mut buf = [0; 1];
+
+        match context.recv(socket, &mut buf) {
+            Ok(0) => self.finish(context),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => self.fail(context, err),
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let (interval, sent) = (self.interval, self.interval_sent);
+        self.interval_sent = 0;
+
+        Report::new(context, "interval")
+            .field("bytes", sent)
+            .field("rate", rate(sent, interval))
+            .write();
+        context.set_timer(interval);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        match self.socket {
+            Some(_) => self.finish(context),
+            None => context.exit(0),
+        }
+    }
+}
+
+struct Transfer {
+    peer: String,
+    connected_at: Duration,
+    received: u64,
+    interval_received: u64,
+}
+
+/// Receives and discards the transfers of any number of senders.
+///
+/// Options: `port` and the report `interval`.
+pub(super) struct BulkSink {
+    port: u16,
+    interval: Duration,
+    transfers: BTreeMap<SocketId, Transfer>,
+    buf: Vec<u8>,
+}
+
+impl BulkSink {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["interval", "port"])?;
+
+        Ok(Self {
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            interval: options.get_or("interval", DEFAULT_INTERVAL, parse_duration)?,
+            transfers: BTreeMap::new(),
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn close(&mut self, context: &mut AppContext, socket: SocketId, event: &str) {
+        let transfer = match self.transfers.remove(&socket) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let duration = context.now() - transfer.connected_at;
+
+        Report::new(context, event)
+            .field("peer", transfer.peer)
+            .field("bytes", transfer.received)
+            .field("duration", seconds(duration))
+            .field("rate", rate(transfer.received, duration))
+            .write();
+        context.close(socket);
+    }
+}
+
+impl SimApp for BulkSink {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            return context.exit(1);
+        }
+
+        context.set_timer(self.interval);
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        let peer = context
+            .peer_addr(socket)
+            .map(|peer| peer.to_string())
+            .unwrap_or_default();
+
+        Report::new(context, "connected")
+            .field("peer", &peer)
+            .write();
+        self.transfers.insert(
+            socket,
+            Transfer {
+                peer,
+                connected_at: context.now(),
+                received: 0,
+                interval_received: 0,
+            },
+        );
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        loop {
+            match context.recv(socket, &mut self.buf) {
+                Ok(0) => return self.close(context, socket, "done"),
+                Ok(len) => {
+                    if let Some(transfer) = self.transfers.get_mut(&socket) {
+                        transfer.received += len as u64;
+                        transfer.interval_received += len as u64;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(_) => return self.close(context, socket, "error"),
+            }
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let interval = self.interval;
+
+        for transfer in self.transfers.values_mut() {
+            let received = std::mem::take(&mut transfer.interval_received);
+            Report::new(context, "interval")
+                .field("peer", &transfer.peer)
+                .field("bytes", received)
+                .field("rate", rate(received, interval))
+                .write();
+        }
+        context.set_timer(interval);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        context.exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::args;
+
+    #[test]
+    fn parse_sender_options() {
+        let sender = BulkSender::new(&args("server=sink size=10mibyte interval=500ms")).unwrap();
+        assert_eq!(sender.server, "sink");
+        assert_eq!(sender.port, DEFAULT_PORT);
+        assert_eq!(sender.size, Some(10 * 1024 * 1024));
+        assert_eq!(sender.interval, Duration::from_millis(500));
+
+        assert!(BulkSender::new(&args("server=sink"))
+            .unwrap()
+            .size
+            .is_none());
+        assert_eq!(
+            BulkSender::new(&args("size=1500")).err(),
+            Some("missing option `server`".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_sink_options() {
+        let sink = BulkSink::new(&args("port=6001")).unwrap();
+        assert_eq!(sink.port, 6001);
+        assert_eq!(sink.interval, DEFAULT_INTERVAL);
+
+        assert!(BulkSink::new(&args("server=sink")).is_err());
+    }
+}
@@ -0,0 +1,686 @@
+//! A peer-to-peer overlay spreading messages like blocks or transactions of a blockchain.
+//!
+//! Peers learn about each other from seed peers, keep a number of outbound connections and
+//! relay every message they see for the first time to their neighbours, either to all of them
+//! (`flood`) or to a random few (`gossip`). All peers of a simulation share a ledger of when
+//! each message reached which peer, from which the origin of a message reports how far and how
+//! fast it spread. Frames on the connections are
+//!
+//! ```text
+//! kind (1) | body length (4) | body
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+use super::{
+    parse_duration, parse_number, parse_string, reason, seconds, Distribution, Options, Report,
+    CHUNK_SIZE,
+};
+
+const DEFAULT_PORT: u16 = 8333;
+const DEFAULT_OUTBOUND: usize = 8;
+const DEFAULT_FANOUT: usize = 3;
+const DEFAULT_REPORT_AFTER: Duration = Duration::from_secs(30);
+
+/// How often a peer replaces lost outbound connections and asks for more addresses.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most addresses sent in answer to a request for peers.
+const MAX_ADDRESSES: usize = 32;
+
+const FRAME_HEADER_SIZE: usize = 5;
+
+/// Size of the message id and creation time every message starts with.
+const MESSAGE_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Announces the port the sender listens on.
+    Hello = 1,
+    GetPeers = 2,
+    /// Addresses of other peers, separated by commas.
+    Peers = 3,
+    Message = 4,
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Kind::Hello),
+            2 => Ok(Kind::GetPeers),
+            3 => Ok(Kind::Peers),
+            4 => Ok(Kind::Message),
+            _ => Err(value),
+        }
+    }
+}
+
+/// When the messages reached the peers of a simulation, shared by all of them.
+#[derive(Default)]
+pub(super) struct Ledger {
+    peers: usize,
+    next_id: u64,
+    /// Delays after which the peers other than the origin received each message.
+    delays: BTreeMap<u64, Vec<Duration>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flood,
+    Gossip(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Timer {
+    Maintenance,
+    Create,
+    Report(u64),
+}
+
+struct Connection {
+    /// The address the peer listens on, once known.
+    address: Option<SocketAddr>,
+    is_outbound: bool,
+    is_open: bool,
+    inbound: Vec<u8>,
+    outbound: VecDeque<u8>,
+}
+
+impl Connection {
+    fn new(address: Option<SocketAddr>, is_outbound: bool) -> Self {
+        Self {
+            address,
+            is_outbound,
+            is_open: !is_outbound,
+            inbound: Vec::new(),
+            outbound: VecDeque::new(),
+        }
+    }
+
+    fn queue_frame(&mut self, kind: Kind, body: &[u8]) {
+        self.outbound.push_back(kind as u8);
+        self.outbound.extend((body.len() as u32).to_be_bytes());
+        self.outbound.extend(body);
+    }
+
+    /// Queues a message of `size` bytes, of which only the header has any meaning.
+    fn queue_message(&mut self, id: u64, created: Duration, size: u64) {
+        self.outbound.push_back(Kind::Message as u8);
+        self.outbound.extend((size as u32).to_be_bytes());
+        self.outbound.extend(id.to_be_bytes());
+        self.outbound
+            .extend((created.as_nanos() as u64).to_be_bytes());
+        self.outbound
+            .resize(self.outbound.len() + size as usize - MESSAGE_HEADER_SIZE, 0);
+    }
+
+    fn flush(&mut self, context: &mut AppContext, socket: SocketId) -> io::Result<()> {
+        while self.is_open && !self.outbound.is_empty() {
+            let (front, _) = self.outbound.as_slices();
+            let len = front.len();
+
+            match context.send(socket, front) {
+                Ok(sent) => {
+                    self.outbound.drain(..sent);
+                    if sent < len {
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads everything available and returns the complete frames and whether the stream
+    /// ended, with errors ending it too.
+    fn receive(
+        &mut self,
+        context: &mut AppContext,
+        socket: SocketId,
+        buf: &mut [u8],
+    ) -> (Vec<(Kind, Vec<u8>)>, bool) {
+        let is_closed = loop {
+            match context.recv(socket, buf) {
+                Ok(0) => break true,
+                Ok(len) => self.inbound.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+
+        let mut frames = Vec::new();
+        let mut start = 0;
+        while let Some(header) = self.inbound.get(start..start + FRAME_HEADER_SIZE) {
+            let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let end = start + FRAME_HEADER_SIZE + len;
+            if self.inbound.len() < end {
+                break;
+            }
+
+            if let Ok(kind) = Kind::try_from(header[0]) {
+                frames.push((kind, self.inbound[start + FRAME_HEADER_SIZE..end].to_vec()));
+            }
+            start = end;
+        }
+        self.inbound.drain(..start);
+
+        (frames, is_closed)
+    }
+}
+
+/// Picks up to `count` of the items at random.
+fn choose<T>(context: &AppContext, mut items: Vec<T>, count: usize) -> Vec<T> {
+    let count = count.min(items.len());
+    let len = items.len();
+
+    for i in 0..count {
+        let j = i + (context.random() * (len - i) as f64) as usize;
+        items.swap(i, j.min(len - 1));
+    }
+    items.truncate(count);
+    items
+}
+
+/// The `p` quantile of sorted delays, by nearest rank.
+fn percentile(delays: &[Duration], p: f64) -> Duration {
+    let rank = (p * delays.len() as f64).ceil() as usize;
+    delays[rank.clamp(1, delays.len()) - 1]
+}
+
+/// A peer of the overlay, creating messages at intervals drawn from `interval` if given.
+///
+/// Options: `seeds`, `port`, `outbound`, `mode`, `fanout`, `interval`, `message_size`,
+/// `messages` and `report_after`.
+pub(super) struct GossipPeer {
+    seeds: Vec<String>,
+    port: u16,
+    outbound: usize,
+    mode: Mode,
+    interval: Option<Distribution>,
+    message_size: Distribution,
+    messages: Option<u64>,
+    report_after: Duration,
+    ledger: Arc<Mutex<Ledger>>,
+    address: Option<SocketAddr>,
+    known: BTreeSet<SocketAddr>,
+    connections: BTreeMap<SocketId, Connection>,
+    timers: BTreeMap<TimerId, Timer>,
+    seen: BTreeSet<u64>,
+    /// Sizes of the messages created here whose propagation is not reported yet.
+    pending: BTreeMap<u64, u64>,
+    created: u64,
+    received: u64,
+    duplicates: u64,
+    buf: Vec<u8>,
+}
+
+impl GossipPeer {
+    pub fn new(args: &[String], ledger: Arc<Mutex<Ledger>>) -> Result<Self, String> {
+        let options = Options::parse(
+            args,
+            &[
+                "fanout",
+                "interval",
+                "message_size",
+                "messages",
+                "mode",
+                "outbound",
+                "port",
+                "report_after",
+                "seeds",
+            ],
+        )?;
+
+        let fanout = options.get_or("fanout", DEFAULT_FANOUT, parse_number)?;
+        let mode = match options
+            .get_or("mode", "flood".to_owned(), parse_string)?
+            .as_str()
+        {
+            "flood" => Mode::Flood,
+            "gossip" => Mode::Gossip(fanout),
+            mode => {
+                return Err(format!(
+                    "option `mode`: `{}` is neither `flood` nor `gossip`",
+                    mode
+                ))
+            }
+        };
+
+        Ok(Self {
+            seeds: options
+                .get_or("seeds", String::new(), parse_string)?
+                .split(',')
+                .filter(|seed| !seed.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            outbound: options.get_or("outbound", DEFAULT_OUTBOUND, parse_number)?,
+            mode,
+            interval: options.get("interval", Distribution::parse_duration)?,
+            message_size: options.get_or(
+                "message_size",
+                Distribution::Fixed(1_000.0),
+                Distribution::parse_bytes,
+            )?,
+            messages: options.get("messages", parse_number)?,
+            report_after: options.get_or("report_after", DEFAULT_REPORT_AFTER, parse_duration)?,
+            ledger,
+            address: None,
+            known: BTreeSet::new(),
+            connections: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            seen: BTreeSet::new(),
+            pending: BTreeMap::new(),
+            created: 0,
+            received: 0,
+            duplicates: 0,
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn set_timer(&mut self, context: &mut AppContext, delay: Duration, timer: Timer) {
+        let id = context.set_timer(delay);
+        self.timers.insert(id, timer);
+    }
+
+    /// Resolves a seed of the form `<host>` or `<host>:<port>`.
+    fn resolve_seed(&self, context: &AppContext, seed: &str) -> Option<SocketAddr> {
+        let (name, port) = match seed.rsplit_once(':') {
+            Some((name, port)) => (name, port.parse().ok()?),
+            None => (seed, self.port),
+        };
+        context.resolve(name).map(|ip| SocketAddr::new(ip, port))
+    }
+
+    /// Opens outbound connections to known peers not connected yet, up to `outbound`.
+    fn connect_known(&mut self, context: &mut AppContext) {
+        let outbound = self
+            .connections
+            .values()
+            .filter(|connection| connection.is_outbound)
+            .count();
+        if outbound >= self.outbound {
+            return;
+        }
+
+        let connected: BTreeSet<_> = self
+            .connections
+            .values()
+            .filter_map(|connection| connection.address)
+            .collect();
+        let candidates = self
+            .known
+            .iter()
+            .filter(|address| !connected.contains(address) && Some(**address) != self.address)
+            .copied()
+            .collect();
+
+        for address in choose(context, candidates, self.outbound - outbound) {
+            if let Ok(socket) = context.connect(address) {
+                self.connections
+                    .insert(socket, Connection::new(Some(address), true));
+            }
+        }
+    }
+
+    /// Asks a random neighbour for more peers while short of outbound connections.
+    fn maintain(&mut self, context: &mut AppContext) {
+        self.connect_known(context);
+
+        let outbound = self
+            .connections
+            .values()
+            .filter(|connection| connection.is_outbound)
+            .count();
+        if outbound < self.outbound {
+            let open = self
+                .connections
+                .iter()
+                .filter(|(_, connection)| connection.is_open)
+                .map(|(socket, _)| *socket)
+                .collect();
+            for socket in choose(context, open, 1) {
+                if let Some(connection) = self.connections.get_mut(&socket) {
+                    connection.queue_frame(Kind::GetPeers, &[]);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, context: &mut AppContext, socket: SocketId, kind: Kind, body: &[u8]) {
+        match kind {
+            Kind::Hello if body.len() == 2 => {
+                let port = u16::from_be_bytes([body[0], body[1]]);
+                let peer = context.peer_addr(socket).ok();
+                if let Some(connection) = self.connections.get_mut(&socket) {
+                    if let Some(peer) = peer {
+                        let address = SocketAddr::new(peer.ip(), port);
+                        connection.address = Some(address);
+                        self.known.insert(address);
+                    }
+                }
+            }
+            Kind::GetPeers => {
+                let requester = self
+                    .connections
+                    .get(&socket)
+                    .and_then(|connection| connection.address);
+                let addresses = self
+                    .known
+                    .iter()
+                    .filter(|address| Some(**address) != requester)
+                    .copied()
+                    .collect();
+                let addresses: Vec<_> = choose(context, addresses, MAX_ADDRESSES)
+                    .iter()
+                    .map(SocketAddr::to_string)
+                    .collect();
+
+                if let Some(connection) = self.connections.get_mut(&socket) {
+                    connection.queue_frame(Kind::Peers, addresses.join(",").as_bytes());
+                }
+            }
+            Kind::Peers => {
+                let addresses = String::from_utf8_lossy(body);
+                for address in addresses.split(',') {
+                    if let Ok(address) = address.parse::<SocketAddr>() {
+                        if Some(address) != self.address {
+                            self.known.insert(address);
+                        }
+                    }
+                }
+                self.connect_known(context);
+            }
+            Kind::Message if body.len() >= MESSAGE_HEADER_SIZE => {
+                let id = u64::from_be_bytes(body[..8].try_into().unwrap_or_default());
+                let created = u64::from_be_bytes(body[8..16].try_into().unwrap_or_default());
+                let created = Duration::from_nanos(created);
+
+                if !self.seen.insert(id) {
+                    self.duplicates += 1;
+                    return;
+                }
+                self.received += 1;
+
+                if let Ok(mut ledger) = self.ledger.lock() {
+                    let delay = context.now().saturating_sub(created);
+                    ledger.delays.entry(id).or_default().push(delay);
+                }
+                self.relay(context, id, created, body.len() as u64, Some(socket));
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends a message on to the neighbours other than the one it came from.
+    fn relay(
+        &mut self,
+        context: &mut AppContext,
+        id: u64,
+        created: Duration,
+        size: u64,
+        source: Option<SocketId>,
+    ) {
+        let neighbours: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(socket, connection)| connection.is_open && Some(**socket) != source)
+            .map(|(socket, _)| *socket)
+            .collect();
+        let targets = match self.mode {
+            Mode::Flood => neighbours,
+            Mode::Gossip(fanout) => choose(context, neighbours, fanout),
+        };
+
+        for socket in targets {
+            if let Some(connection) = self.connections.get_mut(&socket) {
+                connection.queue_message(id, created, size);
+            }
+        }
+    }
+
+    fn create(&mut self, context: &mut AppContext) {
+        let id = match self.ledger.lock() {
+            Ok(mut ledger) => {
+                let id = ledger.next_id;
+                ledger.next_id += 1;
+                ledger.delays.insert(id, Vec::new());
+                id
+            }
+            Err(_) => return,
+        };
+        let size = self
+            .message_size
+            .sample_bytes(context)
+            .clamp(MESSAGE_HEADER_SIZE as u64, u32::MAX as u64);
+
+        self.created += 1;
+        self.seen.insert(id);
+        self.pending.insert(id, size);
+
+        Report::new(context, "message")
+            .field("id", id)
+            .field("bytes", size)
+            .write();
+        self.relay(context, id, context.now(), size, None);
+        self.set_timer(context, self.report_after, Timer::Report(id));
+
+        if let Some(interval) = self.interval {
+            if !matches!(self.messages, Some(messages) if self.created >= messages) {
+                let delay = interval.sample_duration(context);
+                self.set_timer(context, delay, Timer::Create);
+            }
+        }
+    }
+
+    /// Writes how many peers a message created here reached so far and how long it took.
+    fn report(&mut self, context: &mut AppContext, id: u64) {
+        let size = match self.pending.remove(&id) {
+            Some(size) => size,
+            None => return,
+        };
+        let (peers, mut delays) = match self.ledger.lock() {
+            Ok(ledger) => (
+                ledger.peers.saturating_sub(1),
+                ledger.delays.get(&id).cloned().unwrap_or_default(),
+            ),
+            Err(_) => return,
+        };
+        delays.sort();
+
+        let mut report = Report::new(context, "propagation")
+            .field("id", id)
+            .field("bytes", size)
+            .field("reached", delays.len())
+            .field("peers", peers);
+        if !delays.is_empty() {
+            report = report
+                .field("p50", seconds(percentile(&delays, 0.5)))
+                .field("p90", seconds(percentile(&delays, 0.9)))
+                .field("p99", seconds(percentile(&delays, 0.99)))
+                .field("max", seconds(percentile(&delays, 1.0)));
+        }
+        report.write();
+    }
+
+    fn close(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.connections.remove(&socket);
+        context.close(socket);
+    }
+
+    /// Sends what is queued on the connections in one go per connection.
+    fn flush(&mut self, context: &mut AppContext) {
+        let mut failed = Vec::new();
+
+        for (socket, connection) in &mut self.connections {
+            if !connection.outbound.is_empty() && connection.flush(context, *socket).is_err() {
+                failed.push(*socket);
+            }
+        }
+        for socket in failed {
+            self.close(context, socket);
+        }
+    }
+}
+
+impl SimApp for GossipPeer {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            return context.exit(1);
+        }
+        self.address = Some(SocketAddr::new(context.ip(), self.port));
+
+        if let Ok(mut ledger) = self.ledger.lock() {
+            ledger.peers += 1;
+        }
+
+        let seeds: Vec<_> = self
+            .seeds
+            .iter()
+            .filter_map(|seed| self.resolve_seed(context, seed))
+            .filter(|seed| Some(*seed) != self.address)
+            .collect();
+        self.known.extend(seeds);
+
+        self.maintain(context);
+        self.set_timer(context, MAINTENANCE_INTERVAL, Timer::Maintenance);
+
+        if let Some(interval) = self.interval {
+            let delay = interval.sample_duration(context);
+            self.set_timer(context, delay, Timer::Create);
+        }
+        self.flush(context);
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        let connection = self
+            .connections
+            .entry(socket)
+            .or_insert_with(|| Connection::new(None, false));
+
+        if connection.is_outbound {
+            connection.is_open = true;
+            connection.queue_frame(Kind::Hello, &self.port.to_be_bytes());
+            connection.queue_frame(Kind::GetPeers, &[]);
+
+            let peer = connection.address.map(|peer| peer.to_string());
+            Report::new(context, "connected")
+                .field("peer", peer.unwrap_or_default())
+                .write();
+        }
+        self.flush(context);
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let (frames, is_closed) = match self.connections.get_mut(&socket) {
+            Some(connection) => connection.receive(context, socket, &mut self.buf),
+            None => return,
+        };
+
+        for (kind, body) in frames {
+            self.handle(context, socket, kind, &body);
+        }
+        if is_closed {
+            self.close(context, socket);
+        }
+        self.flush(context);
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        let result = match self.connections.get_mut(&socket) {
+            Some(connection) => connection.flush(context, socket),
+            None => return,
+        };
+
+        if result.is_err() {
+            self.close(context, socket);
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, timer: TimerId) {
+        match self.timers.remove(&timer) {
+            Some(Timer::Maintenance) => {
+                self.maintain(context);
+                self.set_timer(context, MAINTENANCE_INTERVAL, Timer::Maintenance);
+            }
+            Some(Timer::Create) => self.create(context),
+            Some(Timer::Report(id)) => self.report(context, id),
+            None => {}
+        }
+        self.flush(context);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        let pending: Vec<_> = self.pending.keys().copied().collect();
+        for id in pending {
+            self.report(context, id);
+        }
+
+        Report::new(context, "done")
+            .field("created", self.created)
+            .field("received", self.received)
+            .field("duplicates", self.duplicates)
+            .field("connections", self.connections.len())
+            .write();
+        context.exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::args;
+
+    fn new_peer(line: &str) -> Result<GossipPeer, String> {
+        GossipPeer::new(&args(line), Arc::new(Mutex::new(Ledger::default())))
+    }
+
+    #[test]
+    fn parse_options() {
+        let peer = new_peer("seeds=a,b:9000 mode=gossip fanout=2 messages=4").unwrap();
+        assert_eq!(peer.seeds, ["a", "b:9000"]);
+        assert_eq!(peer.mode, Mode::Gossip(2));
+        assert_eq!(peer.messages, Some(4));
+        assert!(peer.interval.is_none());
+
+        assert_eq!(new_peer("").unwrap().mode, Mode::Flood);
+        assert!(new_peer("mode=broadcast").is_err());
+    }
+
+    #[test]
+    fn encode_frames() {
+        let mut connection = Connection::new(None, false);
+        connection.queue_frame(Kind::Peers, b"a:1");
+        connection.queue_message(9, Duration::from_nanos(2), 20);
+
+        let bytes: Vec<u8> = connection.outbound.into_iter().collect();
+        assert_eq!(bytes[..8], [3, 0, 0, 0, 3, b'a', b':', b'1']);
+        assert_eq!(bytes[8..13], [4, 0, 0, 0, 20]);
+        assert_eq!(bytes[13..21], 9u64.to_be_bytes());
+        assert_eq!(bytes[21..29], 2u64.to_be_bytes());
+        assert_eq!(bytes.len(), 8 + FRAME_HEADER_SIZE + 20);
+    }
+
+    #[test]
+    fn rank_delays() {
+        let delays: Vec<_> = (1..=10).map(Duration::from_secs).collect();
+        assert_eq!(percentile(&delays, 0.5), Duration::from_secs(5));
+        assert_eq!(percentile(&delays, 0.9), Duration::from_secs(9));
+        assert_eq!(percentile(&delays, 0.0), Duration::from_secs(1));
+        assert_eq!(percentile(&delays, 1.0), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,432 @@
+//! An HTTP-like request/response exchange over persistent TCP connections.
+//!
+//! Requests and responses are messages of an 8 byte big-endian length followed by that many
+//! bytes. The client picks the size of its requests, the server the size of the object it
+//! answers each request with.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+use super::{
+    parse_number, parse_string, rate, reason, seconds, Distribution, Options, Report, CHUNK_SIZE,
+    ZEROS,
+};
+
+const DEFAULT_PORT: u16 = 80;
+pub(super) const HEADER_SIZE: usize = 8;
+
+/// Messages queued on a connection, sent as far as the send buffer allows.
+#[derive(Default)]
+struct Outgoing(VecDeque<(Vec<u8>, u64)>);
+
+impl Outgoing {
+    fn push(&mut self, size: u64) {
+        self.0.push_back((size.to_be_bytes().to_vec(), size));
+    }
+
+    fn flush(&mut self, context: &mut AppContext, socket: SocketId) -> io::Result<()> {
+        while let Some((header, body)) = self.0.front_mut() {
+            let (sent, len) = if !header.is_empty() {
+                let sent = send(context, socket, header)?;
+                header.drain(..sent);
+                (sent, sent + header.len())
+            } else if *body > 0 {
+                let len = (*body).min(CHUNK_SIZE as u64) as usize;
+                let sent = send(context, socket, &ZEROS[..len])?;
+                *body -= sent as u64;
+                (sent, len)
+            } else {
+                self.0.pop_front();
+                continue;
+            };
+
+            if sent < len {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends, treating a full send buffer as nothing sent.
+fn send(context: &mut AppContext, socket: SocketId, buf: &[u8]) -> io::Result<usize> {
+    match context.send(socket, buf) {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+        result => result,
+    }
+}
+
+/// Splits the received stream into messages.
+#[derive(Default)]
+pub(super) struct Incoming {
+    header: Vec<u8>,
+    remaining: Option<u64>,
+    size: u64,
+}
+
+impl Incoming {
+    /// Consumes received bytes and returns the sizes of the messages they completed.
+    pub fn push(&mut self, mut data: &[u8]) -> Vec<u64> {
+        let mut completed = Vec::new();
+
+        while !data.is_empty() || self.remaining == Some(0) {
+            match self.remaining {
+                None => {
+                    let len = (HEADER_SIZE - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..len]);
+                    data = &data[len..];
+
+                    if self.header.len() == HEADER_SIZE {
+                        let mut header = [0; HEADER_SIZE];
+                        header.copy_from_slice(&self.header);
+                        self.size = u64::from_be_bytes(header);
+                        self.remaining = Some(self.size);
+                        self.header.clear();
+                    }
+                }
+                Some(0) => {
+                    completed.push(self.size);
+                    self.remaining = None;
+                }
+                Some(remaining) => {
+                    let len = remaining.min(data.len() as u64);
+                    data = &data[len as usize..];
+                    self.remaining = Some(remaining - len);
+                }
+            }
+        }
+
+        completed
+    }
+}
+
+/// Reads everything available, returning `Ok(None)` at the end of the stream.
+fn receive(
+    context: &mut AppContext,
+    socket: SocketId,
+    incoming: &mut Incoming,
+    buf: &mut [u8],
+) -> io::Result<Option<(usize, Vec<u64>)>> {
+    let mut received = 0;
+    let mut completed = Vec::new();
+
+    loop {
+        match context.recv(socket, buf) {
+            Ok(0) => return Ok(None),
+            Ok(len) => {
+                received += len;
+                completed.extend(incoming.push(&buf[..len]));
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                return Ok(Some((received, completed)))
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+struct Connection {
+    peer: String,
+    incoming: Incoming,
+    outgoing: Outgoing,
+}
+
+/// Answers every request with an object of a size drawn from `object_size`.
+///
+/// Options: `port` and `object_size`.
+pub(super) struct HttpServer {
+    port: u16,
+    object_size: Distribution,
+    connections: BTreeMap<SocketId, Connection>,
+    buf: Vec<u8>,
+}
+
+impl HttpServer {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["object_size", "port"])?;
+
+        Ok(Self {
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            object_size: options.get_or(
+                "object_size",
+                Distribution::Fixed(10_240.0),
+                Distribution::parse_bytes,
+            )?,
+            connections: BTreeMap::new(),
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn close(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.connections.remove(&socket);
+        context.close(socket);
+    }
+}
+
+impl SimApp for HttpServer {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            context.exit(1);
+        }
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        let peer = context
+            .peer_addr(socket)
+            .map(|peer| peer.to_string())
+            .unwrap_or_default();
+
+        self.connections.insert(
+            socket,
+            Connection {
+                peer,
+                incoming: Incoming::default(),
+                outgoing: Outgoing::default(),
+            },
+        );
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let connection = match self.connections.get_mut(&socket) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let requests = match receive(context, socket, &mut connection.incoming, &mut self.buf) {
+            Ok(Some((_, requests))) => requests,
+            Ok(None) | Err(_) => return self.close(context, socket),
+        };
+
+        for request in requests {
+            let size = self.object_size.sample_bytes(context);
+            let connection = match self.connections.get_mut(&socket) {
+                Some(connection) => connection,
+                None => return,
+            };
+            connection.outgoing.push(size);
+
+            let peer = connection.peer.clone();
+            Report::new(context, "request")
+                .field("peer", peer)
+                .field("request_bytes", request)
+                .field("bytes", size)
+                .write();
+        }
+
+        self.on_writable(context, socket);
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        let result = match self.connections.get_mut(&socket) {
+            Some(connection) => connection.outgoing.flush(context, socket),
+            None => return,
+        };
+
+        if result.is_err() {
+            self.close(context, socket);
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        context.exit(0);
+    }
+}
+
+/// Sends requests one after another over a single connection, pausing for a drawn think time
+/// after each response.
+///
+/// Options: `server` (required), `port`, `requests`, `request_size` and `think_time`.
+pub(super) struct HttpClient {
+    server: String,
+    port: u16,
+    requests: Option<u64>,
+    request_size: Distribution,
+    think_time: Distribution,
+    socket: Option<SocketId>,
+    incoming: Incoming,
+    outgoing: Outgoing,
+    completed: u64,
+    received: u64,
+    sent_at: Duration,
+    first_byte_at: Option<Duration>,
+    started_at: Duration,
+    buf: Vec<u8>,
+}
+
+impl HttpClient {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(
+            args,
+            &["port", "request_size", "requests", "server", "think_time"],
+        )?;
+
+        Ok(Self {
+            server: options.require("server", parse_string)?,
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            requests: options.get("requests", parse_number)?,
+            request_size: options.get_or(
+                "request_size",
+                Distribution::Fixed(100.0),
+                Distribution::parse_bytes,
+            )?,
+            think_time: options.get_or(
+                "think_time",
+                Distribution::Fixed(0.0),
+                Distribution::parse_duration,
+            )?,
+            socket: None,
+            incoming: Incoming::default(),
+            outgoing: Outgoing::default(),
+            completed: 0,
+            received: 0,
+            sent_at: Duration::ZERO,
+            first_byte_at: None,
+            started_at: Duration::ZERO,
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn request(&mut self, context: &mut AppContext, socket: SocketId) {
+        if matches!(self.requests, Some(requests) if self.completed >= requests) {
+            return self.finish(context);
+        }
+
+        self.sent_at = context.now();
+        self.first_byte_at = None;
+        self.outgoing.push(self.request_size.sample_bytes(context));
+        self.on_writable(context, socket);
+    }
+
+    fn finish(&mut self, context: &mut AppContext) {
+        let duration = context.now() - self.started_at;
+
+        Report::new(context, "done")
+            .field("requests", self.completed)
+            .field("bytes", self.received)
+            .field("duration", seconds(duration))
+            .field("rate", rate(self.received, duration))
+            .write();
+        context.exit(0);
+    }
+
+    fn fail(&mut self, context: &mut AppContext, err: io::Error) {
+        Report::new(context, "error")
+            .field("requests", self.completed)
+            .field("reason", reason(&err))
+            .write();
+        context.exit(1);
+    }
+}
+
+impl SimApp for HttpClient {
+    fn on_start(&mut self, context: &mut AppContext) {
+        self.started_at = context.now();
+
+        let ip = match context.resolve(&self.server) {
+            Some(ip) => ip,
+            None => return self.fail(context, io::ErrorKind::NotFound.into()),
+        };
+
+        match context.connect(SocketAddr::new(ip, self.port)) {
+            Ok(socket) => self.socket = Some(socket),
+            Err(err) => self.fail(context, err),
+        }
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.request(context, socket);
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let (received, responses) =
+            match receive(context, socket, &mut self.incoming, &mut self.buf) {
+                Ok(Some(result)) => result,
+                Ok(None) => return self.fail(context, io::ErrorKind::UnexpectedEof.into()),
+                Err(err) => return self.fail(context, err),
+            };
+
+        if received > 0 && self.first_byte_at.is_none() {
+            self.first_byte_at = Some(context.now());
+        }
+        self.received += received as u64;
+
+        // Requests are sent one at a time, so at most one response completes.
+        if let Some(size) = responses.first() {
+            self.completed += 1;
+
+            let now = context.now();
+            let first_byte_at = self.first_byte_at.unwrap_or(now);
+            Report::new(context, "response")
+                .field("request", self.completed)
+                .field("bytes", size)
+                .field("first_byte", seconds(first_byte_at - self.sent_at))
+                .field("latency", seconds(now - self.sent_at))
+                .write();
+
+            let think_time = self.think_time.sample_duration(context);
+            context.set_timer(think_time);
+        }
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Err(err) = self.outgoing.flush(context, socket) {
+            self.fail(context, err);
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        if let Some(socket) = self.socket {
+            self.request(context, socket);
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        self.finish(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::args;
+
+    fn message(size: u64) -> Vec<u8> {
+        let mut message = size.to_be_bytes().to_vec();
+        message.resize(HEADER_SIZE + size as usize, 0);
+        message
+    }
+
+    #[test]
+    fn split_messages() {
+        let mut stream = message(3);
+        stream.extend(message(0));
+        stream.extend(message(1000));
+
+        let mut incoming = Incoming::default();
+        assert_eq!(incoming.push(&stream[..5]), []);
+        assert_eq!(incoming.push(&stream[5..20]), [3, 0]);
+        assert_eq!(incoming.push(&stream[20..stream.len() - 1]), []);
+        assert_eq!(incoming.push(&stream[stream.len() - 1..]), [1000]);
+    }
+
+    #[test]
+    fn parse_options() {
+        let client =
+            HttpClient::new(&args("server=web requests=5 think_time=exponential:2s")).unwrap();
+        assert_eq!(client.port, DEFAULT_PORT);
+        assert_eq!(client.requests, Some(5));
+        assert!(matches!(client.think_time, Distribution::Exponential(mean) if mean == 2.0));
+
+        assert!(HttpClient::new(&args("server=web request_size=lots")).is_err());
+        assert!(HttpServer::new(&args("object_size=1mibyte")).is_ok());
+        assert!(HttpServer::new(&args("server=web")).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! Standard workloads built on the application API, available to every configuration.
+//!
+//! The applications take their options as `key=value` arguments and write one line per
+//! report to their output file, starting with the simulated time in seconds and the kind of
+//! report, followed by `key=value` fields:
+//!
+//! ```text
+//! time=1.250000 event=interval bytes=1250000 rate=10000000
+//! ```
+
+mod bulk;
+mod gossip;
+mod http;
+mod onion;
+mod onoff;
+mod ping;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::de::value;
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+
+use crate::simapp::{AppContext, AppRegistry, SimApp};
+use crate::units::{Bits, Bytes, TimeInterval};
+
+/// Chunk size the applications read and write with.
+const CHUNK_SIZE: usize = 65_536;
+
+/// Payload the applications send, whose contents do not matter.
+static ZEROS: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+
+/// Registers the built-in applications.
+pub(crate) fn register(registry: &mut AppRegistry) {
+    let ledger = Arc::new(Mutex::new(gossip::Ledger::default()));
+
+    registry.register("bulk_sender", |args| boxed(bulk::BulkSender::new(args)));
+    registry.register("bulk_sink", |args| boxed(bulk::BulkSink::new(args)));
+    registry.register("gossip_peer", move |args| {
+        boxed(gossip::GossipPeer::new(args, ledger.clone()))
+    });
+    registry.register("http_client", |args| boxed(http::HttpClient::new(args)));
+    registry.register("http_server", |args| boxed(http::HttpServer::new(args)));
+    registry.register("onion_authority", |args| {
+        boxed(onion::OnionAuthority::new(args))
+    });
+    registry.register("onion_client", |args| boxed(onion::OnionClient::new(args)));
+    registry.register("onion_relay", |args| boxed(onion::OnionRelay::new(args)));
+    registry.register("onoff_source", |args| boxed(onoff::OnOffSource::new(args)));
+    registry.register("ping", |args| boxed(ping::PingClient::new(args)));
+    registry.register("udp_sink", |args| boxed(onoff::UdpSink::new(args)));
+}
+
+fn boxed<T>(app: Result<T, String>) -> Result<Box<dyn SimApp>, String>
+where
+    T: SimApp + 'static,
+{
+    app.map(|app| Box::new(app) as Box<dyn SimApp>)
+}
+
+/// The `key=value` arguments of an application.
+struct Options(BTreeMap<String, String>);
+
+impl Options {
+    /// Parses the arguments, rejecting keys not in `known`.
+    fn parse(args: &[String], known: &[&str]) -> Result<Self, String> {
+        let mut options = BTreeMap::new();
+
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("argument `{}` is not of the form `key=value`", arg))?;
+
+            if !known.contains(&key) {
+                return Err(format!(
+                    "unknown option `{}`, expected one of {}",
+                    key,
+                    known.join(", ")
+                ));
+            }
+            options.insert(key.to_owned(), value.to_owned());
+        }
+
+        Ok(Self(options))
+    }
+
+    fn get<T, F>(&self, key: &str, parse: F) -> Result<Option<T>, String>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        self.0
+            .get(key)
+            .map(|value| parse(value).map_err(|err| format!("option `{}`: {}", key, err)))
+            .transpose()
+    }
+
+    fn get_or<T, F>(&self, key: &str, default: T, parse: F) -> Result<T, String>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        self.get(key, parse).map(|value| value.unwrap_or(default))
+    }
+
+    fn require<T, F>(&self, key: &str, parse: F) -> Result<T, String>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        self.get(key, parse)?
+            .ok_or_else(|| format!("missing option `{}`", key))
+    }
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    Ok(value.to_owned())
+}
+
+fn parse_number<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err: T::Err| err.to_string())
+}
+
+/// Parses a size like `1500` or `10 mibyte` into bytes.
+fn parse_bytes(value: &str) -> Result<u64, String> {
+    if let Ok(bytes) = value.parse() {
+        return Ok(bytes);
+    }
+
+    Bytes::deserialize(IntoDeserializer::<value::Error>::into_deserializer(value))
+        .map(|bytes| bytes.bytes())
+        .map_err(|_| format!("invalid size `{}`", value))
+}
+
+/// Parses a rate like `10 mbit` into bits per second.
+fn parse_rate(value: &str) -> Result<u64, String> {
+    if let Ok(bits) = value.parse() {
+        return Ok(bits);
+    }
+
+    Bits::deserialize(IntoDeserializer::<value::Error>::into_deserializer(value))
+        .map(|bits| bits.bit())
+        .map_err(|_| format!("invalid rate `{}`", value))
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<TimeInterval>()
+        .map(Duration::from)
+        .map_err(|err| err.to_string())
+}
+
+/// A random variable, given as `<kind>:<parameters>` with the parameters in the unit of the
+/// option it is used for:
+///
+/// - `fixed:<value>`, or just `<value>`
+/// - `uniform:<min>:<max>`
+/// - `exponential:<mean>`
+/// - `pareto:<scale>:<shape>`, with the scale being the minimum value
+#[derive(Debug, Clone, Copy)]
+enum Distribution {
+    Fixed(f64),
+    Uniform(f64, f64),
+    Exponential(f64),
+    Pareto(f64, f64),
+}
+
+impl Distribution {
+    fn parse<F>(value: &str, parse_value: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Result<f64, String>,
+    {
+        let mut parts = value.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let parameters: Vec<_> = parts.collect();
+
+        let distribution = match (kind, parameters.as_slice()) {
+            ("fixed", [value]) => Distribution::Fixed(parse_value(value)?),
+            ("uniform", [min, max]) => {
+                let (min, max) = (parse_value(min)?, parse_value(max)?);
+                if min > max {
+                    return Err(format!("empty range in `{}`", value));
+                }
+                Distribution::Uniform(min, max)
+            }
+            ("exponential", [mean]) => Distribution::Exponential(parse_value(mean)?),
+            ("pareto", [scale, shape]) => {
+                let shape = parse_number::<f64>(shape)?;
+                if shape <= 0.0 {
+                    return Err(format!("non-positive pareto shape in `{}`", value));
+                }
+                Distribution::Pareto(parse_value(scale)?, shape)
+            }
+            (_, []) => Distribution::Fixed(parse_value(kind)?),
+            _ => return Err(format!("invalid distribution `{}`", value)),
+        };
+
+        Ok(distribution)
+    }
+
+    fn parse_bytes(value: &str) -> Result<Self, String> {
+        Self::parse(value, |value| parse_bytes(value).map(|bytes| bytes as f64))
+    }
+
+    fn parse_duration(value: &str) -> Result<Self, String> {
+        Self::parse(value, |value| {
+            parse_duration(value).map(|duration| duration.as_secs_f64())
+        })
+    }
+
+    /// Draws a value from the seeded random source of the host.
+    fn sample(&self, context: &AppContext) -> f64 {
+        match *self {
+            Distribution::Fixed(value) => value,
+            Distribution::Uniform(min, max) => min + (max - min) * context.random(),
+            Distribution::Exponential(mean) => -mean * (1.0 - context.random()).ln(),
+            Distribution::Pareto(scale, shape) => {
+                scale / (1.0 - context.random()).powf(1.0 / shape)
+            }
+        }
+    }
+
+    fn sample_bytes(&self, context: &AppContext) -> u64 {
+        self.sample(context).round().max(0.0) as u64
+    }
+
+    fn sample_duration(&self, context: &AppContext) -> Duration {
+        Duration::from_secs_f64(self.sample(context).clamp(0.0, u32::MAX as f64))
+    }
+}
+
+/// A report line of an application, written to its output by [`Report::write`].
+struct Report<'a, 'b> {
+    context: &'a mut AppContext<'b>,
+    line: String,
+}
+
+impl<'a, 'b> Report<'a, 'b> {
+    fn new(context: &'a mut AppContext<'b>, event: &str) -> Self {
+        let line = format!("time={} event={}", seconds(context.now()), event);
+        Self { context, line }
+    }
+
+    fn field(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.line.push_str(&format!(" {}={}", key, value));
+        self
+    }
+
+    fn write(self) {
+        self.context.output(&self.line);
+    }
+}
+
+/// Formats a duration as seconds with microsecond precision.
+fn seconds(duration: Duration) -> String {
+    format!("{:.6}", duration.as_secs_f64())
+}
+
+/// Names the kind of an error as a single word, like `connection_refused`. The names are
+/// spelled out here rather than derived from the descriptions of the kinds, so that the
+/// reports stay the same across Rust releases.
+fn reason(err: &io::Error) -> &'static str {
+    match err.raw_os_error() {
+        Some(libc::EHOSTUNREACH) => return "host_unreachable",
+        Some(libc::ENETUNREACH) => return "network_unreachable",
+        _ => {}
+    }
+
+    match err.kind() {
+        io::ErrorKind::NotFound => "not_found",
+        io::ErrorKind::PermissionDenied => "permission_denied",
+        io::ErrorKind::ConnectionRefused => "connection_refused",
+        io::ErrorKind::ConnectionReset => "connection_reset",
+        io::ErrorKind::ConnectionAborted => "connection_aborted",
+        io::ErrorKind::NotConnected => "not_connected",
+        io::ErrorKind::AddrInUse => "address_in_use",
+        io::ErrorKind::AddrNotAvailable => "address_not_available",
+        io::ErrorKind::BrokenPipe => "broken_pipe",
+        io::ErrorKind::AlreadyExists => "already_exists",
+        io::ErrorKind::WouldBlock => "would_block",
+        io::ErrorKind::InvalidInput => "invalid_input",
+        io::ErrorKind::InvalidData => "invalid_data",
+        io::ErrorKind::TimedOut => "timed_out",
+        io::ErrorKind::WriteZero => "write_zero",
+        io::ErrorKind::Interrupted => "interrupted",
+        io::ErrorKind::Unsupported => "unsupported",
+        io::ErrorKind::UnexpectedEof => "unexpected_eof",
+        io::ErrorKind::OutOfMemory => "out_of_memory",
+        _ => "other",
+    }
+}
+
+/// The rate of `bytes` transferred within `duration`, in bits per second.
+fn rate(bytes: u64, duration: Duration) -> u64 {
+    match duration.as_secs_f64() {
+        secs if secs > 0.0 => (bytes as f64 * 8.0 / secs).round() as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_options() {
+        let options = Options::parse(&args("port=80 size=10mibyte"), &["port", "size"]).unwrap();
+        assert_eq!(options.require("port", parse_number::<u16>), Ok(80));
+        assert_eq!(options.get("size", parse_bytes), Ok(Some(10 * 1024 * 1024)));
+        assert_eq!(options.get_or("rate", 5, parse_rate), Ok(5));
+        assert_eq!(
+            options.require("rate", parse_rate),
+            Err("missing option `rate`".to_owned())
+        );
+
+        let options = Options::parse(&args("port=http"), &["port"]).unwrap();
+        assert!(options
+            .require("port", parse_number::<u16>)
+            .unwrap_err()
+            .starts_with("option `port`: "));
+
+        assert!(Options::parse(&args("port"), &["port"]).is_err());
+        assert_eq!(
+            Options::parse(&args("speed=1"), &["port", "rate"]).err(),
+            Some("unknown option `speed`, expected one of port, rate".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse_bytes("1500"), Ok(1500));
+        assert_eq!(parse_bytes("2 kibyte"), Ok(2048));
+        assert!(parse_bytes("2 parsecs").is_err());
+        assert_eq!(parse_rate("2 mbit"), Ok(2_000_000));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn parse_distributions() {
+        assert!(matches!(
+            Distribution::parse_bytes("1 kibyte"),
+            Ok(Distribution::Fixed(value)) if value == 1024.0
+        ));
+        assert!(matches!(
+            Distribution::parse_duration("uniform:1s:3s"),
+            Ok(Distribution::Uniform(min, max)) if min == 1.0 && max == 3.0
+        ));
+        assert!(matches!(
+            Distribution::parse_duration("pareto:500ms:1.5"),
+            Ok(Distribution::Pareto(scale, shape)) if scale == 0.5 && shape == 1.5
+        ));
+        assert!(Distribution::parse_duration("uniform:3s:1s").is_err());
+        assert!(Distribution::parse_duration("pareto:1s:0").is_err());
+        assert!(Distribution::parse_duration("normal:1s:1s").is_err());
+    }
+
+    #[test]
+    fn name_error_reasons() {
+        let reasons: Vec<_> = [
+            io::ErrorKind::NotFound.into(),
+            io::Error::from_raw_os_error(libc::ECONNREFUSED),
+            io::Error::from_raw_os_error(libc::EHOSTUNREACH),
+            io::Error::from_raw_os_error(libc::ETIMEDOUT),
+            io::ErrorKind::AddrInUse.into(),
+            io::ErrorKind::Other.into(),
+        ]
+        .iter()
+        .map(reason)
+        .collect();
+
+        assert_eq!(
+            reasons,
+            [
+                "not_found",
+                "connection_refused",
+                "host_unreachable",
+                "timed_out",
+                "address_in_use",
+                "other",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_reports() {
+        assert_eq!(seconds(Duration::from_micros(1_250_001)), "1.250001");
+        assert_eq!(rate(1_250_000, Duration::from_secs(1)), 10_000_000);
+        assert_eq!(rate(1_250_000, Duration::ZERO), 0);
+    }
+}
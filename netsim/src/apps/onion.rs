@@ -0,0 +1,1080 @@
+//! A simplified onion routing network in the style of Tor.
+//!
+//! Relays register with a directory authority, which hands the list of relays to clients.
+//! Clients build circuits of three relays, picked in proportion to their bandwidth weights,
+//! and download objects from an `http_server` through them. All traffic between the parties
+//! are fixed-size cells on TCP connections, so the load on relays matches real onion routing
+//! without cryptography or flow control:
+//!
+//! ```text
+//! circuit id (4) | command (1) | payload length (2) | payload, padded to 514 bytes
+//! ```
+//!
+//! Relays and hops are named `<host>:<port>` and resolved through the simulated name server.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+use super::http::{Incoming, HEADER_SIZE};
+use super::{
+    parse_duration, parse_number, parse_rate, parse_string, rate, reason, seconds, Distribution,
+    Options, Report, CHUNK_SIZE,
+};
+
+const CELL_SIZE: usize = 514;
+const CELL_HEADER_SIZE: usize = 7;
+const MAX_PAYLOAD: usize = CELL_SIZE - CELL_HEADER_SIZE;
+
+const DEFAULT_AUTHORITY_PORT: u16 = 9030;
+const DEFAULT_RELAY_PORT: u16 = 9001;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const CIRCUIT_LENGTH: usize = 3;
+
+/// Delay before a client asks the authority again when too few relays registered yet.
+const CONSENSUS_RETRY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Create = 1,
+    Created = 2,
+    Destroy = 3,
+    Extend = 4,
+    Extended = 5,
+    Begin = 6,
+    Connected = 7,
+    Data = 8,
+    End = 9,
+    Register = 10,
+    ConsensusRequest = 11,
+    ConsensusEntry = 12,
+    ConsensusEnd = 13,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use Command::*;
+
+        [
+            Create,
+            Created,
+            Destroy,
+            Extend,
+            Extended,
+            Begin,
+            Connected,
+            Data,
+            End,
+            Register,
+            ConsensusRequest,
+            ConsensusEntry,
+            ConsensusEnd,
+        ]
+        .into_iter()
+        .find(|command| *command as u8 == value)
+        .ok_or(value)
+    }
+}
+
+struct Cell {
+    circuit: u32,
+    command: Command,
+    payload: Vec<u8>,
+}
+
+impl Cell {
+    fn new(circuit: u32, command: Command, payload: &[u8]) -> Self {
+        Self {
+            circuit,
+            command,
+            payload: payload[..payload.len().min(MAX_PAYLOAD)].to_vec(),
+        }
+    }
+
+    fn encode(&self) -> [u8; CELL_SIZE] {
+        let mut bytes = [0; CELL_SIZE];
+        bytes[..4].copy_from_slice(&self.circuit.to_be_bytes());
+        bytes[4] = self.command as u8;
+        bytes[5..7].copy_from_slice(&(self.payload.len() as u16).to_be_bytes());
+        bytes[CELL_HEADER_SIZE..CELL_HEADER_SIZE + self.payload.len()]
+            .copy_from_slice(&self.payload);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let circuit = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let command = Command::try_from(bytes[4]).ok()?;
+        let len = u16::from_be_bytes([bytes[5], bytes[6]]) as usize;
+        let payload = bytes
+            .get(CELL_HEADER_SIZE..CELL_HEADER_SIZE + len)?
+            .to_vec();
+
+        Some(Self {
+            circuit,
+            command,
+            payload,
+        })
+    }
+}
+
+/// A relay as registered with the authority.
+#[derive(Clone)]
+struct Descriptor {
+    address: String,
+    bandwidth: u64,
+}
+
+impl Descriptor {
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = self.bandwidth.to_be_bytes().to_vec();
+        payload.extend_from_slice(self.address.as_bytes());
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let bandwidth = u64::from_be_bytes(payload.get(..8)?.try_into().ok()?);
+        let address = String::from_utf8(payload[8..].to_vec()).ok()?;
+        Some(Self { address, bandwidth })
+    }
+}
+
+/// A TCP connection of an onion router, buffering what the socket does not take yet.
+#[derive(Default)]
+struct Channel {
+    is_open: bool,
+    inbound: Vec<u8>,
+    outbound: VecDeque<u8>,
+}
+
+impl Channel {
+    fn open() -> Self {
+        Self {
+            is_open: true,
+            ..Self::default()
+        }
+    }
+
+    fn queue_cell(&mut self, cell: &Cell) {
+        self.outbound.extend(cell.encode());
+    }
+
+    fn flush(&mut self, context: &mut AppContext, socket: SocketId) -> io::Result<()> {
+        while self.is_open && !self.outbound.is_empty() {
+            let (front, _) = self.outbound.as_slices();
+            let len = front.len();
+
+            match context.send(socket, front) {
+                Ok(sent) => {
+                    self.outbound.drain(..sent);
+                    if sent < len {
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads everything available and whether the stream ended, with errors ending it too.
+    fn receive(
+        &mut self,
+        context: &mut AppContext,
+        socket: SocketId,
+        buf: &mut [u8],
+    ) -> (Vec<u8>, bool) {
+        let mut received = Vec::new();
+
+        loop {
+            match context.recv(socket, buf) {
+                Ok(0) => return (received, true),
+                Ok(len) => received.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return (received, false),
+                Err(_) => return (received, true),
+            }
+        }
+    }
+
+    /// Reads the complete cells available and whether the stream ended.
+    fn receive_cells(
+        &mut self,
+        context: &mut AppContext,
+        socket: SocketId,
+        buf: &mut [u8],
+    ) -> (Vec<Cell>, bool) {
+        let (received, is_closed) = self.receive(context, socket, buf);
+        self.inbound.extend_from_slice(&received);
+
+        let complete = self.inbound.len() / CELL_SIZE * CELL_SIZE;
+        let cells = self.inbound[..complete]
+            .chunks(CELL_SIZE)
+            .filter_map(Cell::decode)
+            .collect();
+        self.inbound.drain(..complete);
+
+        (cells, is_closed)
+    }
+}
+
+/// Queues a cell on one of the channels, sent by the next [`flush_channels`].
+fn send_cell(
+    channels: &mut BTreeMap<SocketId, Channel>,
+    (socket, circuit): (SocketId, u32),
+    command: Command,
+    payload: &[u8],
+) {
+    if let Some(channel) = channels.get_mut(&socket) {
+        channel.queue_cell(&Cell::new(circuit, command, payload));
+    }
+}
+
+/// Sends the cells queued on the channels in one go per channel. Channels failing are closed
+/// once they report the error to `on_receive`.
+fn flush_channels(context: &mut AppContext, channels: &mut BTreeMap<SocketId, Channel>) {
+    for (socket, channel) in channels.iter_mut() {
+        if !channel.outbound.is_empty() {
+            let _ = channel.flush(context, *socket);
+        }
+    }
+}
+
+/// Resolves an address of the form `<host>:<port>`.
+fn resolve(context: &AppContext, address: &str) -> Option<SocketAddr> {
+    let (name, port) = address.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    context.resolve(name).map(|ip| SocketAddr::new(ip, port))
+}
+
+/// Collects the relays registered by the relays and hands them to clients.
+///
+/// Options: `port`.
+pub(super) struct OnionAuthority {
+    port: u16,
+    channels: BTreeMap<SocketId, Channel>,
+    relays: BTreeMap<String, Descriptor>,
+    buf: Vec<u8>,
+}
+
+impl OnionAuthority {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["port"])?;
+
+        Ok(Self {
+            port: options.get_or("port", DEFAULT_AUTHORITY_PORT, parse_number)?,
+            channels: BTreeMap::new(),
+            relays: BTreeMap::new(),
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+}
+
+impl SimApp for OnionAuthority {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            context.exit(1);
+        }
+    }
+
+    fn on_connect(&mut self, _context: &mut AppContext, socket: SocketId) {
+        self.channels.insert(socket, Channel::open());
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let (cells, is_closed) = match self.channels.get_mut(&socket) {
+            Some(channel) => channel.receive_cells(context, socket, &mut self.buf),
+            None => return,
+        };
+        if is_closed {
+            self.channels.remove(&socket);
+            context.close(socket);
+        }
+
+        for cell in cells {
+            match cell.command {
+                Command::Register => {
+                    if let Some(relay) = Descriptor::decode(&cell.payload) {
+                        Report::new(context, "register")
+                            .field("relay", &relay.address)
+                            .field("bandwidth", relay.bandwidth)
+                            .write();
+                        self.relays.insert(relay.address.clone(), relay);
+                    }
+                }
+                Command::ConsensusRequest => {
+                    let target = (socket, cell.circuit);
+                    for relay in self.relays.values() {
+                        let payload = relay.encode();
+                        send_cell(
+                            &mut self.channels,
+                            target,
+                            Command::ConsensusEntry,
+                            &payload,
+                        );
+                    }
+                    send_cell(&mut self.channels, target, Command::ConsensusEnd, &[]);
+                }
+                _ => {}
+            }
+        }
+        flush_channels(context, &mut self.channels);
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Some(channel) = self.channels.get_mut(&socket) {
+            let _ = channel.flush(context, socket);
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        context.exit(0);
+    }
+}
+
+/// The side of a circuit a cell arrived on at a relay.
+enum Hop {
+    /// Towards the client, with the next relay or the stream of the exit, once known.
+    Backward {
+        next: Option<(SocketId, u32)>,
+        stream: Option<SocketId>,
+    },
+    /// Towards the exit, with the side towards the client.
+    Forward { previous: (SocketId, u32) },
+}
+
+/// A TCP connection an exit relay opened for a circuit.
+struct Stream {
+    circuit: (SocketId, u32),
+    channel: Channel,
+}
+
+/// Forwards cells along the circuits through it and, as the last relay of a circuit,
+/// extends it or connects it to its destination.
+///
+/// Options: `authority` (required, `<host>:<port>`), `port`, `bandwidth` and the report
+/// `interval`.
+pub(super) struct OnionRelay {
+    authority: String,
+    port: u16,
+    bandwidth: u64,
+    interval: Duration,
+    channels: BTreeMap<SocketId, Channel>,
+    /// Connections opened to other relays, by address.
+    relays: BTreeMap<String, SocketId>,
+    circuits: BTreeMap<(SocketId, u32), Hop>,
+    streams: BTreeMap<SocketId, Stream>,
+    next_circuit: u32,
+    cells: u64,
+    interval_cells: u64,
+    buf: Vec<u8>,
+}
+
+impl OnionRelay {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["authority", "bandwidth", "interval", "port"])?;
+
+        Ok(Self {
+            authority: options.require("authority", parse_string)?,
+            port: options.get_or("port", DEFAULT_RELAY_PORT, parse_number)?,
+            bandwidth: options.get_or("bandwidth", 1_000_000, parse_rate)?,
+            interval: options.get_or("interval", DEFAULT_INTERVAL, parse_duration)?,
+            channels: BTreeMap::new(),
+            relays: BTreeMap::new(),
+            circuits: BTreeMap::new(),
+            streams: BTreeMap::new(),
+            next_circuit: 1,
+            cells: 0,
+            interval_cells: 0,
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn send(&mut self, target: (SocketId, u32), command: Command, payload: &[u8]) {
+        send_cell(&mut self.channels, target, command, payload);
+    }
+
+    fn handle(&mut self, context: &mut AppContext, socket: SocketId, cell: Cell) {
+        let key = (socket, cell.circuit);
+        self.cells += 1;
+        self.interval_cells += 1;
+
+        if cell.command == Command::Create {
+            self.circuits.insert(
+                key,
+                Hop::Backward {
+                    next: None,
+                    stream: None,
+                },
+            );
+            return self.send(key, Command::Created, &[]);
+        }
+
+        match self.circuits.get(&key) {
+            Some(Hop::Forward { previous }) => {
+                let previous = *previous;
+                match cell.command {
+                    Command::Created => self.send(previous, Command::Extended, &[]),
+                    Command::Destroy => {
+                        self.circuits.remove(&key);
+                        self.destroy(context, previous);
+                    }
+                    command => self.send(previous, command, &cell.payload),
+                }
+            }
+            Some(Hop::Backward {
+                next: Some(next), ..
+            }) => {
+                let next = *next;
+                if cell.command == Command::Destroy {
+                    self.circuits.remove(&key);
+                    self.destroy(context, next);
+                } else {
+                    self.send(next, cell.command, &cell.payload);
+                }
+            }
+            Some(Hop::Backward { next: None, stream }) => {
+                let stream = *stream;
+                self.handle_last(context, key, stream, cell);
+            }
+            None => {}
+        }
+    }
+
+    /// Handles a cell for which this relay is the last of the circuit.
+    fn handle_last(
+        &mut self,
+        context: &mut AppContext,
+        key: (SocketId, u32),
+        stream: Option<SocketId>,
+        cell: Cell,
+    ) {
+        let address = String::from_utf8_lossy(&cell.payload).into_owned();
+
+        match (cell.command, stream) {
+            (Command::Extend, None) => match self.extend(context, &address) {
+                Some(next) => {
+                    self.circuits.insert(
+                        key,
+                        Hop::Backward {
+                            next: Some(next),
+                            stream: None,
+                        },
+                    );
+                    self.circuits.insert(next, Hop::Forward { previous: key });
+                    self.send(next, Command::Create, &[]);
+                }
+                None => self.destroy(context, key),
+            },
+            (Command::Begin, None) => {
+                let socket = resolve(context, &address).and_then(|addr| context.connect(addr).ok());
+                match socket {
+                    Some(socket) => {
+                        self.circuits.insert(
+                            key,
+                            Hop::Backward {
+                                next: None,
+                                stream: Some(socket),
+                            },
+                        );
+                        self.streams.insert(
+                            socket,
+                            Stream {
+                                circuit: key,
+                                channel: Channel::default(),
+                            },
+                        );
+                    }
+                    None => self.send(key, Command::End, &[]),
+                }
+            }
+            (Command::Data, Some(socket)) => {
+                if let Some(stream) = self.streams.get_mut(&socket) {
+                    stream.channel.outbound.extend(&cell.payload);
+                    let _ = stream.channel.flush(context, socket);
+                }
+            }
+            (Command::End, Some(socket)) => {
+                self.streams.remove(&socket);
+                context.close(socket);
+                self.circuits.insert(
+                    key,
+                    Hop::Backward {
+                        next: None,
+                        stream: None,
+                    },
+                );
+            }
+            (Command::Destroy, stream) => {
+                self.circuits.remove(&key);
+                if let Some(socket) = stream {
+                    self.streams.remove(&socket);
+                    context.close(socket);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Opens a circuit to the relay at `address` on the connection to it, opening one if
+    /// there is none yet.
+    fn extend(&mut self, context: &mut AppContext, address: &str) -> Option<(SocketId, u32)> {
+        let socket = match self.relays.get(address) {
+            Some(socket) => *socket,
+            None => {
+                let socket = context.connect(resolve(context, address)?).ok()?;
+                self.channels.insert(socket, Channel::default());
+                self.relays.insert(address.to_owned(), socket);
+                socket
+            }
+        };
+
+        let circuit = self.next_circuit;
+        self.next_circuit += 1;
+        Some((socket, circuit))
+    }
+
+    /// Tears a circuit down towards `target`, which is the last hop there if it leads to a
+    /// client.
+    fn destroy(&mut self, context: &mut AppContext, target: (SocketId, u32)) {
+        if let Some(Hop::Backward {
+            stream: Some(socket),
+            ..
+        }) = self.circuits.get(&target)
+        {
+            let socket = *socket;
+            self.streams.remove(&socket);
+            context.close(socket);
+        }
+        self.circuits.remove(&target);
+        self.send(target, Command::Destroy, &[]);
+    }
+
+    /// Destroys the circuits on a connection that closed.
+    fn close_channel(&mut self, context: &mut AppContext, socket: SocketId) {
+        self.channels.remove(&socket);
+        self.relays.retain(|_, relay| *relay != socket);
+        context.close(socket);
+
+        let keys: Vec<_> = self
+            .circuits
+            .keys()
+            .filter(|(channel, _)| *channel == socket)
+            .copied()
+            .collect();
+
+        for key in keys {
+            match self.circuits.remove(&key) {
+                Some(Hop::Forward { previous }) => self.destroy(context, previous),
+                Some(Hop::Backward {
+                    next: Some(next), ..
+                }) => self.destroy(context, next),
+                Some(Hop::Backward {
+                    stream: Some(stream),
+                    ..
+                }) => {
+                    self.streams.remove(&stream);
+                    context.close(stream);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Forwards what the destination of a stream sent as data cells.
+    fn receive_stream(&mut self, context: &mut AppContext, socket: SocketId) {
+        let stream = match self.streams.get_mut(&socket) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let circuit = stream.circuit;
+        let (data, is_closed) = stream.channel.receive(context, socket, &mut self.buf);
+
+        for chunk in data.chunks(MAX_PAYLOAD) {
+            self.send(circuit, Command::Data, chunk);
+        }
+
+        if is_closed {
+            self.streams.remove(&socket);
+            context.close(socket);
+            self.circuits.insert(
+                circuit,
+                Hop::Backward {
+                    next: None,
+                    stream: None,
+                },
+            );
+            self.send(circuit, Command::End, &[]);
+        }
+    }
+}
+
+impl SimApp for OnionRelay {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.listen(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            return context.exit(1);
+        }
+
+        let authority =
+            resolve(context, &self.authority).and_then(|addr| context.connect(addr).ok());
+        let socket = match authority {
+            Some(socket) => socket,
+            None => {
+                Report::new(context, "error")
+                    .field("reason", "authority_unreachable")
+                    .write();
+                return context.exit(1);
+            }
+        };
+
+        let descriptor = Descriptor {
+            address: format!("{}:{}", context.host_name(), self.port),
+            bandwidth: self.bandwidth,
+        };
+        let mut channel = Channel::default();
+        channel.queue_cell(&Cell::new(0, Command::Register, &descriptor.encode()));
+        self.channels.insert(socket, channel);
+
+        context.set_timer(self.interval);
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Some(stream) = self.streams.get_mut(&socket) {
+            stream.channel.is_open = true;
+            let circuit = stream.circuit;
+            let _ = stream.channel.flush(context, socket);
+            self.send(circuit, Command::Connected, &[]);
+            return flush_channels(context, &mut self.channels);
+        }
+
+        let channel = self.channels.entry(socket).or_default();
+        channel.is_open = true;
+        let _ = channel.flush(context, socket);
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        if self.streams.contains_key(&socket) {
+            self.receive_stream(context, socket);
+            return flush_channels(context, &mut self.channels);
+        }
+
+        let (cells, is_closed) = match self.channels.get_mut(&socket) {
+            Some(channel) => channel.receive_cells(context, socket, &mut self.buf),
+            None => return,
+        };
+
+        for cell in cells {
+            self.handle(context, socket, cell);
+        }
+        if is_closed {
+            self.close_channel(context, socket);
+        }
+        flush_channels(context, &mut self.channels);
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Some(stream) = self.streams.get_mut(&socket) {
+            let _ = stream.channel.flush(context, socket);
+        } else if let Some(channel) = self.channels.get_mut(&socket) {
+            let _ = channel.flush(context, socket);
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let cells = std::mem::take(&mut self.interval_cells);
+        let circuits = self
+            .circuits
+            .values()
+            .filter(|hop| matches!(hop, Hop::Backward { .. }))
+            .count();
+
+        Report::new(context, "interval")
+            .field("circuits", circuits)
+            .field("cells", cells)
+            .field("rate", rate(cells * CELL_SIZE as u64, self.interval))
+            .write();
+        context.set_timer(self.interval);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        Report::new(context, "done")
+            .field("cells", self.cells)
+            .write();
+        context.exit(0);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Consensus,
+    Building,
+    Connecting,
+    Ready,
+}
+
+/// The circuit of a client is always the first on its connection to the guard.
+const CLIENT_CIRCUIT: u32 = 1;
+
+/// Downloads objects from an `http_server` through a circuit, like `http_client`.
+///
+/// Options: `authority` (required, `<host>:<port>`), `server` (required, `<host>:<port>`),
+/// `requests`, `request_size` and `think_time`.
+pub(super) struct OnionClient {
+    authority: String,
+    server: String,
+    requests: Option<u64>,
+    request_size: Distribution,
+    think_time: Distribution,
+    phase: Phase,
+    channels: BTreeMap<SocketId, Channel>,
+    consensus: Vec<Descriptor>,
+    path: Vec<String>,
+    guard: Option<SocketId>,
+    hops: usize,
+    built_at: Duration,
+    incoming: Incoming,
+    completed: u64,
+    received: u64,
+    started_at: Duration,
+    sent_at: Duration,
+    first_byte_at: Option<Duration>,
+    buf: Vec<u8>,
+}
+
+impl OnionClient {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(
+            args,
+            &[
+                "authority",
+                "request_size",
+                "requests",
+                "server",
+                "think_time",
+            ],
+        )?;
+
+        Ok(Self {
+            authority: options.require("authority", parse_string)?,
+            server: options.require("server", parse_string)?,
+            requests: options.get("requests", parse_number)?,
+            request_size: options.get_or(
+                "request_size",
+                Distribution::Fixed(100.0),
+                Distribution::parse_bytes,
+            )?,
+            think_time: options.get_or(
+                "think_time",
+                Distribution::Fixed(0.0),
+                Distribution::parse_duration,
+            )?,
+            phase: Phase::Consensus,
+            channels: BTreeMap::new(),
+            consensus: Vec::new(),
+            path: Vec::new(),
+            guard: None,
+            hops: 0,
+            built_at: Duration::ZERO,
+            incoming: Incoming::default(),
+            completed: 0,
+            received: 0,
+            started_at: Duration::ZERO,
+            sent_at: Duration::ZERO,
+            first_byte_at: None,
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn request_consensus(&mut self, context: &mut AppContext) {
+        let socket = resolve(context, &self.authority).and_then(|addr| context.connect(addr).ok());
+
+        match socket {
+            Some(socket) => {
+                let mut channel = Channel::default();
+                channel.queue_cell(&Cell::new(0, Command::ConsensusRequest, &[]));
+                self.channels.insert(socket, channel);
+                self.consensus.clear();
+            }
+            None => self.fail(context, "authority_unreachable"),
+        }
+    }
+
+    /// Picks the relays of the circuit in proportion to their bandwidth, without repeating
+    /// one, and connects to the first.
+    fn build(&mut self, context: &mut AppContext) {
+        let mut candidates = self.consensus.clone();
+        self.path.clear();
+
+        while self.path.len() < CIRCUIT_LENGTH {
+            let total: u64 = candidates.iter().map(|relay| relay.bandwidth.max(1)).sum();
+            let mut pick = (context.random() * total as f64) as u64;
+            let index = candidates
+                .iter()
+                .position(|relay| match pick.checked_sub(relay.bandwidth.max(1)) {
+                    Some(rest) => {
+                        pick = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .unwrap_or(candidates.len() - 1);
+            self.path.push(candidates.remove(index).address);
+        }
+
+        let guard = resolve(context, &self.path[0]).and_then(|addr| context.connect(addr).ok());
+        let socket = match guard {
+            Some(socket) => socket,
+            None => return self.fail(context, "guard_unreachable"),
+        };
+
+        let mut channel = Channel::default();
+        channel.queue_cell(&Cell::new(CLIENT_CIRCUIT, Command::Create, &[]));
+        self.channels.insert(socket, channel);
+        self.guard = Some(socket);
+        self.hops = 0;
+        self.built_at = context.now();
+        self.phase = Phase::Building;
+    }
+
+    fn send(&mut self, command: Command, payload: &[u8]) {
+        if let Some(guard) = self.guard {
+            send_cell(
+                &mut self.channels,
+                (guard, CLIENT_CIRCUIT),
+                command,
+                payload,
+            );
+        }
+    }
+
+    fn request(&mut self, context: &mut AppContext) {
+        if matches!(self.requests, Some(requests) if self.completed >= requests) {
+            return self.finish(context);
+        }
+
+        self.sent_at = context.now();
+        self.first_byte_at = None;
+
+        let size = self.request_size.sample_bytes(context);
+        let mut message = size.to_be_bytes().to_vec();
+        message.resize(HEADER_SIZE + size as usize, 0);
+        for chunk in message.chunks(MAX_PAYLOAD) {
+            self.send(Command::Data, chunk);
+        }
+    }
+
+    fn handle(&mut self, context: &mut AppContext, cell: Cell) {
+        match (self.phase, cell.command) {
+            (Phase::Building, Command::Created | Command::Extended) => {
+                self.hops += 1;
+                match self.path.get(self.hops) {
+                    Some(next) => {
+                        let next = next.clone();
+                        self.send(Command::Extend, next.as_bytes());
+                    }
+                    None => {
+                        let build_time = context.now() - self.built_at;
+                        Report::new(context, "circuit")
+                            .field("path", self.path.join(","))
+                            .field("build_time", seconds(build_time))
+                            .write();
+
+                        self.phase = Phase::Connecting;
+                        let server = self.server.clone();
+                        self.send(Command::Begin, server.as_bytes());
+                    }
+                }
+            }
+            (Phase::Connecting, Command::Connected) => {
+                self.phase = Phase::Ready;
+                self.request(context);
+            }
+            (Phase::Ready, Command::Data) => self.receive(context, &cell.payload),
+            (_, Command::End) => self.fail(context, "stream_closed"),
+            (_, Command::Destroy) => self.fail(context, "circuit_destroyed"),
+            _ => {}
+        }
+    }
+
+    fn receive(&mut self, context: &mut AppContext, data: &[u8]) {
+        if self.first_byte_at.is_none() {
+            self.first_byte_at = Some(context.now());
+        }
+        self.received += data.len() as u64;
+
+        // Requests are sent one at a time, so at most one response completes.
+        if let Some(size) = self.incoming.push(data).first() {
+            self.completed += 1;
+
+            let now = context.now();
+            let first_byte_at = self.first_byte_at.unwrap_or(now);
+            Report::new(context, "response")
+                .field("request", self.completed)
+                .field("bytes", size)
+                .field("first_byte", seconds(first_byte_at - self.sent_at))
+                .field("latency", seconds(now - self.sent_at))
+                .write();
+
+            let think_time = self.think_time.sample_duration(context);
+            context.set_timer(think_time);
+        }
+    }
+
+    fn finish(&mut self, context: &mut AppContext) {
+        let duration = context.now() - self.started_at;
+
+        Report::new(context, "done")
+            .field("requests", self.completed)
+            .field("bytes", self.received)
+            .field("duration", seconds(duration))
+            .field("rate", rate(self.received, duration))
+            .write();
+        context.exit(0);
+    }
+
+    fn fail(&mut self, context: &mut AppContext, reason: &str) {
+        Report::new(context, "error")
+            .field("requests", self.completed)
+            .field("reason", reason)
+            .write();
+        context.exit(1);
+    }
+}
+
+impl SimApp for OnionClient {
+    fn on_start(&mut self, context: &mut AppContext) {
+        self.started_at = context.now();
+        self.request_consensus(context);
+    }
+
+    fn on_connect(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Some(channel) = self.channels.get_mut(&socket) {
+            channel.is_open = true;
+            let _ = channel.flush(context, socket);
+        }
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        let (cells, is_closed) = match self.channels.get_mut(&socket) {
+            Some(channel) => channel.receive_cells(context, socket, &mut self.buf),
+            None => return,
+        };
+
+        if Some(socket) == self.guard {
+            for cell in cells {
+                self.handle(context, cell);
+            }
+            if is_closed {
+                self.channels.remove(&socket);
+                context.close(socket);
+                self.fail(context, "guard_closed");
+            }
+            return flush_channels(context, &mut self.channels);
+        }
+
+        // The connection to the authority, which is closed once the consensus is complete.
+        for cell in cells {
+            match cell.command {
+                Command::ConsensusEntry => self.consensus.extend(Descriptor::decode(&cell.payload)),
+                Command::ConsensusEnd => {
+                    self.channels.remove(&socket);
+                    context.close(socket);
+
+                    if self.consensus.len() < CIRCUIT_LENGTH {
+                        context.set_timer(CONSENSUS_RETRY);
+                    } else {
+                        self.build(context);
+                    }
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        if is_closed {
+            self.channels.remove(&socket);
+            context.close(socket);
+            self.fail(context, "authority_closed");
+        }
+    }
+
+    fn on_writable(&mut self, context: &mut AppContext, socket: SocketId) {
+        if let Some(channel) = self.channels.get_mut(&socket) {
+            let _ = channel.flush(context, socket);
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        match self.phase {
+            Phase::Consensus => self.request_consensus(context),
+            Phase::Ready => self.request(context),
+            Phase::Building | Phase::Connecting => {}
+        }
+        flush_channels(context, &mut self.channels);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        self.finish(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::args;
+
+    #[test]
+    fn encode_cells() {
+        let bytes = Cell::new(7, Command::Extend, b"relay:9001").encode();
+        assert_eq!(bytes.len(), CELL_SIZE);
+        assert_eq!(bytes[..CELL_HEADER_SIZE], [0, 0, 0, 7, 4, 0, 10]);
+
+        let cell = Cell::decode(&bytes).unwrap();
+        assert_eq!(cell.circuit, 7);
+        assert_eq!(cell.command, Command::Extend);
+        assert_eq!(cell.payload, b"relay:9001");
+
+        let cell = Cell::new(1, Command::Data, &[1; CELL_SIZE]);
+        assert_eq!(cell.payload.len(), MAX_PAYLOAD);
+
+        let mut bytes = cell.encode();
+        bytes[4] = 0;
+        assert!(Cell::decode(&bytes).is_none());
+    }
+
+    #[test]
+    fn encode_descriptors() {
+        let descriptor = Descriptor {
+            address: "relay1:9001".to_owned(),
+            bandwidth: 2_000_000,
+        };
+        let decoded = Descriptor::decode(&descriptor.encode()).unwrap();
+        assert_eq!(decoded.address, descriptor.address);
+        assert_eq!(decoded.bandwidth, descriptor.bandwidth);
+
+        assert!(Descriptor::decode(&[0; 7]).is_none());
+    }
+
+    #[test]
+    fn parse_options() {
+        let client = OnionClient::new(&args("authority=dir server=web requests=3")).unwrap();
+        assert_eq!(client.requests, Some(3));
+        assert!(OnionClient::new(&args("authority=dir")).is_err());
+
+        let relay = OnionRelay::new(&args("authority=dir:9030 bandwidth=5mbit")).unwrap();
+        assert_eq!(relay.port, DEFAULT_RELAY_PORT);
+        assert_eq!(relay.bandwidth, 5_000_000);
+        assert!(OnionAuthority::new(&args("authority=dir")).is_err());
+    }
+}
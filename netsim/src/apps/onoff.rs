@@ -0,0 +1,295 @@
+//! An on-off UDP source and a sink measuring what arrives of it.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::simapp::{AppContext, SimApp, SocketId, TimerId};
+
+use super::{
+    parse_bytes, parse_duration, parse_number, parse_rate, parse_string, rate, reason, seconds,
+    Distribution, Options, Report, CHUNK_SIZE,
+};
+
+const DEFAULT_PORT: u16 = 9;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of the sequence number every packet starts with.
+const SEQUENCE_SIZE: usize = 8;
+
+/// Alternates between sending packets at a constant rate and staying silent, for periods
+/// drawn from `on` and `off`.
+///
+/// Options: `destination` (required), `port`, `rate`, `packet_size`, `on` and `off`.
+pub(super) struct OnOffSource {
+    destination: String,
+    port: u16,
+    packet_interval: Duration,
+    packet: Vec<u8>,
+    on: Distribution,
+    off: Distribution,
+    target: Option<(SocketId, SocketAddr)>,
+    sequence: u64,
+    /// End of the current on period, none while off.
+    period_end: Option<Duration>,
+    period_start: Duration,
+    period_packets: u64,
+    period_bytes: u64,
+    period_errors: u64,
+}
+
+impl OnOffSource {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(
+            args,
+            &["destination", "off", "on", "packet_size", "port", "rate"],
+        )?;
+
+        let packet_size = options.get_or("packet_size", 1_000, parse_bytes)?;
+        if !(SEQUENCE_SIZE as u64..=u16::MAX as u64).contains(&packet_size) {
+            return Err(format!(
+                "option `packet_size`: not between {} and {} bytes",
+                SEQUENCE_SIZE,
+                u16::MAX
+            ));
+        }
+        let rate = options.get_or("rate", 1_000_000, parse_rate)?;
+        if rate == 0 {
+            return Err("option `rate`: must not be zero".to_owned());
+        }
+
+        let exponential = Distribution::Exponential(1.0);
+
+        Ok(Self {
+            destination: options.require("destination", parse_string)?,
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            packet_interval: Duration::from_secs_f64(packet_size as f64 * 8.0 / rate as f64),
+            packet: vec![0; packet_size as usize],
+            on: options.get_or("on", exponential, Distribution::parse_duration)?,
+            off: options.get_or("off", exponential, Distribution::parse_duration)?,
+            target: None,
+            sequence: 0,
+            period_end: None,
+            period_start: Duration::ZERO,
+            period_packets: 0,
+            period_bytes: 0,
+            period_errors: 0,
+        })
+    }
+
+    fn start_period(&mut self, context: &mut AppContext) {
+        let now = context.now();
+
+        self.period_start = now;
+        self.period_end = Some(now + self.on.sample_duration(context));
+        self.period_packets = 0;
+        self.period_bytes = 0;
+        self.period_errors = 0;
+    }
+
+    fn end_period(&mut self, context: &mut AppContext) {
+        self.period_end = None;
+
+        let duration = context.now() - self.period_start;
+        Report::new(context, "on_period")
+            .field("duration", seconds(duration))
+            .field("packets", self.period_packets)
+            .field("bytes", self.period_bytes)
+            .field("errors", self.period_errors)
+            .write();
+    }
+
+    fn send_packet(&mut self, context: &mut AppContext) {
+        let (socket, addr) = match self.target {
+            Some(target) => target,
+            None => return,
+        };
+
+        self.packet[..SEQUENCE_SIZE].copy_from_slice(&self.sequence.to_be_bytes());
+        self.sequence += 1;
+
+        match context.send_to(socket, &self.packet, addr) {
+            Ok(len) => {
+                self.period_packets += 1;
+                self.period_bytes += len as u64;
+            }
+            Err(_) => self.period_errors += 1,
+        }
+    }
+}
+
+impl SimApp for OnOffSource {
+    fn on_start(&mut self, context: &mut AppContext) {
+        let ip = match context.resolve(&self.destination) {
+            Some(ip) => ip,
+            None => {
+                Report::new(context, "error")
+                    .field("reason", reason(&io::ErrorKind::NotFound.into()))
+                    .write();
+                return context.exit(1);
+            }
+        };
+
+        match context.bind_udp(0) {
+            Ok(socket) => self.target = Some((socket, SocketAddr::new(ip, self.port))),
+            Err(err) => {
+                Report::new(context, "error")
+                    .field("reason", reason(&err))
+                    .write();
+                return context.exit(1);
+            }
+        }
+
+        self.start_period(context);
+        context.set_timer(Duration::ZERO);
+    }
+
+    /// Sends the next packet of an on period, or ends the period once it is over.
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let now = context.now();
+
+        match self.period_end {
+            Some(end) if now < end => {
+                self.send_packet(context);
+                context.set_timer(self.packet_interval.min(end - now));
+            }
+            Some(_) => {
+                self.end_period(context);
+                let off = self.off.sample_duration(context);
+                context.set_timer(off);
+            }
+            None => {
+                self.start_period(context);
+                context.set_timer(Duration::ZERO);
+            }
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        if self.period_end.is_some() {
+            self.end_period(context);
+        }
+        context.exit(0);
+    }
+}
+
+#[derive(Default)]
+struct Flow {
+    packets: u64,
+    bytes: u64,
+    interval_bytes: u64,
+    /// One past the highest sequence number received.
+    sequence_end: u64,
+}
+
+/// Counts the packets and bytes arriving from every source, and how many of the packets of
+/// an on-off source went missing.
+///
+/// Options: `port` and the report `interval`.
+pub(super) struct UdpSink {
+    port: u16,
+    interval: Duration,
+    flows: BTreeMap<SocketAddr, Flow>,
+    buf: Vec<u8>,
+}
+
+impl UdpSink {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["interval", "port"])?;
+
+        Ok(Self {
+            port: options.get_or("port", DEFAULT_PORT, parse_number)?,
+            interval: options.get_or("interval", DEFAULT_INTERVAL, parse_duration)?,
+            flows: BTreeMap::new(),
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+}
+
+impl SimApp for UdpSink {
+    fn on_start(&mut self, context: &mut AppContext) {
+        if let Err(err) = context.bind_udp(self.port) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            return context.exit(1);
+        }
+
+        context.set_timer(self.interval);
+    }
+
+    fn on_receive(&mut self, context: &mut AppContext, socket: SocketId) {
+        loop {
+            let (len, peer) = match context.recv_from(socket, &mut self.buf) {
+                Ok(received) => received,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(_) => continue,
+            };
+
+            let flow = self.flows.entry(peer).or_default();
+            flow.packets += 1;
+            flow.bytes += len as u64;
+            flow.interval_bytes += len as u64;
+
+            if len >= SEQUENCE_SIZE {
+                let mut sequence = [0; SEQUENCE_SIZE];
+                sequence.copy_from_slice(&self.buf[..SEQUENCE_SIZE]);
+                let sequence = u64::from_be_bytes(sequence);
+                flow.sequence_end = flow.sequence_end.max(sequence.saturating_add(1));
+            }
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let interval = self.interval;
+
+        for (peer, flow) in &mut self.flows {
+            let bytes = std::mem::take(&mut flow.interval_bytes);
+            Report::new(context, "interval")
+                .field("peer", peer)
+                .field("bytes", bytes)
+                .field("rate", rate(bytes, interval))
+                .write();
+        }
+        context.set_timer(interval);
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        for (peer, flow) in &self.flows {
+            Report::new(context, "done")
+                .field("peer", peer)
+                .field("packets", flow.packets)
+                .field("bytes", flow.bytes)
+                .field("lost", flow.sequence_end.saturating_sub(flow.packets))
+                .write();
+        }
+        context.exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::args;
+
+    #[test]
+    fn parse_source_options() {
+        let source =
+            OnOffSource::new(&args("destination=sink rate=2mbit packet_size=500")).unwrap();
+        assert_eq!(source.port, DEFAULT_PORT);
+        assert_eq!(source.packet.len(), 500);
+        assert_eq!(source.packet_interval, Duration::from_millis(2));
+        assert!(matches!(source.on, Distribution::Exponential(mean) if mean == 1.0));
+
+        assert!(OnOffSource::new(&args("destination=sink packet_size=4")).is_err());
+        assert!(OnOffSource::new(&args("destination=sink rate=0")).is_err());
+        assert!(OnOffSource::new(&args("rate=1mbit")).is_err());
+    }
+
+    #[test]
+    fn parse_sink_options() {
+        assert!(UdpSink::new(&args("port=9000 interval=2s")).is_ok());
+        assert!(UdpSink::new(&args("destination=sink")).is_err());
+    }
+}
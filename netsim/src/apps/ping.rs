@@ -0,0 +1,146 @@
+//! An ICMP echo client measuring the round-trip times and loss to a target.
+
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::icmp::{Ping, PingEvent};
+use crate::simapp::{AppContext, SimApp, TimerId};
+use crate::time::SimulationTime;
+
+use super::{parse_duration, parse_number, parse_string, reason, seconds, Options, Report};
+
+const DEFAULT_COUNT: u16 = 10;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time after which a request without a reply counts as lost.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends `count` echo requests to `target`, one every `interval`, and reports every reply and
+/// the statistics once the last reply arrived or timed out.
+///
+/// Options: `target` (required, a name or an address), `count` and `interval`.
+pub(super) struct PingClient {
+    target: String,
+    count: u16,
+    interval: Duration,
+    ping: Option<Ping>,
+}
+
+impl PingClient {
+    pub fn new(args: &[String]) -> Result<Self, String> {
+        let options = Options::parse(args, &["count", "interval", "target"])?;
+
+        let count = options.get_or("count", DEFAULT_COUNT, parse_number)?;
+        if count == 0 {
+            return Err("option `count`: must not be zero".to_owned());
+        }
+        let interval = options.get_or("interval", DEFAULT_INTERVAL, parse_duration)?;
+        if interval.is_zero() {
+            return Err("option `interval`: must not be zero".to_owned());
+        }
+
+        Ok(Self {
+            target: options.require("target", parse_string)?,
+            count,
+            interval,
+            ping: None,
+        })
+    }
+
+    fn finish(&mut self, context: &mut AppContext) {
+        let ping = match self.ping.take() {
+            Some(ping) => ping,
+            None => return context.exit(0),
+        };
+        ping.close(context.host());
+
+        let statistics = ping.statistics();
+        let mut report = Report::new(context, "done")
+            .field("target", ping.target())
+            .field("transmitted", statistics.transmitted)
+            .field("received", statistics.received)
+            .field("unreachable", statistics.unreachable)
+            .field("loss", format!("{:.3}", statistics.loss()));
+
+        if let (Some(min), Some(mean), Some(max)) =
+            (statistics.min(), statistics.mean(), statistics.max())
+        {
+            report = report
+                .field("rtt_min", seconds(duration(min)))
+                .field("rtt_avg", seconds(duration(mean)))
+                .field("rtt_max", seconds(duration(max)));
+        }
+
+        report.write();
+        context.exit(0);
+    }
+}
+
+impl SimApp for PingClient {
+    fn on_start(&mut self, context: &mut AppContext) {
+        let target = self
+            .target
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| context.resolve(&self.target));
+
+        let target = match target {
+            Some(target) => target,
+            None => {
+                Report::new(context, "error")
+                    .field("reason", reason(&io::ErrorKind::NotFound.into()))
+                    .write();
+                return context.exit(1);
+            }
+        };
+
+        let timeout = SimulationTime::from_nanos(TIMEOUT.as_nanos() as i64);
+        let mut ping = Ping::new(target, self.count, timeout);
+
+        if let Err(err) = ping.start(context.host()) {
+            Report::new(context, "error")
+                .field("reason", reason(&err))
+                .write();
+            return context.exit(1);
+        }
+
+        self.ping = Some(ping);
+        context.set_timer(Duration::ZERO);
+    }
+
+    /// Reports the replies since the previous request and sends the next one.
+    fn on_timer(&mut self, context: &mut AppContext, _timer: TimerId) {
+        let ping = match &mut self.ping {
+            Some(ping) => ping,
+            None => return,
+        };
+
+        for event in ping.tick(context.host()) {
+            match event {
+                PingEvent::Reply { src, sequence, rtt } => Report::new(context, "reply")
+                    .field("from", src)
+                    .field("sequence", sequence)
+                    .field("rtt", seconds(duration(rtt)))
+                    .write(),
+                PingEvent::Unreachable { src } => Report::new(context, "unreachable")
+                    .field("from", src)
+                    .write(),
+            }
+        }
+
+        if ping.is_finished() {
+            self.finish(context);
+        } else {
+            context.set_timer(self.interval);
+        }
+    }
+
+    fn on_stop(&mut self, context: &mut AppContext) {
+        self.finish(context);
+    }
+}
+
+fn duration(time: SimulationTime) -> Duration {
+    Duration::from_nanos(time.as_nanos().max(0) as u64)
+}
@@ -1,52 +1,730 @@
-use std::net::IpAddr;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{AddrParseError, IpAddr};
 use std::path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 use log::LevelFilter;
 use serde::Deserialize;
 
-use crate::units::{Bits, TimeInterval};
+use crate::cli::Args;
+use crate::dns::Zone;
+use crate::net::IpCidr;
+use crate::process::{FinalState, RestartPolicy, Signal};
+use crate::trace::TraceFormat;
+use crate::units::{Bits, Bytes, Fraction, TimeInterval};
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub general: GeneralConfig,
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub host_defaults: HostDefaultsConfig,
+    #[serde(default)]
+    pub hosts: Vec<HostsConfig>,
+    #[serde(default)]
+    pub dns: DnsConfig,
+}
+
+impl Config {
+    pub fn load(path: &path::Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+
+        toml::from_str(&contents).map_err(ConfigError::Parse)
+    }
+
+    pub fn apply(&mut self, args: &Args) {
+        let general = &args.general;
+
+        if let Some(data_directory) = &general.output_directory {
+            self.general.data_directory = data_directory.clone();
+        }
+        if let Some(log_level) = general.log_level {
+            self.general.log_level = log_level;
+        }
+        if let Some(seed) = general.seed {
+            self.general.seed = seed;
+        }
+        if let Some(stop_time) = general.stop_time {
+            self.general.stop_time = stop_time;
+        }
+        if let Some(bootstrap_end_time) = general.bootstrap_end_time {
+            self.general.bootstrap_end_time = bootstrap_end_time;
+        }
+        if let Some(heartbeat_interval) = general.heartbeat_interval {
+            self.general.heartbeat_interval = heartbeat_interval;
+        }
+        if let Some(progress) = general.progress {
+            self.general.progress = Some(progress);
+        }
+        if let Some(trace) = general.trace {
+            self.general.trace = Some(trace);
+        }
+        if let Some(use_shortest_path) = args.network.use_shortest_path {
+            self.network.use_shortest_path = use_shortest_path;
+        }
+        if let Some(log_level) = args.host_default.log_level {
+            self.host_defaults.log_level = Some(log_level);
+        }
+        if let Some(pcap_directory) = &args.host_default.pcap {
+            self.host_defaults.pcap_directory = Some(pcap_directory.clone());
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct GeneralConfig {
-    bootstrap_end_time: TimeInterval,
-    data_directory: PathBuf,
-    heartbeat_interval: TimeInterval,
-    log_level: LevelFilter,
-    parallelism: u64,
-    seed: u64,
-    stop_time: TimeInterval,
-    template_directory: PathBuf,
+    #[serde(default)]
+    pub bootstrap_end_time: TimeInterval,
+    #[serde(default = "GeneralConfig::default_data_directory")]
+    pub data_directory: PathBuf,
+    #[serde(default = "GeneralConfig::default_heartbeat_interval")]
+    pub heartbeat_interval: TimeInterval,
+    #[serde(default = "GeneralConfig::default_log_level")]
+    pub log_level: LevelFilter,
+    #[serde(default = "GeneralConfig::default_parallelism")]
+    pub parallelism: u64,
+    #[serde(default)]
+    pub progress: Option<bool>,
+    #[serde(default = "GeneralConfig::default_seed")]
+    pub seed: u64,
+    pub stop_time: TimeInterval,
+    #[serde(default)]
+    pub template_directory: Option<PathBuf>,
+    #[serde(default)]
+    pub trace: Option<TraceFormat>,
+}
+
+impl GeneralConfig {
+    fn default_data_directory() -> PathBuf {
+        PathBuf::from("netsim.data")
+    }
+
+    fn default_heartbeat_interval() -> TimeInterval {
+        Duration::from_secs(60).into()
+    }
+
+    fn default_log_level() -> LevelFilter {
+        LevelFilter::Info
+    }
+
+    fn default_parallelism() -> u64 {
+        1
+    }
+
+    fn default_seed() -> u64 {
+        1
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct NetworkConfig {
-    use_shortest_path: bool,
+    #[serde(default = "NetworkConfig::default_use_shortest_path")]
+    pub use_shortest_path: bool,
+    pub graph: GraphConfig,
+}
+
+impl NetworkConfig {
+    fn default_use_shortest_path() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphConfig {
+    #[serde(deserialize_with = "one_or_many")]
+    pub node: Vec<NodeConfig>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub edge: Vec<EdgeConfig>,
 }
 
 #[derive(Debug, Deserialize)]
+pub struct NodeConfig {
+    pub id: u64,
+    #[serde(default)]
+    pub host_bandwidth_down: Option<Bits>,
+    #[serde(default)]
+    pub host_bandwidth_up: Option<Bits>,
+    #[serde(default)]
+    pub ip_addr_pool: Option<IpCidr>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EdgeConfig {
+    pub source: u64,
+    pub target: u64,
+    pub latency: TimeInterval,
+    #[serde(default)]
+    pub jitter: TimeInterval,
+    #[serde(default = "EdgeConfig::default_packet_loss")]
+    pub packet_loss: Fraction,
+}
+
+impl EdgeConfig {
+    fn default_packet_loss() -> Fraction {
+        Fraction::try_from(0).unwrap_or_else(|_| unreachable!())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsConfig {
+    #[serde(default)]
+    pub zone: Zone,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct HostDefaultsConfig {
-    log_level: log::LevelFilter,
-    pcap_directory: path::PathBuf,
+    #[serde(default)]
+    pub environment: Environment,
+    #[serde(default)]
+    pub log_level: Option<log::LevelFilter>,
+    #[serde(default)]
+    pub pcap_capture_size: Option<Bytes>,
+    #[serde(default)]
+    pub pcap_directory: Option<path::PathBuf>,
+    #[serde(default)]
+    pub pcap_enabled: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct HostsConfig {
-    bandwidth_down: Bits,
-    bandwidth_up: Bits,
-    ip_addr: IpAddr,
-    network_node_id: u64,
-    options: HostDefaultsConfig,
-    quantity: u64,
-    processes: Vec<ProcessConfig>,
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub bandwidth_down: Option<Bits>,
+    #[serde(default)]
+    pub bandwidth_up: Option<Bits>,
+    #[serde(default)]
+    pub ip_addr: Option<IpAddrConfig>,
+    #[serde(default)]
+    pub ipv6_addr: Option<IpAddrConfig>,
+    pub network_node_id: u64,
+    #[serde(default)]
+    pub options: HostDefaultsConfig,
+    #[serde(default = "default_quantity")]
+    pub quantity: u64,
+    #[serde(default)]
+    pub processes: Vec<ProcessConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ProcessConfig {
-    args: String,
-    environment: String,
-    path: PathBuf,
-    quantity: u64,
-    start_time: TimeInterval,
-    stop_time: TimeInterval,
+    #[serde(default)]
+    pub app: Option<String>,
+    #[serde(default)]
+    pub args: Arguments,
+    #[serde(default)]
+    pub environment: Environment,
+    #[serde(default)]
+    pub expected_final_state: Option<FinalState>,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default = "default_quantity")]
+    pub quantity: u64,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default = "ProcessConfig::default_restart_delay")]
+    pub restart_delay: TimeInterval,
+    #[serde(default)]
+    pub start_time: TimeInterval,
+    #[serde(default = "ProcessConfig::default_stop_grace_period")]
+    pub stop_grace_period: TimeInterval,
+    #[serde(default = "ProcessConfig::default_stop_signal")]
+    pub stop_signal: Signal,
+    #[serde(default)]
+    pub stop_time: Option<TimeInterval>,
+}
+
+impl ProcessConfig {
+    fn default_restart_delay() -> TimeInterval {
+        Duration::from_secs(1).into()
+    }
+
+    fn default_stop_grace_period() -> TimeInterval {
+        Duration::from_secs(10).into()
+    }
+
+    fn default_stop_signal() -> Signal {
+        Signal::TERM
+    }
+}
+
+fn default_quantity() -> u64 {
+    1
+}
+
+/// Command line arguments of a process, given as a string split into words by the quoting
+/// rules of a POSIX shell, or as an array of strings taken verbatim. Neither variables nor
+/// globs are expanded.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "ArgumentsConfig")]
+pub struct Arguments(Vec<String>);
+
+impl Arguments {
+    pub fn to_vec(&self) -> Vec<String> {
+        self.0.clone()
+    }
+}
+
+impl FromStr for Arguments {
+    type Err = InvalidArguments;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.contains('\0') {
+            return Err(InvalidArguments::NulByte);
+        }
+
+        let mut words = Vec::new();
+        let mut word: Option<String> = None;
+        let mut chars = value.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => words.extend(word.take()),
+                '\\' => match chars.next() {
+                    Some('\n') => {}
+                    Some(c) => word.get_or_insert_with(String::new).push(c),
+                    None => return Err(InvalidArguments::TrailingBackslash),
+                },
+                '\'' => {
+                    let word = word.get_or_insert_with(String::new);
+                    loop {
+                        match chars.next() {
+                            Some('\'') => break,
+                            Some(c) => word.push(c),
+                            None => return Err(InvalidArguments::UnterminatedQuote('\'')),
+                        }
+                    }
+                }
+                '"' => {
+                    let word = word.get_or_insert_with(String::new);
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            // Within double quotes, a backslash only escapes the characters
+                            // that are special there.
+                            Some('\\') => match chars.next() {
+                                Some('\n') => {}
+                                Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                                Some(c) => {
+                                    word.push('\\');
+                                    word.push(c);
+                                }
+                                None => return Err(InvalidArguments::UnterminatedQuote('"')),
+                            },
+                            Some(c) => word.push(c),
+                            None => return Err(InvalidArguments::UnterminatedQuote('"')),
+                        }
+                    }
+                }
+                c => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+
+        words.extend(word);
+        Ok(Arguments(words))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ArgumentsConfig {
+    Line(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<ArgumentsConfig> for Arguments {
+    type Error = InvalidArguments;
+
+    fn try_from(value: ArgumentsConfig) -> Result<Self, Self::Error> {
+        match value {
+            ArgumentsConfig::Line(line) => line.parse(),
+            ArgumentsConfig::List(list) => match list.iter().any(|arg| arg.contains('\0')) {
+                true => Err(InvalidArguments::NulByte),
+                false => Ok(Arguments(list)),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidArguments {
+    UnterminatedQuote(char),
+    TrailingBackslash,
+    NulByte,
+}
+
+impl std::error::Error for InvalidArguments {}
+
+impl fmt::Display for InvalidArguments {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidArguments::UnterminatedQuote(quote) => {
+                write!(formatter, "invalid arguments: unterminated {} quote", quote)
+            }
+            InvalidArguments::TrailingBackslash => {
+                formatter.write_str("invalid arguments: trailing backslash")
+            }
+            InvalidArguments::NulByte => formatter.write_str("invalid arguments: nul byte"),
+        }
+    }
+}
+
+/// Environment variables of a process, given as `KEY=VALUE` pairs separated by `;` or as a
+/// table.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "EnvironmentConfig")]
+pub struct Environment(Vec<(String, String)>);
+
+impl Environment {
+    /// Sets a variable, replacing an earlier value.
+    pub fn set(&mut self, key: &str, value: String) {
+        match self.0.iter_mut().find(|(known, _)| known == key) {
+            Some((_, known)) => *known = value,
+            None => self.0.push((key.to_owned(), value)),
+        }
+    }
+
+    /// Adds the variables of `other`, which take precedence over the own ones.
+    pub fn merge(&mut self, other: &Environment) {
+        for (key, value) in &other.0 {
+            self.set(key, value.clone());
+        }
+    }
+
+    pub fn into_pairs(self) -> Vec<(String, String)> {
+        self.0
+    }
+}
+
+impl FromStr for Environment {
+    type Err = InvalidEnvironment;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut environment = Environment::default();
+
+        for pair in value.split(';').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| InvalidEnvironment(pair.to_owned()))?;
+            environment.set(valid_key(key.trim())?, value.to_owned());
+        }
+
+        Ok(environment)
+    }
+}
+
+fn valid_key(key: &str) -> Result<&str, InvalidEnvironment> {
+    match key.is_empty() || key.contains(['=', '\0']) {
+        true => Err(InvalidEnvironment(key.to_owned())),
+        false => Ok(key),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnvironmentConfig {
+    Pairs(String),
+    Table(BTreeMap<String, EnvironmentValue>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnvironmentValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl fmt::Display for EnvironmentValue {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvironmentValue::String(value) => formatter.write_str(value),
+            EnvironmentValue::Integer(value) => write!(formatter, "{}", value),
+            EnvironmentValue::Float(value) => write!(formatter, "{}", value),
+            EnvironmentValue::Boolean(value) => write!(formatter, "{}", value),
+        }
+    }
+}
+
+impl TryFrom<EnvironmentConfig> for Environment {
+    type Error = InvalidEnvironment;
+
+    fn try_from(value: EnvironmentConfig) -> Result<Self, Self::Error> {
+        match value {
+            EnvironmentConfig::Pairs(pairs) => pairs.parse(),
+            EnvironmentConfig::Table(table) => {
+                let mut environment = Environment::default();
+                for (key, value) in table {
+                    environment.set(valid_key(&key)?, value.to_string());
+                }
+                Ok(environment)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidEnvironment(String);
+
+impl std::error::Error for InvalidEnvironment {}
+
+impl fmt::Display for InvalidEnvironment {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "invalid environment variable `{}`, expected `KEY=VALUE`",
+            self.0
+        )
+    }
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => {
+                write!(
+                    formatter,
+                    "cannot read config `{}`: {}",
+                    path.display(),
+                    err
+                )
+            }
+            ConfigError::Parse(err) => write!(formatter, "invalid config: {}", err),
+            ConfigError::Invalid(reason) => write!(formatter, "invalid config: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum IpAddrConfig {
+    Auto,
+    Fixed(IpAddr),
+}
+
+impl FromStr for IpAddrConfig {
+    type Err = InvalidIpAddrConfig;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(IpAddrConfig::Auto),
+            value => value
+                .parse::<IpAddr>()
+                .map(IpAddrConfig::Fixed)
+                .map_err(|err| InvalidIpAddrConfig {
+                    span: value.to_owned(),
+                    source: err,
+                }),
+        }
+    }
+}
+
+impl TryFrom<String> for IpAddrConfig {
+    type Error = InvalidIpAddrConfig;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidIpAddrConfig {
+    span: String,
+    source: AddrParseError,
+}
+
+impl std::error::Error for InvalidIpAddrConfig {}
+
+impl fmt::Display for InvalidIpAddrConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "invalid ip address (\"{}\"): {}",
+            self.span, self.source
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(value: &str) -> Vec<String> {
+        value.parse::<Arguments>().unwrap().to_vec()
+    }
+
+    fn env(value: &str) -> Vec<(String, String)> {
+        value.parse::<Environment>().unwrap().into_pairs()
+    }
+
+    fn pairs(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn split_arguments() {
+        assert_eq!(args("  -v   --port 80 "), ["-v", "--port", "80"]);
+        assert_eq!(args("-c 'echo $HOME; ls *'"), ["-c", "echo $HOME; ls *"]);
+        assert_eq!(args(r#"--name "a b"c'd e'"#), ["--name", "a bcd e"]);
+        assert_eq!(args("a\\ b \\'c\\\nd"), ["a b", "'cd"]);
+        assert!(args("").is_empty());
+        assert!(args(" \t\n").is_empty());
+    }
+
+    #[test]
+    fn split_escaped_quotes() {
+        assert_eq!(args(r#""say \"hi\"""#), [r#"say "hi""#]);
+        assert_eq!(args(r#""\$HOME \\ \n \`""#), [r#"$HOME \ \n `"#]);
+        assert_eq!(args(r#"'a\b' 'it'\''s'"#), [r#"a\b"#, "it's"]);
+    }
+
+    #[test]
+    fn split_empty_strings() {
+        assert_eq!(args(r#"-a "" -b ''"#), ["-a", "", "-b", ""]);
+        assert_eq!(args("''\"\""), [""]);
+    }
+
+    #[test]
+    fn reject_invalid_arguments() {
+        for (value, expected) in [
+            ("'unterminated", "invalid arguments: unterminated ' quote"),
+            ("\"unterminated", "invalid arguments: unterminated \" quote"),
+            (
+                "\"escaped quote\\\"",
+                "invalid arguments: unterminated \" quote",
+            ),
+            ("\"trailing \\", "invalid arguments: unterminated \" quote"),
+            ("trailing \\", "invalid arguments: trailing backslash"),
+            ("nul\0byte", "invalid arguments: nul byte"),
+        ] {
+            let err = value.parse::<Arguments>().unwrap_err();
+            assert_eq!(err.to_string(), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn deserialize_arguments() {
+        #[derive(Deserialize)]
+        struct Process {
+            args: Arguments,
+        }
+
+        let parse = |value: &str| toml::from_str::<Process>(value).map(|process| process.args.0);
+
+        assert_eq!(parse(r#"args = "-n 'a b'""#).unwrap(), ["-n", "a b"]);
+        assert_eq!(parse(r#"args = ["-n", "'a b'"]"#).unwrap(), ["-n", "'a b'"]);
+        assert!(parse(r#"args = "'a b""#).is_err());
+        assert!(parse(r#"args = ["a\u0000b"]"#).is_err());
+    }
+
+    #[test]
+    fn parse_environment() {
+        assert_eq!(
+            env("LANG=C; TZ=UTC;;PATH=/bin:/usr/bin"),
+            pairs(&[("LANG", "C"), ("TZ", "UTC"), ("PATH", "/bin:/usr/bin")])
+        );
+        assert_eq!(env("EMPTY=;A=b=c"), pairs(&[("EMPTY", ""), ("A", "b=c")]));
+        assert_eq!(env("A=1;A=2"), pairs(&[("A", "2")]));
+        assert!(env("").is_empty());
+
+        for invalid in ["NOVALUE", "=value", "A=1;B", " =x"] {
+            assert!(invalid.parse::<Environment>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn deserialize_environment() {
+        #[derive(Deserialize)]
+        struct Process {
+            environment: Environment,
+        }
+
+        let parse = |value: &str| {
+            toml::from_str::<Process>(value).map(|process| process.environment.into_pairs())
+        };
+
+        assert_eq!(
+            parse("environment = { B = 4, A = \"x\", C = true, D = 0.5 }").unwrap(),
+            pairs(&[("A", "x"), ("B", "4"), ("C", "true"), ("D", "0.5")])
+        );
+        assert_eq!(
+            parse("environment = \"A=1\"").unwrap(),
+            pairs(&[("A", "1")])
+        );
+        assert!(parse("environment = { \"\" = 1 }").is_err());
+        assert!(parse("environment = \"A\"").is_err());
+    }
+
+    #[test]
+    fn merge_environment() {
+        let global: Environment = "LANG=C;TZ=UTC;LEVEL=global".parse().unwrap();
+        let host: Environment = "TZ=CET;LEVEL=host;HOST=1".parse().unwrap();
+        let process: Environment = "LEVEL=process".parse().unwrap();
+
+        // The order of the simulation: global defaults, then host, then process.
+        let mut environment = global.clone();
+        environment.merge(&host);
+        assert_eq!(
+            environment.clone().into_pairs(),
+            pairs(&[
+                ("LANG", "C"),
+                ("TZ", "CET"),
+                ("LEVEL", "host"),
+                ("HOST", "1")
+            ])
+        );
+
+        environment.merge(&process);
+        assert_eq!(
+            environment.into_pairs(),
+            pairs(&[
+                ("LANG", "C"),
+                ("TZ", "CET"),
+                ("LEVEL", "process"),
+                ("HOST", "1")
+            ])
+        );
+
+        let mut unchanged = global.clone();
+        unchanged.merge(&Environment::default());
+        assert_eq!(unchanged.into_pairs(), global.into_pairs());
+    }
 }
@@ -0,0 +1,833 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::host::Host;
+use crate::net::AddressFamily;
+use crate::process::Process;
+use crate::simapp::HostedApp;
+use crate::task::Task;
+use crate::tcp::TcpSocket;
+use crate::time::SimulationTime;
+use crate::udp::UdpSocket;
+use crate::worker::Worker;
+
+pub const PIPE_CAPACITY: usize = 65_536;
+
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+enum Waiter {
+    Process(Process),
+    App(HostedApp),
+}
+
+/// Processes blocked on a file and applications using it, resumed or woken when its state
+/// changes. The generation counts the changes, so edge-triggered `epoll` can tell new events
+/// from ones already reported.
+#[derive(Default)]
+pub struct Notifier {
+    waiters: Vec<Waiter>,
+    generation: u64,
+}
+
+impl Notifier {
+    pub fn register(&mut self, process: &Process) {
+        let is_waiting = self.waiters.iter().any(|waiter| match waiter {
+            Waiter::Process(waiter) => waiter.ptr_eq(process),
+            Waiter::App(_) => false,
+        });
+
+        if !is_waiting {
+            self.waiters.push(Waiter::Process(process.clone()));
+        }
+    }
+
+    pub fn register_app(&mut self, app: &HostedApp) {
+        let is_waiting = self.waiters.iter().any(|waiter| match waiter {
+            Waiter::Process(_) => false,
+            Waiter::App(waiter) => waiter.ptr_eq(app),
+        });
+
+        if !is_waiting {
+            self.waiters.push(Waiter::App(app.clone()));
+        }
+    }
+
+    pub fn notify(&mut self, host: &Arc<Host>) {
+        self.generation += 1;
+
+        for waiter in self.waiters.drain(..) {
+            let task = match waiter {
+                Waiter::Process(process) => Task::ResumeProcess(process),
+                Waiter::App(app) => Task::WakeApp(app),
+            };
+            Worker::schedule_task(task, host, SimulationTime::zero());
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// The object behind an emulated descriptor.
+pub enum File {
+    Timer(Timer),
+    EventFd(EventFd),
+    PipeReader(Pipe),
+    PipeWriter(Pipe),
+    Udp(UdpSocket, AddressFamily),
+    Tcp(TcpSocket, AddressFamily),
+    Epoll(Epoll),
+}
+
+/// An emulated open file description, shared by all descriptors duplicated from it. Descriptor
+/// numbers are backed by a native placeholder in the managed process, so they never collide
+/// with the files it opens natively.
+pub struct Descriptor {
+    file: File,
+    is_nonblocking: bool,
+}
+
+impl Descriptor {
+    pub fn new(file: File, is_nonblocking: bool) -> Self {
+        Self {
+            file,
+            is_nonblocking,
+        }
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.is_nonblocking
+    }
+
+    pub fn set_nonblocking(&mut self, is_nonblocking: bool) {
+        self.is_nonblocking = is_nonblocking;
+    }
+
+    pub fn is_socket(&self) -> bool {
+        matches!(self.file, File::Udp(..) | File::Tcp(..))
+    }
+
+    /// Readiness in terms of `epoll` events.
+    pub fn events(&self, now: SimulationTime) -> u32 {
+        match &self.file {
+            File::Timer(timer) if timer.expirations(now) > 0 => libc::EPOLLIN as u32,
+            File::Timer(_) => 0,
+            File::EventFd(eventfd) => eventfd.events(),
+            File::PipeReader(pipe) => pipe.reader_events(),
+            File::PipeWriter(pipe) => pipe.writer_events(),
+            File::Udp(socket, _) => socket.events(),
+            File::Tcp(socket, _) => socket.events(),
+            File::Epoll(_) => 0,
+        }
+    }
+
+    /// Counts the changes of the file, for edge-triggered notification.
+    pub fn generation(&self, now: SimulationTime) -> u64 {
+        match &self.file {
+            File::Timer(timer) => timer.generation(now),
+            File::EventFd(eventfd) => eventfd.notifier.generation(),
+            File::PipeReader(pipe) | File::PipeWriter(pipe) => pipe.generation(),
+            File::Udp(socket, _) => socket.notifier_generation(),
+            File::Tcp(socket, _) => socket.notifier_generation(),
+            File::Epoll(_) => 0,
+        }
+    }
+
+    /// Registers the process to be resumed on the next change of the file and returns when a
+    /// timer expires, which is resumed by time rather than by notification.
+    pub fn wait(&mut self, process: &Process) -> Option<SimulationTime> {
+        match &mut self.file {
+            File::Timer(timer) => return timer.next_expiration(),
+            File::EventFd(eventfd) => eventfd.notifier.register(process),
+            File::PipeReader(pipe) | File::PipeWriter(pipe) => pipe.wait(process),
+            File::Udp(socket, _) => socket.wait(process),
+            File::Tcp(socket, _) => socket.wait(process),
+            File::Epoll(_) => {}
+        }
+
+        None
+    }
+
+    /// Releases the file once the last descriptor referring to it is closed.
+    pub fn close(self, host: &Arc<Host>) {
+        match self.file {
+            File::PipeReader(pipe) => pipe.close_reader(host),
+            File::PipeWriter(pipe) => pipe.close_writer(host),
+            File::Udp(socket, _) => socket.close(host),
+            File::Tcp(socket, _) => socket.close(host),
+            File::Timer(_) | File::EventFd(_) | File::Epoll(_) => {}
+        }
+    }
+}
+
+/// The emulated descriptors of a process, ordered so that closing them all is deterministic.
+#[derive(Default)]
+pub struct DescriptorTable {
+    entries: BTreeMap<i32, Arc<Mutex<Descriptor>>>,
+}
+
+impl DescriptorTable {
+    pub fn get(&self, fd: i32) -> Option<Arc<Mutex<Descriptor>>> {
+        self.entries.get(&fd).cloned()
+    }
+
+    pub fn contains(&self, fd: i32) -> bool {
+        self.entries.contains_key(&fd)
+    }
+
+    pub fn insert(&mut self, fd: i32, descriptor: Arc<Mutex<Descriptor>>) {
+        self.entries.insert(fd, descriptor);
+    }
+
+    pub fn remove(&mut self, fd: i32) -> Option<Arc<Mutex<Descriptor>>> {
+        self.entries.remove(&fd)
+    }
+
+    /// Removes the descriptors whose number is not kept.
+    pub fn retain(&mut self, mut keep: impl FnMut(i32) -> bool) -> Vec<Arc<Mutex<Descriptor>>> {
+        let (kept, removed): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|(fd, _)| keep(*fd));
+        self.entries = kept;
+        removed.into_values().collect()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Arc<Mutex<Descriptor>>> {
+        self.entries.values()
+    }
+
+    pub fn drain(&mut self) -> Vec<Arc<Mutex<Descriptor>>> {
+        std::mem::take(&mut self.entries).into_values().collect()
+    }
+}
+
+/// Closes the file behind a descriptor if no other descriptor refers to it anymore.
+pub fn release(descriptor: Arc<Mutex<Descriptor>>, host: &Arc<Host>) {
+    if let Ok(descriptor) = Arc::try_unwrap(descriptor) {
+        descriptor
+            .into_inner()
+            .expect("tried to close poisoned descriptor")
+            .close(host);
+    }
+}
+
+/// A `timerfd` counting expirations in simulated time.
+pub struct Timer {
+    clock: libc::clockid_t,
+    next: Option<SimulationTime>,
+    interval: SimulationTime,
+    taken: u64,
+}
+
+impl Timer {
+    pub fn new(clock: libc::clockid_t) -> Self {
+        Self {
+            clock,
+            next: None,
+            interval: SimulationTime::zero(),
+            taken: 0,
+        }
+    }
+
+    /// The clock absolute expiration times are given in.
+    pub fn clock(&self) -> libc::clockid_t {
+        self.clock
+    }
+
+    pub fn next_expiration(&self) -> Option<SimulationTime> {
+        self.next
+    }
+
+    /// Arms the timer to first expire at `next`, or disarms it, and returns the previous
+    /// remaining time and interval.
+    pub fn arm(
+        &mut self,
+        now: SimulationTime,
+        next: Option<SimulationTime>,
+        interval: SimulationTime,
+    ) -> (SimulationTime, SimulationTime) {
+        let previous = self.remaining(now);
+        self.next = next;
+        self.interval = interval;
+        previous
+    }
+
+    pub fn remaining(&self, now: SimulationTime) -> (SimulationTime, SimulationTime) {
+        let remaining = match self.next {
+            Some(next) if next > now => next - now,
+            Some(_) if self.interval > SimulationTime::zero() => {
+                self.interval - (now - self.next.unwrap_or(now)) % self.interval
+            }
+            _ => SimulationTime::zero(),
+        };
+
+        (remaining, self.interval)
+    }
+
+    pub fn expirations(&self, now: SimulationTime) -> u64 {
+        match self.next {
+            Some(next) if next <= now && self.interval > SimulationTime::zero() => {
+                1 + ((now - next).as_nanos() / self.interval.as_nanos()) as u64
+            }
+            Some(next) if next <= now => 1,
+            _ => 0,
+        }
+    }
+
+    fn generation(&self, now: SimulationTime) -> u64 {
+        self.taken + self.expirations(now)
+    }
+
+    /// Reads and resets the expiration count, rearming a periodic timer for its next period.
+    pub fn take_expirations(&mut self, now: SimulationTime) -> u64 {
+        let count = self.expirations(now);
+
+        if count > 0 {
+            self.next = match self.next {
+                Some(next) if self.interval > SimulationTime::zero() => {
+                    Some(next + SimulationTime::from_nanos(self.interval.as_nanos() * count as i64))
+                }
+                _ => None,
+            };
+            self.taken += count;
+        }
+
+        count
+    }
+}
+
+/// An `eventfd` counter.
+pub struct EventFd {
+    counter: u64,
+    is_semaphore: bool,
+    notifier: Notifier,
+}
+
+impl EventFd {
+    pub fn new(initial: u64, is_semaphore: bool) -> Self {
+        Self {
+            counter: initial,
+            is_semaphore,
+            notifier: Notifier::default(),
+        }
+    }
+
+    fn events(&self) -> u32 {
+        let mut events = 0;
+
+        if self.counter > 0 {
+            events |= libc::EPOLLIN;
+        }
+        if self.counter < EVENTFD_MAX {
+            events |= libc::EPOLLOUT;
+        }
+
+        events as u32
+    }
+
+    pub fn read(&mut self, host: &Arc<Host>) -> io::Result<u64> {
+        if self.counter == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let value = match self.is_semaphore {
+            true => 1,
+            false => self.counter,
+        };
+        self.counter -= value;
+        self.notifier.notify(host);
+        Ok(value)
+    }
+
+    pub fn write(&mut self, host: &Arc<Host>, value: u64) -> io::Result<()> {
+        if value == u64::MAX {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        if EVENTFD_MAX - self.counter < value {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        self.counter += value;
+        self.notifier.notify(host);
+        Ok(())
+    }
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    has_reader: bool,
+    has_writer: bool,
+    notifier: Notifier,
+}
+
+/// A pipe between two descriptors, emulated so that a process blocked on it does not stall
+/// the simulation.
+#[derive(Clone)]
+pub struct Pipe(Arc<Mutex<PipeState>>);
+
+impl Pipe {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(PipeState {
+            buffer: VecDeque::new(),
+            capacity: PIPE_CAPACITY,
+            has_reader: true,
+            has_writer: true,
+            notifier: Notifier::default(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, PipeState> {
+        self.0.lock().expect("accessed poisoned pipe")
+    }
+
+    pub fn read(&self, host: &Arc<Host>, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state();
+
+        if state.buffer.is_empty() {
+            return match state.has_writer {
+                true => Err(io::ErrorKind::WouldBlock.into()),
+                false => Ok(0),
+            };
+        }
+
+        let len = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..len)) {
+            *dst = src;
+        }
+        state.notifier.notify(host);
+        Ok(len)
+    }
+
+    pub fn write(&self, host: &Arc<Host>, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state();
+
+        if !state.has_reader {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        let len = buf.len().min(state.capacity - state.buffer.len());
+        if len == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        state.buffer.extend(&buf[..len]);
+        state.notifier.notify(host);
+        Ok(len)
+    }
+
+    pub fn available(&self) -> usize {
+        self.state().buffer.len()
+    }
+
+    fn reader_events(&self) -> u32 {
+        let state = self.state();
+        let mut events = 0;
+
+        if !state.buffer.is_empty() {
+            events |= libc::EPOLLIN;
+        }
+        if !state.has_writer {
+            events |= libc::EPOLLHUP;
+        }
+
+        events as u32
+    }
+
+    fn writer_events(&self) -> u32 {
+        let state = self.state();
+
+        match state.has_reader {
+            true if state.buffer.len() < state.capacity => libc::EPOLLOUT as u32,
+            true => 0,
+            false => (libc::EPOLLERR | libc::EPOLLOUT) as u32,
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.state().notifier.generation()
+    }
+
+    fn wait(&self, process: &Process) {
+        self.state().notifier.register(process);
+    }
+
+    fn close_reader(&self, host: &Arc<Host>) {
+        let mut state = self.state();
+        state.has_reader = false;
+        state.buffer.clear();
+        state.notifier.notify(host);
+    }
+
+    fn close_writer(&self, host: &Arc<Host>) {
+        let mut state = self.state();
+        state.has_writer = false;
+        state.notifier.notify(host);
+    }
+}
+
+struct Interest {
+    file: Option<Weak<Mutex<Descriptor>>>,
+    events: u32,
+    data: u64,
+    reported: Option<u64>,
+    is_disabled: bool,
+}
+
+/// An `epoll` instance. Native descriptors may be added as well, their readiness is queried
+/// from the kernel when events are collected.
+#[derive(Default)]
+pub struct Epoll {
+    interests: BTreeMap<i32, Interest>,
+    cursor: i32,
+}
+
+impl Epoll {
+    pub fn add(
+        &mut self,
+        fd: i32,
+        file: Option<Weak<Mutex<Descriptor>>>,
+        events: u32,
+        data: u64,
+    ) -> io::Result<()> {
+        if self.interests.contains_key(&fd) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+
+        self.interests.insert(
+            fd,
+            Interest {
+                file,
+                events,
+                data,
+                reported: None,
+                is_disabled: false,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn modify(&mut self, fd: i32, events: u32, data: u64) -> io::Result<()> {
+        let interest = self.interests.get_mut(&fd).ok_or(io::ErrorKind::NotFound)?;
+
+        interest.events = events;
+        interest.data = data;
+        interest.reported = None;
+        interest.is_disabled = false;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, fd: i32) -> io::Result<()> {
+        self.interests
+            .remove(&fd)
+            .map(|_| ())
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    /// Drops the interest in a native descriptor, which the kernel does once it is closed.
+    pub fn forget_native(&mut self, fd: i32) {
+        if matches!(self.interests.get(&fd), Some(interest) if interest.file.is_none()) {
+            self.interests.remove(&fd);
+        }
+    }
+
+    /// Collects up to `max` ready events, continuing after the descriptor reported last so that
+    /// busy descriptors cannot starve the others. `native` returns the readiness of a native
+    /// descriptor for the events asked.
+    pub fn collect(
+        &mut self,
+        now: SimulationTime,
+        max: usize,
+        native: impl Fn(i32, u32) -> u32,
+    ) -> Vec<(u32, u64)> {
+        let always = (libc::EPOLLERR | libc::EPOLLHUP) as u32;
+        let edge = libc::EPOLLET as u32;
+        let oneshot = libc::EPOLLONESHOT as u32;
+
+        self.interests.retain(|_, interest| match &interest.file {
+            Some(file) => file.strong_count() > 0,
+            None => true,
+        });
+
+        let fds: Vec<i32> = self
+            .interests
+            .range(self.cursor.saturating_add(1)..)
+            .chain(self.interests.range(..=self.cursor))
+            .map(|(fd, _)| *fd)
+            .collect();
+
+        let mut ready = Vec::new();
+
+        for fd in fds {
+            if ready.len() >= max {
+                break;
+            }
+
+            let interest = match self.interests.get_mut(&fd) {
+                Some(interest) if !interest.is_disabled => interest,
+                _ => continue,
+            };
+
+            let (events, generation) = match interest.file.as_ref().and_then(Weak::upgrade) {
+                Some(file) => {
+                    let file = file.lock().expect("tried to poll poisoned descriptor");
+                    (file.events(now), file.generation(now))
+                }
+                None => (native(fd, interest.events | always), 0),
+            };
+
+            let events = events & (interest.events | always);
+            if events == 0 {
+                continue;
+            }
+
+            if interest.events & edge != 0 {
+                if interest.reported == Some(generation) {
+                    continue;
+                }
+                interest.reported = Some(generation);
+            }
+
+            if interest.events & oneshot != 0 {
+                interest.is_disabled = true;
+            }
+
+            ready.push((events, interest.data));
+            self.cursor = fd;
+        }
+
+        ready
+    }
+
+    /// Registers the process on all watched files and returns the earliest timer expiration.
+    pub fn wait(&self, process: &Process) -> Option<SimulationTime> {
+        self.interests
+            .values()
+            .filter(|interest| !interest.is_disabled)
+            .filter_map(|interest| interest.file.as_ref()?.upgrade())
+            .filter_map(|file| {
+                file.lock()
+                    .expect("tried to wait on poisoned descriptor")
+                    .wait(process)
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::TestNetwork;
+
+    const IN: u32 = libc::EPOLLIN as u32;
+    const OUT: u32 = libc::EPOLLOUT as u32;
+    const HUP: u32 = libc::EPOLLHUP as u32;
+    const ERR: u32 = libc::EPOLLERR as u32;
+
+    fn secs(secs: i64) -> SimulationTime {
+        SimulationTime::from_millis(secs * 1000)
+    }
+
+    fn shared(file: File) -> Arc<Mutex<Descriptor>> {
+        Arc::new(Mutex::new(Descriptor::new(file, true)))
+    }
+
+    /// Collects events with every native descriptor ready for reading.
+    fn collect(epoll: &mut Epoll, max: usize) -> Vec<(u32, u64)> {
+        epoll.collect(SimulationTime::zero(), max, |_, events| events & IN)
+    }
+
+    #[test]
+    fn pipe_readiness() {
+        let network = TestNetwork::new(&["11.0.0.1"]);
+        let host = network.host(0);
+        let now = SimulationTime::zero();
+        let pipe = Pipe::new();
+        let reader = Descriptor::new(File::PipeReader(pipe.clone()), false);
+        let writer = Descriptor::new(File::PipeWriter(pipe.clone()), false);
+
+        assert_eq!(reader.events(now), 0);
+        assert_eq!(writer.events(now), OUT);
+
+        let mut buf = [0; 4];
+        assert_eq!(
+            pipe.read(host, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(pipe.write(host, b"abc").unwrap(), 3);
+        assert_eq!(reader.events(now), IN);
+        assert_eq!(pipe.available(), 3);
+        assert_eq!(pipe.read(host, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+
+        let full = vec![0; PIPE_CAPACITY + 1];
+        assert_eq!(pipe.write(host, &full).unwrap(), PIPE_CAPACITY);
+        assert_eq!(writer.events(now), 0);
+        assert_eq!(
+            pipe.write(host, b"x").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        writer.close(host);
+        assert_eq!(reader.events(now), IN | HUP);
+        while pipe.read(host, &mut buf).unwrap() > 0 {}
+        assert_eq!(reader.events(now), HUP);
+
+        let pipe = Pipe::new();
+        let writer = Descriptor::new(File::PipeWriter(pipe.clone()), false);
+        Descriptor::new(File::PipeReader(pipe.clone()), false).close(host);
+        assert_eq!(writer.events(now), ERR | OUT);
+        assert_eq!(
+            pipe.write(host, b"x").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn eventfd_counter() {
+        let network = TestNetwork::new(&["11.0.0.1"]);
+        let host = network.host(0);
+
+        let mut eventfd = EventFd::new(0, false);
+        assert_eq!(eventfd.events(), OUT);
+        assert_eq!(
+            eventfd.read(host).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        eventfd.write(host, 2).unwrap();
+        eventfd.write(host, 3).unwrap();
+        assert_eq!(eventfd.events(), IN | OUT);
+        assert_eq!(eventfd.read(host).unwrap(), 5);
+        assert_eq!(eventfd.events(), OUT);
+
+        assert_eq!(
+            eventfd.write(host, u64::MAX).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        eventfd.write(host, EVENTFD_MAX).unwrap();
+        assert_eq!(eventfd.events(), IN);
+        assert_eq!(
+            eventfd.write(host, 1).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let mut semaphore = EventFd::new(2, true);
+        assert_eq!(semaphore.read(host).unwrap(), 1);
+        assert_eq!(semaphore.read(host).unwrap(), 1);
+        assert_eq!(
+            semaphore.read(host).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn timer_expirations() {
+        let mut timer = Timer::new(libc::CLOCK_MONOTONIC);
+        assert_eq!(timer.clock(), libc::CLOCK_MONOTONIC);
+        assert_eq!(timer.expirations(secs(5)), 0);
+
+        timer.arm(
+            SimulationTime::zero(),
+            Some(secs(1)),
+            SimulationTime::zero(),
+        );
+        assert_eq!(timer.expirations(SimulationTime::from_millis(999)), 0);
+        assert_eq!(
+            timer.remaining(SimulationTime::from_millis(400)).0,
+            SimulationTime::from_millis(600)
+        );
+        assert_eq!(timer.expirations(secs(3)), 1);
+        assert_eq!(timer.take_expirations(secs(3)), 1);
+        assert_eq!(timer.next_expiration(), None);
+        assert_eq!(timer.take_expirations(secs(4)), 0);
+
+        let interval = SimulationTime::from_millis(500);
+        let previous = timer.arm(SimulationTime::zero(), Some(secs(1)), interval);
+        assert_eq!(previous, (SimulationTime::zero(), SimulationTime::zero()));
+        assert_eq!(timer.expirations(secs(2)), 3);
+        assert_eq!(timer.take_expirations(secs(2)), 3);
+        assert_eq!(
+            timer.next_expiration(),
+            Some(SimulationTime::from_millis(2500))
+        );
+        assert_eq!(timer.remaining(secs(2)), (interval, interval));
+
+        timer.arm(secs(2), None, SimulationTime::zero());
+        assert_eq!(timer.expirations(secs(10)), 0);
+    }
+
+    #[test]
+    fn epoll_interests() {
+        let network = TestNetwork::new(&["11.0.0.1"]);
+        let host = network.host(0);
+        let eventfd = shared(File::EventFd(EventFd::new(0, false)));
+        let mut epoll = Epoll::default();
+
+        epoll
+            .add(3, Some(Arc::downgrade(&eventfd)), IN, 30)
+            .unwrap();
+        assert_eq!(
+            epoll.add(3, None, IN, 30).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            epoll.modify(4, IN, 40).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(epoll.remove(4).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(collect(&mut epoll, 8).is_empty());
+
+        let write = |value| match eventfd.lock().unwrap().file_mut() {
+            File::EventFd(eventfd) => eventfd.write(host, value).unwrap(),
+            _ => unreachable!(),
+        };
+
+        write(1);
+        assert_eq!(collect(&mut epoll, 8), [(IN, 30)]);
+        assert_eq!(collect(&mut epoll, 8), [(IN, 30)]);
+
+        epoll.modify(3, IN | libc::EPOLLET as u32, 31).unwrap();
+        assert_eq!(collect(&mut epoll, 8), [(IN, 31)]);
+        assert!(collect(&mut epoll, 8).is_empty());
+        write(1);
+        assert_eq!(collect(&mut epoll, 8), [(IN, 31)]);
+
+        epoll.modify(3, IN | libc::EPOLLONESHOT as u32, 32).unwrap();
+        assert_eq!(collect(&mut epoll, 8), [(IN, 32)]);
+        assert!(collect(&mut epoll, 8).is_empty());
+        epoll.modify(3, IN, 33).unwrap();
+        assert_eq!(collect(&mut epoll, 8), [(IN, 33)]);
+
+        drop(eventfd);
+        assert!(collect(&mut epoll, 8).is_empty());
+        assert_eq!(epoll.remove(3).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn epoll_native_descriptors() {
+        let mut epoll = Epoll::default();
+
+        epoll.add(5, None, IN, 50).unwrap();
+        epoll.add(6, None, OUT, 60).unwrap();
+        assert_eq!(collect(&mut epoll, 8), [(IN, 50)]);
+
+        epoll.add(7, None, IN, 70).unwrap();
+        assert_eq!(collect(&mut epoll, 1), [(IN, 70)]);
+        assert_eq!(collect(&mut epoll, 1), [(IN, 50)]);
+
+        epoll.forget_native(5);
+        assert_eq!(collect(&mut epoll, 8), [(IN, 70)]);
+        assert_eq!(epoll.remove(5).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
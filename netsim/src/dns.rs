@@ -0,0 +1,891 @@
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::net::{AddressFamily, AliasRecord, NameServer};
+
+pub const DNS_PORT: u16 = 53;
+pub const RESOLVER_IPV4: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 53);
+pub const RESOLVER_IPV6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53);
+
+const HEADER_SIZE: usize = 12;
+const CLASS_IN: u16 = 1;
+const MAX_NAME_SIZE: usize = 255;
+const MAX_LABEL_SIZE: usize = 63;
+const MAX_POINTERS: usize = 16;
+
+/// Largest message that fits into a UDP datagram without EDNS.
+const MAX_UDP_SIZE: usize = 512;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+
+pub fn is_resolver(ip: IpAddr) -> bool {
+    ip == IpAddr::V4(RESOLVER_IPV4) || ip == IpAddr::V6(RESOLVER_IPV6)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Ptr,
+    Other(u16),
+}
+
+impl RecordType {
+    fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Ptr => 12,
+            RecordType::Aaaa => 28,
+            RecordType::Other(code) => *code,
+        }
+    }
+
+    fn from_code(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            5 => RecordType::Cname,
+            12 => RecordType::Ptr,
+            28 => RecordType::Aaaa,
+            code => RecordType::Other(code),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    NameError,
+    NotImplemented,
+    Refused,
+}
+
+impl ResponseCode {
+    fn code(&self) -> u16 {
+        match self {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+        }
+    }
+
+    fn from_code(code: u16) -> Self {
+        match code {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
+            3 => ResponseCode::NameError,
+            4 => ResponseCode::NotImplemented,
+            5 => ResponseCode::Refused,
+            _ => ResponseCode::ServerFailure,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub kind: RecordType,
+    pub class: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    Other(u16, Vec<u8>),
+}
+
+impl RecordData {
+    fn kind(&self) -> RecordType {
+        match self {
+            RecordData::A(_) => RecordType::A,
+            RecordData::Aaaa(_) => RecordType::Aaaa,
+            RecordData::Cname(_) => RecordType::Cname,
+            RecordData::Ptr(_) => RecordType::Ptr,
+            RecordData::Other(code, _) => RecordType::Other(*code),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub is_response: bool,
+    pub opcode: u8,
+    pub is_authoritative: bool,
+    pub is_truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub code: ResponseCode,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+}
+
+impl Message {
+    fn response(query: &Message, code: ResponseCode, answers: Vec<Record>) -> Self {
+        Self {
+            id: query.id,
+            is_response: true,
+            opcode: query.opcode,
+            is_authoritative: true,
+            is_truncated: false,
+            recursion_desired: query.recursion_desired,
+            recursion_available: true,
+            code,
+            questions: query.questions.clone(),
+            answers,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = (u16::from(self.opcode & 0x0f) << 11) | self.code.code();
+        for (flag, is_set) in [
+            (FLAG_RESPONSE, self.is_response),
+            (FLAG_AUTHORITATIVE, self.is_authoritative),
+            (FLAG_TRUNCATED, self.is_truncated),
+            (FLAG_RECURSION_DESIRED, self.recursion_desired),
+            (FLAG_RECURSION_AVAILABLE, self.recursion_available),
+        ] {
+            if is_set {
+                flags |= flag;
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend(self.id.to_be_bytes());
+        bytes.extend(flags.to_be_bytes());
+        bytes.extend((self.questions.len() as u16).to_be_bytes());
+        bytes.extend((self.answers.len() as u16).to_be_bytes());
+        bytes.extend([0, 0, 0, 0]);
+
+        for question in &self.questions {
+            encode_name(&mut bytes, &question.name);
+            bytes.extend(question.kind.code().to_be_bytes());
+            bytes.extend(question.class.to_be_bytes());
+        }
+
+        for record in &self.answers {
+            encode_name(&mut bytes, &record.name);
+            bytes.extend(record.data.kind().code().to_be_bytes());
+            bytes.extend(CLASS_IN.to_be_bytes());
+            bytes.extend(record.ttl.to_be_bytes());
+
+            let mut data = Vec::new();
+            match &record.data {
+                RecordData::A(ip) => data.extend(ip.octets()),
+                RecordData::Aaaa(ip) => data.extend(ip.octets()),
+                RecordData::Cname(name) | RecordData::Ptr(name) => encode_name(&mut data, name),
+                RecordData::Other(_, raw) => data.extend(raw),
+            }
+            bytes.extend((data.len() as u16).to_be_bytes());
+            bytes.extend(data);
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(..HEADER_SIZE)?;
+        let field = |index: usize| u16::from_be_bytes([header[index], header[index + 1]]);
+
+        let flags = field(2);
+        let mut message = Self {
+            id: field(0),
+            is_response: flags & FLAG_RESPONSE != 0,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            is_authoritative: flags & FLAG_AUTHORITATIVE != 0,
+            is_truncated: flags & FLAG_TRUNCATED != 0,
+            recursion_desired: flags & FLAG_RECURSION_DESIRED != 0,
+            recursion_available: flags & FLAG_RECURSION_AVAILABLE != 0,
+            code: ResponseCode::from_code(flags & 0x0f),
+            questions: Vec::new(),
+            answers: Vec::new(),
+        };
+
+        let mut offset = HEADER_SIZE;
+
+        for _ in 0..field(4) {
+            let name = decode_name(bytes, &mut offset)?;
+            let fixed = bytes.get(offset..offset + 4)?;
+            offset += 4;
+
+            message.questions.push(Question {
+                name,
+                kind: RecordType::from_code(u16::from_be_bytes([fixed[0], fixed[1]])),
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+        }
+
+        for _ in 0..field(6) {
+            let name = decode_name(bytes, &mut offset)?;
+            let fixed = bytes.get(offset..offset + 10)?;
+            offset += 10;
+
+            let kind = RecordType::from_code(u16::from_be_bytes([fixed[0], fixed[1]]));
+            let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+            let size = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+            let raw = bytes.get(offset..offset + size)?;
+
+            let data = match kind {
+                RecordType::A => RecordData::A(<[u8; 4]>::try_from(raw).ok()?.into()),
+                RecordType::Aaaa => RecordData::Aaaa(<[u8; 16]>::try_from(raw).ok()?.into()),
+                RecordType::Ptr => RecordData::Ptr(decode_name(bytes, &mut offset.clone())?),
+                RecordType::Cname => RecordData::Cname(decode_name(bytes, &mut offset.clone())?),
+                RecordType::Other(code) => RecordData::Other(code, raw.to_vec()),
+            };
+            offset += size;
+
+            message.answers.push(Record { name, ttl, data });
+        }
+
+        Some(message)
+    }
+}
+
+/// Answers a wire format query from the tables of the name server, or returns `None` if the
+/// bytes are not a query at all and should be dropped. Answers that do not fit into a UDP
+/// datagram are left out and the response is marked as truncated.
+pub fn respond(query: &[u8], name_server: &mut NameServer) -> Option<Vec<u8>> {
+    let query = match Message::decode(query) {
+        Some(query) if query.is_response => return None,
+        Some(query) => query,
+        None => {
+            let header = query.get(..HEADER_SIZE)?;
+            if header[2] & 0x80 != 0 {
+                return None;
+            }
+
+            let mut bytes = header[..2].to_vec();
+            bytes.extend((FLAG_RESPONSE | ResponseCode::FormatError.code()).to_be_bytes());
+            bytes.extend([0; 8]);
+            return Some(bytes);
+        }
+    };
+
+    let mut response = match (query.opcode, query.questions.as_slice()) {
+        (0, [question]) if question.class == CLASS_IN => {
+            let (code, answers) = resolve(question, name_server);
+            Message::response(&query, code, answers)
+        }
+        (0, [_]) => Message::response(&query, ResponseCode::NotImplemented, Vec::new()),
+        (0, _) => Message::response(&query, ResponseCode::FormatError, Vec::new()),
+        _ => Message::response(&query, ResponseCode::NotImplemented, Vec::new()),
+    };
+
+    let mut bytes = response.encode();
+    while bytes.len() > MAX_UDP_SIZE && response.answers.pop().is_some() {
+        response.is_truncated = true;
+        bytes = response.encode();
+    }
+
+    Some(bytes)
+}
+
+fn resolve(question: &Question, name_server: &mut NameServer) -> (ResponseCode, Vec<Record>) {
+    let name = question.name.trim_end_matches('.');
+
+    let family = match question.kind {
+        RecordType::A => AddressFamily::Inet,
+        RecordType::Aaaa => AddressFamily::Inet6,
+        RecordType::Ptr => {
+            let answers: Vec<_> = reverse_name(name)
+                .map(|ip| name_server.reverse_lookup(ip))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|target| Record {
+                    name: question.name.clone(),
+                    ttl: NameServer::DEFAULT_TTL,
+                    data: RecordData::Ptr(target),
+                })
+                .collect();
+
+            return match answers.is_empty() {
+                true => (ResponseCode::NameError, answers),
+                false => (ResponseCode::NoError, answers),
+            };
+        }
+        RecordType::Cname => {
+            return match name_server.alias(name) {
+                Some(alias) => (ResponseCode::NoError, vec![alias.into()]),
+                None if name_server.exists(name) => (ResponseCode::NoError, Vec::new()),
+                None => (ResponseCode::NameError, Vec::new()),
+            };
+        }
+        RecordType::Other(_) if name_server.exists(name) => {
+            return (ResponseCode::NoError, Vec::new());
+        }
+        RecordType::Other(_) => return (ResponseCode::NameError, Vec::new()),
+    };
+
+    let resolution = match name_server.resolve(name, family) {
+        Some(resolution) => resolution,
+        None => return (ResponseCode::NameError, Vec::new()),
+    };
+
+    let mut answers: Vec<Record> = resolution.aliases.into_iter().map(Record::from).collect();
+    answers.extend(resolution.addresses.into_iter().map(|address| Record {
+        name: resolution.canonical.clone(),
+        ttl: address.ttl,
+        data: match address.ip {
+            IpAddr::V4(ip) => RecordData::A(ip),
+            IpAddr::V6(ip) => RecordData::Aaaa(ip),
+        },
+    }));
+
+    (ResponseCode::NoError, answers)
+}
+
+impl From<AliasRecord> for Record {
+    fn from(alias: AliasRecord) -> Self {
+        Record {
+            name: alias.name,
+            ttl: alias.ttl,
+            data: RecordData::Cname(alias.target),
+        }
+    }
+}
+
+/// Parses `d.c.b.a.in-addr.arpa` and nibble-reversed `ip6.arpa` names back into addresses.
+fn reverse_name(name: &str) -> Option<IpAddr> {
+    let lower = name.to_ascii_lowercase();
+
+    if let Some(labels) = lower.strip_suffix(".in-addr.arpa") {
+        let mut octets = labels
+            .split('.')
+            .map(|label| label.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        octets.reverse();
+        return Some(IpAddr::V4(<[u8; 4]>::try_from(octets).ok()?.into()));
+    }
+
+    let labels = lower.strip_suffix(".ip6.arpa")?;
+    let nibbles = labels
+        .split('.')
+        .rev()
+        .map(|label| match label.len() {
+            1 => u8::from_str_radix(label, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if nibbles.len() != 32 {
+        return None;
+    }
+
+    let mut octets = [0u8; 16];
+    for (octet, pair) in octets.iter_mut().zip(nibbles.chunks(2)) {
+        *octet = (pair[0] << 4) | pair[1];
+    }
+    Some(IpAddr::V6(octets.into()))
+}
+
+fn encode_name(bytes: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        let label = &label.as_bytes()[..label.len().min(MAX_LABEL_SIZE)];
+        bytes.push(label.len() as u8);
+        bytes.extend(label);
+    }
+    bytes.push(0);
+}
+
+/// Reads a possibly compressed name starting at `offset` and advances it past the name.
+fn decode_name(bytes: &[u8], offset: &mut usize) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut position = *offset;
+    let mut pointers = 0;
+    let mut size = 0;
+
+    loop {
+        let length = *bytes.get(position)?;
+
+        match length & 0xc0 {
+            0xc0 => {
+                let target =
+                    usize::from(u16::from_be_bytes([length, *bytes.get(position + 1)?]) & 0x3fff);
+                if pointers == 0 {
+                    *offset = position + 2;
+                }
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                position = target;
+            }
+            0x00 if length == 0 => {
+                if pointers == 0 {
+                    *offset = position + 1;
+                }
+                break;
+            }
+            0x00 => {
+                let label = bytes.get(position + 1..position + 1 + usize::from(length))?;
+                size += label.len() + 1;
+                if size > MAX_NAME_SIZE {
+                    return None;
+                }
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                position += 1 + usize::from(length);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(labels.join("."))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZoneData {
+    Address(IpAddr),
+    Alias(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZoneEntry {
+    pub name: String,
+    pub ttl: u32,
+    pub data: ZoneData,
+}
+
+/// Records in zone file notation, one `<name> [ttl] [IN] <A|AAAA|CNAME> <value>` per line, with
+/// `;` comments and the `$ORIGIN` and `$TTL` directives. Relative names are completed with the
+/// current origin and `@` stands for the origin itself.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Zone {
+    entries: Vec<ZoneEntry>,
+}
+
+impl Zone {
+    pub fn entries(&self) -> &[ZoneEntry] {
+        &self.entries
+    }
+
+    fn qualify(name: &str, origin: &str) -> String {
+        match (name, origin) {
+            ("@", _) => origin.to_owned(),
+            (name, "") => name.trim_end_matches('.').to_owned(),
+            (name, _) if name.ends_with('.') => name.trim_end_matches('.').to_owned(),
+            (name, origin) => format!("{}.{}", name, origin),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = InvalidZone;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut origin = String::new();
+        let mut default_ttl = NameServer::DEFAULT_TTL;
+        let mut entries = Vec::new();
+
+        for (index, line) in value.lines().enumerate() {
+            let invalid = |reason: &str| InvalidZone {
+                line: index + 1,
+                reason: reason.to_owned(),
+            };
+            let line = line.split(';').next().unwrap_or_default();
+            let mut fields = line.split_whitespace().peekable();
+
+            let name = match fields.next() {
+                Some(name) => name,
+                None => continue,
+            };
+
+            match name {
+                "$ORIGIN" => {
+                    let value = fields.next().ok_or_else(|| invalid("missing origin"))?;
+                    origin = Self::qualify(value, "");
+                    continue;
+                }
+                "$TTL" => {
+                    let value = fields.next().ok_or_else(|| invalid("missing ttl"))?;
+                    default_ttl = value.parse().map_err(|_| invalid("invalid ttl"))?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let ttl = match fields.peek().map(|field| field.parse::<u32>()) {
+                Some(Ok(ttl)) => {
+                    fields.next();
+                    ttl
+                }
+                _ => default_ttl,
+            };
+
+            if matches!(fields.peek(), Some(field) if field.eq_ignore_ascii_case("IN")) {
+                fields.next();
+            }
+
+            let kind = fields
+                .next()
+                .ok_or_else(|| invalid("missing record type"))?;
+            let value = fields
+                .next()
+                .ok_or_else(|| invalid("missing record data"))?;
+
+            if fields.next().is_some() {
+                return Err(invalid("trailing fields"));
+            }
+
+            let data = match kind.to_ascii_uppercase().as_str() {
+                "A" => match value.parse() {
+                    Ok(IpAddr::V4(ip)) => ZoneData::Address(IpAddr::V4(ip)),
+                    _ => return Err(invalid("invalid ipv4 address")),
+                },
+                "AAAA" => match value.parse() {
+                    Ok(IpAddr::V6(ip)) => ZoneData::Address(IpAddr::V6(ip)),
+                    _ => return Err(invalid("invalid ipv6 address")),
+                },
+                "CNAME" => ZoneData::Alias(Self::qualify(value, &origin)),
+                _ => return Err(invalid("unsupported record type")),
+            };
+
+            entries.push(ZoneEntry {
+                name: Self::qualify(name, &origin),
+                ttl,
+                data,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = InvalidZone;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidZone {
+    line: usize,
+    reason: String,
+}
+
+impl std::error::Error for InvalidZone {}
+
+impl Display for InvalidZone {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            formatter,
+            "invalid zone at line {}: {}",
+            self.line, self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, kind: RecordType) -> Message {
+        Message {
+            id,
+            is_response: false,
+            opcode: 0,
+            is_authoritative: false,
+            is_truncated: false,
+            recursion_desired: true,
+            recursion_available: false,
+            code: ResponseCode::NoError,
+            questions: vec![Question {
+                name: name.to_owned(),
+                kind,
+                class: CLASS_IN,
+            }],
+            answers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let message = query(0x1234, "server.example", RecordType::Aaaa);
+        assert_eq!(Message::decode(&message.encode()), Some(message));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let mut message = Message::response(
+            &query(7, "www.example", RecordType::A),
+            ResponseCode::NoError,
+            vec![
+                Record {
+                    name: "www.example".to_owned(),
+                    ttl: 60,
+                    data: RecordData::Cname("server.example".to_owned()),
+                },
+                Record {
+                    name: "server.example".to_owned(),
+                    ttl: 300,
+                    data: RecordData::A(Ipv4Addr::new(11, 0, 0, 1)),
+                },
+                Record {
+                    name: "server.example".to_owned(),
+                    ttl: 300,
+                    data: RecordData::Aaaa("2000::1".parse().unwrap()),
+                },
+                Record {
+                    name: "1.0.0.11.in-addr.arpa".to_owned(),
+                    ttl: 300,
+                    data: RecordData::Ptr("server.example".to_owned()),
+                },
+                Record {
+                    name: "server.example".to_owned(),
+                    ttl: 0,
+                    data: RecordData::Other(16, b"\x05hello".to_vec()),
+                },
+            ],
+        );
+        message.is_truncated = true;
+
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded, message);
+        assert!(decoded.is_response && decoded.is_authoritative && decoded.is_truncated);
+    }
+
+    #[test]
+    fn decode_compressed_names() {
+        let mut bytes = query(1, "www.example", RecordType::Cname).encode();
+        bytes[7] = 1;
+        // The answer name points to the question and the alias reuses its `example` suffix.
+        bytes.extend([0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6]);
+        bytes.extend([3, b'w', b'e', b'b', 0xc0, 16]);
+
+        let message = Message::decode(&bytes).unwrap();
+        assert_eq!(message.answers[0].name, "www.example");
+        assert_eq!(
+            message.answers[0].data,
+            RecordData::Cname("web.example".to_owned())
+        );
+    }
+
+    #[test]
+    fn decode_malformed() {
+        let bytes = query(1, "www.example", RecordType::A).encode();
+        assert_eq!(Message::decode(&bytes[..HEADER_SIZE - 1]), None);
+        assert_eq!(Message::decode(&bytes[..bytes.len() - 1]), None);
+
+        // A name pointing to itself must not loop forever.
+        let mut looping = bytes[..HEADER_SIZE].to_vec();
+        looping.extend([0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(Message::decode(&looping), None);
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name("1.0.0.11.in-addr.arpa"),
+            Some("11.0.0.1".parse().unwrap())
+        );
+        let name = format!("1.{}0.0.0.2.ip6.arpa", "0.".repeat(27));
+        assert_eq!(reverse_name(&name), Some("2000::1".parse().unwrap()));
+        assert_eq!(reverse_name("1.0.11.in-addr.arpa"), None);
+        assert_eq!(reverse_name("server.example"), None);
+    }
+
+    #[test]
+    fn respond_truncates_large_answers() {
+        let mut name_server = NameServer::new();
+        for index in 1..=40 {
+            let ip = IpAddr::V4(Ipv4Addr::new(11, 0, 0, index));
+            name_server
+                .add_address("many.example", ip, NameServer::DEFAULT_TTL)
+                .unwrap();
+        }
+
+        let request = query(9, "many.example", RecordType::A).encode();
+        let bytes = respond(&request, &mut name_server).unwrap();
+        assert!(bytes.len() <= MAX_UDP_SIZE);
+
+        let response = Message::decode(&bytes).unwrap();
+        assert!(response.is_truncated);
+        assert_eq!(response.code, ResponseCode::NoError);
+        assert!(!response.answers.is_empty() && response.answers.len() < 40);
+
+        let request = query(10, "unknown.example", RecordType::A).encode();
+        let response = Message::decode(&respond(&request, &mut name_server).unwrap()).unwrap();
+        assert!(!response.is_truncated);
+        assert_eq!(response.code, ResponseCode::NameError);
+    }
+
+    fn zone(value: &str) -> Vec<ZoneEntry> {
+        value.parse::<Zone>().unwrap().entries
+    }
+
+    fn entry(name: &str, ttl: u32, data: ZoneData) -> ZoneEntry {
+        ZoneEntry {
+            name: name.to_owned(),
+            ttl,
+            data,
+        }
+    }
+
+    #[test]
+    fn parse_zone() {
+        let entries = zone(
+            "
+            ; records of the example zone
+            $ORIGIN example.com.
+            @           A       11.0.0.1
+            api  120    IN  A   11.0.0.2 ; with a comment
+            api         in aaaa 2000::2
+            $TTL 300
+            docs        CNAME   www
+            mirror 30   CNAME   files.example.org.
+            other.org.  A       11.0.0.3
+            ",
+        );
+
+        assert_eq!(
+            entries,
+            vec![
+                entry(
+                    "example.com",
+                    60,
+                    ZoneData::Address("11.0.0.1".parse().unwrap())
+                ),
+                entry(
+                    "api.example.com",
+                    120,
+                    ZoneData::Address("11.0.0.2".parse().unwrap())
+                ),
+                entry(
+                    "api.example.com",
+                    60,
+                    ZoneData::Address("2000::2".parse().unwrap())
+                ),
+                entry(
+                    "docs.example.com",
+                    300,
+                    ZoneData::Alias("www.example.com".to_owned())
+                ),
+                entry(
+                    "mirror.example.com",
+                    30,
+                    ZoneData::Alias("files.example.org".to_owned())
+                ),
+                entry(
+                    "other.org",
+                    300,
+                    ZoneData::Address("11.0.0.3".parse().unwrap())
+                ),
+            ]
+        );
+
+        assert!(zone("").is_empty());
+        assert_eq!(zone("www A 11.0.0.1")[0].name, "www");
+    }
+
+    #[test]
+    fn parse_malformed_zone() {
+        for (value, line, reason) in [
+            ("www A", 1, "missing record data"),
+            ("www 60", 1, "missing record type"),
+            ("\nwww A 11.0.0.1 extra", 2, "trailing fields"),
+            ("www A 2000::1", 1, "invalid ipv4 address"),
+            ("www AAAA 11.0.0.1", 1, "invalid ipv6 address"),
+            ("www A not-an-address", 1, "invalid ipv4 address"),
+            ("www MX mail", 1, "unsupported record type"),
+            ("$ORIGIN", 1, "missing origin"),
+            ("$TTL\n", 1, "missing ttl"),
+            ("www A 11.0.0.1\n$TTL -1", 2, "invalid ttl"),
+        ] {
+            let err = value.parse::<Zone>().unwrap_err();
+            assert_eq!((err.line, err.reason.as_str()), (line, reason), "{}", value);
+        }
+
+        let err = "www A".parse::<Zone>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid zone at line 1: missing record data"
+        );
+    }
+
+    #[test]
+    fn resolve_zone_wildcards() {
+        let mut name_server = NameServer::new();
+        for entry in zone(
+            "
+            $ORIGIN example.com.
+            *.test      A       11.0.0.1
+            a.test      A       11.0.0.2
+            *.cdn       CNAME   a.test
+            ",
+        ) {
+            match entry.data {
+                ZoneData::Address(ip) => name_server.add_address(&entry.name, ip, entry.ttl),
+                ZoneData::Alias(target) => name_server.add_alias(&entry.name, &target, entry.ttl),
+            }
+            .unwrap();
+        }
+
+        let mut lookup = |name: &str| {
+            let request = query(1, name, RecordType::A).encode();
+            let response = Message::decode(&respond(&request, &mut name_server).unwrap()).unwrap();
+            let answers: Vec<_> = response
+                .answers
+                .into_iter()
+                .map(|record| record.data)
+                .collect();
+            (response.code, answers)
+        };
+
+        let address = |ip: [u8; 4]| RecordData::A(ip.into());
+        assert_eq!(
+            lookup("b.test.example.com"),
+            (ResponseCode::NoError, vec![address([11, 0, 0, 1])])
+        );
+        assert_eq!(
+            lookup("x.b.test.example.com"),
+            (ResponseCode::NoError, vec![address([11, 0, 0, 1])])
+        );
+        assert_eq!(
+            lookup("a.test.example.com"),
+            (ResponseCode::NoError, vec![address([11, 0, 0, 2])])
+        );
+        assert_eq!(
+            lookup("img.cdn.example.com"),
+            (
+                ResponseCode::NoError,
+                vec![
+                    RecordData::Cname("a.test.example.com".to_owned()),
+                    address([11, 0, 0, 2])
+                ]
+            )
+        );
+        assert_eq!(
+            lookup("test.example.com"),
+            (ResponseCode::NameError, Vec::new())
+        );
+    }
+}
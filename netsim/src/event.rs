@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 use crate::host::Host;
@@ -5,25 +6,39 @@ use crate::task::Task;
 use crate::time::SimulationTime;
 use crate::worker::Worker;
 
-#[derive(PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EventId(i64);
 
+impl From<i64> for EventId {
+    fn from(value: i64) -> Self {
+        EventId(value)
+    }
+}
+
+impl From<EventId> for i64 {
+    fn from(value: EventId) -> Self {
+        value.0
+    }
+}
+
 pub struct Event {
     src: Arc<Host>,
     dst: Arc<Host>,
     task: Arc<Task>,
     time: SimulationTime,
-    // event_id: EventId,
+    event_id: EventId,
 }
 
 impl Event {
     pub fn new(task: Arc<Task>, delay: SimulationTime, src: Arc<Host>, dst: Arc<Host>) -> Self {
+        let event_id = src.new_event_id();
+
         Self {
             src,
             dst,
             task,
             time: delay,
-            //event_id: src.new_event_id(),
+            event_id,
         }
     }
 
@@ -31,39 +46,59 @@ impl Event {
         self.dst.clone()
     }
 
+    pub fn src(&self) -> &Arc<Host> {
+        &self.src
+    }
+
+    pub fn task(&self) -> &Task {
+        &self.task
+    }
+
+    pub fn event_id(&self) -> EventId {
+        self.event_id
+    }
+
+    pub fn time(&self) -> SimulationTime {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: SimulationTime) {
+        self.time = time;
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.src.id() == self.dst.id()
+    }
+
     pub fn execute(&self) {
         Worker::set_active_host(self.dst.clone());
 
-        // self.task.execute(self.host())
+        self.task.execute(self.host());
+
+        Worker::clear_active_host();
     }
 }
 
 impl PartialEq for Event {
     fn eq(&self, other: &Self) -> bool {
-        self.partial_cmp(&other) == Some(core::cmp::Ordering::Equal)
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-impl PartialOrd for Event {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let ord = self.time.partial_cmp(&other.time);
-
-        if ord != Some(core::cmp::Ordering::Equal) {
-            return ord;
-        }
+impl Eq for Event {}
 
-        let ord = self.dst.partial_cmp(&other.dst);
-
-        if ord != Some(core::cmp::Ordering::Equal) {
-            return ord;
-        }
-
-        let ord = self.src.partial_cmp(&other.src);
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        if ord != Some(core::cmp::Ordering::Equal) {
-            return ord;
-        }
-        ord
-        //self.event_id.partial_cmp(&other.event_id)
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| self.dst.id().cmp(&other.dst.id()))
+            .then_with(|| self.src.id().cmp(&other.src.id()))
+            .then_with(|| self.event_id.cmp(&other.event_id))
     }
 }
@@ -1,16 +1,196 @@
-use std::{collections::HashMap, sync::Arc};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::config::{ConfigError, NetworkConfig};
+use crate::net::IpCidr;
 use crate::units::{Bits, Fraction, TimeInterval};
 
-pub struct Topology;
+pub struct Topology {
+    network: Network,
+    use_shortest_path: bool,
+}
+
+impl Topology {
+    pub fn new(network: Network, use_shortest_path: bool) -> Self {
+        Self {
+            network,
+            use_shortest_path,
+        }
+    }
+
+    pub fn path(&self, src: NodeId, dst: NodeId) -> Option<Path> {
+        if self.use_shortest_path {
+            self.network.shortest_path(src, dst)
+        } else {
+            self.network.direct_path(src, dst)
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<Arc<Node>> {
+        self.network.node(id)
+    }
+
+    /// Counts a packet of `bytes` on every edge of the path it travels.
+    pub fn count_traffic(&self, path: &Path, bytes: u64) {
+        for hop in path.path.windows(2) {
+            if let Some(edge) = self.network.edge(*hop[0], *hop[1]) {
+                edge.packets.fetch_add(1, Ordering::Relaxed);
+                edge.bytes.fetch_add(bytes, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The traffic every edge carried in either direction, ordered by source and target.
+    pub fn edge_traffic(&self) -> Vec<EdgeTraffic> {
+        let mut traffic: Vec<_> = self
+            .network
+            .edges
+            .values()
+            .map(|edge| EdgeTraffic {
+                source: edge.src,
+                target: edge.dst,
+                packets: edge.packets.load(Ordering::Relaxed),
+                bytes: edge.bytes.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        traffic.sort_by_key(|edge| (edge.source, edge.target));
+        traffic
+    }
+
+    pub fn min_latency(&self) -> Option<TimeInterval> {
+        self.network
+            .edges
+            .values()
+            .map(|edge| edge.latency)
+            .filter(|latency| Duration::from(*latency) > Duration::ZERO)
+            .min()
+    }
+}
+
+impl TryFrom<&NetworkConfig> for Topology {
+    type Error = ConfigError;
+
+    fn try_from(config: &NetworkConfig) -> Result<Self, Self::Error> {
+        let mut network = Network::default();
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+        for node in &config.graph.node {
+            let id = NodeId::try_from(node.id)?;
+
+            if let Some(pool) = node.ip_addr_pool {
+                let invalid = |reason: &str| {
+                    ConfigError::Invalid(format!(
+                        "ip address pool `{}` of graph node `{}` {}",
+                        pool,
+                        isize::from(id),
+                        reason
+                    ))
+                };
+
+                if pool.host_bits() > IpCidr::MAX_POOL_HOST_BITS {
+                    return Err(invalid("is larger than a /64"));
+                }
+                if !pool.has_assignable() {
+                    return Err(invalid(
+                        "holds only private, reserved or loopback addresses, which hosts cannot have",
+                    ));
+                }
+            }
+            let node = Node {
+                id,
+                bandwidth_down: node.host_bandwidth_down,
+                bandwidth_up: node.host_bandwidth_up,
+                ip_addr_pool: node.ip_addr_pool,
+            };
+
+            if network.nodes.insert(id, Arc::new(node)).is_some() {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate graph node `{}`",
+                    isize::from(id)
+                )));
+            }
+        }
+
+        for edge in &config.graph.edge {
+            let src = NodeId::try_from(edge.source)?;
+            let dst = NodeId::try_from(edge.target)?;
+
+            for id in [src, dst] {
+                if network.node(id).is_none() {
+                    return Err(ConfigError::Invalid(format!(
+                        "graph edge references unknown node `{}`",
+                        isize::from(id)
+                    )));
+                }
+            }
+
+            let edge = Edge {
+                src,
+                dst,
+                latency: edge.latency,
+                jitter: edge.jitter,
+                loss: edge.packet_loss,
+                packets: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+            };
+            network.edges.insert((src, dst), Arc::new(edge));
+        }
+
+        Ok(Topology::new(network, config.use_shortest_path))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct NodeId(isize);
 
+impl From<isize> for NodeId {
+    fn from(value: isize) -> Self {
+        NodeId(value)
+    }
+}
+
+impl From<NodeId> for isize {
+    fn from(value: NodeId) -> Self {
+        value.0
+    }
+}
+
+impl TryFrom<u64> for NodeId {
+    type Error = ConfigError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        isize::try_from(value)
+            .map(NodeId)
+            .map_err(|_| ConfigError::Invalid(format!("graph node id `{}` out of range", value)))
+    }
+}
+
 pub struct Node {
     id: NodeId,
-    bandwidth_down: Bits,
-    bandwidth_up: Bits,
+    bandwidth_down: Option<Bits>,
+    bandwidth_up: Option<Bits>,
+    ip_addr_pool: Option<IpCidr>,
+}
+
+impl Node {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn bandwidth_down(&self) -> Option<Bits> {
+        self.bandwidth_down
+    }
+
+    pub fn bandwidth_up(&self) -> Option<Bits> {
+        self.bandwidth_up
+    }
+
+    pub fn ip_addr_pool(&self) -> Option<IpCidr> {
+        self.ip_addr_pool
+    }
 }
 
 pub struct Edge {
@@ -19,20 +199,104 @@ pub struct Edge {
     latency: TimeInterval,
     jitter: TimeInterval,
     loss: Fraction,
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// Packets and bytes an edge carried since the simulation started.
+pub struct EdgeTraffic {
+    pub source: NodeId,
+    pub target: NodeId,
+    pub packets: u64,
+    pub bytes: u64,
 }
 
+#[derive(Default)]
 pub struct Network {
     nodes: HashMap<NodeId, Arc<Node>>,
     edges: HashMap<(NodeId, NodeId), Arc<Edge>>,
 }
 
 impl Network {
-    fn node(&self, id: NodeId) -> Option<Arc<Node>> {
+    pub fn node(&self, id: NodeId) -> Option<Arc<Node>> {
         self.nodes.get(&id).map(|node| node.clone())
     }
 
     fn edge(&self, src: NodeId, dst: NodeId) -> Option<Arc<Edge>> {
-        self.edges.get(&(src, dst)).map(|edge| edge.clone())
+        self.edges
+            .get(&(src, dst))
+            .or_else(|| self.edges.get(&(dst, src)))
+            .map(|edge| edge.clone())
+    }
+
+    fn neighbours(&self, id: NodeId) -> impl Iterator<Item = Arc<Edge>> + '_ {
+        self.edges.values().filter_map(move |edge| {
+            if edge.src == id {
+                Some(edge.clone())
+            } else if edge.dst == id {
+                Some(Arc::new(Edge {
+                    src: edge.dst,
+                    dst: edge.src,
+                    latency: edge.latency,
+                    jitter: edge.jitter,
+                    loss: edge.loss,
+                    packets: AtomicU64::new(0),
+                    bytes: AtomicU64::new(0),
+                }))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn direct_path(&self, src: NodeId, dst: NodeId) -> Option<Path> {
+        self.edge(src, dst)
+            .map(|edge| Path::from_edges(src, &[edge]))
+    }
+
+    fn shortest_path(&self, src: NodeId, dst: NodeId) -> Option<Path> {
+        if src == dst {
+            return self.direct_path(src, dst);
+        }
+
+        let mut latencies: HashMap<NodeId, Duration> = HashMap::new();
+        let mut previous: HashMap<NodeId, Arc<Edge>> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        latencies.insert(src, Duration::ZERO);
+        queue.push(Reverse((Duration::ZERO, src)));
+
+        while let Some(Reverse((latency, node))) = queue.pop() {
+            if node == dst {
+                break;
+            }
+
+            if matches!(latencies.get(&node), Some(best) if latency > *best) {
+                continue;
+            }
+
+            for edge in self.neighbours(node) {
+                let candidate = latency + Duration::from(edge.latency);
+
+                if !matches!(latencies.get(&edge.dst), Some(best) if candidate >= *best) {
+                    latencies.insert(edge.dst, candidate);
+                    queue.push(Reverse((candidate, edge.dst)));
+                    previous.insert(edge.dst, edge);
+                }
+            }
+        }
+
+        let mut edges = Vec::new();
+        let mut node = dst;
+
+        while node != src {
+            let edge = previous.get(&node)?.clone();
+            node = edge.src;
+            edges.push(edge);
+        }
+
+        edges.reverse();
+        Some(Path::from_edges(src, &edges))
     }
 }
 
@@ -42,6 +306,40 @@ pub struct Path {
     loss: Fraction,
 }
 
+impl Path {
+    fn from_edges(src: NodeId, edges: &[Arc<Edge>]) -> Self {
+        let path = std::iter::once(src)
+            .chain(edges.iter().map(|edge| edge.dst))
+            .map(Arc::new)
+            .collect();
+        let latency = edges
+            .iter()
+            .map(|edge| Duration::from(edge.latency))
+            .sum::<Duration>()
+            .into();
+        let delivered = edges
+            .iter()
+            .map(|edge| 1f64 - f64::from(edge.loss))
+            .product::<f64>();
+        let loss = Fraction::try_from(((1f64 - delivered) * 100f64).round() as u64)
+            .unwrap_or_else(|_| unreachable!());
+
+        Self {
+            path,
+            latency,
+            loss,
+        }
+    }
+
+    pub fn latency(&self) -> TimeInterval {
+        self.latency
+    }
+
+    pub fn loss(&self) -> Fraction {
+        self.loss
+    }
+}
+
 impl PartialEq for Path {
     fn eq(&self, other: &Self) -> bool {
         self.partial_cmp(other) == Some(core::cmp::Ordering::Equal)
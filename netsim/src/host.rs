@@ -1,8 +1,22 @@
-use std::{net::IpAddr, sync::Arc};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+use log::{Level, LevelFilter};
+use rand::prelude::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::event::EventId;
+use crate::graph::NodeId;
+use crate::logger;
+use crate::net::{AddressFamily, Interface, InterfaceStats, Protocol};
+use crate::pcap::PcapWriter;
 use crate::task::Task;
+use crate::units::Bits;
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct HostId(isize);
 
 impl From<isize> for HostId {
@@ -17,25 +31,61 @@ impl From<HostId> for isize {
     }
 }
 
-pub struct HostParams {}
+pub struct HostParams {
+    pub id: HostId,
+    pub name: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub node_id: NodeId,
+    pub bandwidth_down: Bits,
+    pub bandwidth_up: Bits,
+    pub seed: u64,
+    pub log_level: LevelFilter,
+    pub log_file: Option<File>,
+    pub pcap: Option<PcapWriter>,
+}
 
 pub struct HostInfo {
     id: HostId,
     name: String,
-    ip: IpAddr,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    node_id: NodeId,
 }
 
 pub struct Host {
     info: Arc<HostInfo>,
+    interface: Arc<Mutex<Interface>>,
+    random: Mutex<SmallRng>,
+    event_counter: AtomicI64,
+    retransmissions: AtomicU64,
+    log_level: LevelFilter,
+    log: Option<Mutex<BufWriter<File>>>,
 }
 
 impl Host {
-    fn execute(&self, task: Task) {}
+    pub fn new(params: HostParams) -> Self {
+        let interface = Interface::new(params.bandwidth_up, params.bandwidth_down, params.pcap);
 
-    pub fn info(&self) -> Arc<HostInfo> {
-        self.info.clone()
+        Self {
+            info: Arc::new(HostInfo {
+                id: params.id,
+                name: params.name,
+                ipv4: params.ipv4,
+                ipv6: params.ipv6,
+                node_id: params.node_id,
+            }),
+            interface: Arc::new(Mutex::new(interface)),
+            random: Mutex::new(SmallRng::seed_from_u64(params.seed)),
+            event_counter: AtomicI64::new(0),
+            retransmissions: AtomicU64::new(0),
+            log_level: params.log_level,
+            log: params.log_file.map(|file| Mutex::new(BufWriter::new(file))),
+        }
     }
 
+    fn execute(&self, task: Task) {}
+
     pub fn name(&self) -> &str {
         &self.info.name
     }
@@ -45,7 +95,131 @@ impl Host {
     }
 
     pub fn ip(&self) -> IpAddr {
-        self.info.ip
+        self.info
+            .ipv4
+            .map(IpAddr::V4)
+            .or_else(|| self.info.ipv6.map(IpAddr::V6))
+            .expect("host without ip address")
+    }
+
+    pub fn ip_for(&self, family: AddressFamily) -> Option<IpAddr> {
+        match family {
+            AddressFamily::Inet => self.info.ipv4.map(IpAddr::V4),
+            AddressFamily::Inet6 => self.info.ipv6.map(IpAddr::V6),
+        }
+    }
+
+    pub fn has_ip(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.info.ipv4 == Some(ip),
+            IpAddr::V6(ip) => self.info.ipv6 == Some(ip),
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.info.node_id
+    }
+
+    pub fn interface(&self) -> Arc<Mutex<Interface>> {
+        self.interface.clone()
+    }
+
+    pub fn random_f64(&self) -> f64 {
+        self.random
+            .lock()
+            .expect("accessed poisoned host random source")
+            .gen()
+    }
+
+    pub fn fill_random(&self, bytes: &mut [u8]) {
+        self.random
+            .lock()
+            .expect("accessed poisoned host random source")
+            .fill_bytes(bytes)
+    }
+
+    pub fn new_event_id(&self) -> EventId {
+        self.event_counter.fetch_add(1, Ordering::Relaxed).into()
+    }
+
+    pub fn count_retransmission(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> InterfaceStats {
+        self.interface
+            .lock()
+            .expect("accessed poisoned interface")
+            .stats()
+    }
+
+    /// Writes the traffic of the host so far and the state of its sockets and queues to its
+    /// log, without echoing it to stderr like other records.
+    pub fn heartbeat(&self) {
+        let message = {
+            let interface = self.interface.lock().expect("accessed poisoned interface");
+            let stats = interface.stats();
+
+            format!(
+                "heartbeat packets_sent={} bytes_sent={} packets_received={} bytes_received={} \
+                 packets_dropped={} packets_overflowed={} retransmissions={} tcp_sockets={} \
+                 udp_sockets={} send_queue={} receive_queue={}",
+                stats.packets_sent,
+                stats.bytes_sent,
+                stats.packets_received,
+                stats.bytes_received,
+                stats.packets_dropped,
+                stats.packets_overflowed,
+                self.retransmissions(),
+                interface.socket_count(Protocol::Tcp),
+                interface.socket_count(Protocol::Udp),
+                interface.send_queue_len(),
+                interface.recv_queue_len(),
+            )
+        };
+
+        let line = logger::format(
+            &log::Record::builder()
+                .level(Level::Info)
+                .target(module_path!())
+                .args(format_args!("{}", message))
+                .build(),
+        );
+        self.log(Level::Info, &line);
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level
+    }
+
+    /// Appends a formatted record to the log of the host if the level of the host enables it.
+    /// Failing writes are dropped, as there is nowhere left to report them.
+    pub fn log(&self, level: Level, line: &str) {
+        if level > self.log_level {
+            return;
+        }
+
+        if let Some(log) = &self.log {
+            let mut log = log.lock().expect("accessed poisoned host log");
+            let _ = writeln!(log, "{}", line);
+        }
+    }
+
+    pub fn flush_pcap(&self) {
+        self.interface
+            .lock()
+            .expect("accessed poisoned interface")
+            .flush_pcap();
+    }
+
+    pub fn flush_log(&self) {
+        if let Some(log) = &self.log {
+            let _ = log.lock().expect("accessed poisoned host log").flush();
+        }
     }
 }
 
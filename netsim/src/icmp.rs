@@ -0,0 +1,544 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::host::Host;
+use crate::net::{checksum, Packet, Protocol};
+use crate::time::SimulationTime;
+use crate::worker::Worker;
+
+const ECHO_REPLY_V4: u8 = 0;
+const UNREACHABLE_V4: u8 = 3;
+const ECHO_REQUEST_V4: u8 = 8;
+
+const UNREACHABLE_V6: u8 = 1;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+const HEADER_SIZE: usize = 8;
+const QUOTED_TRANSPORT_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unreachable {
+    Host,
+    Port,
+}
+
+impl Unreachable {
+    fn code(&self, ip: IpAddr) -> u8 {
+        match (self, ip) {
+            (Unreachable::Host, IpAddr::V4(_)) => 1,
+            (Unreachable::Port, IpAddr::V4(_)) => 3,
+            (Unreachable::Host, IpAddr::V6(_)) => 3,
+            (Unreachable::Port, IpAddr::V6(_)) => 4,
+        }
+    }
+
+    fn from_code(code: u8, ip: IpAddr) -> Option<Self> {
+        match (code, ip) {
+            (1, IpAddr::V4(_)) | (3, IpAddr::V6(_)) => Some(Unreachable::Host),
+            (3, IpAddr::V4(_)) | (4, IpAddr::V6(_)) => Some(Unreachable::Port),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IcmpMessage {
+    EchoRequest {
+        identifier: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    EchoReply {
+        identifier: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    Unreachable {
+        reason: Unreachable,
+        original: Vec<u8>,
+    },
+}
+
+impl IcmpMessage {
+    pub fn unreachable(reason: Unreachable, original: &Packet) -> Self {
+        let mut quoted = original.ip_header();
+        quoted.extend(
+            original
+                .transport_header()
+                .into_iter()
+                .chain(original.payload().iter().copied())
+                .take(QUOTED_TRANSPORT_SIZE),
+        );
+
+        IcmpMessage::Unreachable {
+            reason,
+            original: quoted,
+        }
+    }
+
+    pub fn encode(&self, src: IpAddr, dst: IpAddr) -> Vec<u8> {
+        let is_v4 = dst.is_ipv4();
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+
+        match self {
+            IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data,
+            }
+            | IcmpMessage::EchoReply {
+                identifier,
+                sequence,
+                data,
+            } => {
+                let kind = match (self, is_v4) {
+                    (IcmpMessage::EchoRequest { .. }, true) => ECHO_REQUEST_V4,
+                    (IcmpMessage::EchoRequest { .. }, false) => ECHO_REQUEST_V6,
+                    (_, true) => ECHO_REPLY_V4,
+                    (_, false) => ECHO_REPLY_V6,
+                };
+                bytes.extend([kind, 0, 0, 0]);
+                bytes.extend(identifier.to_be_bytes());
+                bytes.extend(sequence.to_be_bytes());
+                bytes.extend(data);
+            }
+            IcmpMessage::Unreachable { reason, original } => {
+                let kind = if is_v4 {
+                    UNREACHABLE_V4
+                } else {
+                    UNREACHABLE_V6
+                };
+                bytes.extend([kind, reason.code(dst), 0, 0, 0, 0, 0, 0]);
+                bytes.extend(original);
+            }
+        }
+
+        let sum = match (src, dst) {
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let mut pseudo = Vec::with_capacity(40 + bytes.len());
+                pseudo.extend(src.octets());
+                pseudo.extend(dst.octets());
+                pseudo.extend((bytes.len() as u32).to_be_bytes());
+                pseudo.extend([0, 0, 0, 58]);
+                pseudo.extend(&bytes);
+                checksum(&pseudo)
+            }
+            _ => checksum(&bytes),
+        };
+        bytes[2..4].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8], dst: IpAddr) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let identifier = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let sequence = u16::from_be_bytes([bytes[6], bytes[7]]);
+        let data = bytes[HEADER_SIZE..].to_vec();
+
+        match (bytes[0], dst) {
+            (ECHO_REQUEST_V4, IpAddr::V4(_)) | (ECHO_REQUEST_V6, IpAddr::V6(_)) => {
+                Some(IcmpMessage::EchoRequest {
+                    identifier,
+                    sequence,
+                    data,
+                })
+            }
+            (ECHO_REPLY_V4, IpAddr::V4(_)) | (ECHO_REPLY_V6, IpAddr::V6(_)) => {
+                Some(IcmpMessage::EchoReply {
+                    identifier,
+                    sequence,
+                    data,
+                })
+            }
+            (UNREACHABLE_V4, IpAddr::V4(_)) | (UNREACHABLE_V6, IpAddr::V6(_)) => {
+                Some(IcmpMessage::Unreachable {
+                    reason: Unreachable::from_code(bytes[1], dst)?,
+                    original: data,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct Quoted {
+    pub protocol: Protocol,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+impl Quoted {
+    pub fn parse(original: &[u8]) -> Option<Self> {
+        let (header_size, protocol, src, dst): (usize, u8, IpAddr, IpAddr) =
+            match original.first()? >> 4 {
+                4 if original.len() >= 20 => {
+                    let src: [u8; 4] = original[12..16].try_into().ok()?;
+                    let dst: [u8; 4] = original[16..20].try_into().ok()?;
+                    let header_size = usize::from(original[0] & 0x0f) * 4;
+                    (header_size, original[9], src.into(), dst.into())
+                }
+                6 if original.len() >= 40 => {
+                    let src: [u8; 16] = original[8..24].try_into().ok()?;
+                    let dst: [u8; 16] = original[24..40].try_into().ok()?;
+                    (40, original[6], src.into(), dst.into())
+                }
+                _ => return None,
+            };
+
+        let ports = original.get(header_size..header_size + 4)?;
+        let protocol = Protocol::from_number(protocol)?;
+
+        Some(Self {
+            protocol,
+            src: SocketAddr::new(src, u16::from_be_bytes([ports[0], ports[1]])),
+            dst: SocketAddr::new(dst, u16::from_be_bytes([ports[2], ports[3]])),
+        })
+    }
+}
+
+struct Reply {
+    src: IpAddr,
+    message: IcmpMessage,
+    arrival: SimulationTime,
+}
+
+struct IcmpSocketState {
+    identifier: Option<u16>,
+    recv_queue: VecDeque<Reply>,
+}
+
+#[derive(Clone)]
+pub struct IcmpSocket(Arc<Mutex<IcmpSocketState>>);
+
+impl IcmpSocket {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(IcmpSocketState {
+            identifier: None,
+            recv_queue: VecDeque::new(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, IcmpSocketState> {
+        self.0.lock().expect("accessed poisoned icmp socket")
+    }
+
+    pub fn bind(&self, host: &Host) -> io::Result<u16> {
+        if let Some(identifier) = self.state().identifier {
+            return Ok(identifier);
+        }
+
+        let interface = host.interface();
+        let mut interface = interface.lock().expect("accessed poisoned interface");
+        let identifier = interface
+            .ephemeral_port(Protocol::Icmp)
+            .ok_or(io::ErrorKind::AddrInUse)?;
+
+        interface.associate(Protocol::Icmp, identifier, self.clone().into());
+        self.state().identifier = Some(identifier);
+        Ok(identifier)
+    }
+
+    pub fn send_echo(
+        &self,
+        host: &Arc<Host>,
+        dst: IpAddr,
+        sequence: u16,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        let identifier = self.bind(host)?;
+        let src = if dst.is_loopback() {
+            dst
+        } else {
+            host.ip_for(dst.into())
+                .ok_or(io::ErrorKind::AddrNotAvailable)?
+        };
+        let message = IcmpMessage::EchoRequest {
+            identifier,
+            sequence,
+            data,
+        };
+        let packet = Packet::icmp(src, dst, identifier, &message);
+
+        host.interface()
+            .lock()
+            .expect("accessed poisoned interface")
+            .send(host, packet);
+
+        Ok(())
+    }
+
+    pub fn recv(&self) -> io::Result<(IpAddr, IcmpMessage, SimulationTime)> {
+        self.state()
+            .recv_queue
+            .pop_front()
+            .map(|reply| (reply.src, reply.message, reply.arrival))
+            .ok_or_else(|| io::ErrorKind::WouldBlock.into())
+    }
+
+    pub fn close(&self, host: &Host) {
+        let identifier = self.state().identifier.take();
+
+        if let Some(identifier) = identifier {
+            host.interface()
+                .lock()
+                .expect("accessed poisoned interface")
+                .disassociate(Protocol::Icmp, identifier);
+        }
+    }
+
+    pub(crate) fn push_in_message(&self, src: IpAddr, message: IcmpMessage) {
+        let arrival = Worker::current_time()
+            .expect("Current time not set for worker")
+            .into();
+
+        self.state().recv_queue.push_back(Reply {
+            src,
+            message,
+            arrival,
+        });
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PingStatistics {
+    pub transmitted: u64,
+    pub received: u64,
+    pub unreachable: u64,
+    pub rtts: Vec<SimulationTime>,
+}
+
+impl PingStatistics {
+    pub fn loss(&self) -> f64 {
+        if self.transmitted == 0 {
+            return 0f64;
+        }
+
+        1f64 - self.received as f64 / self.transmitted as f64
+    }
+
+    pub fn min(&self) -> Option<SimulationTime> {
+        self.rtts.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<SimulationTime> {
+        self.rtts.iter().max().copied()
+    }
+
+    pub fn mean(&self) -> Option<SimulationTime> {
+        let count = self.rtts.len() as i64;
+        let total: i64 = self.rtts.iter().map(SimulationTime::as_nanos).sum();
+
+        (count > 0).then(|| SimulationTime::from_nanos(total / count))
+    }
+}
+
+/// A reply to an echo request, or a notice that the target cannot be reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PingEvent {
+    Reply {
+        src: IpAddr,
+        sequence: u16,
+        rtt: SimulationTime,
+    },
+    Unreachable {
+        src: IpAddr,
+    },
+}
+
+/// Sends `count` echo requests to a target, one per tick, and collects the replies arriving
+/// in between. Requests without a reply within the timeout count as lost.
+pub struct Ping {
+    socket: IcmpSocket,
+    target: IpAddr,
+    count: u16,
+    timeout: SimulationTime,
+    sequence: u16,
+    pending: HashMap<u16, SimulationTime>,
+    statistics: PingStatistics,
+}
+
+impl Ping {
+    const PAYLOAD_SIZE: usize = 56;
+
+    pub fn new(target: IpAddr, count: u16, timeout: SimulationTime) -> Self {
+        Self {
+            socket: IcmpSocket::new(),
+            target,
+            count,
+            timeout,
+            sequence: 0,
+            pending: HashMap::new(),
+            statistics: PingStatistics::default(),
+        }
+    }
+
+    pub fn target(&self) -> IpAddr {
+        self.target
+    }
+
+    /// Binds the socket the echo requests are sent from and the replies arrive at.
+    pub fn start(&mut self, host: &Host) -> io::Result<()> {
+        self.socket.bind(host).map(|_| ())
+    }
+
+    pub fn statistics(&self) -> &PingStatistics {
+        &self.statistics
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sequence >= self.count && self.pending.is_empty()
+    }
+
+    /// Collects the replies that arrived since the previous tick, gives up on requests older
+    /// than the timeout and sends the next request, if any is left.
+    pub fn tick(&mut self, host: &Arc<Host>) -> Vec<PingEvent> {
+        let now: SimulationTime = Worker::current_time()
+            .expect("Current time not set for worker")
+            .into();
+
+        let events = self.collect_replies();
+
+        let timeout = self.timeout;
+        self.pending.retain(|_, sent| now - *sent < timeout);
+
+        if self.sequence < self.count {
+            let sequence = self.sequence;
+            let data = vec![0u8; Self::PAYLOAD_SIZE];
+
+            match self.socket.send_echo(host, self.target, sequence, data) {
+                Ok(()) => {
+                    self.pending.insert(sequence, now);
+                    self.statistics.transmitted += 1;
+                }
+                Err(err) => log::warn!("{}: ping {} failed: {}", host.name(), self.target, err),
+            }
+
+            self.sequence += 1;
+        }
+
+        events
+    }
+
+    pub fn close(&self, host: &Host) {
+        self.socket.close(host);
+    }
+
+    fn collect_replies(&mut self) -> Vec<PingEvent> {
+        let mut events = Vec::new();
+
+        while let Ok((src, message, arrival)) = self.socket.recv() {
+            match message {
+                IcmpMessage::EchoReply { sequence, .. } => {
+                    if let Some(sent) = self.pending.remove(&sequence) {
+                        let rtt = arrival - sent;
+                        self.statistics.received += 1;
+                        self.statistics.rtts.push(rtt);
+                        events.push(PingEvent::Reply { src, sequence, rtt });
+                    }
+                }
+                IcmpMessage::Unreachable { .. } => {
+                    self.statistics.unreachable += 1;
+                    events.push(PingEvent::Unreachable { src });
+                }
+                IcmpMessage::EchoRequest { .. } => {}
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn reply(src: IpAddr, sequence: u16, arrival_millis: i64) -> Reply {
+        Reply {
+            src,
+            message: IcmpMessage::EchoReply {
+                identifier: 0,
+                sequence,
+                data: Vec::new(),
+            },
+            arrival: SimulationTime::from_millis(arrival_millis),
+        }
+    }
+
+    #[test]
+    fn statistics_of_replies_and_losses() {
+        let target = IpAddr::V4(Ipv4Addr::new(11, 0, 0, 2));
+        let mut ping = Ping::new(target, 4, SimulationTime::from_millis(10_000));
+
+        for sequence in 0..4 {
+            let sent = SimulationTime::from_millis(i64::from(sequence) * 1_000);
+            ping.pending.insert(sequence, sent);
+        }
+        ping.sequence = 4;
+        ping.statistics.transmitted = 4;
+
+        {
+            let mut state = ping.socket.state();
+            state.recv_queue.push_back(reply(target, 0, 20));
+            state.recv_queue.push_back(reply(target, 1, 1_040));
+            state.recv_queue.push_back(reply(target, 3, 3_030));
+            // Duplicates and replies to unknown requests count once and not at all.
+            state.recv_queue.push_back(reply(target, 3, 3_050));
+            state.recv_queue.push_back(reply(target, 7, 3_060));
+        }
+
+        let events = ping.collect_replies();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[1],
+            PingEvent::Reply {
+                src: target,
+                sequence: 1,
+                rtt: SimulationTime::from_millis(40),
+            }
+        );
+
+        let statistics = ping.statistics();
+        assert_eq!(statistics.transmitted, 4);
+        assert_eq!(statistics.received, 3);
+        assert_eq!(statistics.loss(), 0.25);
+        assert_eq!(statistics.min(), Some(SimulationTime::from_millis(20)));
+        assert_eq!(statistics.mean(), Some(SimulationTime::from_millis(30)));
+        assert_eq!(statistics.max(), Some(SimulationTime::from_millis(40)));
+        assert!(!ping.is_finished());
+
+        ping.pending.clear();
+        assert!(ping.is_finished());
+    }
+
+    #[test]
+    fn statistics_without_requests() {
+        let statistics = PingStatistics::default();
+
+        assert_eq!(statistics.loss(), 0.0);
+        assert_eq!(statistics.min(), None);
+        assert_eq!(statistics.mean(), None);
+    }
+
+    #[test]
+    fn echo_round_trip() {
+        let src = IpAddr::V4(Ipv4Addr::new(11, 0, 0, 1));
+        let dst = IpAddr::V4(Ipv4Addr::new(11, 0, 0, 2));
+        let request = IcmpMessage::EchoRequest {
+            identifier: 7,
+            sequence: 3,
+            data: vec![1, 2, 3],
+        };
+
+        let bytes = request.encode(src, dst);
+        assert_eq!(checksum(&bytes), 0);
+        assert_eq!(IcmpMessage::decode(&bytes, dst), Some(request));
+    }
+}
@@ -0,0 +1,36 @@
+//! Stopping the simulation early on SIGINT, so that a run cut short still leaves complete
+//! output behind. A second SIGINT exits right away.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Exit status of a run interrupted twice, as after a default SIGINT.
+const EXIT_STATUS: libc::c_int = 128 + libc::SIGINT;
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(EXIT_STATUS) };
+    }
+}
+
+/// Replaces the default handling of SIGINT, which would end the simulator on the spot.
+pub fn install() -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
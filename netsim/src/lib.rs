@@ -7,16 +7,32 @@ use std::fmt::{Debug, Display};
 use std::ops::Range;
 
 pub mod app;
+mod apps;
 pub mod cli;
 mod config;
+mod descriptor;
+mod dns;
 mod event;
 mod graph;
 mod host;
+mod icmp;
+mod interrupt;
+mod logger;
 mod net;
+mod pcap;
+mod process;
 mod processor;
+mod progress;
 mod sim;
+mod simapp;
+mod summary;
+mod syscall;
 mod task;
+mod tcp;
+mod template;
 mod time;
+mod trace;
+mod udp;
 mod units;
 mod worker;
 
@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::config::Config;
+use crate::progress;
+use crate::worker::Worker;
+
+/// Size at which the buffered records of a worker are written to stderr.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Age at which the buffered records of a worker are written to stderr with the next record.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+}
+
+std::thread_local! {
+    static BUFFER: RefCell<Buffer> = RefCell::new(Buffer::new());
+}
+
+/// Records of a worker not yet written to stderr, so that workers do not contend for it on
+/// every record.
+struct Buffer {
+    lines: String,
+    flushed: Instant,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Self {
+            lines: String::new(),
+            flushed: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, line: &str, level: Level) {
+        self.lines.push_str(line);
+        self.lines.push('\n');
+
+        if level <= Level::Warn
+            || self.lines.len() >= BUFFER_SIZE
+            || self.flushed.elapsed() >= FLUSH_INTERVAL
+        {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut stderr = io::stderr().lock();
+        progress::clear(&mut stderr);
+        let _ = stderr.write_all(self.lines.as_bytes());
+        self.lines.clear();
+        self.flushed = Instant::now();
+    }
+}
+
+/// Writes records to stderr up to the general log level. Records emitted while an event of a
+/// host executes are filtered by the level of the host instead, and also go to its log.
+///
+/// Every record starts with the wall-clock time since the start, the emulated time, the worker
+/// and the active host, with `-` for those not known when it was emitted:
+///
+/// ```text
+/// 00:00:01.024731 2000-01-01 00:00:10.250000000 [worker-0] [client] INFO  [netsim::simapp] ...
+/// ```
+pub struct Logger {
+    level: LevelFilter,
+}
+
+impl Logger {
+    pub fn init(config: &Config) -> Result<(), SetLoggerError> {
+        let level = config.general.log_level;
+        let max_level = config
+            .hosts
+            .iter()
+            .filter_map(|host| host.options.log_level)
+            .chain(config.host_defaults.log_level)
+            .fold(level, Ord::max);
+
+        lazy_static::initialize(&STARTED);
+        log::set_boxed_logger(Box::new(Logger { level }))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = Worker::with_active_host(|host| host.log_level()).unwrap_or(self.level);
+        metadata.level() <= level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format(record);
+        Worker::with_active_host(|host| host.log(record.level(), &line));
+
+        let _ = BUFFER.try_with(|buffer| buffer.borrow_mut().push(&line, record.level()));
+    }
+
+    /// Writes the buffered records of the calling worker to stderr.
+    fn flush(&self) {
+        let _ = BUFFER.try_with(|buffer| buffer.borrow_mut().flush());
+    }
+}
+
+/// Formats a record as a line of the logs, without the line break.
+pub fn format(record: &Record) -> String {
+    let wall = STARTED.elapsed();
+    let secs = wall.as_secs();
+    let emulated = Worker::current_time()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%.9f"))
+        .unwrap_or_else(|| "-".to_owned());
+    let worker = Worker::worker_id()
+        .map(|id| format!("worker-{}", u32::from(id)))
+        .unwrap_or_else(|| "-".to_owned());
+    let host =
+        Worker::with_active_host(|host| host.name().to_owned()).unwrap_or_else(|| "-".to_owned());
+
+    format!(
+        "{:02}:{:02}:{:02}.{:06} {} [{}] [{}] {:<5} [{}] {}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        wall.subsec_micros(),
+        emulated,
+        worker,
+        host,
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
@@ -1,12 +1,49 @@
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+use serde::Deserialize;
+
+use crate::dns;
 use crate::host::Host;
+use crate::icmp::{IcmpMessage, IcmpSocket, Quoted, Unreachable};
+use crate::pcap::PcapWriter;
+use crate::task::Task;
+use crate::tcp::{self, Segment, TcpSocket};
 use crate::time::SimulationTime;
+use crate::trace::{DropReason, PacketState};
+use crate::udp::UdpSocket;
+use crate::units::Bits;
 use crate::worker::Worker;
 
+const MTU: u64 = 1500;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const DEFAULT_TTL: u8 = 64;
+
+/// Id of the next packet created, which the trace follows packets by.
+static NEXT_PACKET_ID: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum = bytes
+        .chunks(2)
+        .map(|chunk| match *chunk {
+            [hi, lo] => u32::from(u16::from_be_bytes([hi, lo])),
+            [hi] => u32::from(u16::from_be_bytes([hi, 0])),
+            _ => 0,
+        })
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
 mod ipv4 {
 
     use std::net::Ipv4Addr;
@@ -15,101 +52,270 @@ mod ipv4 {
         Ipv4Addr::new(a, b, c, d)
     }
 
-    fn in_range(ip: Ipv4Addr, lower: Ipv4Addr, upper: Ipv4Addr) -> bool {
-        ip >= lower && ip <= upper
+    /// Ranges of addresses hosts cannot have, from the first to the last address.
+    const RESTRICTED: [(Ipv4Addr, Ipv4Addr); 14] = [
+        // Current network
+        (addr(0, 0, 0, 0), addr(0, 255, 255, 255)),
+        // Private
+        (addr(10, 0, 0, 0), addr(10, 255, 255, 255)),
+        (addr(172, 16, 0, 0), addr(172, 31, 255, 255)),
+        (addr(192, 168, 0, 0), addr(192, 168, 255, 255)),
+        // Shared
+        (addr(100, 64, 0, 0), addr(100, 127, 255, 255)),
+        // Link local
+        (addr(169, 254, 0, 0), addr(169, 254, 255, 255)),
+        // IETF protocol assignments
+        (addr(192, 0, 0, 0), addr(192, 0, 0, 255)),
+        // Test nets
+        (addr(192, 0, 2, 0), addr(192, 0, 2, 255)),
+        (addr(198, 51, 100, 0), addr(198, 51, 100, 255)),
+        (addr(203, 0, 113, 0), addr(203, 0, 113, 255)),
+        (addr(233, 252, 0, 0), addr(233, 252, 0, 255)),
+        // Reserved
+        (addr(192, 88, 99, 0), addr(192, 88, 99, 255)),
+        (addr(240, 0, 0, 0), addr(255, 255, 255, 254)),
+        // Broadcast
+        (addr(255, 255, 255, 255), addr(255, 255, 255, 255)),
+    ];
+
+    /// The restricted range an address falls into.
+    pub(super) fn restricted_range(ip: Ipv4Addr) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        RESTRICTED
+            .iter()
+            .copied()
+            .find(|(lower, upper)| ip >= *lower && ip <= *upper)
     }
+}
+
+mod ipv6 {
+    use std::net::Ipv6Addr;
 
-    fn is_current_network(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(0, 0, 0, 0), addr(0, 255, 255, 255))
+    #[allow(clippy::too_many_arguments)]
+    const fn addr(a: u16, b: u16, c: u16, d: u16, e: u16, f: u16, g: u16, h: u16) -> Ipv6Addr {
+        Ipv6Addr::new(a, b, c, d, e, f, g, h)
     }
-    fn is_private(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(10, 0, 0, 0), addr(10, 255, 255, 255))
-            || in_range(ip, addr(172, 16, 0, 0), addr(172, 31, 255, 255))
-            || in_range(ip, addr(192, 168, 0, 0), addr(192, 168, 255, 255))
+
+    const MAX: u16 = 0xffff;
+
+    /// Ranges of addresses hosts cannot have, from the first to the last address.
+    const RESTRICTED: [(Ipv6Addr, Ipv6Addr); 10] = [
+        // Unspecified
+        (addr(0, 0, 0, 0, 0, 0, 0, 0), addr(0, 0, 0, 0, 0, 0, 0, 0)),
+        // IPv4 mapped
+        (
+            addr(0, 0, 0, 0, 0, 0xffff, 0, 0),
+            addr(0, 0, 0, 0, 0, 0xffff, MAX, MAX),
+        ),
+        // IPv4 translated
+        (
+            addr(0, 0, 0, 0, 0xffff, 0, 0, 0),
+            addr(0, 0, 0, 0, 0xffff, 0, MAX, MAX),
+        ),
+        (
+            addr(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+            addr(0x64, 0xff9b, 0, 0, 0, 0, MAX, MAX),
+        ),
+        // Discard
+        (
+            addr(0x100, 0, 0, 0, 0, 0, 0, 0),
+            addr(0x100, 0, 0, 0, MAX, MAX, MAX, MAX),
+        ),
+        // IETF protocol assignments
+        (
+            addr(0x2001, 0, 0, 0, 0, 0, 0, 0),
+            addr(0x2001, 0x1ff, MAX, MAX, MAX, MAX, MAX, MAX),
+        ),
+        // Documentation
+        (
+            addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            addr(0x2001, 0xdb8, MAX, MAX, MAX, MAX, MAX, MAX),
+        ),
+        // Unique local
+        (
+            addr(0xfc00, 0, 0, 0, 0, 0, 0, 0),
+            addr(0xfdff, MAX, MAX, MAX, MAX, MAX, MAX, MAX),
+        ),
+        // Link local
+        (
+            addr(0xfe80, 0, 0, 0, 0, 0, 0, 0),
+            addr(0xfebf, MAX, MAX, MAX, MAX, MAX, MAX, MAX),
+        ),
+        // Multicast
+        (
+            addr(0xff00, 0, 0, 0, 0, 0, 0, 0),
+            addr(MAX, MAX, MAX, MAX, MAX, MAX, MAX, MAX),
+        ),
+    ];
+
+    /// The restricted range an address falls into.
+    pub(super) fn restricted_range(ip: Ipv6Addr) -> Option<(Ipv6Addr, Ipv6Addr)> {
+        RESTRICTED
+            .iter()
+            .copied()
+            .find(|(lower, upper)| ip >= *lower && ip <= *upper)
     }
+}
 
-    fn is_shared(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(100, 64, 0, 0), addr(100, 127, 255, 255))
+/// The restricted range an address falls into, as indices like those of [`IpCidr::nth`].
+fn restricted_range(ip: IpAddr) -> Option<(u128, u128)> {
+    match ip {
+        IpAddr::V4(addr) => ipv4::restricted_range(addr)
+            .map(|(lower, upper)| (u32::from(lower).into(), u32::from(upper).into())),
+        IpAddr::V6(addr) => {
+            ipv6::restricted_range(addr).map(|(lower, upper)| (lower.into(), upper.into()))
+        }
     }
+}
 
-    fn is_link_local(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(169, 254, 0, 0), addr(169, 254, 255, 255))
+fn is_restricted(ip: IpAddr) -> bool {
+    restricted_range(ip).is_some()
+}
+
+/// The range of addresses hosts cannot be given that an address falls into, either
+/// restricted or loopback.
+fn unassignable_range(ip: IpAddr) -> Option<(u128, u128)> {
+    match ip {
+        IpAddr::V4(addr) if addr.is_loopback() => Some((0x7f00_0000, 0x7fff_ffff)),
+        IpAddr::V6(addr) if addr.is_loopback() => Some((1, 1)),
+        ip => restricted_range(ip),
     }
+}
 
-    fn is_ietf_protocol_assigned(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(192, 0, 0, 0), addr(192, 0, 0, 255))
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Most host bits of a range hosts get addresses from, those of an IPv6 /64.
+    pub const MAX_POOL_HOST_BITS: u32 = 64;
+
+    pub fn family(&self) -> AddressFamily {
+        self.network.into()
     }
 
-    fn is_test_net(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(192, 0, 2, 0), addr(192, 0, 2, 255))
-            || in_range(ip, addr(198, 51, 100, 0), addr(198, 51, 100, 255))
-            || in_range(ip, addr(203, 0, 113, 0), addr(203, 0, 113, 255))
-            || in_range(ip, addr(233, 252, 0, 0), addr(233, 252, 0, 255))
+    pub fn host_bits(&self) -> u32 {
+        let width = match self.network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        width - u32::from(self.prefix)
     }
 
-    fn is_multicast(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(224, 0, 0, 0), addr(239, 255, 255, 255))
+    fn bounds(&self) -> (u128, u128) {
+        let (network, width) = match self.network {
+            IpAddr::V4(ip) => (u128::from(u32::from(ip)), 32),
+            IpAddr::V6(ip) => (u128::from(ip), 128),
+        };
+        let host_bits = width - u32::from(self.prefix);
+        let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+        let first = network & mask;
+        let last = first | !mask & (u128::MAX >> (128 - width));
+
+        (first, last)
     }
 
-    fn is_reserved(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(192, 88, 99, 0), addr(192, 88, 99, 255))
-            || in_range(ip, addr(240, 0, 0, 0), addr(255, 255, 255, 255))
+    /// The first and last address hosts can get from the range, without the network and
+    /// broadcast address of an IPv4 range that has them.
+    fn host_bounds(&self) -> (u128, u128) {
+        let (first, last) = self.bounds();
+
+        match (self.network, self.prefix) {
+            (IpAddr::V4(_), prefix) if prefix < 31 => (first + 1, last - 1),
+            _ => (first, last),
+        }
     }
 
-    fn is_benchmark(ip: Ipv4Addr) -> bool {
-        in_range(ip, addr(198, 18, 0, 0), addr(198, 19, 255, 255))
+    fn nth(&self, index: u128) -> IpAddr {
+        match self.network {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(index as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(index)),
+        }
     }
 
-    fn is_broadcast(ip: Ipv4Addr) -> bool {
-        ip == addr(255, 255, 255, 255)
+    /// The index of the first address from `index` on that hosts can get, skipping restricted
+    /// and loopback ranges as a whole.
+    fn next_assignable(&self, mut index: u128) -> Option<u128> {
+        let (_, last) = self.host_bounds();
+
+        while index <= last {
+            match unassignable_range(self.nth(index)) {
+                Some((_, upper)) => index = upper.checked_add(1)?,
+                None => return Some(index),
+            }
+        }
+
+        None
     }
 
-    pub(super) fn is_restricted(ip: Ipv4Addr) -> bool {
-        is_current_network(ip)
-            || is_private(ip)
-            || is_shared(ip)
-            || is_link_local(ip)
-            || is_ietf_protocol_assigned(ip)
-            || is_test_net(ip)
-            || is_reserved(ip)
-            || is_broadcast(ip)
+    /// Whether hosts can get any address of the range.
+    pub fn has_assignable(&self) -> bool {
+        let (first, _) = self.host_bounds();
+        self.next_assignable(first).is_some()
     }
 }
 
-// mod ipv6 {
-//     use std::net::Ipv6Addr;
+impl FromStr for IpCidr {
+    type Err = InvalidIpCidr;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidIpCidr {
+            span: value.to_owned(),
+        };
+        let (network, prefix) = value.split_once('/').ok_or_else(invalid)?;
+        let network = network.trim().parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix = prefix.trim().parse::<u8>().map_err(|_| invalid())?;
+        let width = if network.is_ipv4() { 32 } else { 128 };
 
-//     fn addr(a: u16, b: u16, c: u16, d: u16, e: u16, f: u16, g: u16, h: u16) -> Ipv6Addr {
-//         Ipv6Addr::new(a, b, c, d, e, f, g, h)
-//     }
+        if prefix > width {
+            return Err(invalid());
+        }
 
-//     fn in_range(ip: Ipv6Addr, lower: Ipv6Addr, upper: Ipv6Addr) -> bool {
-//         ip >= lower && ip <= upper
-//     }
+        Ok(Self { network, prefix })
+    }
+}
 
-//     fn is_unspecified(ip: Ipv6Addr) -> bool {
-//         ip == Ipv6Addr::new(0, 0, 0 , 0, 0, 0, 0, 0)
-//     }
+impl TryFrom<String> for IpCidr {
+    type Error = InvalidIpCidr;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for IpCidr {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}", self.network, self.prefix)
+    }
+}
 
-//     fn is_ipv4_mapped(ip: Ipv6Addr) -> bool {
-//         in_range(ip, addr(0, 0, 0, 0, 0, 0xffff, 0, 0), addr(0, 0, 0, 0, 0, 0xffff, 0xffff, 0xffff))
-//     }
+#[derive(Debug)]
+pub struct InvalidIpCidr {
+    span: String,
+}
 
-//     fn is_ipv4_translated(ip: Ipv6Addr) -> bool {
-//         in_range(ip, addr(0, 0, 0, 0, 0xffff, 0, 0, 0), addr(0, 0, 0, 0, 0xffff, 0, 0xffff, 0xffff))
-//     }
+impl std::error::Error for InvalidIpCidr {}
 
-//     pub(super) fn is_restricted(ip: Ipv6Addr) -> bool {
-//         ip.is_unspecified()
-//             || ip.is
-//     }
+impl Display for InvalidIpCidr {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "invalid cidr (\"{}\")", self.span)
+    }
+}
 
-// }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    Inet,
+    Inet6,
+}
 
-fn is_restricted(ip: IpAddr) -> bool {
-    match ip {
-        IpAddr::V4(addr) => ipv4::is_restricted(addr),
-        IpAddr::V6(_) => false,
+impl From<IpAddr> for AddressFamily {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => AddressFamily::Inet,
+            IpAddr::V6(_) => AddressFamily::Inet6,
+        }
     }
 }
 
@@ -120,12 +326,33 @@ pub struct TokenBucket {
 }
 
 impl TokenBucket {
-    fn consume(&mut self, bytes: u64) {
-        self.remaining.saturating_sub(bytes);
+    fn new(bandwidth: Bits, interval: SimulationTime) -> Self {
+        let refill =
+            (bandwidth.bit() / 8).saturating_mul(interval.as_nanos() as u64) / NANOS_PER_SEC;
+        let refill = refill.max(1);
+        let capacity = refill.max(MTU);
+
+        Self {
+            capacity,
+            remaining: capacity,
+            refill,
+        }
+    }
+
+    fn consume(&mut self, bytes: u64) -> bool {
+        if self.remaining < bytes && self.remaining < self.capacity {
+            return false;
+        }
+
+        self.remaining = self.remaining.saturating_sub(bytes);
+        true
     }
 
     fn refill(&mut self) {
-        self.remaining.saturating_add(self.refill);
+        self.remaining = self
+            .remaining
+            .saturating_add(self.refill)
+            .min(self.capacity);
     }
 
     const fn needs_refill(&self) -> bool {
@@ -134,44 +361,510 @@ impl TokenBucket {
 }
 
 pub struct Router {
-    queue: VecDeque<Packet>,
+    queue: VecDeque<Arc<Packet>>,
+    queued_bytes: u64,
 }
 
 impl Router {
+    const QUEUE_LIMIT: u64 = 1024 * MTU;
+
     fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            queued_bytes: 0,
         }
     }
 
-    fn forward(&self, src: Host, packet: Packet) {
-        todo!()
+    /// Passes a packet on to the interface of its destination, or returns why it was dropped.
+    fn forward(src: &Arc<Host>, packet: Arc<Packet>) -> Result<(), DropReason> {
+        let ip = packet.dst().ip();
+
+        if dns::is_resolver(ip) {
+            Self::resolve(src, &packet);
+            return Ok(());
+        }
+
+        let dst = match ip.is_loopback() {
+            true => Some(src.clone()),
+            false => Worker::resolve_host(ip),
+        };
+
+        // Queries to name servers outside the simulation, such as the one the managed process
+        // found in the `resolv.conf` of the real system, are answered by the simulated resolver.
+        let is_foreign_query = dst.is_none()
+            && packet.protocol() == Protocol::Udp
+            && packet.dst().port() == dns::DNS_PORT
+            && !ip.is_loopback()
+            && !Worker::is_registered(ip);
+
+        if is_foreign_query {
+            Self::resolve(src, &packet);
+            return Ok(());
+        }
+
+        let dst = match dst {
+            Some(dst) => dst,
+            None => {
+                log::debug!("dropped packet to unknown host `{}`", ip);
+                Self::reject(src, &packet, Unreachable::Host);
+                return Err(DropReason::UnknownHost);
+            }
+        };
+
+        let latency = if Arc::ptr_eq(src, &dst) {
+            SimulationTime::zero()
+        } else {
+            let path = match Worker::path_between(src.node_id(), dst.node_id()) {
+                Some(path) => path,
+                None => {
+                    log::debug!(
+                        "dropped packet without path from `{}` to `{}`",
+                        src.name(),
+                        dst.name()
+                    );
+                    Self::reject(src, &packet, Unreachable::Host);
+                    return Err(DropReason::NoPath);
+                }
+            };
+
+            if src.random_f64() < f64::from(path.loss()) {
+                log::trace!("dropped packet from `{}` on path loss", src.name());
+                return Err(DropReason::PathLoss);
+            }
+
+            Worker::topology().count_traffic(&path, packet.size());
+            path.latency().into()
+        };
+
+        let task = Task::ReceivePacket(dst.interface(), packet);
+        Worker::schedule_task(task, &dst, latency);
+        Ok(())
+    }
+
+    /// Answers queries to the simulated resolver on the access router, so they never leave the
+    /// host and take no simulated time.
+    fn resolve(src: &Arc<Host>, packet: &Packet) {
+        if packet.protocol() != Protocol::Udp {
+            return;
+        }
+
+        if packet.dst().port() != dns::DNS_PORT {
+            Self::reject(src, packet, Unreachable::Port);
+            return;
+        }
+
+        let response = dns::respond(
+            packet.payload(),
+            &mut Worker::name_server()
+                .lock()
+                .expect("tried to acquire poisoned name server lock"),
+        );
+
+        if let Some(payload) = response {
+            let reply = Packet::udp(packet.dst(), packet.src(), payload);
+            let task = Task::ReceivePacket(src.interface(), Arc::new(reply));
+            Worker::schedule_task(task, src, SimulationTime::zero());
+        }
     }
 
-    fn enqueue(&mut self) {
-        todo!()
+    fn reject(src: &Arc<Host>, packet: &Packet, reason: Unreachable) {
+        if packet.is_icmp_error() {
+            return;
+        }
+
+        let dst = packet.src().ip();
+        let message = IcmpMessage::unreachable(reason, packet);
+        let reply = Packet::icmp(src.ip_for(dst.into()).unwrap_or(dst), dst, 0, &message);
+        let task = Task::ReceivePacket(src.interface(), Arc::new(reply));
+        Worker::schedule_task(task, src, SimulationTime::zero());
     }
 
-    fn dequeue(&mut self) {
-        todo!()
+    fn enqueue(&mut self, packet: Arc<Packet>) -> bool {
+        if self.queued_bytes + packet.size() > Self::QUEUE_LIMIT {
+            return false;
+        }
+
+        self.queued_bytes += packet.size();
+        self.queue.push_back(packet);
+        true
+    }
+
+    fn peek(&self) -> Option<&Arc<Packet>> {
+        self.queue.front()
+    }
+
+    fn dequeue(&mut self) -> Option<Arc<Packet>> {
+        let packet = self.queue.pop_front()?;
+        self.queued_bytes -= packet.size();
+        Some(packet)
+    }
+}
+
+/// Traffic through an interface since the simulation started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InterfaceStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Packets dropped on arrival because the queue of the router was full.
+    pub packets_dropped: u64,
+    /// Packets sent but lost on the path by the loss model of the graph.
+    pub packets_lost: u64,
+    /// Packets sent to an unknown address or to a host without a path to it.
+    pub packets_unroutable: u64,
+    /// Datagrams dropped on arrival because the receive buffer of their socket was full.
+    pub packets_overflowed: u64,
+    pub bytes_overflowed: u64,
+    /// Packets that could not be sent when queued, for want of upstream bandwidth.
+    pub packets_delayed_up: u64,
+    /// Packets that could not be delivered on arrival, for want of downstream bandwidth.
+    pub packets_delayed_down: u64,
+}
+
+impl InterfaceStats {
+    fn count_drop(&mut self, packet: &Packet, reason: DropReason) {
+        match reason {
+            DropReason::QueueFull => self.packets_dropped += 1,
+            DropReason::PathLoss => self.packets_lost += 1,
+            DropReason::UnknownHost | DropReason::NoPath => self.packets_unroutable += 1,
+            DropReason::ReceiveBuffer => {
+                self.packets_overflowed += 1;
+                self.bytes_overflowed += packet.payload().len() as u64;
+            }
+        }
     }
 }
 
 pub struct Interface {
-    ip_addr: IpAddr,
     send_bucket: TokenBucket,
     recv_bucket: TokenBucket,
     is_refill_pending: bool,
     upstream_router: Option<Router>,
     refill_started: SimulationTime,
+    send_queue: VecDeque<Arc<Packet>>,
+    /// Packets at the front of the send queue and of the router queue already counted as
+    /// delayed.
+    delayed_up: usize,
+    delayed_down: usize,
+    associations: HashMap<(Protocol, u16), Socket>,
+    connections: HashMap<(u16, SocketAddr), TcpSocket>,
+    next_ephemeral_port: u16,
+    stats: InterfaceStats,
+    pcap: Option<PcapWriter>,
 }
 
 impl Interface {
-    fn receive_packets(&self, host: &Host) {}
+    const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
 
-    fn send_packets(&self, host: &Host) {}
+    pub fn new(bandwidth_up: Bits, bandwidth_down: Bits, pcap: Option<PcapWriter>) -> Self {
+        let interval = Self::refill_interval();
 
-    pub fn refill_buckets(&mut self, host: &Host) {
+        Self {
+            send_bucket: TokenBucket::new(bandwidth_up, interval),
+            recv_bucket: TokenBucket::new(bandwidth_down, interval),
+            is_refill_pending: false,
+            upstream_router: Some(Router::new()),
+            refill_started: SimulationTime::zero(),
+            send_queue: VecDeque::new(),
+            delayed_up: 0,
+            delayed_down: 0,
+            associations: HashMap::new(),
+            connections: HashMap::new(),
+            next_ephemeral_port: *Self::EPHEMERAL_PORTS.start(),
+            stats: InterfaceStats::default(),
+            pcap,
+        }
+    }
+
+    pub fn stats(&self) -> InterfaceStats {
+        self.stats
+    }
+
+    /// Counts the sockets bound to a port, and for TCP the connections accepted on them.
+    pub fn socket_count(&self, protocol: Protocol) -> usize {
+        let bound = self
+            .associations
+            .keys()
+            .filter(|(other, _)| *other == protocol)
+            .count();
+
+        match protocol {
+            Protocol::Tcp => bound + self.connections.len(),
+            _ => bound,
+        }
+    }
+
+    /// Packets waiting for upstream bandwidth.
+    pub fn send_queue_len(&self) -> usize {
+        self.send_queue.len()
+    }
+
+    /// Packets waiting in the router for downstream bandwidth.
+    pub fn recv_queue_len(&self) -> usize {
+        self.upstream_router
+            .as_ref()
+            .map_or(0, |router| router.queue.len())
+    }
+
+    pub fn is_associated(&self, protocol: Protocol, port: u16) -> bool {
+        self.associations.contains_key(&(protocol, port))
+    }
+
+    pub fn associate(&mut self, protocol: Protocol, port: u16, socket: Socket) {
+        self.associations.insert((protocol, port), socket);
+    }
+
+    pub fn disassociate(&mut self, protocol: Protocol, port: u16) {
+        self.associations.remove(&(protocol, port));
+    }
+
+    /// Removes a connection accepted on `port`, unless the slot was taken over by a newer one.
+    pub fn remove_connection(&mut self, port: u16, peer: SocketAddr, socket: &TcpSocket) {
+        if let Some(existing) = self.connections.get(&(port, peer)) {
+            if existing.is_same(socket) {
+                self.connections.remove(&(port, peer));
+            }
+        }
+    }
+
+    /// Picks the next free port after the one handed out last, so that a closed port is not
+    /// reused while the peer may still hold state of its connection.
+    pub fn ephemeral_port(&mut self, protocol: Protocol) -> Option<u16> {
+        let port = (self.next_ephemeral_port..=*Self::EPHEMERAL_PORTS.end())
+            .chain(*Self::EPHEMERAL_PORTS.start()..self.next_ephemeral_port)
+            .find(|port| !self.is_associated(protocol, *port))?;
+
+        self.next_ephemeral_port = match port == *Self::EPHEMERAL_PORTS.end() {
+            true => *Self::EPHEMERAL_PORTS.start(),
+            false => port + 1,
+        };
+        Some(port)
+    }
+
+    pub fn send(&mut self, host: &Arc<Host>, packet: Packet) {
+        self.queue_packet(host, Arc::new(packet));
+        self.send_packets(host);
+    }
+
+    fn queue_packet(&mut self, host: &Arc<Host>, packet: Arc<Packet>) {
+        Worker::trace_packet(host, &packet, PacketState::Queued);
+        self.send_queue.push_back(packet);
+    }
+
+    pub fn receive(&mut self, host: &Arc<Host>, packet: Arc<Packet>) {
+        Worker::trace_packet(host, &packet, PacketState::Arrived);
+
+        match self.upstream_router.as_mut() {
+            Some(router) => {
+                if !router.enqueue(packet.clone()) {
+                    log::trace!("dropped packet on full router queue of `{}`", host.name());
+                    self.drop_packet(host, &packet, DropReason::QueueFull);
+                }
+                self.receive_packets(host);
+            }
+            None => self.deliver(host, packet),
+        }
+    }
+
+    fn receive_packets(&mut self, host: &Arc<Host>) {
+        while let Some(router) = self.upstream_router.as_mut() {
+            let size = match router.peek() {
+                Some(packet) => packet.size(),
+                None => break,
+            };
+
+            if !self.recv_bucket.consume(size) {
+                break;
+            }
+
+            if let Some(packet) = router.dequeue() {
+                self.delayed_down = self.delayed_down.saturating_sub(1);
+                self.deliver(host, packet);
+            }
+        }
+
+        let queued = self.recv_queue_len();
+        self.stats.packets_delayed_down += (queued - self.delayed_down.min(queued)) as u64;
+        self.delayed_down = queued;
+
+        self.schedule_refill(host);
+    }
+
+    fn send_packets(&mut self, host: &Arc<Host>) {
+        while let Some(packet) = self.send_queue.front() {
+            if !self.send_bucket.consume(packet.size()) {
+                break;
+            }
+
+            if let Some(packet) = self.send_queue.pop_front() {
+                self.delayed_up = self.delayed_up.saturating_sub(1);
+                self.stats.packets_sent += 1;
+                self.stats.bytes_sent += packet.size();
+                self.capture(&packet);
+                Worker::trace_packet(host, &packet, PacketState::Sent);
+                if let Err(reason) = Router::forward(host, packet.clone()) {
+                    self.drop_packet(host, &packet, reason);
+                }
+            }
+        }
+
+        let queued = self.send_queue.len();
+        self.stats.packets_delayed_up += (queued - self.delayed_up.min(queued)) as u64;
+        self.delayed_up = queued;
+
+        self.schedule_refill(host);
+    }
+
+    fn drop_packet(&mut self, host: &Arc<Host>, packet: &Packet, reason: DropReason) {
+        Worker::trace_packet(host, packet, PacketState::Dropped(reason));
+        self.stats.count_drop(packet, reason);
+    }
+
+    fn deliver(&mut self, host: &Arc<Host>, packet: Arc<Packet>) {
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += packet.size();
+        self.capture(&packet);
+        Worker::trace_packet(host, &packet, PacketState::Delivered);
+
+        match packet.protocol() {
+            Protocol::Udp => match self.associations.get(&(Protocol::Udp, packet.dst().port())) {
+                Some(Socket::Udp(socket)) => {
+                    if !socket.push_in_packet(host, packet.clone()) {
+                        self.drop_packet(host, &packet, DropReason::ReceiveBuffer);
+                    }
+                }
+                _ => {
+                    log::trace!("dropped packet for closed port `{}`", packet.dst());
+                    let message = IcmpMessage::unreachable(Unreachable::Port, &packet);
+                    let reply = Packet::icmp(packet.dst().ip(), packet.src().ip(), 0, &message);
+                    self.queue_packet(host, Arc::new(reply));
+                    self.send_packets(host);
+                }
+            },
+            Protocol::Tcp => self.deliver_tcp(host, packet),
+            Protocol::Icmp => self.deliver_icmp(host, packet),
+        }
+    }
+
+    /// Hands a segment to its connection, or to the socket bound to the port, which is either a
+    /// listener or a socket that connected from it.
+    fn deliver_tcp(&mut self, host: &Arc<Host>, packet: Arc<Packet>) {
+        let port = packet.dst().port();
+
+        // A new connection may take over one lingering in `TIME-WAIT`.
+        if let Some(socket) = self.connections.get(&(port, packet.src())) {
+            if socket.is_reusable_by(&packet) {
+                self.connections.remove(&(port, packet.src()));
+            }
+        }
+
+        let replies = if let Some(socket) = self.connections.get(&(port, packet.src())).cloned() {
+            let replies = socket.push_in_packet(host, &packet);
+            if socket.unbind_if_closed() {
+                self.connections.remove(&(port, packet.src()));
+            }
+            replies
+        } else {
+            match self.associations.get(&(Protocol::Tcp, port)).cloned() {
+                Some(Socket::Tcp(socket)) if socket.is_listening() => {
+                    let (connection, replies) = socket.push_in_syn(host, &packet);
+                    if let Some(connection) = connection {
+                        self.connections.insert((port, packet.src()), connection);
+                    }
+                    replies
+                }
+                Some(Socket::Tcp(socket)) => {
+                    let replies = socket.push_in_packet(host, &packet);
+                    if socket.unbind_if_closed() {
+                        self.disassociate(Protocol::Tcp, port);
+                    }
+                    replies
+                }
+                _ => {
+                    log::trace!("reset segment for closed port `{}`", packet.dst());
+                    tcp::reset_for(&packet).into_iter().collect()
+                }
+            }
+        };
+
+        for reply in replies {
+            self.queue_packet(host, Arc::new(reply));
+        }
+        self.send_packets(host);
+    }
+
+    fn deliver_icmp(&mut self, host: &Arc<Host>, packet: Arc<Packet>) {
+        let message = match IcmpMessage::decode(packet.payload(), packet.dst().ip()) {
+            Some(message) => message,
+            None => return,
+        };
+
+        match message {
+            IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data,
+            } => {
+                let message = IcmpMessage::EchoReply {
+                    identifier,
+                    sequence,
+                    data,
+                };
+                let reply =
+                    Packet::icmp(packet.dst().ip(), packet.src().ip(), identifier, &message);
+                self.queue_packet(host, Arc::new(reply));
+                self.send_packets(host);
+            }
+            IcmpMessage::EchoReply { identifier, .. } => {
+                if let Some(Socket::Icmp(socket)) =
+                    self.associations.get(&(Protocol::Icmp, identifier))
+                {
+                    socket.push_in_message(packet.src().ip(), message);
+                }
+            }
+            IcmpMessage::Unreachable {
+                reason,
+                ref original,
+            } => {
+                let quoted = match Quoted::parse(original) {
+                    Some(quoted) => quoted,
+                    None => return,
+                };
+
+                match self.associations.get(&(quoted.protocol, quoted.src.port())) {
+                    Some(Socket::Udp(socket)) => {
+                        socket.push_in_unreachable(host, quoted.dst, reason)
+                    }
+                    Some(Socket::Tcp(socket)) => {
+                        let socket = socket.clone();
+                        socket.push_in_unreachable(host, quoted.dst, reason);
+                        if socket.unbind_if_closed() {
+                            self.disassociate(Protocol::Tcp, quoted.src.port());
+                        }
+                    }
+                    Some(Socket::Icmp(socket)) => {
+                        socket.push_in_message(packet.src().ip(), message)
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn capture(&mut self, packet: &Packet) {
+        if let (Some(pcap), Some(now)) = (self.pcap.as_mut(), Worker::current_time()) {
+            pcap.write(&now, packet);
+        }
+    }
+
+    pub fn flush_pcap(&mut self) {
+        if let Some(pcap) = self.pcap.as_mut() {
+            pcap.flush();
+        }
+    }
+
+    pub fn refill_buckets(&mut self, host: &Arc<Host>) {
         self.is_refill_pending = false;
         self.send_bucket.refill();
         self.recv_bucket.refill();
@@ -183,9 +876,29 @@ impl Interface {
         self.send_packets(host);
     }
 
+    fn schedule_refill(&mut self, host: &Arc<Host>) {
+        if !self.is_refill_needed() {
+            return;
+        }
+
+        // Without a current time the simulation is over, like when stopped processes close
+        // their sockets, and nothing is left to refill for.
+        let now: SimulationTime = match Worker::current_time() {
+            Some(now) => now.into(),
+            None => return,
+        };
+
+        let interval = Interface::refill_interval();
+        let last_refill = now - self.refill_started;
+        let next_refill = interval - last_refill % interval;
+        let task = Task::RefillBuckets(host.interface());
+
+        self.is_refill_pending = Worker::schedule_task(task, host, next_refill);
+    }
+
     pub const fn is_refill_needed(&self) -> bool {
         (self.send_bucket.needs_refill() || self.recv_bucket.needs_refill())
-            && self.is_refill_pending
+            && !self.is_refill_pending
     }
 
     pub const fn refill_started(&self) -> SimulationTime {
@@ -203,30 +916,156 @@ pub struct NameRecord {
 }
 
 impl NameRecord {
-    fn a(&self) -> IpAddr {
+    pub fn a(&self) -> IpAddr {
         self.a
     }
 
-    fn cname(&self) -> String {
+    pub fn cname(&self) -> String {
         self.cname.clone()
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressRecord {
+    pub ip: IpAddr,
+    pub ttl: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AliasRecord {
+    pub name: String,
+    pub target: String,
+    pub ttl: u32,
+}
+
+/// Outcome of resolving a name: the aliases followed on the way and the addresses of the
+/// canonical name, rotated by one position on every lookup.
+#[derive(Debug)]
+pub struct Resolution {
+    pub aliases: Vec<AliasRecord>,
+    pub canonical: String,
+    pub addresses: Vec<AddressRecord>,
+}
+
 pub struct NameServer {
     by_a: HashMap<IpAddr, Arc<NameRecord>>,
-    by_cname: HashMap<String, Arc<NameRecord>>,
+    by_name: HashMap<String, Vec<AddressRecord>>,
+    aliases: HashMap<String, AliasRecord>,
+    names: HashMap<IpAddr, Vec<String>>,
+    rotation: HashMap<(String, AddressFamily), usize>,
+    next_ipv4: Ipv4Addr,
+    next_ipv6: Ipv6Addr,
+    /// Index of the next address to try in each pool, so allocations do not start over.
+    next_in_pool: HashMap<IpCidr, u128>,
 }
 
 impl NameServer {
-    fn lookup(&self, name: &str) -> Option<Arc<NameRecord>> {
-        self.by_cname.get(name).cloned()
+    pub const DEFAULT_TTL: u32 = 60;
+    const MAX_ALIAS_CHAIN: usize = 8;
+    const IPV4_AUTO_START: Ipv4Addr = Ipv4Addr::new(11, 0, 0, 1);
+    const IPV6_AUTO_START: Ipv6Addr = Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 1);
+
+    pub fn new() -> Self {
+        Self {
+            by_a: HashMap::new(),
+            by_name: HashMap::new(),
+            aliases: HashMap::new(),
+            names: HashMap::new(),
+            rotation: HashMap::new(),
+            next_ipv4: Self::IPV4_AUTO_START,
+            next_ipv6: Self::IPV6_AUTO_START,
+            next_in_pool: HashMap::new(),
+        }
     }
 
-    fn reverse_lookup(&self, ip: IpAddr) -> Option<Arc<NameRecord>> {
-        self.by_a.get(&ip).cloned()
+    fn normalize(name: &str) -> String {
+        name.trim_end_matches('.').to_ascii_lowercase()
     }
 
-    fn is_unique(&self, ip: IpAddr) -> bool {
+    /// Finds the key a name is stored under, either the name itself or the most specific
+    /// wildcard `*.<suffix>` covering it.
+    fn find(&self, name: &str) -> Option<String> {
+        let exists = |key: &str| self.by_name.contains_key(key) || self.aliases.contains_key(key);
+
+        if exists(name) {
+            return Some(name.to_owned());
+        }
+
+        name.match_indices('.')
+            .map(|(index, _)| format!("*{}", &name[index..]))
+            .find(|key| exists(key))
+    }
+
+    pub fn resolve(&mut self, name: &str, family: AddressFamily) -> Option<Resolution> {
+        let mut name = Self::normalize(name);
+        let mut aliases = Vec::new();
+
+        for _ in 0..=Self::MAX_ALIAS_CHAIN {
+            let key = match self.find(&name) {
+                Some(key) => key,
+                None if aliases.is_empty() => return None,
+                None => break,
+            };
+
+            if let Some(alias) = self.aliases.get(&key) {
+                let target = alias.target.clone();
+                aliases.push(AliasRecord {
+                    name: name.clone(),
+                    target: target.clone(),
+                    ttl: alias.ttl,
+                });
+                name = target;
+                continue;
+            }
+
+            let mut addresses: Vec<_> = self.by_name[&key]
+                .iter()
+                .filter(|record| AddressFamily::from(record.ip) == family)
+                .copied()
+                .collect();
+
+            if !addresses.is_empty() {
+                let count = addresses.len();
+                let turn = self.rotation.entry((key, family)).or_insert(0);
+                addresses.rotate_left(*turn % count);
+                *turn = turn.wrapping_add(1);
+            }
+
+            return Some(Resolution {
+                aliases,
+                canonical: name,
+                addresses,
+            });
+        }
+
+        Some(Resolution {
+            aliases,
+            canonical: name,
+            addresses: Vec::new(),
+        })
+    }
+
+    pub fn alias(&self, name: &str) -> Option<AliasRecord> {
+        let name = Self::normalize(name);
+        let alias = self.aliases.get(&self.find(&name)?)?;
+
+        Some(AliasRecord {
+            name,
+            target: alias.target.clone(),
+            ttl: alias.ttl,
+        })
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.find(&Self::normalize(name)).is_some()
+    }
+
+    /// All names pointing at an address, the name of the owning host first.
+    pub fn reverse_lookup(&self, ip: IpAddr) -> Vec<String> {
+        self.names.get(&ip).cloned().unwrap_or_default()
+    }
+
+    pub fn is_unique(&self, ip: IpAddr) -> bool {
         !self.by_a.contains_key(&ip)
     }
 
@@ -241,41 +1080,124 @@ impl NameServer {
                 cname: domain,
             }));
         } else if is_restricted(ip) {
-            return Err(DNSRegistrationError {
-                domain,
-                ip,
-                reason: Reason::Restricted,
-            });
+            return Err(DNSRegistrationError::new(domain, ip, Reason::Restricted));
         } else if !self.is_unique(ip) {
-            return Err(DNSRegistrationError {
-                domain,
-                ip,
-                reason: Reason::Duplicate,
-            });
+            return Err(DNSRegistrationError::new(domain, ip, Reason::Duplicate));
         }
 
+        self.add_address(&domain, ip, Self::DEFAULT_TTL)?;
+
         let record = Arc::new(NameRecord {
             a: ip,
             cname: domain,
         });
-        self.insert(record.clone());
+        self.by_a.insert(ip, record.clone());
         Ok(record)
     }
 
+    /// Adds an A or AAAA record to a name without claiming the address for a host, so several
+    /// names can share an address and a name can carry several addresses.
+    pub fn add_address(
+        &mut self,
+        name: &str,
+        ip: IpAddr,
+        ttl: u32,
+    ) -> Result<(), DNSRegistrationError> {
+        let name = Self::normalize(name);
+
+        if is_restricted(ip) || ip.is_loopback() {
+            return Err(DNSRegistrationError::new(name, ip, Reason::Restricted));
+        } else if self.aliases.contains_key(&name) {
+            return Err(DNSRegistrationError::new(name, ip, Reason::Conflict));
+        }
+
+        let records = self.by_name.entry(name.clone()).or_default();
+        if records.iter().any(|record| record.ip == ip) {
+            return Ok(());
+        }
+        records.push(AddressRecord { ip, ttl });
+
+        if !name.starts_with("*.") {
+            self.names.entry(ip).or_default().push(name);
+        }
+
+        Ok(())
+    }
+
+    pub fn add_alias(
+        &mut self,
+        name: &str,
+        target: &str,
+        ttl: u32,
+    ) -> Result<(), DNSRegistrationError> {
+        let name = Self::normalize(name);
+        let target = Self::normalize(target);
+
+        if self.by_name.contains_key(&name) || self.aliases.contains_key(&name) || name == target {
+            return Err(DNSRegistrationError::new(name, target, Reason::Conflict));
+        }
+
+        self.aliases
+            .insert(name.clone(), AliasRecord { name, target, ttl });
+        Ok(())
+    }
+
+    pub fn allocate(&mut self, family: AddressFamily) -> Option<IpAddr> {
+        loop {
+            let ip = match family {
+                AddressFamily::Inet => {
+                    let ip = self.next_ipv4;
+                    self.next_ipv4 = u32::from(ip).checked_add(1)?.into();
+                    IpAddr::V4(ip)
+                }
+                AddressFamily::Inet6 => {
+                    let ip = self.next_ipv6;
+                    self.next_ipv6 = u128::from(ip).checked_add(1)?.into();
+                    IpAddr::V6(ip)
+                }
+            };
+
+            if !is_restricted(ip) && !ip.is_loopback() && self.is_unique(ip) {
+                return Some(ip);
+            }
+        }
+    }
+
+    pub fn allocate_from(&mut self, pool: &IpCidr) -> Option<IpAddr> {
+        let (first, _) = pool.host_bounds();
+        let mut index = self.next_in_pool.get(pool).copied().unwrap_or(first);
+
+        loop {
+            index = pool.next_assignable(index)?;
+            let ip = pool.nth(index);
+            index = index.checked_add(1)?;
+
+            if self.is_unique(ip) {
+                self.next_in_pool.insert(*pool, index);
+                return Some(ip);
+            }
+        }
+    }
+
     pub fn deregister(&mut self, record: Arc<NameRecord>) {
         if !record.a().is_loopback() {
             self.remove(record);
         }
     }
 
-    fn insert(&mut self, record: Arc<NameRecord>) {
-        self.by_a.insert(record.a(), record.clone());
-        self.by_cname.insert(record.cname(), record.clone());
-    }
-
     fn remove(&mut self, record: Arc<NameRecord>) {
+        let name = Self::normalize(&record.cname());
+
         self.by_a.remove(&record.a());
-        self.by_cname.remove(&record.cname());
+        if let Some(records) = self.by_name.get_mut(&name) {
+            records.retain(|entry| entry.ip != record.a());
+            if records.is_empty() {
+                self.by_name.remove(&name);
+            }
+        }
+        if let Some(names) = self.names.get_mut(&record.a()) {
+            names.retain(|entry| *entry != name);
+        }
     }
 }
 
@@ -283,27 +1205,440 @@ impl NameServer {
 enum Reason {
     Restricted,
     Duplicate,
+    Conflict,
 }
 
 #[derive(Debug)]
 pub struct DNSRegistrationError {
     domain: String,
-    ip: IpAddr,
+    target: String,
     reason: Reason,
 }
 
+impl DNSRegistrationError {
+    fn new(domain: String, target: impl ToString, reason: Reason) -> Self {
+        Self {
+            domain,
+            target: target.to_string(),
+            reason,
+        }
+    }
+}
+
+impl std::error::Error for DNSRegistrationError {}
+
 impl Display for DNSRegistrationError {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let reason = match self.reason {
-            Reason::Restricted => "restricted",
-            Reason::Duplicate => "duplicate",
+            Reason::Restricted => "restricted ip",
+            Reason::Duplicate => "duplicate ip",
+            Reason::Conflict => "conflicting name",
         };
         write!(
             formatter,
-            "invalid registration for {} ip `{}` to domain `{}` ",
-            reason, self.ip, self.domain
+            "invalid registration for {} `{}` to domain `{}` ",
+            reason, self.target, self.domain
         )
     }
 }
 
-pub struct Packet;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn number(&self, ip: IpAddr) -> u8 {
+        match (self, ip) {
+            (Protocol::Icmp, IpAddr::V4(_)) => 1,
+            (Protocol::Icmp, IpAddr::V6(_)) => 58,
+            (Protocol::Tcp, _) => 6,
+            (Protocol::Udp, _) => 17,
+        }
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            1 | 58 => Some(Protocol::Icmp),
+            6 => Some(Protocol::Tcp),
+            17 => Some(Protocol::Udp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Socket {
+    Icmp(IcmpSocket),
+    Tcp(TcpSocket),
+    Udp(UdpSocket),
+}
+
+impl From<IcmpSocket> for Socket {
+    fn from(socket: IcmpSocket) -> Self {
+        Socket::Icmp(socket)
+    }
+}
+
+impl From<TcpSocket> for Socket {
+    fn from(socket: TcpSocket) -> Self {
+        Socket::Tcp(socket)
+    }
+}
+
+impl From<UdpSocket> for Socket {
+    fn from(socket: UdpSocket) -> Self {
+        Socket::Udp(socket)
+    }
+}
+
+pub struct Packet {
+    id: u64,
+    protocol: Protocol,
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: Vec<u8>,
+}
+
+impl Packet {
+    const IPV4_HEADER_SIZE: u64 = 20;
+    const IPV6_HEADER_SIZE: u64 = 40;
+    const UDP_HEADER_SIZE: u64 = 8;
+
+    pub fn udp(src: SocketAddr, dst: SocketAddr, payload: Vec<u8>) -> Self {
+        Self {
+            id: NEXT_PACKET_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Udp,
+            src,
+            dst,
+            payload,
+        }
+    }
+
+    pub fn tcp(src: SocketAddr, dst: SocketAddr, segment: &Segment) -> Self {
+        Self {
+            id: NEXT_PACKET_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Tcp,
+            src,
+            dst,
+            payload: segment.encode(src.ip(), dst.ip()),
+        }
+    }
+
+    pub fn icmp(src: IpAddr, dst: IpAddr, identifier: u16, message: &IcmpMessage) -> Self {
+        Self {
+            id: NEXT_PACKET_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Icmp,
+            src: SocketAddr::new(src, identifier),
+            dst: SocketAddr::new(dst, identifier),
+            payload: message.encode(src, dst),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn is_icmp_error(&self) -> bool {
+        self.protocol == Protocol::Icmp
+            && matches!(
+                IcmpMessage::decode(&self.payload, self.dst.ip()),
+                Some(IcmpMessage::Unreachable { .. }) | None
+            )
+    }
+
+    pub fn src(&self) -> SocketAddr {
+        self.src
+    }
+
+    pub fn dst(&self) -> SocketAddr {
+        self.dst
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn size(&self) -> u64 {
+        let ip_header = match self.dst.ip() {
+            IpAddr::V4(_) => Self::IPV4_HEADER_SIZE,
+            IpAddr::V6(_) => Self::IPV6_HEADER_SIZE,
+        };
+        let transport_header = match self.protocol {
+            Protocol::Icmp | Protocol::Tcp => 0,
+            Protocol::Udp => Self::UDP_HEADER_SIZE,
+        };
+
+        ip_header + transport_header + self.payload.len() as u64
+    }
+
+    pub fn ip_header(&self) -> Vec<u8> {
+        let protocol = self.protocol.number(self.dst.ip());
+
+        match (self.src.ip(), self.dst.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut header = Vec::with_capacity(Self::IPV4_HEADER_SIZE as usize);
+                header.extend([0x45, 0]);
+                header.extend((self.size() as u16).to_be_bytes());
+                header.extend([0, 0, 0x40, 0, DEFAULT_TTL, protocol, 0, 0]);
+                header.extend(src.octets());
+                header.extend(dst.octets());
+                let sum = checksum(&header);
+                header[10..12].copy_from_slice(&sum.to_be_bytes());
+                header
+            }
+            (src, dst) => {
+                let payload_len = self.size() - Self::IPV6_HEADER_SIZE;
+                let mut header = Vec::with_capacity(Self::IPV6_HEADER_SIZE as usize);
+                header.extend([0x60, 0, 0, 0]);
+                header.extend((payload_len as u16).to_be_bytes());
+                header.extend([protocol, DEFAULT_TTL]);
+                header.extend(to_ipv6_octets(src));
+                header.extend(to_ipv6_octets(dst));
+                header
+            }
+        }
+    }
+
+    pub fn transport_header(&self) -> Vec<u8> {
+        match self.protocol {
+            Protocol::Icmp | Protocol::Tcp => Vec::new(),
+            Protocol::Udp => {
+                let len = Self::UDP_HEADER_SIZE + self.payload.len() as u64;
+                let mut header = Vec::with_capacity(Self::UDP_HEADER_SIZE as usize);
+                header.extend(self.src.port().to_be_bytes());
+                header.extend(self.dst.port().to_be_bytes());
+                header.extend((len as u16).to_be_bytes());
+                header.extend([0, 0]);
+                header
+            }
+        }
+    }
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(value: &str) -> IpCidr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidr() {
+        assert_eq!(cidr("11.0.0.0/24").to_string(), "11.0.0.0/24");
+        assert_eq!(cidr(" 2000:: / 64 ").to_string(), "2000::/64");
+        assert_eq!(cidr("11.0.0.0/24").family(), AddressFamily::Inet);
+        assert_eq!(cidr("2000::/64").family(), AddressFamily::Inet6);
+
+        for invalid in [
+            "11.0.0.0",
+            "11.0.0.0/33",
+            "2000::/129",
+            "11.0.0/24",
+            "11.0.0.0/-1",
+        ] {
+            assert!(invalid.parse::<IpCidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn bounds_of_cidr() {
+        assert_eq!(cidr("11.0.0.77/24").bounds(), (0x0b00_0000, 0x0b00_00ff));
+        assert_eq!(
+            cidr("11.0.0.0/24").host_bounds(),
+            (0x0b00_0001, 0x0b00_00fe)
+        );
+        assert_eq!(
+            cidr("11.0.0.0/31").host_bounds(),
+            (0x0b00_0000, 0x0b00_0001)
+        );
+        assert_eq!(
+            cidr("11.0.0.1/32").host_bounds(),
+            (0x0b00_0001, 0x0b00_0001)
+        );
+        assert_eq!(cidr("0.0.0.0/0").bounds(), (0, u32::MAX as u128));
+
+        let (first, last) = cidr("2000::/64").bounds();
+        assert_eq!(
+            first,
+            u128::from(Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 0))
+        );
+        assert_eq!(last, first + u128::from(u64::MAX));
+        assert_eq!(cidr("::/0").bounds(), (0, u128::MAX));
+    }
+
+    #[test]
+    fn nth_of_cidr() {
+        let pool = cidr("11.0.0.0/24");
+        let (first, last) = pool.host_bounds();
+        assert_eq!(pool.nth(first), "11.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(pool.nth(last), "11.0.0.254".parse::<IpAddr>().unwrap());
+
+        let pool = cidr("2000::/64");
+        let (first, _) = pool.host_bounds();
+        assert_eq!(pool.nth(first + 1), "2000::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn pools_without_assignable_addresses() {
+        assert!(cidr("11.0.0.0/24").has_assignable());
+        assert!(cidr("2000::/64").has_assignable());
+        assert!(!cidr("10.0.0.0/8").has_assignable());
+        assert!(!cidr("127.0.0.0/8").has_assignable());
+        assert!(!cidr("fd00::/64").has_assignable());
+        assert!(!cidr("192.0.2.0/24").has_assignable());
+        // The restricted ranges are skipped as a whole, however large.
+        assert!(cidr("::/0").has_assignable());
+        assert!(cidr("0.0.0.0/0").has_assignable());
+        assert_eq!(cidr("2000::/64").host_bits(), 64);
+        assert_eq!(cidr("0.0.0.0/0").host_bits(), 32);
+    }
+
+    #[test]
+    fn allocate_until_exhausted() {
+        let mut name_server = NameServer::new();
+        let pool = cidr("11.0.0.0/30");
+
+        let first = name_server.allocate_from(&pool).unwrap();
+        assert_eq!(first, "11.0.0.1".parse::<IpAddr>().unwrap());
+        name_server.register("a".to_owned(), first).unwrap();
+
+        // Addresses taken by hosts with fixed addresses are skipped.
+        let second = name_server.allocate_from(&pool).unwrap();
+        assert_eq!(second, "11.0.0.2".parse::<IpAddr>().unwrap());
+        name_server.register("b".to_owned(), second).unwrap();
+
+        assert_eq!(name_server.allocate_from(&pool), None);
+        assert_eq!(name_server.allocate_from(&pool), None);
+    }
+
+    #[test]
+    fn allocate_after_fixed_addresses() {
+        let mut name_server = NameServer::new();
+        let pool = cidr("11.0.0.0/29");
+
+        for ip in ["11.0.0.1", "11.0.0.3"] {
+            name_server
+                .register(ip.to_owned(), ip.parse().unwrap())
+                .unwrap();
+        }
+
+        let allocated: Vec<_> = std::iter::from_fn(|| {
+            let ip = name_server.allocate_from(&pool)?;
+            name_server.register(ip.to_string(), ip).unwrap();
+            Some(ip.to_string())
+        })
+        .collect();
+
+        assert_eq!(allocated, ["11.0.0.2", "11.0.0.4", "11.0.0.5", "11.0.0.6"]);
+    }
+
+    #[test]
+    fn allocate_past_restricted_ranges() {
+        let mut name_server = NameServer::new();
+
+        let pool = cidr("10.0.0.0/7");
+        let ip = name_server.allocate_from(&pool).unwrap();
+        assert_eq!(ip, "11.0.0.0".parse::<IpAddr>().unwrap());
+
+        let pool = cidr("fc00::/6");
+        let ip = name_server.allocate_from(&pool).unwrap();
+        assert_eq!(ip, "fe00::".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn restricted_ipv6_ranges() {
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+
+        for (first, last, before, after) in [
+            ("::", "::", None, Some("::1")),
+            (
+                "::ffff:0:0",
+                "::ffff:ffff:ffff",
+                Some("::fffe:ffff:ffff"),
+                Some("::1:0:0:0"),
+            ),
+            (
+                "64:ff9b::",
+                "64:ff9b::ffff:ffff",
+                Some("64:ff9a:ffff:ffff:ffff:ffff:ffff:ffff"),
+                Some("64:ff9b::1:0:0"),
+            ),
+            (
+                "2001:db8::",
+                "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff",
+                None,
+                Some("2001:db9::"),
+            ),
+            (
+                "fc00::",
+                "fdff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+                Some("fbff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+                Some("fe00::"),
+            ),
+            (
+                "fe80::",
+                "febf:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+                Some("fe7f:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+                Some("fec0::"),
+            ),
+            (
+                "ff00::",
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+                Some("feff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+                None,
+            ),
+        ] {
+            let index = |value: &str| u128::from(value.parse::<Ipv6Addr>().unwrap());
+            let range = (index(first), index(last));
+            assert_eq!(restricted_range(ip(first)), Some(range), "{}", first);
+            assert_eq!(restricted_range(ip(last)), Some(range), "{}", last);
+
+            for outside in before.into_iter().chain(after) {
+                assert_eq!(restricted_range(ip(outside)), None, "{}", outside);
+            }
+        }
+
+        let mut name_server = NameServer::new();
+        for restricted in [
+            "::",
+            "::ffff:11.0.0.1",
+            "64:ff9b::b00:1",
+            "2001:db8::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(
+                name_server
+                    .register("host".to_owned(), ip(restricted))
+                    .is_err(),
+                "{}",
+                restricted
+            );
+        }
+        assert!(is_restricted(ip("2001:db8::1")));
+
+        // Loopback is no restricted range, but no host can have it either.
+        assert_eq!(restricted_range(ip("::1")), None);
+        assert!(unassignable_range(ip("::1")).is_some());
+
+        let routable = ip("2606:4700::1111");
+        assert_eq!(unassignable_range(routable), None);
+        assert!(name_server.register("host".to_owned(), routable).is_ok());
+    }
+}
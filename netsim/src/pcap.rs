@@ -0,0 +1,95 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::net::Packet;
+use crate::time::{EmulatedTime, UNIX_EPOCH};
+
+/// Snap length of hosts not configuring `pcap_capture_size`.
+pub const DEFAULT_SNAP_LEN: u32 = 65_535;
+
+/// Magic number of capture files with nanosecond timestamps.
+const MAGIC: u32 = 0xa1b2_3c4d;
+const VERSION: (u16, u16) = (2, 4);
+const LINKTYPE_ETHERNET: u32 = 1;
+
+const ETHERNET_HEADER_SIZE: u64 = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Writes the packets passing an interface to a file in the libpcap format, which Wireshark
+/// and tcpdump read. The simulated packets are framed in Ethernet with addresses derived from
+/// the IP addresses, and stamped with the emulated time they passed.
+pub struct PcapWriter {
+    writer: BufWriter<File>,
+    snap_len: u32,
+}
+
+impl PcapWriter {
+    pub fn create(path: &Path, snap_len: u32) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(&MAGIC.to_le_bytes())?;
+        writer.write_all(&VERSION.0.to_le_bytes())?;
+        writer.write_all(&VERSION.1.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&snap_len.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+
+        Ok(Self { writer, snap_len })
+    }
+
+    /// Appends a packet, cut to the snap length. Failing writes are dropped, so that capturing
+    /// never changes the course of the simulation.
+    pub fn write(&mut self, time: &EmulatedTime, packet: &Packet) {
+        let since_epoch = time.duration_since(&UNIX_EPOCH);
+        let secs = since_epoch.num_seconds();
+        let nanos = (since_epoch - chrono::Duration::seconds(secs))
+            .num_nanoseconds()
+            .unwrap_or(0);
+
+        let ip_header = packet.ip_header();
+        let ethertype = match ip_header.first().map(|byte| byte >> 4) {
+            Some(6) => ETHERTYPE_IPV6,
+            _ => ETHERTYPE_IPV4,
+        };
+
+        let mut frame = Vec::with_capacity((ETHERNET_HEADER_SIZE + packet.size()) as usize);
+        frame.extend(mac_address(packet.dst().ip()));
+        frame.extend(mac_address(packet.src().ip()));
+        frame.extend(ethertype.to_be_bytes());
+        frame.extend(ip_header);
+        frame.extend(packet.transport_header());
+        frame.extend(packet.payload());
+
+        let len = frame.len() as u32;
+        let captured = len.min(self.snap_len);
+
+        let _ = self
+            .writer
+            .write_all(&(secs as u32).to_le_bytes())
+            .and_then(|_| self.writer.write_all(&(nanos as u32).to_le_bytes()))
+            .and_then(|_| self.writer.write_all(&captured.to_le_bytes()))
+            .and_then(|_| self.writer.write_all(&len.to_le_bytes()))
+            .and_then(|_| self.writer.write_all(&frame[..captured as usize]));
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// A locally administered address ending in the last four bytes of the IP address.
+fn mac_address(ip: IpAddr) -> [u8; 6] {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets(),
+        IpAddr::V6(ip) => {
+            let octets = ip.octets();
+            [octets[12], octets[13], octets[14], octets[15]]
+        }
+    };
+
+    [0x02, 0x00, octets[0], octets[1], octets[2], octets[3]]
+}
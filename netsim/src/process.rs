@@ -0,0 +1,1265 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::Deserialize;
+
+use crate::descriptor::{self, DescriptorTable};
+use crate::host::Host;
+use crate::syscall::{self, ExitHook, SyscallContext, SyscallResult};
+use crate::task::Task;
+use crate::time::SimulationTime;
+use crate::worker::{Thread, Worker};
+
+const PTRACE_OPTIONS: libc::c_int = libc::PTRACE_O_TRACESYSGOOD
+    | libc::PTRACE_O_EXITKILL
+    | libc::PTRACE_O_TRACEEXEC
+    | libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK;
+
+const SYSCALL_STOP: libc::c_int = libc::SIGTRAP | 0x80;
+
+pub struct ProcessParams {
+    pub name: String,
+    pub path: PathBuf,
+    pub args: Vec<String>,
+    pub environment: Vec<(String, String)>,
+    pub working_directory: PathBuf,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+    pub stop_signal: Signal,
+    pub stop_grace_period: SimulationTime,
+    pub restart: RestartPolicy,
+    pub restart_delay: SimulationTime,
+    pub expected_final_state: Option<FinalState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(i32),
+    FailedToStart,
+}
+
+const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGILL", libc::SIGILL),
+    ("SIGTRAP", libc::SIGTRAP),
+    ("SIGABRT", libc::SIGABRT),
+    ("SIGBUS", libc::SIGBUS),
+    ("SIGFPE", libc::SIGFPE),
+    ("SIGKILL", libc::SIGKILL),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGSEGV", libc::SIGSEGV),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGPIPE", libc::SIGPIPE),
+    ("SIGALRM", libc::SIGALRM),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGCHLD", libc::SIGCHLD),
+    ("SIGCONT", libc::SIGCONT),
+    ("SIGSTOP", libc::SIGSTOP),
+    ("SIGTSTP", libc::SIGTSTP),
+    ("SIGTTIN", libc::SIGTTIN),
+    ("SIGTTOU", libc::SIGTTOU),
+    ("SIGURG", libc::SIGURG),
+    ("SIGXCPU", libc::SIGXCPU),
+    ("SIGXFSZ", libc::SIGXFSZ),
+    ("SIGVTALRM", libc::SIGVTALRM),
+    ("SIGPROF", libc::SIGPROF),
+    ("SIGWINCH", libc::SIGWINCH),
+    ("SIGIO", libc::SIGIO),
+    ("SIGPWR", libc::SIGPWR),
+    ("SIGSYS", libc::SIGSYS),
+];
+
+/// A signal given by name, with or without the `SIG` prefix, or by number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "SignalConfig")]
+pub struct Signal(libc::c_int);
+
+impl Signal {
+    pub const KILL: Signal = Signal(libc::SIGKILL);
+    pub const TERM: Signal = Signal(libc::SIGTERM);
+
+    pub fn number(&self) -> libc::c_int {
+        self.0
+    }
+}
+
+impl TryFrom<libc::c_int> for Signal {
+    type Error = InvalidSignal;
+
+    fn try_from(value: libc::c_int) -> Result<Self, Self::Error> {
+        match value {
+            1..=64 => Ok(Signal(value)),
+            _ => Err(InvalidSignal(value.to_string())),
+        }
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = InvalidSignal;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let name = value.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+        SIGNALS
+            .iter()
+            .find(|(known, _)| known[3..] == *name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or_else(|| InvalidSignal(value.to_owned()))
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match SIGNALS.iter().find(|(_, number)| *number == self.0) {
+            Some((name, _)) => formatter.write_str(name),
+            None => write!(formatter, "signal {}", self.0),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignalConfig {
+    Number(libc::c_int),
+    Name(String),
+}
+
+impl TryFrom<SignalConfig> for Signal {
+    type Error = InvalidSignal;
+
+    fn try_from(value: SignalConfig) -> Result<Self, Self::Error> {
+        match value {
+            SignalConfig::Number(number) => number.try_into(),
+            SignalConfig::Name(name) => name.parse(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidSignal(String);
+
+impl std::error::Error for InvalidSignal {}
+
+impl fmt::Display for InvalidSignal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "unknown signal `{}`", self.0)
+    }
+}
+
+/// Whether a process that exited on its own is started again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restarts the process unless it exited with status 0.
+    OnFailure,
+    Always,
+}
+
+/// The state a process is in when the simulation ends, written as `"running"`,
+/// `{ exited = 0 }` or `{ signaled = "SIGTERM" }` in the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinalState {
+    Running,
+    Exited(i32),
+    Signaled(Signal),
+}
+
+impl fmt::Display for FinalState {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FinalState::Running => formatter.write_str("running"),
+            FinalState::Exited(code) => write!(formatter, "exited with status {}", code),
+            FinalState::Signaled(signal) => write!(formatter, "killed by {}", signal),
+        }
+    }
+}
+
+/// A traced native thread, stopped by the kernel at every syscall entry and exit.
+struct Tracee {
+    in_syscall: bool,
+    is_blocked: bool,
+    pending_return: Option<i64>,
+    exit_hook: Option<ExitHook>,
+    wakeup: Option<SimulationTime>,
+    unapplied_latency: SimulationTime,
+    clear_child_tid: Option<u64>,
+}
+
+impl Tracee {
+    fn new() -> Self {
+        Self {
+            in_syscall: false,
+            is_blocked: false,
+            pending_return: None,
+            exit_hook: None,
+            wakeup: None,
+            unapplied_latency: SimulationTime::zero(),
+            clear_child_tid: None,
+        }
+    }
+}
+
+/// A thread blocked in `futex` until another thread of the process wakes it.
+struct FutexWaiter {
+    tid: libc::pid_t,
+    address: u64,
+    bitset: u32,
+}
+
+pub struct ProcessState {
+    params: ProcessParams,
+    leader: Option<libc::pid_t>,
+    threads: BTreeMap<libc::pid_t, Tracee>,
+    active: Option<libc::pid_t>,
+    futex_waiters: Vec<FutexWaiter>,
+    is_exiting: bool,
+    is_stopping: bool,
+    descriptors: DescriptorTable,
+    exit_status: Option<ExitStatus>,
+}
+
+/// A real binary run as a child of the simulator under `ptrace`. Syscalls that touch the
+/// network, the clock or the host identity are answered by the simulation, everything else is
+/// passed through to the kernel. Registers are read in the x86_64 layout, so managed processes
+/// are only supported on x86_64 Linux.
+///
+/// Threads of the process are scheduled cooperatively: only one of them runs at a time, until
+/// it blocks on a simulated condition or exits, while the others wait stopped by the tracer.
+/// Blocking `futex` operations are therefore emulated, so that threads synchronizing with each
+/// other hand over control deterministically.
+///
+/// At its stop time the process receives its stop signal and, if still running after the
+/// grace period, `SIGKILL`. A process that exits before may be restarted by its restart
+/// policy.
+#[derive(Clone)]
+pub struct Process(Arc<Mutex<ProcessState>>);
+
+impl Process {
+    pub fn new(params: ProcessParams) -> Self {
+        Self(Arc::new(Mutex::new(ProcessState {
+            params,
+            leader: None,
+            threads: BTreeMap::new(),
+            active: None,
+            futex_waiters: Vec::new(),
+            is_exiting: false,
+            is_stopping: false,
+            descriptors: DescriptorTable::default(),
+            exit_status: None,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ProcessState> {
+        self.0
+            .lock()
+            .expect("tried to acquire poisoned process lock")
+    }
+
+    pub fn ptr_eq(&self, other: &Process) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn name(&self) -> String {
+        self.lock().params.name.clone()
+    }
+
+    /// The state the process is in, or `None` if it never started.
+    pub fn final_state(&self) -> Option<FinalState> {
+        let state = self.lock();
+
+        match (state.leader, state.exit_status) {
+            (Some(_), _) => Some(FinalState::Running),
+            (None, Some(ExitStatus::Exited(code))) => Some(FinalState::Exited(code)),
+            (None, Some(ExitStatus::Signaled(signal))) => {
+                Some(FinalState::Signaled(Signal(signal)))
+            }
+            (None, Some(ExitStatus::FailedToStart) | None) => None,
+        }
+    }
+
+    pub fn expected_final_state(&self) -> Option<FinalState> {
+        self.lock().params.expected_final_state
+    }
+
+    pub fn start(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+
+        if state.leader.is_some() || state.is_stopping {
+            return;
+        }
+        let is_restart = state.exit_status.take().is_some();
+        if is_restart {
+            log::info!(
+                "restarting process `{}` on `{}`",
+                state.params.name,
+                host.name()
+            );
+        }
+
+        let pid = match spawn(&state.params, is_restart) {
+            Ok(pid) => {
+                log::info!(
+                    "started process `{}` on `{}` with native pid {}",
+                    state.params.name,
+                    host.name(),
+                    pid
+                );
+                state.leader = Some(pid);
+                state.threads.insert(pid, Tracee::new());
+                pid
+            }
+            Err(err) => {
+                log::error!(
+                    "failed to start process `{}` on `{}`: {}",
+                    state.params.name,
+                    host.name(),
+                    err
+                );
+                state.exit_status = Some(ExitStatus::FailedToStart);
+                return;
+            }
+        };
+
+        Worker::set_active_process(self.clone());
+        state.run(host, pid);
+        Worker::clear_active_process();
+    }
+
+    /// Continues the threads blocked on a simulated condition by retrying their pending
+    /// syscalls, in the order of their thread ids.
+    pub fn resume(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+
+        let blocked: Vec<_> = state
+            .threads
+            .iter()
+            .filter(|(_, tracee)| tracee.is_blocked)
+            .map(|(tid, _)| *tid)
+            .collect();
+
+        Worker::set_active_process(self.clone());
+        for tid in blocked {
+            state.run(host, tid);
+        }
+        Worker::clear_active_process();
+    }
+
+    /// Runs a single thread, which either just started or has its pending syscall completed or
+    /// due for a retry.
+    pub fn start_thread(&self, host: &Arc<Host>, thread: Thread) {
+        let mut state = self.lock();
+
+        Worker::set_active_process(self.clone());
+        state.run(host, thread.id() as libc::pid_t);
+        Worker::clear_active_process();
+    }
+
+    /// Sends the stop signal and kills the process if it is still running after the grace
+    /// period.
+    pub fn stop(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+        state.is_stopping = true;
+
+        if state.leader.is_none() {
+            return;
+        }
+
+        let signal = state.params.stop_signal;
+        log::info!(
+            "stopping process `{}` on `{}` with {}",
+            state.params.name,
+            host.name(),
+            signal
+        );
+
+        if signal == Signal::KILL {
+            state.kill(host);
+            return;
+        }
+
+        Worker::set_active_process(self.clone());
+        state.signal(host, signal);
+        Worker::clear_active_process();
+
+        let grace_period = state.params.stop_grace_period;
+        Worker::schedule_task(Task::KillProcess(self.clone()), host, grace_period);
+    }
+
+    pub fn kill(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+        state.is_stopping = true;
+
+        if state.leader.is_some() {
+            log::info!(
+                "killing process `{}` on `{}`",
+                state.params.name,
+                host.name()
+            );
+            state.kill(host);
+        }
+    }
+}
+
+impl ProcessState {
+    pub fn descriptors(&mut self) -> &mut DescriptorTable {
+        &mut self.descriptors
+    }
+
+    /// The thread whose syscall is being handled.
+    fn tracee(&self) -> Option<&Tracee> {
+        self.active.and_then(|tid| self.threads.get(&tid))
+    }
+
+    fn tracee_mut(&mut self) -> Option<&mut Tracee> {
+        match self.active {
+            Some(tid) => self.threads.get_mut(&tid),
+            None => None,
+        }
+    }
+
+    /// Whether the pending syscall is retried after having blocked.
+    pub fn is_retrying(&self) -> bool {
+        matches!(self.tracee(), Some(tracee) if tracee.is_blocked)
+    }
+
+    /// The time the blocked syscall waits for, kept across retries of the same syscall.
+    pub fn wakeup(&self) -> Option<SimulationTime> {
+        self.tracee().and_then(|tracee| tracee.wakeup)
+    }
+
+    pub fn set_wakeup(&mut self, time: SimulationTime) {
+        if let Some(tracee) = self.tracee_mut() {
+            tracee.wakeup = Some(time);
+        }
+    }
+
+    /// Charges simulated time for work that does not block and returns the total not yet
+    /// spent waiting.
+    pub fn add_latency(&mut self, latency: SimulationTime) -> SimulationTime {
+        match self.tracee_mut() {
+            Some(tracee) => {
+                tracee.unapplied_latency = tracee.unapplied_latency + latency;
+                tracee.unapplied_latency
+            }
+            None => SimulationTime::zero(),
+        }
+    }
+
+    pub fn take_latency(&mut self) -> SimulationTime {
+        match self.tracee_mut() {
+            Some(tracee) => mem::replace(&mut tracee.unapplied_latency, SimulationTime::zero()),
+            None => SimulationTime::zero(),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.active.is_some() && self.active == self.leader
+    }
+
+    /// The native process id, which is the thread id of the leader.
+    pub fn pid(&self) -> Option<libc::pid_t> {
+        self.leader
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Marks the process as exiting as a whole, once one of its threads called `exit_group`.
+    pub fn set_exiting(&mut self) {
+        self.is_exiting = true;
+    }
+
+    /// Remembers the address the kernel clears when the thread exits, which threads joining it
+    /// wait on.
+    pub fn set_clear_child_tid(&mut self, tid: libc::pid_t, address: u64) {
+        if let Some(tracee) = self.threads.get_mut(&tid) {
+            tracee.clear_child_tid = Some(address);
+        }
+    }
+
+    /// Whether the thread handled waits in `futex`, which only a wake or its timeout ends.
+    pub fn is_waiting_futex(&self) -> bool {
+        self.futex_waiters
+            .iter()
+            .any(|waiter| Some(waiter.tid) == self.active)
+    }
+
+    pub fn wait_futex(&mut self, address: u64, bitset: u32) {
+        if let Some(tid) = self.active {
+            self.futex_waiters.push(FutexWaiter {
+                tid,
+                address,
+                bitset,
+            });
+        }
+    }
+
+    pub fn cancel_futex_wait(&mut self) {
+        let active = self.active;
+        self.futex_waiters
+            .retain(|waiter| Some(waiter.tid) != active);
+    }
+
+    /// Wakes up to `count` threads waiting on the address, oldest first, and returns how many
+    /// were woken.
+    pub fn wake_futex(
+        &mut self,
+        host: &Arc<Host>,
+        address: u64,
+        count: usize,
+        bitset: u32,
+    ) -> usize {
+        let mut woken = Vec::new();
+
+        self.futex_waiters.retain(|waiter| {
+            let is_woken =
+                woken.len() < count && waiter.address == address && waiter.bitset & bitset != 0;
+            if is_woken {
+                woken.push(waiter.tid);
+            }
+            !is_woken
+        });
+
+        for tid in &woken {
+            self.finish(host, *tid, 0);
+        }
+
+        woken.len()
+    }
+
+    /// Moves up to `count` threads waiting on `from` to wait on `to` instead.
+    pub fn requeue_futex(&mut self, from: u64, to: u64, count: usize) -> usize {
+        let mut moved = 0;
+
+        for waiter in &mut self.futex_waiters {
+            if moved < count && waiter.address == from {
+                waiter.address = to;
+                moved += 1;
+            }
+        }
+
+        moved
+    }
+
+    /// Completes the syscall a blocked thread is stopped at with `value` and schedules the
+    /// thread to continue.
+    fn finish(&mut self, host: &Arc<Host>, tid: libc::pid_t, value: i64) {
+        let tracee = match self.threads.get_mut(&tid) {
+            Some(tracee) if tracee.is_blocked => tracee,
+            _ => return,
+        };
+
+        let result = get_regs(tid).and_then(|mut regs| {
+            regs.orig_rax = u64::MAX;
+            set_regs(tid, &regs)
+        });
+        if let Err(err) = result {
+            log::error!(
+                "failed to complete syscall of `{}`: {}",
+                self.params.name,
+                err
+            );
+        }
+
+        tracee.is_blocked = false;
+        tracee.wakeup = None;
+        tracee.pending_return = Some(value);
+        schedule_thread(host, tid);
+    }
+
+    /// Drives a thread until it blocks on a simulated condition or exits.
+    fn run(&mut self, host: &Arc<Host>, tid: libc::pid_t) {
+        let previous = self.active.replace(tid);
+        Worker::set_active_thread(Thread::from(tid as u64));
+
+        let is_blocked = match self.threads.get(&tid) {
+            Some(tracee) => tracee.is_blocked,
+            None => true,
+        };
+        let can_continue = !is_blocked || self.enter_syscall(host, tid);
+
+        if can_continue {
+            self.drive(host, tid);
+        }
+
+        Worker::clear_active_thread();
+        self.active = previous;
+    }
+
+    fn drive(&mut self, host: &Arc<Host>, tid: libc::pid_t) {
+        let mut signal = 0;
+
+        loop {
+            match self.threads.get(&tid) {
+                Some(tracee) if !tracee.is_blocked => {}
+                _ => return,
+            }
+
+            if unsafe { libc::ptrace(libc::PTRACE_SYSCALL, tid, 0, signal) } < 0 {
+                log::error!(
+                    "lost control of process `{}`: {}",
+                    self.params.name,
+                    io::Error::last_os_error()
+                );
+                self.kill(host);
+                return;
+            }
+            signal = 0;
+
+            // The other threads are stopped by the tracer and only die once reaped, so an
+            // exiting process is collected as a whole.
+            if self.is_exiting {
+                self.reap(host, None);
+                return;
+            }
+
+            let status = match wait(tid) {
+                Ok(status) => status,
+                Err(err) => {
+                    log::error!("failed to wait for `{}`: {}", self.params.name, err);
+                    self.kill(host);
+                    return;
+                }
+            };
+
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.thread_exited(host, tid, status);
+                return;
+            }
+
+            match libc::WSTOPSIG(status) {
+                SYSCALL_STOP => {
+                    let tracee = match self.threads.get_mut(&tid) {
+                        Some(tracee) => tracee,
+                        None => return,
+                    };
+                    tracee.in_syscall = !tracee.in_syscall;
+
+                    if tracee.in_syscall {
+                        self.enter_syscall(host, tid);
+                    } else {
+                        self.exit_syscall(tid);
+                    }
+                }
+                libc::SIGTRAP if status >> 16 == libc::PTRACE_EVENT_CLONE => {
+                    self.attach_thread(host, tid)
+                }
+                libc::SIGTRAP if status >> 16 == libc::PTRACE_EVENT_EXEC => self.exec(host, tid),
+                libc::SIGTRAP
+                    if status >> 16 == libc::PTRACE_EVENT_FORK
+                        || status >> 16 == libc::PTRACE_EVENT_VFORK =>
+                {
+                    self.kill_child(tid)
+                }
+                libc::SIGTRAP if status >> 16 != 0 => {}
+                stop_signal => signal = stop_signal,
+            }
+        }
+    }
+
+    /// Takes over the thread a `clone` created, which the kernel attached stopped, and
+    /// schedules it to run once the creating thread yields.
+    fn attach_thread(&mut self, host: &Arc<Host>, parent: libc::pid_t) {
+        let mut message: libc::c_ulong = 0;
+        if unsafe { libc::ptrace(libc::PTRACE_GETEVENTMSG, parent, 0, &mut message) } < 0 {
+            log::error!(
+                "failed to get the new thread of `{}`: {}",
+                self.params.name,
+                io::Error::last_os_error()
+            );
+            return;
+        }
+
+        let tid = message as libc::pid_t;
+        match wait(tid) {
+            Ok(status) if libc::WIFSTOPPED(status) => {}
+            _ => {
+                log::error!("new thread {} of `{}` vanished", tid, self.params.name);
+                return;
+            }
+        }
+
+        log::debug!("process `{}` started thread {}", self.params.name, tid);
+        self.threads.insert(tid, Tracee::new());
+        schedule_thread(host, tid);
+    }
+
+    /// Kills a child process that escaped the refusal of forks, which the kernel attached
+    /// stopped, before it runs outside of the host.
+    fn kill_child(&mut self, parent: libc::pid_t) {
+        let mut message: libc::c_ulong = 0;
+        if unsafe { libc::ptrace(libc::PTRACE_GETEVENTMSG, parent, 0, &mut message) } < 0 {
+            return;
+        }
+
+        let pid = message as libc::pid_t;
+        log::error!("killed child process {} of `{}`", pid, self.params.name);
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        wait_exit(pid);
+    }
+
+    /// Takes over the new program after `execve`. The kernel already ended the other threads
+    /// and closed the descriptors marked close-on-exec, whose emulated files are released here,
+    /// and mapped a fresh vDSO to patch.
+    fn exec(&mut self, host: &Arc<Host>, tid: libc::pid_t) {
+        let others: Vec<_> = self.threads.keys().copied().filter(|t| *t != tid).collect();
+        for other in others {
+            wait_exit(other);
+            self.threads.remove(&other);
+        }
+        self.futex_waiters.clear();
+        if let Some(tracee) = self.threads.get_mut(&tid) {
+            tracee.clear_child_tid = None;
+        }
+
+        let open: Vec<i32> = match fs::read_dir(format!("/proc/{}/fd", tid)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        for descriptor in self.descriptors.retain(|fd| open.contains(&fd)) {
+            descriptor::release(descriptor, host);
+        }
+
+        log::debug!("process `{}` executed a new program", self.params.name);
+        if let Err(err) = patch_vdso(tid) {
+            log::error!("failed to patch `{}` after exec: {}", self.params.name, err);
+            self.kill(host);
+        }
+    }
+
+    /// Dispatches the syscall the thread is stopped at and returns whether it may continue.
+    fn enter_syscall(&mut self, host: &Arc<Host>, tid: libc::pid_t) -> bool {
+        if !self.threads.contains_key(&tid) {
+            return false;
+        }
+
+        let mut regs = match get_regs(tid) {
+            Ok(regs) => regs,
+            Err(err) => {
+                log::error!(
+                    "failed to read registers of `{}`: {}",
+                    self.params.name,
+                    err
+                );
+                return false;
+            }
+        };
+
+        let result = syscall::handle(&mut SyscallContext::new(host, self, tid, &regs));
+        let tracee = match self.threads.get_mut(&tid) {
+            Some(tracee) => tracee,
+            None => return false,
+        };
+
+        match result {
+            SyscallResult::Native => {
+                tracee.is_blocked = false;
+                tracee.wakeup = None;
+            }
+            SyscallResult::Replace {
+                number,
+                args,
+                on_exit,
+            } => {
+                regs.orig_rax = number as u64;
+                regs.rdi = args[0];
+                regs.rsi = args[1];
+                regs.rdx = args[2];
+                regs.r10 = args[3];
+                regs.r8 = args[4];
+                regs.r9 = args[5];
+                if let Err(err) = set_regs(tid, &regs) {
+                    log::error!(
+                        "failed to replace syscall of `{}`: {}",
+                        self.params.name,
+                        err
+                    );
+                }
+                tracee.is_blocked = false;
+                tracee.wakeup = None;
+                tracee.exit_hook = Some(on_exit);
+            }
+            SyscallResult::Done(value) => {
+                // An invalid syscall number makes the kernel skip the call, the result is
+                // written into the return register once the tracee reaches the exit stop.
+                regs.orig_rax = u64::MAX;
+                if let Err(err) = set_regs(tid, &regs) {
+                    log::error!("failed to skip syscall of `{}`: {}", self.params.name, err);
+                }
+                tracee.is_blocked = false;
+                tracee.wakeup = None;
+                tracee.pending_return = Some(value);
+            }
+            SyscallResult::Block => {
+                tracee.is_blocked = true;
+            }
+        }
+
+        !tracee.is_blocked
+    }
+
+    fn exit_syscall(&mut self, tid: libc::pid_t) {
+        let tracee = match self.threads.get_mut(&tid) {
+            Some(tracee) => tracee,
+            None => return,
+        };
+
+        let pending = match tracee.exit_hook.take() {
+            Some(on_exit) => get_regs(tid)
+                .ok()
+                .map(|regs| on_exit(self, regs.rax as i64)),
+            None => tracee.pending_return.take(),
+        };
+
+        if let Some(value) = pending {
+            let result = get_regs(tid).and_then(|mut regs| {
+                regs.rax = value as u64;
+                set_regs(tid, &regs)
+            });
+
+            if let Err(err) = result {
+                log::error!(
+                    "failed to set syscall result of `{}`: {}",
+                    self.params.name,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Handles the exit of a thread. Only threads leaving on their own let the process go on,
+    /// the exit of the leader or a fatal signal takes all threads down.
+    fn thread_exited(&mut self, host: &Arc<Host>, tid: libc::pid_t, status: libc::c_int) {
+        if Some(tid) == self.leader || libc::WIFSIGNALED(status) {
+            self.reap(host, Some((tid, status)));
+            return;
+        }
+
+        let tracee = self.threads.remove(&tid);
+        self.futex_waiters.retain(|waiter| waiter.tid != tid);
+        log::debug!("thread {} of `{}` exited", tid, self.params.name);
+
+        // The kernel clears the thread id on exit and wakes its waiters, which wait in the
+        // emulated futex instead.
+        if let Some(address) = tracee.and_then(|tracee| tracee.clear_child_tid) {
+            self.wake_futex(
+                host,
+                address,
+                usize::MAX,
+                libc::FUTEX_BITSET_MATCH_ANY as u32,
+            );
+        }
+
+        // A leader that exited on its own waits for the last thread to finish.
+        if let Some(leader) = self.leader {
+            let is_leader_blocked = matches!(self.threads.get(&leader), Some(t) if t.is_blocked);
+            if self.threads.len() == 1 && is_leader_blocked {
+                schedule_thread(host, leader);
+            }
+        }
+    }
+
+    /// Sends a signal to the process. The kernel only delivers it once a thread returns to
+    /// user space, so the thread it is sent to is interrupted if blocked in a syscall.
+    fn signal(&mut self, host: &Arc<Host>, signal: Signal) {
+        let leader = match self.leader {
+            Some(leader) => leader,
+            None => return,
+        };
+        let tid = match self.threads.get(&leader) {
+            Some(tracee) if tracee.is_blocked => leader,
+            _ => self
+                .threads
+                .iter()
+                .find(|(_, tracee)| tracee.is_blocked)
+                .map_or(leader, |(tid, _)| *tid),
+        };
+
+        unsafe { libc::syscall(libc::SYS_tgkill, leader, tid, signal.number()) };
+
+        self.futex_waiters.retain(|waiter| waiter.tid != tid);
+        self.finish(host, tid, -libc::EINTR as i64);
+    }
+
+    fn kill(&mut self, host: &Arc<Host>) {
+        if let Some(leader) = self.leader {
+            unsafe { libc::kill(leader, libc::SIGKILL) };
+        }
+
+        self.reap(host, None);
+    }
+
+    /// Collects all threads of a process that exits as a whole, the leader last as the kernel
+    /// only reports it once all others are gone.
+    fn reap(&mut self, host: &Arc<Host>, exited: Option<(libc::pid_t, libc::c_int)>) {
+        let leader = match self.leader {
+            Some(leader) => leader,
+            None => return,
+        };
+
+        let tids: Vec<_> = self.threads.keys().copied().collect();
+
+        for tid in tids.into_iter().filter(|tid| *tid != leader) {
+            if exited.map(|(exited, _)| exited) != Some(tid) {
+                wait_exit(tid);
+            }
+        }
+
+        let status = match exited {
+            Some((tid, status)) if tid == leader => status,
+            _ => wait_exit(leader),
+        };
+        self.exited(host, status);
+    }
+
+    fn exited(&mut self, host: &Arc<Host>, status: libc::c_int) {
+        let status = if libc::WIFEXITED(status) {
+            ExitStatus::Exited(libc::WEXITSTATUS(status))
+        } else {
+            ExitStatus::Signaled(libc::WTERMSIG(status))
+        };
+
+        log::info!("process `{}` finished with {:?}", self.params.name, status);
+        self.leader = None;
+        self.threads.clear();
+        self.futex_waiters.clear();
+        self.is_exiting = false;
+        self.exit_status = Some(status);
+
+        for descriptor in self.descriptors.drain() {
+            descriptor::release(descriptor, host);
+        }
+
+        let is_restarted = !self.is_stopping
+            && match self.params.restart {
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => status != ExitStatus::Exited(0),
+                RestartPolicy::Always => true,
+            };
+
+        if is_restarted {
+            if let Some(process) = Worker::with_active_process(|process| process.clone()) {
+                let task = Task::StartProcess(process);
+                Worker::schedule_task(task, host, self.params.restart_delay);
+            }
+        }
+    }
+}
+
+/// Schedules a thread of the active process to run at the current time.
+fn schedule_thread(host: &Arc<Host>, tid: libc::pid_t) {
+    if let Some(process) = Worker::with_active_process(|process| process.clone()) {
+        let task = Task::StartThread(process, Thread::from(tid as u64));
+        Worker::schedule_task(task, host, SimulationTime::zero());
+    }
+}
+
+/// Waits until a thread exits, letting it continue from any stop on the way.
+fn wait_exit(tid: libc::pid_t) -> libc::c_int {
+    loop {
+        match wait(tid) {
+            Ok(status) if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) => return status,
+            Ok(_) => {
+                unsafe { libc::ptrace(libc::PTRACE_CONT, tid, 0, 0) };
+            }
+            Err(_) => return libc::SIGKILL,
+        }
+    }
+}
+
+/// Starts the process stopped at its first instruction, with its output appended to the
+/// output of its previous run when restarted.
+fn spawn(params: &ProcessParams, is_restart: bool) -> io::Result<libc::pid_t> {
+    let output = |path: &PathBuf| {
+        fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(is_restart)
+            .truncate(!is_restart)
+            .open(path)
+    };
+
+    let mut command = Command::new(&params.path);
+    command
+        .args(&params.args)
+        .env_clear()
+        .envs(params.environment.iter().map(|(key, value)| (key, value)))
+        .current_dir(&params.working_directory)
+        .stdin(Stdio::null())
+        .stdout(output(&params.stdout)?)
+        .stderr(output(&params.stderr)?);
+
+    // Runs in the forked child right before `execve`, the stop after `execve` hands control
+    // to the simulator before the first instruction of the binary. The own process group keeps
+    // a SIGINT from the terminal to the simulator.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::personality(libc::ADDR_NO_RANDOMIZE as libc::c_ulong);
+            Ok(())
+        });
+    }
+
+    let child = command.spawn()?;
+    let pid = child.id() as libc::pid_t;
+    mem::forget(child);
+
+    let status = wait(pid)?;
+    if !libc::WIFSTOPPED(status) {
+        return Err(io::Error::other("process exited before it could be traced"));
+    }
+
+    if unsafe { libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, PTRACE_OPTIONS) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        return Err(err);
+    }
+
+    if let Err(err) = patch_vdso(pid) {
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        return Err(err);
+    }
+
+    Ok(pid)
+}
+
+/// Replaces the clock functions of the vDSO with real syscalls, which the tracer can stop at.
+/// Otherwise time is read from shared kernel memory without ever entering the kernel.
+fn patch_vdso(pid: libc::pid_t) -> io::Result<()> {
+    let auxv = std::fs::read(format!("/proc/{}/auxv", pid))?;
+    let base = auxv
+        .chunks_exact(16)
+        .map(|pair| {
+            let key = u64::from_ne_bytes(pair[..8].try_into().unwrap_or_default());
+            let value = u64::from_ne_bytes(pair[8..].try_into().unwrap_or_default());
+            (key, value)
+        })
+        .find(|(key, _)| *key == libc::AT_SYSINFO_EHDR)
+        .map(|(_, value)| value);
+
+    let base = match base {
+        Some(base) if base != 0 => base,
+        _ => return Ok(()),
+    };
+
+    let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    let size = maps
+        .lines()
+        .filter(|line| line.ends_with("[vdso]"))
+        .filter_map(|line| line.split_whitespace().next()?.split_once('-'))
+        .filter_map(|(start, end)| {
+            let start = u64::from_str_radix(start, 16).ok()?;
+            let end = u64::from_str_radix(end, 16).ok()?;
+            (start == base).then(|| end - start)
+        })
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "vdso mapping not found"))?;
+
+    let image = Memory::new(pid).read(base, size as usize)?;
+    let symbols = vdso::symbols(&image)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed vdso image"))?;
+
+    let mem = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/proc/{}/mem", pid))?;
+
+    for (name, offset) in symbols {
+        let number = match name.trim_start_matches("__vdso_") {
+            "clock_gettime" => libc::SYS_clock_gettime,
+            "gettimeofday" => libc::SYS_gettimeofday,
+            "time" => libc::SYS_time,
+            "clock_getres" => libc::SYS_clock_getres,
+            _ => continue,
+        };
+
+        // mov eax, <number>; syscall; ret
+        let mut stub = vec![0xb8];
+        stub.extend((number as u32).to_le_bytes());
+        stub.extend([0x0f, 0x05, 0xc3]);
+
+        std::os::unix::fs::FileExt::write_all_at(&mem, &stub, base + offset)?;
+    }
+
+    Ok(())
+}
+
+mod vdso {
+    const SHT_DYNSYM: u32 = 11;
+    const PT_LOAD: u32 = 1;
+
+    fn u16_at(image: &[u8], offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(
+            image.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    }
+
+    fn u32_at(image: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            image.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    fn u64_at(image: &[u8], offset: usize) -> Option<u64> {
+        Some(u64::from_le_bytes(
+            image.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    }
+
+    /// Lists the defined dynamic symbols of an ELF64 image with their offsets from the load
+    /// address.
+    pub fn symbols(image: &[u8]) -> Option<Vec<(String, u64)>> {
+        if image.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+
+        let program_offset = u64_at(image, 0x20)? as usize;
+        let section_offset = u64_at(image, 0x28)? as usize;
+        let program_size = u16_at(image, 0x36)? as usize;
+        let program_count = u16_at(image, 0x38)? as usize;
+        let section_size = u16_at(image, 0x3a)? as usize;
+        let section_count = u16_at(image, 0x3c)? as usize;
+
+        let load_address = (0..program_count)
+            .map(|index| program_offset + index * program_size)
+            .find(|header| u32_at(image, *header) == Some(PT_LOAD))
+            .and_then(|header| u64_at(image, header + 0x10))?;
+
+        let section = |index: usize| section_offset + index * section_size;
+        let dynsym = (0..section_count)
+            .map(section)
+            .find(|header| u32_at(image, header + 4) == Some(SHT_DYNSYM))?;
+
+        let strtab = section(u32_at(image, dynsym + 0x28)? as usize);
+        let strings = u64_at(image, strtab + 0x18)? as usize;
+        let table = u64_at(image, dynsym + 0x18)? as usize;
+        let table_size = u64_at(image, dynsym + 0x20)? as usize;
+        let entry_size = (u64_at(image, dynsym + 0x38)? as usize).max(24);
+
+        let mut symbols = Vec::new();
+        for entry in (table..table + table_size).step_by(entry_size) {
+            let name = strings + u32_at(image, entry)? as usize;
+            let value = u64_at(image, entry + 8)?;
+            let section_index = u16_at(image, entry + 6)?;
+
+            if value == 0 || section_index == 0 {
+                continue;
+            }
+
+            let end = image.get(name..)?.iter().position(|byte| *byte == 0)?;
+            let name = String::from_utf8_lossy(&image[name..name + end]).into_owned();
+            symbols.push((name, value - load_address));
+        }
+
+        Some(symbols)
+    }
+}
+
+fn wait(pid: libc::pid_t) -> io::Result<libc::c_int> {
+    let mut status = 0;
+
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            _ => return Ok(status),
+        }
+    }
+}
+
+fn get_regs(pid: libc::pid_t) -> io::Result<libc::user_regs_struct> {
+    let mut regs = mem::MaybeUninit::<libc::user_regs_struct>::uninit();
+
+    match unsafe { libc::ptrace(libc::PTRACE_GETREGS, pid, 0, regs.as_mut_ptr()) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(unsafe { regs.assume_init() }),
+    }
+}
+
+fn set_regs(pid: libc::pid_t, regs: &libc::user_regs_struct) -> io::Result<()> {
+    match unsafe { libc::ptrace(libc::PTRACE_SETREGS, pid, 0, regs as *const _) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads and writes the address space of a tracee.
+pub struct Memory {
+    pid: libc::pid_t,
+}
+
+impl Memory {
+    pub fn new(pid: libc::pid_t) -> Self {
+        Self { pid }
+    }
+
+    pub fn read(&self, address: u64, size: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; size];
+        let local = libc::iovec {
+            iov_base: bytes.as_mut_ptr() as *mut libc::c_void,
+            iov_len: size,
+        };
+        let remote = libc::iovec {
+            iov_base: address as *mut libc::c_void,
+            iov_len: size,
+        };
+
+        match unsafe { libc::process_vm_readv(self.pid, &local, 1, &remote, 1, 0) } {
+            -1 => Err(io::Error::last_os_error()),
+            read if read as usize == size => Ok(bytes),
+            _ => Err(io::Error::from_raw_os_error(libc::EFAULT)),
+        }
+    }
+
+    pub fn write(&self, address: u64, bytes: &[u8]) -> io::Result<()> {
+        let local = libc::iovec {
+            iov_base: bytes.as_ptr() as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+        let remote = libc::iovec {
+            iov_base: address as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+
+        match unsafe { libc::process_vm_writev(self.pid, &local, 1, &remote, 1, 0) } {
+            -1 => Err(io::Error::last_os_error()),
+            written if written as usize == bytes.len() => Ok(()),
+            _ => Err(io::Error::from_raw_os_error(libc::EFAULT)),
+        }
+    }
+
+    pub fn read_value<T: Copy>(&self, address: u64) -> io::Result<T> {
+        let bytes = self.read(address, mem::size_of::<T>())?;
+        Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+
+    pub fn write_value<T: Copy>(&self, address: u64, value: &T) -> io::Result<()> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+        };
+        self.write(address, bytes)
+    }
+}
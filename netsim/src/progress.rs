@@ -0,0 +1,111 @@
+//! A line at the bottom of the terminal showing how far the simulation got, redrawn in place
+//! while it runs.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::time::SimulationTime;
+
+/// Wall-clock time between redraws of the line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Moves the cursor to the start of the line and erases it.
+const CLEAR_LINE: &[u8] = b"\r\x1b[K";
+
+/// Whether the line is on the terminal, to be cleared before other output.
+static IS_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether stderr is a terminal, where the progress is shown by default.
+pub fn is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+/// Clears the line from the terminal, for other output to take its place until the next
+/// redraw.
+pub fn clear(out: &mut impl Write) {
+    if IS_DRAWN.swap(false, Ordering::Relaxed) {
+        let _ = out.write_all(CLEAR_LINE);
+    }
+}
+
+/// Shows the simulated time against the stop time, the estimated wall-clock time left and the
+/// rate of events since the previous redraw.
+pub struct Progress {
+    is_enabled: bool,
+    stop_time: SimulationTime,
+    started: Instant,
+    last: (Instant, u64),
+    events: u64,
+}
+
+impl Progress {
+    pub fn new(is_enabled: bool, stop_time: SimulationTime) -> Self {
+        let now = Instant::now();
+
+        Self {
+            is_enabled,
+            stop_time,
+            started: now,
+            last: (now, 0),
+            events: 0,
+        }
+    }
+
+    pub fn count_event(&mut self, time: SimulationTime) {
+        self.events += 1;
+
+        // Reading the clock costs little next to an event, and counting events instead would
+        // leave the line standing while events take long, like those running processes.
+        if !self.is_enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let (last_wall, last_events) = self.last;
+        if now.duration_since(last_wall) < REDRAW_INTERVAL {
+            return;
+        }
+
+        let simulated = time.as_nanos() as f64 / 1e9;
+        let stop = self.stop_time.as_nanos() as f64 / 1e9;
+        let fraction = match stop {
+            stop if stop > 0.0 => (simulated / stop).min(1.0),
+            _ => 1.0,
+        };
+        let wall = now.duration_since(self.started).as_secs_f64();
+        let eta = match fraction {
+            fraction if fraction > 0.0 => format_duration(wall * (1.0 - fraction) / fraction),
+            _ => "--:--:--".to_owned(),
+        };
+        let events_per_sec =
+            (self.events - last_events) as f64 / now.duration_since(last_wall).as_secs_f64();
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[Ksimulated {:.3}s of {:.3}s ({:.1}%), ETA {}, {:.0} events/s",
+            simulated,
+            stop,
+            fraction * 100.0,
+            eta,
+            events_per_sec
+        );
+        let _ = stderr.flush();
+        IS_DRAWN.store(true, Ordering::Relaxed);
+
+        self.last = (now, self.events);
+    }
+
+    /// Clears the line when the simulation ends.
+    pub fn finish(&mut self) {
+        if self.is_enabled {
+            clear(&mut io::stderr().lock());
+        }
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -1,64 +1,117 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
 use std::error;
-use std::net::IpAddr;
+use std::fmt;
+use std::fs::{self, File};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
 use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 use rand::prelude::SmallRng;
 use rand::{RngCore, SeedableRng};
 
+use crate::config::{Config, ConfigError, Environment, HostsConfig, IpAddrConfig};
+use crate::dns::ZoneData;
 use crate::event::Event;
-use crate::host::{Host, HostId};
-use crate::time::SimulationTime;
-use crate::worker::WorkerPool;
-
-type Seed = [u8; 32];
+use crate::graph::{Node, NodeId, Topology};
+use crate::host::{Host, HostId, HostParams};
+use crate::interrupt;
+use crate::net::{AddressFamily, NameServer};
+use crate::pcap::{PcapWriter, DEFAULT_SNAP_LEN};
+use crate::process::{FinalState, Process, ProcessParams};
+use crate::processor::Processors;
+use crate::progress::{self, Progress};
+use crate::simapp::{AppRegistry, HostedApp, HostedAppParams};
+use crate::summary::Summary;
+use crate::task::Task;
+use crate::template::{Placeholders, Template};
+use crate::time::{PerfTimer, SimulationTime};
+use crate::trace::{EventState, Tracer};
+use crate::worker::{Worker, WorkerPool};
 
 trait Policy {
     fn add_host(&mut self, host: &Host);
-    fn hosts(&self) -> &Host;
-    fn push(&self, event: &Event, host: &Host, time: SimulationTime);
-    fn pop(&self, time: SimulationTime) -> Event;
-    fn next_time(&self) -> SimulationTime;
+    fn push(&mut self, event: Event);
+    fn pop(&mut self, barrier: SimulationTime) -> Option<Event>;
+    fn next_time(&self) -> Option<SimulationTime>;
+}
+
+/// Keeps the events of each host in a queue of its own, and the time of the next event of each
+/// host in an ordered set to find the earliest across hosts without visiting them all.
+struct HostSinglePolicy {
+    queues: HashMap<HostId, BinaryHeap<Reverse<Event>>>,
+    heads: BTreeSet<(SimulationTime, HostId)>,
 }
 
-struct HostSinglePolicy;
+impl HostSinglePolicy {
+    fn new() -> Self {
+        Self {
+            queues: HashMap::new(),
+            heads: BTreeSet::new(),
+        }
+    }
+}
 
 impl Policy for HostSinglePolicy {
     fn add_host(&mut self, host: &Host) {
-        todo!()
+        self.queues.entry(host.id()).or_default();
     }
 
-    fn hosts(&self) -> &Host {
-        todo!()
-    }
+    fn push(&mut self, event: Event) {
+        let id = event.host().id();
+        let queue = self.queues.entry(id).or_default();
 
-    fn push(&self, event: &Event, host: &Host, time: SimulationTime) {
-        todo!()
+        let head = queue.peek().map(|head| head.0.time());
+        if head.is_none() || matches!(head, Some(time) if event.time() < time) {
+            if let Some(time) = head {
+                self.heads.remove(&(time, id));
+            }
+            self.heads.insert((event.time(), id));
+        }
+
+        queue.push(Reverse(event));
     }
 
-    fn pop(&self, time: SimulationTime) -> Event {
-        todo!()
+    fn pop(&mut self, barrier: SimulationTime) -> Option<Event> {
+        let (time, id) = *self.heads.iter().next()?;
+        if time >= barrier {
+            return None;
+        }
+
+        self.heads.remove(&(time, id));
+        let queue = self.queues.get_mut(&id)?;
+        let event = queue.pop().map(|event| event.0);
+        if let Some(head) = queue.peek() {
+            self.heads.insert((head.0.time(), id));
+        }
+
+        event
     }
 
-    fn next_time(&self) -> SimulationTime {
-        todo!()
+    fn next_time(&self) -> Option<SimulationTime> {
+        self.heads.iter().next().map(|(time, _)| *time)
     }
 }
 
 pub struct Scheduler {
     is_running: bool,
-    worker: WorkerPool,
-    hosts: HashMap<HostId, Host>,
-    //policy: Box<dyn Policy>,
+    hosts: HashMap<HostId, Arc<Host>>,
+    addresses: HashMap<IpAddr, Arc<Host>>,
+    policy: Box<dyn Policy + Send>,
+    round_end: SimulationTime,
+    tracer: Option<Arc<Mutex<Tracer>>>,
 }
 
 impl Scheduler {
     fn new() -> Self {
         Self {
             is_running: false,
-            worker: WorkerPool::new(),
             hosts: HashMap::new(),
-            //policy: Box::new(),
+            addresses: HashMap::new(),
+            policy: Box::new(HostSinglePolicy::new()),
+            round_end: SimulationTime::zero(),
+            tracer: None,
         }
     }
 
@@ -70,8 +123,56 @@ impl Scheduler {
         self.is_running = false
     }
 
-    pub fn push(&self, event: Event, host: &Host, delay: SimulationTime) -> bool {
-        todo!()
+    fn add_host(&mut self, host: Arc<Host>) {
+        self.policy.add_host(&host);
+        for family in [AddressFamily::Inet, AddressFamily::Inet6] {
+            if let Some(ip) = host.ip_for(family) {
+                self.addresses.insert(ip, host.clone());
+            }
+        }
+        self.hosts.insert(host.id(), host);
+    }
+
+    pub fn host_by_ip(&self, ip: IpAddr) -> Option<Arc<Host>> {
+        self.addresses.get(&ip).cloned()
+    }
+
+    pub fn push(&mut self, mut event: Event) -> bool {
+        if !self.is_running {
+            return false;
+        }
+
+        if !event.is_local() && event.time() < self.round_end {
+            event.set_time(self.round_end);
+        }
+
+        self.trace(EventState::Scheduled, &event);
+        self.policy.push(event);
+        true
+    }
+
+    /// Takes the next event to execute before the barrier.
+    fn pop(&mut self, barrier: SimulationTime) -> Option<Event> {
+        let event = self.policy.pop(barrier)?;
+        self.trace(EventState::Executed, &event);
+        Some(event)
+    }
+
+    fn trace(&self, state: EventState, event: &Event) {
+        if let Some(tracer) = &self.tracer {
+            tracer
+                .lock()
+                .expect("tried to acquire poisoned tracer lock")
+                .event(state, event);
+        }
+    }
+
+    fn next_time(&self) -> Option<SimulationTime> {
+        self.policy.next_time()
+    }
+
+    fn set_round_end(&mut self, time: SimulationTime) {
+        self.round_end = time;
     }
 
     pub fn is_running(&self) -> bool {
@@ -81,24 +182,151 @@ impl Scheduler {
 
 pub struct Simulation {
     scheduler: Arc<Mutex<Scheduler>>,
+    name_server: Arc<Mutex<NameServer>>,
+    topology: Arc<Topology>,
+    pool: Arc<Mutex<WorkerPool>>,
 }
 
 impl Simulation {
-    fn new() -> Self {
+    fn new(topology: Topology) -> Self {
+        let scheduler = Arc::new(Mutex::new(Scheduler::new()));
+        let name_server = Arc::new(Mutex::new(NameServer::new()));
+        let topology = Arc::new(topology);
+        let pool = WorkerPool::new(scheduler.clone(), topology.clone(), name_server.clone());
+
         Self {
-            scheduler: Arc::new(Mutex::new(Scheduler::new())),
+            scheduler,
+            name_server,
+            topology,
+            pool: Arc::new(Mutex::new(pool)),
         }
     }
 
-    fn run(&self) -> Result<(), Box<dyn error::Error>> {
+    /// Writes the trace of events and packets from now on.
+    fn set_tracer(&self, tracer: Tracer) -> Result<(), PoisonError<()>> {
+        let tracer = Arc::new(Mutex::new(tracer));
+
+        self.with_scheduler(|s| s.tracer = Some(tracer.clone()))?;
+        self.pool
+            .lock()
+            .map(|mut pool| pool.set_tracer(tracer))
+            .map_err(|_| PoisonError::new(()))
+    }
+
+    fn flush_trace(&self) -> Result<(), PoisonError<()>> {
+        self.with_scheduler(|s| {
+            if let Some(tracer) = &s.tracer {
+                tracer
+                    .lock()
+                    .expect("tried to acquire poisoned tracer lock")
+                    .flush();
+            }
+        })
+    }
+
+    fn add_host(&self, host: Arc<Host>) -> Result<(), PoisonError<()>> {
+        self.scheduler
+            .lock()
+            .map(|mut s| s.add_host(host))
+            .map_err(|_| PoisonError::new(()))
+    }
+
+    fn run(
+        &self,
+        stop_time: SimulationTime,
+        minimal_time_jump: SimulationTime,
+        bootstrap_end_time: SimulationTime,
+        heartbeat_interval: SimulationTime,
+        show_progress: bool,
+        parallelism: usize,
+    ) -> Result<RunStats, Box<dyn error::Error>> {
+        Worker::spawn(self.pool.clone(), 0.into(), bootstrap_end_time.into());
         self.start_scheduler()?;
 
-        loop {
-            break;
+        // The single worker runs on the first processor, which idles outside of events, while
+        // the others stay idle for the whole run.
+        let processors = Processors::new(parallelism);
+        let mut heartbeat = HeartBeat::new(heartbeat_interval);
+        let mut progress = Progress::new(show_progress, stop_time);
+        let mut end_time = SimulationTime::zero();
+        let mut is_interrupted = false;
+
+        'rounds: loop {
+            let next_time = self.with_scheduler(|s| s.next_time())?;
+            let start = match next_time {
+                Some(start) if start < stop_time => start,
+                Some(_) => {
+                    end_time = stop_time;
+                    break;
+                }
+                None => break,
+            };
+            let barrier = (start + minimal_time_jump).min(stop_time);
+
+            self.with_scheduler(|s| s.set_round_end(barrier))?;
+            Worker::set_round_end_time(barrier.into());
+
+            while let Some(event) = self.with_scheduler(|s| s.pop(barrier))? {
+                processors.pause_idle_timer(0);
+                Worker::set_current_time(event.time().into());
+                event.execute();
+                Worker::set_last_event_time(event.time().into());
+                heartbeat.count_event(event.time());
+                progress.count_event(event.time());
+                end_time = event.time();
+                processors.resume_idle_timer(0);
+
+                if interrupt::is_interrupted() {
+                    is_interrupted = true;
+                    break 'rounds;
+                }
+            }
         }
 
+        progress.finish();
+        (0..parallelism).for_each(|processor| processors.pause_idle_timer(processor));
+        Worker::clear_current_time();
         self.stop_scheduler()?;
-        Ok(())
+
+        if is_interrupted {
+            log::warn!(
+                "interrupted, stopping at simulated {:.3}s",
+                end_time.as_nanos() as f64 / 1e9
+            );
+        }
+
+        Ok(RunStats {
+            events: heartbeat.events,
+            end_time,
+            idle_times: (0..parallelism)
+                .map(|processor| processors.lapsed_idle_time(processor))
+                .collect(),
+            is_interrupted,
+        })
+    }
+
+    /// Queues a task before the simulation starts, when the worker cannot schedule yet.
+    fn schedule(
+        &self,
+        task: Task,
+        host: &Arc<Host>,
+        time: SimulationTime,
+    ) -> Result<(), PoisonError<()>> {
+        let event = Event::new(Arc::new(task), time, host.clone(), host.clone());
+        self.with_scheduler(|s| {
+            s.trace(EventState::Scheduled, &event);
+            s.policy.push(event)
+        })
+    }
+
+    fn with_scheduler<F, R>(&self, func: F) -> Result<R, PoisonError<()>>
+    where
+        F: FnOnce(&mut Scheduler) -> R,
+    {
+        self.scheduler
+            .lock()
+            .map(|mut s| func(&mut s))
+            .map_err(|_| PoisonError::new(()))
     }
 
     fn start_scheduler(&self) -> Result<(), PoisonError<()>> {
@@ -116,30 +344,514 @@ impl Simulation {
     }
 }
 
+/// How far a run of the simulation got.
+struct RunStats {
+    events: u64,
+    end_time: SimulationTime,
+    idle_times: Vec<Duration>,
+    is_interrupted: bool,
+}
+
+/// Logs the progress of the simulation whenever another `interval` of simulated time passed,
+/// with the rate of events and the speed relative to real time since the previous heartbeat.
+struct HeartBeat {
+    interval: SimulationTime,
+    next: SimulationTime,
+    started: Instant,
+    last: (Instant, SimulationTime, u64),
+    events: u64,
+}
+
+impl HeartBeat {
+    fn new(interval: SimulationTime) -> Self {
+        let now = Instant::now();
+
+        Self {
+            interval,
+            next: interval,
+            started: now,
+            last: (now, SimulationTime::zero(), 0),
+            events: 0,
+        }
+    }
+
+    fn count_event(&mut self, time: SimulationTime) {
+        self.events += 1;
+
+        if self.interval <= SimulationTime::zero() || time < self.next {
+            return;
+        }
+
+        let now = Instant::now();
+        let (last_wall, last_time, last_events) = self.last;
+        let wall = now.duration_since(last_wall).as_secs_f64();
+        let simulated = (time - last_time).as_nanos() as f64 / 1e9;
+        let (events_per_sec, speed) = match wall {
+            wall if wall > 0.0 => ((self.events - last_events) as f64 / wall, simulated / wall),
+            _ => (0.0, 0.0),
+        };
+
+        log::info!(
+            "heartbeat: simulated {:.3}s, wall {:.3}s, {} events, {:.0} events/s, {:.2}x real time",
+            time.as_nanos() as f64 / 1e9,
+            now.duration_since(self.started).as_secs_f64(),
+            self.events,
+            events_per_sec,
+            speed
+        );
+
+        self.last = (now, time, self.events);
+        self.next = time - time % self.interval + self.interval;
+    }
+}
+
 pub struct Driver {
+    config: Config,
     minimal_time_jump: SimulationTime,
     simulation: Simulation,
     random: Box<dyn RngCore>,
+    hosts: Vec<Arc<Host>>,
+    processes: Vec<(Arc<Host>, Instance)>,
+    registry: AppRegistry,
 }
 
 impl Driver {
-    pub fn new() -> Self {
-        Self {
-            minimal_time_jump: SimulationTime::from_millis(10),
-            simulation: Simulation::new(),
-            random: Box::new(SmallRng::from_entropy()),
-        }
+    pub fn new(config: Config, registry: AppRegistry) -> Result<Self, ConfigError> {
+        let topology = Topology::try_from(&config.network)?;
+        let minimal_time_jump = topology
+            .min_latency()
+            .map(SimulationTime::from)
+            .unwrap_or_else(|| SimulationTime::from_millis(10));
+
+        Ok(Self {
+            minimal_time_jump,
+            simulation: Simulation::new(topology),
+            random: Box::new(SmallRng::seed_from_u64(config.general.seed)),
+            hosts: Vec::new(),
+            processes: Vec::new(),
+            registry,
+            config,
+        })
     }
 
     pub fn run(mut self) -> Result<(), Box<dyn error::Error>> {
-        // load topology
+        let total = PerfTimer::start();
+        let setup = PerfTimer::start();
+
+        let data_directory = self.config.general.data_directory.clone();
+        fs::create_dir_all(&data_directory)?;
+
+        if let Some(format) = self.config.general.trace {
+            self.simulation
+                .set_tracer(Tracer::create(&data_directory, format)?)?;
+        }
+
+        self.create_hosts()?;
+        interrupt::install()?;
+        let setup = setup.stop();
+
+        let simulation = PerfTimer::start();
+        let general = &self.config.general;
+        let result = self.simulation.run(
+            general.stop_time.into(),
+            self.minimal_time_jump,
+            general.bootstrap_end_time.into(),
+            general.heartbeat_interval.into(),
+            general.progress.unwrap_or_else(progress::is_terminal),
+            general.parallelism.max(1) as usize,
+        );
+        let simulation = simulation.stop();
+
+        let teardown = PerfTimer::start();
+        // The expected final states refer to the stop time, which an interrupted run missed.
+        let is_interrupted = matches!(
+            result,
+            Ok(RunStats {
+                is_interrupted: true,
+                ..
+            })
+        );
+        let mismatches = match is_interrupted {
+            true => 0,
+            false => self.check_final_states(),
+        };
+
+        for (host, process) in &self.processes {
+            process.kill(host);
+        }
+        for host in &self.hosts {
+            host.flush_log();
+            host.flush_pcap();
+        }
+        self.simulation.flush_trace()?;
+        let teardown = teardown.stop();
+
+        let stats = result?;
+
+        let summary = Summary {
+            simulated_time: stats.end_time,
+            events: stats.events,
+            wall_times: vec![
+                ("setup", setup),
+                ("simulation", simulation),
+                ("teardown", teardown),
+                ("total", total.stop()),
+            ],
+            idle_times: stats.idle_times,
+            hosts: &self.hosts,
+            edges: self.simulation.topology.edge_traffic(),
+            is_interrupted,
+        };
+        summary.write(&data_directory.join("summary.json"))?;
+
+        if is_interrupted {
+            let seconds = stats.end_time.as_nanos() as f64 / 1e9;
+            return Err(Box::new(Interrupted(seconds)));
+        }
+
+        match mismatches {
+            0 => Ok(()),
+            count => Err(Box::new(UnexpectedFinalState(count))),
+        }
+    }
+
+    /// Compares the state of every process with the expected one and returns the number of
+    /// processes that ended differently.
+    fn check_final_states(&self) -> usize {
+        let mut mismatches = 0;
+
+        for (host, process) in &self.processes {
+            let expected = match process.expected_final_state() {
+                Some(expected) => expected,
+                None => continue,
+            };
+
+            match process.final_state() {
+                Some(state) if state == expected => {}
+                Some(state) => {
+                    log::error!(
+                        "process `{}` on `{}` is {}, expected it to be {}",
+                        process.name(),
+                        host.name(),
+                        state,
+                        expected
+                    );
+                    mismatches += 1;
+                }
+                None => {
+                    log::error!(
+                        "process `{}` on `{}` never started, expected it to be {}",
+                        process.name(),
+                        host.name(),
+                        expected
+                    );
+                    mismatches += 1;
+                }
+            }
+        }
+
+        mismatches
+    }
+
+    fn create_hosts(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let mut hosts = Vec::new();
+
+        for config in &self.config.hosts {
+            let node_id = NodeId::try_from(config.network_node_id)?;
+            let node = self.simulation.topology.node(node_id).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "host `{}` references unknown graph node `{}`",
+                    config.name, config.network_node_id
+                ))
+            })?;
+
+            for index in 0..config.quantity {
+                let name = if config.quantity > 1 {
+                    format!("{}{}", config.name, index + 1)
+                } else {
+                    config.name.clone()
+                };
+
+                hosts.push(HostAddresses::new(name, config, node.clone())?);
+            }
+        }
+
+        let mut name_server = self
+            .simulation
+            .name_server
+            .lock()
+            .map_err(|_| PoisonError::new(()))?;
+
+        for host in &hosts {
+            for ip in host.ipv4.into_iter().map(IpAddr::V4) {
+                name_server.register(host.name.clone(), ip)?;
+            }
+            for ip in host.ipv6.into_iter().map(IpAddr::V6) {
+                name_server.register(host.name.clone(), ip)?;
+            }
+        }
+
+        for host in &mut hosts {
+            host.allocate(&mut name_server)?;
+        }
+
+        for host in &hosts {
+            let ips = host
+                .ipv4
+                .map(IpAddr::V4)
+                .into_iter()
+                .chain(host.ipv6.map(IpAddr::V6));
+
+            for ip in ips {
+                for alias in &host.config.aliases {
+                    name_server.add_address(alias, ip, NameServer::DEFAULT_TTL)?;
+                }
+            }
+        }
+
+        for entry in self.config.dns.zone.entries() {
+            match &entry.data {
+                ZoneData::Address(ip) => name_server.add_address(&entry.name, *ip, entry.ttl)?,
+                ZoneData::Alias(target) => name_server.add_alias(&entry.name, target, entry.ttl)?,
+            }
+        }
+
+        drop(name_server);
+
+        let template = match &self.config.general.template_directory {
+            Some(root) => Some(Template::new(root.clone())?),
+            None => None,
+        };
+
+        for (id, host) in hosts.into_iter().enumerate() {
+            let config = host.config;
+            let bandwidth_down = config
+                .bandwidth_down
+                .or_else(|| host.node.bandwidth_down())
+                .ok_or_else(|| {
+                    ConfigError::Invalid(format!("no downstream bandwidth for `{}`", host.name))
+                })?;
+            let bandwidth_up = config
+                .bandwidth_up
+                .or_else(|| host.node.bandwidth_up())
+                .ok_or_else(|| {
+                    ConfigError::Invalid(format!("no upstream bandwidth for `{}`", host.name))
+                })?;
+
+            let directory = self
+                .config
+                .general
+                .data_directory
+                .join("hosts")
+                .join(&host.name);
+            fs::create_dir_all(&directory)?;
+
+            if let Some(template) = &template {
+                let mut placeholders = Placeholders::default();
+                placeholders.insert("hostname", &host.name);
+                if let Some(ip) = host.ipv4.map(IpAddr::V4).or(host.ipv6.map(IpAddr::V6)) {
+                    placeholders.insert("ip", ip);
+                }
+                if let Some(ipv4) = host.ipv4 {
+                    placeholders.insert("ipv4", ipv4);
+                }
+                if let Some(ipv6) = host.ipv6 {
+                    placeholders.insert("ipv6", ipv6);
+                }
+
+                template.expand(&directory, &[&config.name, &host.name], &placeholders)?;
+            }
+
+            let log_file = File::create(directory.join(format!("{}.log", host.name)))?;
+            let log_level = config
+                .options
+                .log_level
+                .or(self.config.host_defaults.log_level)
+                .unwrap_or(self.config.general.log_level);
+
+            let pcap = self.create_pcap(&directory, &host.name, config)?;
+
+            let seed = self.random.next_u64();
+
+            let mut environment = self.config.host_defaults.environment.clone();
+            environment.merge(&config.options.environment);
+
+            // Set last, so that processes can rely on them.
+            let mut automatic = Environment::default();
+            automatic.set("NETSIM_HOSTNAME", host.name.clone());
+            if let Some(ip) = host.ipv4.map(IpAddr::V4).or(host.ipv6.map(IpAddr::V6)) {
+                automatic.set("NETSIM_HOST_IP", ip.to_string());
+            }
+            automatic.set("NETSIM_SEED", seed.to_string());
+
+            let host = Host::new(HostParams {
+                id: (id as isize).into(),
+                name: host.name,
+                ipv4: host.ipv4,
+                ipv6: host.ipv6,
+                node_id: host.node.id(),
+                bandwidth_down,
+                bandwidth_up,
+                seed,
+                log_level,
+                log_file: Some(log_file),
+                pcap,
+            });
+
+            let host = Arc::new(host);
+            self.simulation.add_host(host.clone())?;
+            self.hosts.push(host.clone());
+
+            let heartbeat_interval = self.config.general.heartbeat_interval.into();
+            if heartbeat_interval > SimulationTime::zero() {
+                let task = Task::HeartBeat(heartbeat_interval);
+                self.simulation.schedule(task, &host, heartbeat_interval)?;
+            }
+
+            let processes =
+                self.create_processes(&directory, &host, config, &environment, &automatic)?;
+            self.processes
+                .extend(processes.into_iter().map(|process| (host.clone(), process)));
+        }
 
-        let seed = self.derive_seed();
-        SmallRng::from_seed(seed);
-        // create new simulation
         Ok(())
     }
 
+    /// Opens the capture file of a host if it captures, by its own `pcap_enabled` or else the
+    /// default, which is to capture whenever a `pcap_directory` is configured. The file goes into
+    /// that directory, or the directory of the host without one.
+    fn create_pcap(
+        &self,
+        directory: &Path,
+        name: &str,
+        config: &HostsConfig,
+    ) -> Result<Option<PcapWriter>, Box<dyn error::Error>> {
+        let options = &config.options;
+        let defaults = &self.config.host_defaults;
+
+        let pcap_directory = options
+            .pcap_directory
+            .as_ref()
+            .or(defaults.pcap_directory.as_ref());
+        let is_enabled = options
+            .pcap_enabled
+            .or(defaults.pcap_enabled)
+            .unwrap_or(pcap_directory.is_some());
+        if !is_enabled {
+            return Ok(None);
+        }
+
+        let pcap_directory = pcap_directory.map_or(directory, |path| path.as_path());
+        fs::create_dir_all(pcap_directory)?;
+
+        let snap_len = options
+            .pcap_capture_size
+            .or(defaults.pcap_capture_size)
+            .map_or(DEFAULT_SNAP_LEN, |size| {
+                size.bytes().clamp(1, u32::MAX as u64) as u32
+            });
+        let path = pcap_directory.join(format!("{}.pcap", name));
+
+        Ok(Some(PcapWriter::create(&path, snap_len)?))
+    }
+
+    /// Creates the processes of a host, which run in the directory of the host and write their
+    /// output next to it. The environment of a process is its own merged between the defaults of
+    /// the host and the automatic variables. Applications only take the arguments.
+    fn create_processes(
+        &self,
+        directory: &Path,
+        host: &Arc<Host>,
+        config: &HostsConfig,
+        defaults: &Environment,
+        automatic: &Environment,
+    ) -> Result<Vec<Instance>, Box<dyn error::Error>> {
+        let mut processes = Vec::new();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+
+        for process in &config.processes {
+            let path = match (&process.path, &process.app) {
+                (Some(path), None) => Some(fs::canonicalize(path).map_err(|err| {
+                    ConfigError::Invalid(format!(
+                        "process `{}` of host `{}`: {}",
+                        path.display(),
+                        host.name(),
+                        err
+                    ))
+                })?),
+                (None, Some(_)) => None,
+                _ => {
+                    return Err(ConfigError::Invalid(format!(
+                        "processes of host `{}` need either a `path` or an `app`",
+                        host.name()
+                    ))
+                    .into())
+                }
+            };
+            let name = match (&path, &process.app) {
+                (Some(path), _) => path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                (None, app) => app.clone().unwrap_or_default(),
+            };
+
+            let mut environment = defaults.clone();
+            environment.merge(&process.environment);
+            environment.merge(automatic);
+
+            for _ in 0..process.quantity {
+                let count = counts.entry(name.clone()).or_insert(0);
+                *count += 1;
+
+                let name = format!("{}.{}", name, count);
+
+                let instance = match (&path, &process.app) {
+                    (Some(path), _) => Instance::Process(Process::new(ProcessParams {
+                        stdout: directory.join(format!("{}.stdout", name)),
+                        stderr: directory.join(format!("{}.stderr", name)),
+                        name,
+                        path: path.clone(),
+                        args: process.args.to_vec(),
+                        environment: environment.clone().into_pairs(),
+                        working_directory: directory.to_owned(),
+                        stop_signal: process.stop_signal,
+                        stop_grace_period: process.stop_grace_period.into(),
+                        restart: process.restart,
+                        restart_delay: process.restart_delay.into(),
+                        expected_final_state: process.expected_final_state,
+                    })),
+                    (None, app) => Instance::App(HostedApp::new(HostedAppParams {
+                        app: self
+                            .registry
+                            .create(app.as_deref().unwrap_or_default(), &process.args.to_vec())?,
+                        stdout: directory.join(format!("{}.stdout", name)),
+                        name,
+                        stop_signal: process.stop_signal,
+                        expected_final_state: process.expected_final_state,
+                    })),
+                };
+
+                let (start, stop) = match &instance {
+                    Instance::Process(process) => (
+                        Task::StartProcess(process.clone()),
+                        Task::StopProcess(process.clone()),
+                    ),
+                    Instance::App(app) => (Task::StartApp(app.clone()), Task::StopApp(app.clone())),
+                };
+                self.simulation
+                    .schedule(start, host, process.start_time.into())?;
+                if let Some(stop_time) = process.stop_time {
+                    self.simulation.schedule(stop, host, stop_time.into())?;
+                }
+
+                processes.push(instance);
+            }
+        }
+
+        Ok(processes)
+    }
+
     fn lapsed(&self) {}
 
     fn has_finished_current_rount(&self, simulation: &Simulation) -> bool {
@@ -147,10 +859,330 @@ impl Driver {
     }
 
     fn latency_between(&self, src: &IpAddr, dst: &IpAddr) {}
+}
+
+struct HostAddresses<'a> {
+    name: String,
+    config: &'a HostsConfig,
+    node: Arc<Node>,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+}
+
+impl<'a> HostAddresses<'a> {
+    fn new(name: String, config: &'a HostsConfig, node: Arc<Node>) -> Result<Self, ConfigError> {
+        let invalid = |reason: &str| ConfigError::Invalid(format!("host `{}` {}", name, reason));
+        let is_fixed = |ip: Option<IpAddrConfig>| matches!(ip, Some(IpAddrConfig::Fixed(_)));
+
+        if config.quantity > 1 && (is_fixed(config.ip_addr) || is_fixed(config.ipv6_addr)) {
+            return Err(invalid("with quantity > 1 cannot have a fixed ip address"));
+        }
+
+        let (ipv4, ipv6) = match (config.ip_addr, config.ipv6_addr) {
+            (Some(IpAddrConfig::Fixed(IpAddr::V6(_))), Some(_)) => {
+                return Err(invalid("has an ipv6 `ip_addr` and an `ipv6_addr`"));
+            }
+            (_, Some(IpAddrConfig::Fixed(IpAddr::V4(_)))) => {
+                return Err(invalid("has an ipv4 `ipv6_addr`"));
+            }
+            (Some(IpAddrConfig::Fixed(IpAddr::V6(ip))), None) => (None, Some(ip)),
+            (Some(IpAddrConfig::Fixed(IpAddr::V4(ip))), ipv6) => (Some(ip), ipv6_of(ipv6)),
+            (Some(IpAddrConfig::Auto) | None, ipv6) => (None, ipv6_of(ipv6)),
+        };
+
+        Ok(Self {
+            name,
+            config,
+            node,
+            ipv4,
+            ipv6,
+        })
+    }
+
+    fn allocate(&mut self, name_server: &mut NameServer) -> Result<(), Box<dyn error::Error>> {
+        let wants_ipv4 = !matches!(
+            self.config.ip_addr,
+            Some(IpAddrConfig::Fixed(IpAddr::V6(_)))
+        );
+        let wants_ipv6 = self.config.ipv6_addr == Some(IpAddrConfig::Auto);
+
+        if wants_ipv4 && self.ipv4.is_none() {
+            if let IpAddr::V4(ip) = self.next_free(name_server, AddressFamily::Inet)? {
+                self.ipv4 = Some(ip);
+            }
+        }
+
+        if wants_ipv6 && self.ipv6.is_none() {
+            if let IpAddr::V6(ip) = self.next_free(name_server, AddressFamily::Inet6)? {
+                self.ipv6 = Some(ip);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_free(
+        &self,
+        name_server: &mut NameServer,
+        family: AddressFamily,
+    ) -> Result<IpAddr, Box<dyn error::Error>> {
+        let ip = match self.node.ip_addr_pool() {
+            Some(pool) if pool.family() == family => {
+                name_server.allocate_from(&pool).ok_or_else(|| {
+                    ConfigError::Invalid(format!(
+                        "ip address pool `{}` of graph node `{}` is exhausted",
+                        pool,
+                        isize::from(self.node.id())
+                    ))
+                })?
+            }
+            _ => name_server.allocate(family).ok_or_else(|| {
+                ConfigError::Invalid(format!("no free {:?} address left", family))
+            })?,
+        };
 
-    fn derive_seed(&mut self) -> Seed {
-        let mut seed = [0u8; 32];
-        self.random.fill_bytes(&mut seed);
-        seed
+        name_server.register(self.name.clone(), ip)?;
+        Ok(ip)
+    }
+}
+
+/// A process of a host, either running a binary or a registered application.
+enum Instance {
+    Process(Process),
+    App(HostedApp),
+}
+
+impl Instance {
+    fn name(&self) -> String {
+        match self {
+            Instance::Process(process) => process.name(),
+            Instance::App(app) => app.name(),
+        }
+    }
+
+    fn final_state(&self) -> Option<FinalState> {
+        match self {
+            Instance::Process(process) => process.final_state(),
+            Instance::App(app) => app.final_state(),
+        }
+    }
+
+    fn expected_final_state(&self) -> Option<FinalState> {
+        match self {
+            Instance::Process(process) => process.expected_final_state(),
+            Instance::App(app) => app.expected_final_state(),
+        }
+    }
+
+    fn kill(&self, host: &Arc<Host>) {
+        match self {
+            Instance::Process(process) => process.kill(host),
+            Instance::App(app) => app.kill(host),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnexpectedFinalState(usize);
+
+impl error::Error for UnexpectedFinalState {}
+
+impl fmt::Display for UnexpectedFinalState {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{} process(es) did not end in their expected final state",
+            self.0
+        )
+    }
+}
+
+#[derive(Debug)]
+/// Seconds of simulated time a run reached before SIGINT stopped it.
+pub struct Interrupted(f64);
+
+impl error::Error for Interrupted {}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "simulation interrupted at {:.3}s of simulated time",
+            self.0
+        )
+    }
+}
+
+fn ipv6_of(config: Option<IpAddrConfig>) -> Option<Ipv6Addr> {
+    match config {
+        Some(IpAddrConfig::Fixed(IpAddr::V6(ip))) => Some(ip),
+        _ => None,
+    }
+}
+
+/// A network for tests of the parts that only work within a running simulation, like sockets
+/// sending packets. Its events execute on the calling thread when the test advances the time.
+#[cfg(test)]
+mod tests {
+    use super::testing::TestNetwork;
+    use super::*;
+
+    fn millis(millis: i64) -> SimulationTime {
+        SimulationTime::from_millis(millis)
+    }
+
+    #[test]
+    fn pop_events_in_order_across_hosts() {
+        let network = TestNetwork::new(&["11.0.0.1", "11.0.0.2", "11.0.0.3"]);
+        let event = |time: i64, index: usize| {
+            let host = network.host(index).clone();
+            let task = Arc::new(Task::HeartBeat(SimulationTime::zero()));
+            Event::new(task, millis(time), host.clone(), host)
+        };
+        let id = |index: usize| network.host(index).id();
+
+        let mut policy = HostSinglePolicy::new();
+        for (time, index) in [(30, 0), (10, 1), (20, 2), (10, 0), (5, 2), (40, 1)] {
+            policy.push(event(time, index));
+        }
+        assert_eq!(policy.next_time(), Some(millis(5)));
+
+        let mut popped = Vec::new();
+        while let Some(event) = policy.pop(millis(35)) {
+            popped.push((event.time(), event.host().id()));
+        }
+        assert_eq!(
+            popped,
+            [
+                (millis(5), id(2)),
+                (millis(10), id(0)),
+                (millis(10), id(1)),
+                (millis(20), id(2)),
+                (millis(30), id(0)),
+            ]
+        );
+        assert_eq!(policy.next_time(), Some(millis(40)));
+
+        policy.push(event(35, 1));
+        assert_eq!(policy.next_time(), Some(millis(35)));
+        assert_eq!(
+            policy.pop(millis(36)).map(|event| event.time()),
+            Some(millis(35))
+        );
+        assert!(policy.pop(millis(36)).is_none());
+        assert_eq!(
+            policy.pop(millis(41)).map(|event| event.time()),
+            Some(millis(40))
+        );
+        assert_eq!(policy.next_time(), None);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use log::LevelFilter;
+
+    use super::*;
+    use crate::config::NetworkConfig;
+
+    const NETWORK: &str = r#"
+        [graph]
+        node = { id = 0, host_bandwidth_down = "100 mbit", host_bandwidth_up = "100 mbit" }
+        edge = { source = 0, target = 0, latency = "10 ms" }
+    "#;
+
+    pub(crate) struct TestNetwork {
+        simulation: Simulation,
+        hosts: Vec<Arc<Host>>,
+        now: SimulationTime,
+    }
+
+    impl TestNetwork {
+        /// Starts a simulation with a host for each address, 10 ms apart from each other, and
+        /// makes the calling thread its worker.
+        pub fn new(ips: &[&str]) -> Self {
+            let config: NetworkConfig = toml::from_str(NETWORK).expect("invalid test network");
+            let simulation = Simulation::new(Topology::try_from(&config).expect("no topology"));
+            let node = simulation
+                .topology
+                .node(0.into())
+                .expect("test network without node");
+
+            let mut hosts = Vec::new();
+            for (index, ip) in ips.iter().enumerate() {
+                let ip: IpAddr = ip.parse().expect("invalid test address");
+                let name = format!("host{}", index + 1);
+
+                simulation
+                    .name_server
+                    .lock()
+                    .expect("accessed poisoned name server")
+                    .register(name.clone(), ip)
+                    .expect("cannot register test host");
+
+                let host = Arc::new(Host::new(HostParams {
+                    id: (index as isize).into(),
+                    name,
+                    ipv4: match ip {
+                        IpAddr::V4(ip) => Some(ip),
+                        IpAddr::V6(_) => None,
+                    },
+                    ipv6: match ip {
+                        IpAddr::V4(_) => None,
+                        IpAddr::V6(ip) => Some(ip),
+                    },
+                    node_id: node.id(),
+                    bandwidth_down: node.bandwidth_down().expect("no bandwidth"),
+                    bandwidth_up: node.bandwidth_up().expect("no bandwidth"),
+                    seed: index as u64,
+                    log_level: LevelFilter::Off,
+                    log_file: None,
+                    pcap: None,
+                }));
+                simulation
+                    .add_host(host.clone())
+                    .expect("cannot add test host");
+                hosts.push(host);
+            }
+
+            Worker::spawn(
+                simulation.pool.clone(),
+                0.into(),
+                SimulationTime::zero().into(),
+            );
+            simulation
+                .start_scheduler()
+                .expect("cannot start scheduler");
+            Worker::set_current_time(SimulationTime::zero().into());
+
+            Self {
+                simulation,
+                hosts,
+                now: SimulationTime::zero(),
+            }
+        }
+
+        pub fn host(&self, index: usize) -> &Arc<Host> {
+            &self.hosts[index]
+        }
+
+        /// Executes the events up to and including `delay` from now, and leaves the clock at
+        /// that time for the calls of the test.
+        pub fn run_for(&mut self, delay: SimulationTime) {
+            let end = self.now + delay;
+            let barrier = end + SimulationTime::from_nanos(1);
+
+            while let Some(event) = self
+                .simulation
+                .with_scheduler(|s| s.pop(barrier))
+                .expect("accessed poisoned scheduler")
+            {
+                Worker::set_current_time(event.time().into());
+                event.execute();
+            }
+
+            self.now = end;
+            Worker::set_current_time(end.into());
+        }
     }
 }
@@ -0,0 +1,638 @@
+//! Applications written in Rust that run inside the simulation in place of real binaries.
+//!
+//! An application implements [`SimApp`] and is registered by name with
+//! [`AppBuilder::register_app`](crate::app::AppBuilder::register_app), after which hosts run it
+//! with `app = "<name>"` instead of a `path` in their `processes`. Its callbacks are events of
+//! the host, so applications cost no native process and are as deterministic as the rest of
+//! the simulation.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::config::ConfigError;
+use crate::host::Host;
+use crate::net::AddressFamily;
+use crate::process::{FinalState, Signal};
+use crate::task::Task;
+use crate::tcp::{unspecified, TcpSocket};
+use crate::time::SimulationTime;
+use crate::udp::UdpSocket;
+use crate::worker::Worker;
+
+const LISTEN_BACKLOG: usize = 1024;
+
+/// A simulated application, driven by callbacks on the events of its sockets and timers.
+///
+/// Socket callbacks are edge-triggered: `on_receive` is called once new data, the end of the
+/// stream or an error arrived, so the application should read until `recv` would block.
+pub trait SimApp: Send {
+    /// Called at the start time of the application.
+    fn on_start(&mut self, context: &mut AppContext);
+
+    /// Called when a timer set with [`AppContext::set_timer`] expires.
+    fn on_timer(&mut self, _context: &mut AppContext, _timer: TimerId) {}
+
+    /// Called when an outgoing TCP connection is established, or a listening socket accepted
+    /// a new connection, which `socket` then refers to.
+    fn on_connect(&mut self, _context: &mut AppContext, _socket: SocketId) {}
+
+    /// Called when data, the end of the stream or an error is ready to be received.
+    fn on_receive(&mut self, _context: &mut AppContext, _socket: SocketId) {}
+
+    /// Called when a TCP socket has room again after a send was cut short.
+    fn on_writable(&mut self, _context: &mut AppContext, _socket: SocketId) {}
+
+    /// Called at the stop time of the application. An application that does not exit here
+    /// ends as if killed by its stop signal.
+    fn on_stop(&mut self, _context: &mut AppContext) {}
+}
+
+/// Creates an application from the arguments of its configuration.
+pub type AppFactory = Box<dyn Fn(&[String]) -> Result<Box<dyn SimApp>, String> + Send + Sync>;
+
+/// The applications available to the configuration, by name.
+#[derive(Default)]
+pub struct AppRegistry {
+    factories: BTreeMap<String, AppFactory>,
+}
+
+impl AppRegistry {
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&[String]) -> Result<Box<dyn SimApp>, String> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.to_owned(), Box::new(factory));
+    }
+
+    pub(crate) fn create(
+        &self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Box<dyn SimApp>, ConfigError> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| ConfigError::Invalid(format!("unknown app `{}`", name)))?;
+
+        factory(args).map_err(|err| ConfigError::Invalid(format!("app `{}`: {}", name, err)))
+    }
+}
+
+/// A socket opened by an application, valid until closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SocketId(u32);
+
+impl fmt::Display for SocketId {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "socket {}", self.0)
+    }
+}
+
+/// A timer set by an application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+enum Socket {
+    Tcp(TcpSocket),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn generation(&self) -> u64 {
+        match self {
+            Socket::Tcp(socket) => socket.notifier_generation(),
+            Socket::Udp(socket) => socket.notifier_generation(),
+        }
+    }
+
+    fn events(&self) -> u32 {
+        match self {
+            Socket::Tcp(socket) => socket.events(),
+            Socket::Udp(socket) => socket.events(),
+        }
+    }
+
+    fn close(&self, host: &Arc<Host>) {
+        match self {
+            Socket::Tcp(socket) => socket.close(host),
+            Socket::Udp(socket) => socket.close(host),
+        }
+    }
+}
+
+struct AppSocket {
+    socket: Socket,
+    /// The notifier generation of the socket when its events were last dispatched.
+    generation: Option<u64>,
+    is_connecting: bool,
+    is_send_blocked: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Running,
+    Exited(i32),
+    Stopped(Signal),
+}
+
+pub struct HostedAppParams {
+    pub name: String,
+    pub app: Box<dyn SimApp>,
+    pub stop_signal: Signal,
+    pub expected_final_state: Option<FinalState>,
+    /// File the output of the application is written to, created when it starts.
+    pub stdout: PathBuf,
+}
+
+struct HostedAppState {
+    params: HostedAppParams,
+    status: Status,
+    output: Option<BufWriter<File>>,
+    sockets: BTreeMap<SocketId, AppSocket>,
+    next_socket: u32,
+    timers: BTreeSet<TimerId>,
+    next_timer: u64,
+}
+
+/// An application running on a host, woken by the notifiers of its sockets like a blocked
+/// process.
+#[derive(Clone)]
+pub struct HostedApp(Arc<Mutex<HostedAppState>>);
+
+impl HostedApp {
+    pub fn new(params: HostedAppParams) -> Self {
+        Self(Arc::new(Mutex::new(HostedAppState {
+            params,
+            status: Status::Pending,
+            output: None,
+            sockets: BTreeMap::new(),
+            next_socket: 0,
+            timers: BTreeSet::new(),
+            next_timer: 0,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HostedAppState> {
+        self.0.lock().expect("accessed poisoned app")
+    }
+
+    pub fn ptr_eq(&self, other: &HostedApp) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn name(&self) -> String {
+        self.lock().params.name.clone()
+    }
+
+    pub fn final_state(&self) -> Option<FinalState> {
+        match self.lock().status {
+            Status::Pending => None,
+            Status::Running => Some(FinalState::Running),
+            Status::Exited(code) => Some(FinalState::Exited(code)),
+            Status::Stopped(signal) => Some(FinalState::Signaled(signal)),
+        }
+    }
+
+    pub fn expected_final_state(&self) -> Option<FinalState> {
+        self.lock().params.expected_final_state
+    }
+
+    pub fn start(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+
+        if state.status != Status::Pending {
+            return;
+        }
+
+        log::info!("started app `{}` on `{}`", state.params.name, host.name());
+        state.status = Status::Running;
+
+        match File::create(&state.params.stdout) {
+            Ok(file) => state.output = Some(BufWriter::new(file)),
+            Err(err) => log::warn!(
+                "cannot create output `{}` of app `{}`: {}",
+                state.params.stdout.display(),
+                state.params.name,
+                err
+            ),
+        }
+
+        self.dispatch(&mut state, host, |app, context| app.on_start(context));
+        self.settle(&mut state, host);
+    }
+
+    pub fn stop(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+
+        if state.status != Status::Running {
+            if state.status == Status::Pending {
+                state.status = Status::Stopped(state.params.stop_signal);
+            }
+            return;
+        }
+
+        log::info!("stopping app `{}` on `{}`", state.params.name, host.name());
+        self.dispatch(&mut state, host, |app, context| app.on_stop(context));
+
+        if state.status == Status::Running {
+            let signal = state.params.stop_signal;
+            state.finish(host, Status::Stopped(signal));
+        }
+    }
+
+    /// Ends the application at the end of the simulation, without a callback.
+    pub fn kill(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+
+        if state.status == Status::Running {
+            state.finish(host, Status::Stopped(Signal::KILL));
+        }
+    }
+
+    pub fn fire_timer(&self, host: &Arc<Host>, timer: TimerId) {
+        let mut state = self.lock();
+
+        if state.timers.remove(&timer) {
+            self.dispatch(&mut state, host, |app, context| {
+                app.on_timer(context, timer)
+            });
+            self.settle(&mut state, host);
+        }
+    }
+
+    /// Dispatches the events of the sockets whose notifiers fired, in the order the sockets
+    /// were opened, until none is left.
+    pub fn wake(&self, host: &Arc<Host>) {
+        let mut state = self.lock();
+        self.settle(&mut state, host);
+    }
+
+    fn settle(&self, state: &mut HostedAppState, host: &Arc<Host>) {
+        loop {
+            let changed: Vec<_> = state
+                .sockets
+                .iter()
+                .filter(|(_, entry)| entry.generation != Some(entry.socket.generation()))
+                .map(|(id, _)| *id)
+                .collect();
+
+            if changed.is_empty() || state.status != Status::Running {
+                break;
+            }
+
+            for id in changed {
+                self.dispatch_socket(state, host, id);
+            }
+        }
+
+        if state.status == Status::Running {
+            for entry in state.sockets.values() {
+                match &entry.socket {
+                    Socket::Tcp(socket) => socket.wait_app(self),
+                    Socket::Udp(socket) => socket.wait_app(self),
+                }
+            }
+        }
+    }
+
+    fn dispatch_socket(&self, state: &mut HostedAppState, host: &Arc<Host>, id: SocketId) {
+        let entry = match state.sockets.get_mut(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.generation = Some(entry.socket.generation());
+
+        if let Socket::Tcp(socket) = &entry.socket {
+            if socket.is_listening() {
+                let socket = socket.clone();
+                while let Ok(connection) = socket.accept() {
+                    let id = state.insert(Socket::Tcp(connection));
+                    self.dispatch(state, host, |app, context| app.on_connect(context, id));
+                }
+                return;
+            }
+        }
+
+        let events = entry.socket.events();
+        let is_readable = events
+            & (libc::EPOLLIN | libc::EPOLLERR | libc::EPOLLHUP | libc::EPOLLRDHUP) as u32
+            != 0;
+        let is_writable = events & libc::EPOLLOUT as u32 != 0;
+        let is_failed = events & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0;
+
+        let is_connected = entry.is_connecting && is_writable && !is_failed;
+        if is_connected || (entry.is_connecting && is_failed) {
+            entry.is_connecting = false;
+        }
+        let is_unblocked = entry.is_send_blocked && is_writable && !entry.is_connecting;
+        if is_unblocked {
+            entry.is_send_blocked = false;
+        }
+
+        if is_connected {
+            self.dispatch(state, host, |app, context| app.on_connect(context, id));
+        }
+        if is_readable && state.sockets.contains_key(&id) {
+            self.dispatch(state, host, |app, context| app.on_receive(context, id));
+        }
+        if is_unblocked && state.sockets.contains_key(&id) {
+            self.dispatch(state, host, |app, context| app.on_writable(context, id));
+        }
+    }
+
+    /// Runs a callback of the application unless it has ended.
+    fn dispatch<F>(&self, state: &mut HostedAppState, host: &Arc<Host>, callback: F)
+    where
+        F: FnOnce(&mut dyn SimApp, &mut AppContext),
+    {
+        if !matches!(state.status, Status::Running) {
+            return;
+        }
+
+        // The application is borrowed from the state the context hands out, so it is swapped
+        // out for the duration of the callback.
+        let mut app = std::mem::replace(&mut state.params.app, Box::new(Detached));
+        let mut context = AppContext {
+            this: self,
+            host,
+            state,
+        };
+        callback(app.as_mut(), &mut context);
+        state.params.app = app;
+    }
+}
+
+impl HostedAppState {
+    fn insert(&mut self, socket: Socket) -> SocketId {
+        let id = SocketId(self.next_socket);
+        self.next_socket += 1;
+
+        self.sockets.insert(
+            id,
+            AppSocket {
+                socket,
+                generation: None,
+                is_connecting: false,
+                is_send_blocked: false,
+            },
+        );
+        id
+    }
+
+    fn socket(&mut self, id: SocketId) -> io::Result<&mut AppSocket> {
+        self.sockets
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    fn finish(&mut self, host: &Arc<Host>, status: Status) {
+        self.status = status;
+        self.timers.clear();
+
+        for (_, entry) in std::mem::take(&mut self.sockets) {
+            entry.socket.close(host);
+        }
+
+        if let Some(mut output) = self.output.take() {
+            if let Err(err) = output.flush() {
+                log::warn!("cannot write output of app `{}`: {}", self.params.name, err);
+            }
+        }
+
+        let state = match status {
+            Status::Exited(code) => FinalState::Exited(code),
+            Status::Stopped(signal) => FinalState::Signaled(signal),
+            Status::Pending | Status::Running => FinalState::Running,
+        };
+        log::info!("app `{}` finished, {}", self.params.name, state);
+    }
+}
+
+/// Stands in for the application while one of its callbacks runs.
+struct Detached;
+
+impl SimApp for Detached {
+    fn on_start(&mut self, _context: &mut AppContext) {}
+}
+
+/// The view of the simulation an application gets in its callbacks.
+pub struct AppContext<'a> {
+    this: &'a HostedApp,
+    host: &'a Arc<Host>,
+    state: &'a mut HostedAppState,
+}
+
+impl<'a> AppContext<'a> {
+    /// The simulated time since the start of the simulation.
+    pub fn now(&self) -> Duration {
+        let now = Worker::current_time()
+            .map(SimulationTime::from)
+            .unwrap_or_else(SimulationTime::zero);
+        Duration::from_nanos(now.as_nanos().max(0) as u64)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.state.params.name
+    }
+
+    /// The host the application runs on, for the built-in applications using parts of the
+    /// network stack not exposed here.
+    pub(crate) fn host(&self) -> &Arc<Host> {
+        self.host
+    }
+
+    pub fn host_name(&self) -> &str {
+        self.host.name()
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.host.ip()
+    }
+
+    /// A number in `[0, 1)` drawn from the seeded random source of the host.
+    pub fn random(&self) -> f64 {
+        self.host.random_f64()
+    }
+
+    pub fn fill_random(&self, bytes: &mut [u8]) {
+        self.host.fill_random(bytes)
+    }
+
+    /// Looks a name up in the simulated name server, preferring the address family of the
+    /// host.
+    pub fn resolve(&self, name: &str) -> Option<IpAddr> {
+        let name_server = Worker::name_server();
+        let mut name_server = name_server.lock().ok()?;
+        let family = AddressFamily::from(self.host.ip());
+        let other = match family {
+            AddressFamily::Inet => AddressFamily::Inet6,
+            AddressFamily::Inet6 => AddressFamily::Inet,
+        };
+
+        [family, other].into_iter().find_map(|family| {
+            name_server
+                .resolve(name, family)
+                .and_then(|resolution| resolution.addresses.first().map(|record| record.ip))
+        })
+    }
+
+    /// Calls `on_timer` with the returned id after `delay`.
+    pub fn set_timer(&mut self, delay: Duration) -> TimerId {
+        let timer = TimerId(self.state.next_timer);
+        self.state.next_timer += 1;
+        self.state.timers.insert(timer);
+
+        let delay = SimulationTime::from_nanos(delay.as_nanos().min(i64::MAX as u128) as i64);
+        Worker::schedule_task(Task::AppTimer(self.this.clone(), timer), self.host, delay);
+        timer
+    }
+
+    pub fn cancel_timer(&mut self, timer: TimerId) {
+        self.state.timers.remove(&timer);
+    }
+
+    /// Opens a TCP socket listening on `port` of all addresses of the host, whose
+    /// connections are reported by `on_connect`.
+    pub fn listen(&mut self, port: u16) -> io::Result<SocketId> {
+        let socket = TcpSocket::new();
+        let family = AddressFamily::from(self.host.ip());
+        socket.bind(self.host, SocketAddr::new(unspecified(family), port))?;
+        socket.listen(self.host, LISTEN_BACKLOG, family)?;
+
+        Ok(self.state.insert(Socket::Tcp(socket)))
+    }
+
+    /// Starts a TCP connection, reported by `on_connect` once established or by `on_receive`
+    /// with the error once failed.
+    pub fn connect(&mut self, addr: SocketAddr) -> io::Result<SocketId> {
+        let socket = TcpSocket::new();
+
+        match socket.connect(self.host, addr) {
+            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(err) => return Err(err),
+            Ok(()) => {}
+        }
+
+        let id = self.state.insert(Socket::Tcp(socket));
+        self.state.socket(id)?.is_connecting = true;
+        Ok(id)
+    }
+
+    /// Opens a UDP socket bound to `port` of all addresses of the host, or to an ephemeral
+    /// port for 0.
+    pub fn bind_udp(&mut self, port: u16) -> io::Result<SocketId> {
+        let socket = UdpSocket::new();
+        let family = AddressFamily::from(self.host.ip());
+        socket.bind(self.host, SocketAddr::new(unspecified(family), port))?;
+
+        Ok(self.state.insert(Socket::Udp(socket)))
+    }
+
+    /// Sends on a connected socket and returns how much was sent. A TCP send cut short
+    /// because the send buffer is full is followed by `on_writable`.
+    pub fn send(&mut self, socket: SocketId, buf: &[u8]) -> io::Result<usize> {
+        let host = self.host;
+        let entry = self.state.socket(socket)?;
+
+        let result = match &entry.socket {
+            Socket::Tcp(socket) => socket.send(host, buf),
+            Socket::Udp(socket) => socket.send(host, buf),
+        };
+
+        if let Socket::Tcp(_) = entry.socket {
+            match &result {
+                Ok(len) if *len < buf.len() => entry.is_send_blocked = true,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => entry.is_send_blocked = true,
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    pub fn send_to(&mut self, socket: SocketId, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Udp(socket) => socket.send_to(self.host, buf, addr),
+            Socket::Tcp(_) => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    /// Receives into `buf`, returning 0 at the end of a TCP stream and `WouldBlock` once
+    /// nothing is left.
+    pub fn recv(&mut self, socket: SocketId, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Tcp(socket) => socket.recv(self.host, buf, false),
+            Socket::Udp(socket) => socket.recv(buf),
+        }
+    }
+
+    pub fn recv_from(
+        &mut self,
+        socket: SocketId,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Udp(socket) => socket.recv_from(buf),
+            Socket::Tcp(socket) => {
+                let peer = socket.peer_addr()?;
+                socket.recv(self.host, buf, false).map(|len| (len, peer))
+            }
+        }
+    }
+
+    /// Ends the sending direction of a TCP connection once the data sent so far is through.
+    pub fn shutdown(&mut self, socket: SocketId) -> io::Result<()> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Tcp(socket) => socket.shutdown(self.host, false, true),
+            Socket::Udp(_) => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    pub fn close(&mut self, socket: SocketId) {
+        if let Some(entry) = self.state.sockets.remove(&socket) {
+            entry.socket.close(self.host);
+        }
+    }
+
+    pub fn local_addr(&mut self, socket: SocketId) -> io::Result<SocketAddr> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Tcp(socket) => socket.local_addr(),
+            Socket::Udp(socket) => socket.local_addr(),
+        }
+    }
+
+    pub fn peer_addr(&mut self, socket: SocketId) -> io::Result<SocketAddr> {
+        match &self.state.socket(socket)?.socket {
+            Socket::Tcp(socket) => socket.peer_addr(),
+            Socket::Udp(socket) => socket.peer_addr(),
+        }
+    }
+
+    /// Writes a line to the output file of the application.
+    pub fn output(&mut self, line: &str) {
+        if let Some(output) = &mut self.state.output {
+            if let Err(err) = writeln!(output, "{}", line) {
+                log::warn!(
+                    "cannot write output of app `{}`: {}",
+                    self.state.params.name,
+                    err
+                );
+                self.state.output = None;
+            }
+        }
+    }
+
+    /// Ends the application with an exit status, closing its sockets and timers.
+    pub fn exit(&mut self, code: i32) {
+        if self.state.status == Status::Running {
+            self.state.finish(self.host, Status::Exited(code));
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! The summary of a run, written as `summary.json` into the data directory when the
+//! simulation ends, for tools to read instead of the logs.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::graph::EdgeTraffic;
+use crate::host::Host;
+use crate::net::InterfaceStats;
+use crate::time::SimulationTime;
+use crate::trace::push_json_string;
+
+pub struct Summary<'a> {
+    /// Simulated time the run reached.
+    pub simulated_time: SimulationTime,
+    pub events: u64,
+    /// Wall-clock durations of the phases of the run, by name.
+    pub wall_times: Vec<(&'static str, Duration)>,
+    /// Wall-clock time each processor spent without executing events.
+    pub idle_times: Vec<Duration>,
+    pub hosts: &'a [Arc<Host>],
+    pub edges: Vec<EdgeTraffic>,
+    /// Whether SIGINT stopped the run before the stop time.
+    pub is_interrupted: bool,
+}
+
+impl Summary<'_> {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    fn to_json(&self) -> String {
+        let stats: Vec<_> = self.hosts.iter().map(|host| host.stats()).collect();
+        let mut json = String::from("{\n");
+
+        let _ = writeln!(
+            json,
+            "  \"simulated_time\": {:.9},",
+            self.simulated_time.as_nanos() as f64 / 1e9
+        );
+        let _ = writeln!(json, "  \"interrupted\": {},", self.is_interrupted);
+        let _ = writeln!(json, "  \"events\": {},", self.events);
+
+        let wall_times: Vec<_> = self
+            .wall_times
+            .iter()
+            .map(|(phase, duration)| format!("\"{}\": {:.6}", phase, duration.as_secs_f64()))
+            .collect();
+        let _ = writeln!(json, "  \"wall_time\": {{{}}},", wall_times.join(", "));
+
+        let processors: Vec<_> = self
+            .idle_times
+            .iter()
+            .enumerate()
+            .map(|(id, idle)| {
+                format!(
+                    "{{\"id\": {}, \"idle_time\": {:.6}}}",
+                    id,
+                    idle.as_secs_f64()
+                )
+            })
+            .collect();
+        let _ = writeln!(json, "  \"processors\": [{}],", processors.join(", "));
+
+        let total = |count: fn(&InterfaceStats) -> u64| stats.iter().map(count).sum::<u64>();
+        let _ = writeln!(
+            json,
+            "  \"drops\": {{\"path_loss\": {}, \"queue_full\": {}, \"unroutable\": {}, \
+             \"receive_buffer\": {}}},",
+            total(|stats| stats.packets_lost),
+            total(|stats| stats.packets_dropped),
+            total(|stats| stats.packets_unroutable),
+            total(|stats| stats.packets_overflowed),
+        );
+        let _ = writeln!(
+            json,
+            "  \"bandwidth_delays\": {{\"up\": {}, \"down\": {}}},",
+            total(|stats| stats.packets_delayed_up),
+            total(|stats| stats.packets_delayed_down),
+        );
+
+        let hosts: Vec<_> = self
+            .hosts
+            .iter()
+            .zip(&stats)
+            .map(|(host, stats)| {
+                let mut line = String::from("    {\"name\": ");
+                push_json_string(&mut line, host.name());
+                let _ = write!(
+                    line,
+                    ", \"packets_sent\": {}, \"bytes_sent\": {}, \"packets_received\": {}, \
+                     \"bytes_received\": {}, \"packets_dropped\": {}, \"packets_lost\": {}, \
+                     \"packets_unroutable\": {}, \"packets_overflowed\": {}, \
+                     \"bytes_overflowed\": {}, \"packets_delayed_up\": {}, \
+                     \"packets_delayed_down\": {}, \"retransmissions\": {}}}",
+                    stats.packets_sent,
+                    stats.bytes_sent,
+                    stats.packets_received,
+                    stats.bytes_received,
+                    stats.packets_dropped,
+                    stats.packets_lost,
+                    stats.packets_unroutable,
+                    stats.packets_overflowed,
+                    stats.bytes_overflowed,
+                    stats.packets_delayed_up,
+                    stats.packets_delayed_down,
+                    host.retransmissions()
+                );
+                line
+            })
+            .collect();
+        let _ = writeln!(json, "  \"hosts\": [\n{}\n  ],", hosts.join(",\n"));
+
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "    {{\"source\": {}, \"target\": {}, \"packets\": {}, \"bytes\": {}}}",
+                    isize::from(edge.source),
+                    isize::from(edge.target),
+                    edge.packets,
+                    edge.bytes
+                )
+            })
+            .collect();
+        let _ = writeln!(json, "  \"edges\": [\n{}\n  ]", edges.join(",\n"));
+
+        json.push_str("}\n");
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::TestNetwork;
+
+    #[test]
+    fn write_json() {
+        let network = TestNetwork::new(&["11.0.0.1"]);
+        let summary = Summary {
+            simulated_time: SimulationTime::from_millis(1500),
+            events: 42,
+            wall_times: vec![
+                ("setup", Duration::from_millis(1)),
+                ("total", Duration::from_millis(20)),
+            ],
+            idle_times: vec![Duration::from_micros(250), Duration::from_millis(20)],
+            hosts: &[network.host(0).clone()],
+            edges: vec![EdgeTraffic {
+                source: 0.into(),
+                target: 1.into(),
+                packets: 3,
+                bytes: 4500,
+            }],
+            is_interrupted: false,
+        };
+
+        assert_eq!(
+            summary.to_json(),
+            r#"{
+  "simulated_time": 1.500000000,
+  "interrupted": false,
+  "events": 42,
+  "wall_time": {"setup": 0.001000, "total": 0.020000},
+  "processors": [{"id": 0, "idle_time": 0.000250}, {"id": 1, "idle_time": 0.020000}],
+  "drops": {"path_loss": 0, "queue_full": 0, "unroutable": 0, "receive_buffer": 0},
+  "bandwidth_delays": {"up": 0, "down": 0},
+  "hosts": [
+    {"name": "host1", "packets_sent": 0, "bytes_sent": 0, "packets_received": 0, "bytes_received": 0, "packets_dropped": 0, "packets_lost": 0, "packets_unroutable": 0, "packets_overflowed": 0, "bytes_overflowed": 0, "packets_delayed_up": 0, "packets_delayed_down": 0, "retransmissions": 0}
+  ],
+  "edges": [
+    {"source": 0, "target": 1, "packets": 3, "bytes": 4500}
+  ]
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::descriptor::{Descriptor, File, Timer};
+use crate::time::{SimulationTime, SIMULATION_START, UNIX_EPOCH};
+
+use super::{errno, now, placeholder, sleep_until, SyscallContext, SyscallResult};
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Simulated cost of reading the clock, so that processes polling the time in a loop still
+/// see it advance.
+const CLOCK_READ_LATENCY_NANOS: i64 = 1_000;
+const MAX_UNAPPLIED_LATENCY_NANOS: i64 = 1_000_000;
+
+/// Offset of a clock from the simulation start, or `None` for clocks measuring cpu time, which
+/// are left to the kernel.
+pub(super) fn clock_offset(clock: libc::clockid_t) -> Option<SimulationTime> {
+    match clock {
+        libc::CLOCK_REALTIME
+        | libc::CLOCK_REALTIME_COARSE
+        | libc::CLOCK_REALTIME_ALARM
+        | libc::CLOCK_TAI => Some(SIMULATION_START.duration_since(&UNIX_EPOCH).into()),
+        libc::CLOCK_MONOTONIC
+        | libc::CLOCK_MONOTONIC_RAW
+        | libc::CLOCK_MONOTONIC_COARSE
+        | libc::CLOCK_BOOTTIME
+        | libc::CLOCK_BOOTTIME_ALARM => Some(SimulationTime::zero()),
+        _ => None,
+    }
+}
+
+fn to_timespec(time: SimulationTime) -> libc::timespec {
+    let nanos = time.as_nanos();
+    libc::timespec {
+        tv_sec: nanos.div_euclid(NANOS_PER_SEC),
+        tv_nsec: nanos.rem_euclid(NANOS_PER_SEC),
+    }
+}
+
+pub(super) fn from_timespec(time: &libc::timespec) -> Option<SimulationTime> {
+    if time.tv_sec < 0 || !(0..NANOS_PER_SEC).contains(&time.tv_nsec) {
+        return None;
+    }
+
+    Some(SimulationTime::from_nanos(
+        time.tv_sec
+            .saturating_mul(NANOS_PER_SEC)
+            .saturating_add(time.tv_nsec),
+    ))
+}
+
+/// Charges the clock read and returns the current time once all charged time has passed.
+fn read_clock(context: &mut SyscallContext) -> Option<SimulationTime> {
+    if let Some(deadline) = context.process.wakeup() {
+        return sleep_until(context, deadline).then(now);
+    }
+
+    let latency = context
+        .process
+        .add_latency(SimulationTime::from_nanos(CLOCK_READ_LATENCY_NANOS));
+
+    if latency.as_nanos() >= MAX_UNAPPLIED_LATENCY_NANOS {
+        let deadline = now() + context.process.take_latency();
+        return sleep_until(context, deadline).then(now);
+    }
+
+    Some(now())
+}
+
+pub(super) fn clock_gettime(context: &mut SyscallContext) -> SyscallResult {
+    let offset = match clock_offset(context.args[0] as libc::clockid_t) {
+        Some(offset) => offset,
+        None => return SyscallResult::Native,
+    };
+
+    let now = match read_clock(context) {
+        Some(now) => now,
+        None => return SyscallResult::Block,
+    };
+
+    match context
+        .memory
+        .write_value(context.args[1], &to_timespec(now + offset))
+    {
+        Ok(()) => SyscallResult::Done(0),
+        Err(_) => errno(libc::EFAULT),
+    }
+}
+
+pub(super) fn gettimeofday(context: &mut SyscallContext) -> SyscallResult {
+    let now = match read_clock(context) {
+        Some(now) => now,
+        None => return SyscallResult::Block,
+    };
+
+    if context.args[0] == 0 {
+        return SyscallResult::Done(0);
+    }
+
+    let time =
+        to_timespec(now + clock_offset(libc::CLOCK_REALTIME).unwrap_or_else(SimulationTime::zero));
+    let value = libc::timeval {
+        tv_sec: time.tv_sec,
+        tv_usec: time.tv_nsec / 1000,
+    };
+
+    match context.memory.write_value(context.args[0], &value) {
+        Ok(()) => SyscallResult::Done(0),
+        Err(_) => errno(libc::EFAULT),
+    }
+}
+
+pub(super) fn time(context: &mut SyscallContext) -> SyscallResult {
+    let now = match read_clock(context) {
+        Some(now) => now,
+        None => return SyscallResult::Block,
+    };
+
+    let seconds =
+        to_timespec(now + clock_offset(libc::CLOCK_REALTIME).unwrap_or_else(SimulationTime::zero))
+            .tv_sec;
+
+    if context.args[0] != 0
+        && context
+            .memory
+            .write_value(context.args[0], &seconds)
+            .is_err()
+    {
+        return errno(libc::EFAULT);
+    }
+
+    SyscallResult::Done(seconds)
+}
+
+pub(super) fn nanosleep(context: &mut SyscallContext) -> SyscallResult {
+    let deadline = match context.process.wakeup() {
+        Some(deadline) => deadline,
+        None => match context.memory.read_value::<libc::timespec>(context.args[0]) {
+            Ok(request) => match from_timespec(&request) {
+                Some(duration) => now() + duration,
+                None => return errno(libc::EINVAL),
+            },
+            Err(_) => return errno(libc::EFAULT),
+        },
+    };
+
+    match sleep_until(context, deadline) {
+        true => SyscallResult::Done(0),
+        false => SyscallResult::Block,
+    }
+}
+
+pub(super) fn clock_nanosleep(context: &mut SyscallContext) -> SyscallResult {
+    let offset = match clock_offset(context.args[0] as libc::clockid_t) {
+        Some(offset) => offset,
+        None => return SyscallResult::Native,
+    };
+
+    let deadline = match context.process.wakeup() {
+        Some(deadline) => deadline,
+        None => {
+            let request = match context.memory.read_value::<libc::timespec>(context.args[2]) {
+                Ok(request) => request,
+                Err(_) => return SyscallResult::Done(i64::from(libc::EFAULT)),
+            };
+            let value = match from_timespec(&request) {
+                Some(value) => value,
+                None => return SyscallResult::Done(i64::from(libc::EINVAL)),
+            };
+
+            match context.args[1] as libc::c_int & libc::TIMER_ABSTIME {
+                0 => now() + value,
+                _ => value - offset,
+            }
+        }
+    };
+
+    // Unlike the other calls, clock_nanosleep returns the error number as a positive value.
+    match sleep_until(context, deadline) {
+        true => SyscallResult::Done(0),
+        false => SyscallResult::Block,
+    }
+}
+
+/// Waits for a signal, which never arrives as signals between simulated processes are not
+/// delivered, so the thread stays blocked until its process exits.
+pub(super) fn pause(_context: &mut SyscallContext) -> SyscallResult {
+    SyscallResult::Block
+}
+
+pub(super) fn timerfd_create(context: &mut SyscallContext) -> SyscallResult {
+    let clock = context.args[0] as libc::clockid_t;
+    if clock_offset(clock).is_none() {
+        return errno(libc::EINVAL);
+    }
+
+    let flags = context.args[1];
+    let is_nonblocking = flags & libc::TFD_NONBLOCK as u64 != 0;
+    placeholder(
+        flags,
+        Descriptor::new(File::Timer(Timer::new(clock)), is_nonblocking),
+    )
+}
+
+fn with_timer<F>(context: &mut SyscallContext, func: F) -> SyscallResult
+where
+    F: FnOnce(&mut SyscallContext, &mut Timer) -> SyscallResult,
+{
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return errno(libc::EBADF),
+    };
+
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    match descriptor.file_mut() {
+        File::Timer(timer) => func(context, timer),
+        _ => errno(libc::EINVAL),
+    }
+}
+
+fn write_itimerspec(
+    context: &SyscallContext,
+    address: u64,
+    (value, interval): (SimulationTime, SimulationTime),
+) -> Result<(), SyscallResult> {
+    if address == 0 {
+        return Ok(());
+    }
+
+    let spec = libc::itimerspec {
+        it_interval: to_timespec(interval),
+        it_value: to_timespec(value),
+    };
+
+    context
+        .memory
+        .write_value(address, &spec)
+        .map_err(|_| errno(libc::EFAULT))
+}
+
+pub(super) fn timerfd_settime(context: &mut SyscallContext) -> SyscallResult {
+    let spec = match context
+        .memory
+        .read_value::<libc::itimerspec>(context.args[2])
+    {
+        Ok(spec) => spec,
+        Err(_) => return errno(libc::EFAULT),
+    };
+
+    let (value, interval) = match (
+        from_timespec(&spec.it_value),
+        from_timespec(&spec.it_interval),
+    ) {
+        (Some(value), Some(interval)) => (value, interval),
+        _ => return errno(libc::EINVAL),
+    };
+
+    let is_absolute = context.args[1] & libc::TFD_TIMER_ABSTIME as u64 != 0;
+
+    with_timer(context, |context, timer| {
+        let now = now();
+        let next = match (value > SimulationTime::zero(), is_absolute) {
+            (false, _) => None,
+            (true, false) => Some(now + value),
+            (true, true) => {
+                Some(value - clock_offset(timer.clock()).unwrap_or_else(SimulationTime::zero))
+            }
+        };
+
+        let previous = timer.arm(now, next, interval);
+        match write_itimerspec(context, context.args[3], previous) {
+            Ok(()) => SyscallResult::Done(0),
+            Err(result) => result,
+        }
+    })
+}
+
+pub(super) fn timerfd_gettime(context: &mut SyscallContext) -> SyscallResult {
+    with_timer(context, |context, timer| {
+        match write_itimerspec(context, context.args[1], timer.remaining(now())) {
+            Ok(()) => SyscallResult::Done(0),
+            Err(result) => result,
+        }
+    })
+}
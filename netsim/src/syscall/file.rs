@@ -0,0 +1,900 @@
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::descriptor::{self, Descriptor, Epoll, EventFd, File, Pipe};
+use crate::process::Memory;
+use crate::time::SimulationTime;
+use crate::worker::Worker;
+
+use super::{
+    complete, errno, errno_of, now, placeholder, resume_at, sleep_until, SyscallContext,
+    SyscallResult, Timeout,
+};
+
+/// Upper bound of the data moved by a single read or write, larger requests are served
+/// partially like the kernel does for sockets.
+pub(super) const MAX_TRANSFER_SIZE: usize = 4_194_304;
+
+const EPOLL_EVENT_SIZE: u64 = 12;
+const POLLFD_SIZE: u64 = 8;
+const FD_SETSIZE: usize = 1024;
+const STAT_MODE_OFFSET: u64 = 24;
+const STATX_MODE_OFFSET: u64 = 28;
+
+fn fault() -> io::Error {
+    io::Error::from_raw_os_error(libc::EFAULT)
+}
+
+/// Reads from an emulated file, returning the data and, for datagrams, the sender.
+pub(super) fn receive(
+    context: &SyscallContext,
+    descriptor: &mut Descriptor,
+    len: usize,
+    flags: libc::c_int,
+) -> io::Result<(Vec<u8>, Option<SocketAddr>)> {
+    let len = len.min(MAX_TRANSFER_SIZE);
+    let peek = flags & libc::MSG_PEEK != 0;
+    let mut buf = vec![0; len];
+
+    let (len, addr) = match descriptor.file_mut() {
+        File::Timer(timer) => {
+            if len < mem::size_of::<u64>() {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            match timer.take_expirations(now()) {
+                0 => return Err(io::ErrorKind::WouldBlock.into()),
+                count => return Ok((count.to_ne_bytes().to_vec(), None)),
+            }
+        }
+        File::EventFd(eventfd) => {
+            if len < mem::size_of::<u64>() {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            let value = eventfd.read(context.host)?;
+            return Ok((value.to_ne_bytes().to_vec(), None));
+        }
+        File::PipeReader(pipe) => (pipe.read(context.host, &mut buf)?, None),
+        File::Udp(socket, _) if peek => {
+            let (len, addr) = socket.peek_from(&mut buf)?;
+            (len, Some(addr))
+        }
+        File::Udp(socket, _) => {
+            let (len, addr) = socket.recv_from(&mut buf)?;
+            (len, Some(addr))
+        }
+        File::Tcp(socket, _) => (socket.recv(context.host, &mut buf, peek)?, None),
+        File::PipeWriter(_) => return Err(io::Error::from_raw_os_error(libc::EBADF)),
+        File::Epoll(_) => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    buf.truncate(len);
+    Ok((buf, addr))
+}
+
+/// Writes to an emulated file, sending datagrams to `addr` if given.
+pub(super) fn transmit(
+    context: &SyscallContext,
+    descriptor: &mut Descriptor,
+    data: &[u8],
+    addr: Option<SocketAddr>,
+    flags: libc::c_int,
+) -> io::Result<usize> {
+    let result = match descriptor.file_mut() {
+        File::EventFd(eventfd) => match data.get(..mem::size_of::<u64>()) {
+            Some(bytes) => {
+                let value = u64::from_ne_bytes(bytes.try_into().unwrap_or_default());
+                eventfd
+                    .write(context.host, value)
+                    .map(|()| mem::size_of::<u64>())
+            }
+            None => Err(io::ErrorKind::InvalidInput.into()),
+        },
+        File::PipeWriter(pipe) => pipe.write(context.host, data),
+        File::Udp(socket, _) => match addr {
+            Some(addr) => socket.send_to(context.host, data, addr),
+            None => socket
+                .send(context.host, data)
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::NotConnected => io::Error::from_raw_os_error(libc::EDESTADDRREQ),
+                    _ => err,
+                }),
+        },
+        File::Tcp(socket, _) => socket.send(context.host, data),
+        File::PipeReader(_) => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        File::Timer(_) | File::Epoll(_) => Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    // Writing to a closed pipe or connection raises SIGPIPE, which the process sees once it
+    // continues.
+    if let Err(err) = &result {
+        if err.kind() == io::ErrorKind::BrokenPipe && flags & libc::MSG_NOSIGNAL == 0 {
+            unsafe { libc::kill(context.pid, libc::SIGPIPE) };
+        }
+    }
+
+    result
+}
+
+/// Reads an array of `iovec` as address and length pairs.
+pub(super) fn read_iovecs(
+    memory: &Memory,
+    address: u64,
+    count: usize,
+) -> io::Result<Vec<(u64, usize)>> {
+    if count > libc::UIO_MAXIOV as usize {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+
+    (0..count)
+        .map(|index| {
+            let iovec = memory
+                .read_value::<libc::iovec>(address + (index * mem::size_of::<libc::iovec>()) as u64)
+                .map_err(|_| fault())?;
+            Ok((iovec.iov_base as u64, iovec.iov_len))
+        })
+        .collect()
+}
+
+pub(super) fn gather(memory: &Memory, iovecs: &[(u64, usize)]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    for (base, len) in iovecs {
+        let len = (*len).min(MAX_TRANSFER_SIZE.saturating_sub(data.len()));
+        data.extend(memory.read(*base, len).map_err(|_| fault())?);
+    }
+
+    Ok(data)
+}
+
+pub(super) fn scatter(memory: &Memory, iovecs: &[(u64, usize)], mut data: &[u8]) -> io::Result<()> {
+    for (base, len) in iovecs {
+        let len = (*len).min(data.len());
+        memory.write(*base, &data[..len]).map_err(|_| fault())?;
+        data = &data[len..];
+    }
+
+    Ok(())
+}
+
+pub(super) fn read(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    let result =
+        receive(context, &mut descriptor, context.args[2] as usize, 0).and_then(|(data, _)| {
+            context
+                .memory
+                .write(context.args[1], &data)
+                .map_err(|_| fault())?;
+            Ok(data.len() as i64)
+        });
+
+    let is_nonblocking = descriptor.is_nonblocking();
+    complete(context, &mut descriptor, is_nonblocking, result)
+}
+
+pub(super) fn readv(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    let result = read_iovecs(&context.memory, context.args[1], context.args[2] as usize).and_then(
+        |iovecs| {
+            let len = iovecs.iter().map(|(_, len)| len).sum();
+            let (data, _) = receive(context, &mut descriptor, len, 0)?;
+            scatter(&context.memory, &iovecs, &data)?;
+            Ok(data.len() as i64)
+        },
+    );
+
+    let is_nonblocking = descriptor.is_nonblocking();
+    complete(context, &mut descriptor, is_nonblocking, result)
+}
+
+pub(super) fn write(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    let len = (context.args[2] as usize).min(MAX_TRANSFER_SIZE);
+    let result = context
+        .memory
+        .read(context.args[1], len)
+        .map_err(|_| fault())
+        .and_then(|data| transmit(context, &mut descriptor, &data, None, 0))
+        .map(|len| len as i64);
+
+    let is_nonblocking = descriptor.is_nonblocking();
+    complete(context, &mut descriptor, is_nonblocking, result)
+}
+
+pub(super) fn writev(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    let result = read_iovecs(&context.memory, context.args[1], context.args[2] as usize)
+        .and_then(|iovecs| gather(&context.memory, &iovecs))
+        .and_then(|data| transmit(context, &mut descriptor, &data, None, 0))
+        .map(|len| len as i64);
+
+    let is_nonblocking = descriptor.is_nonblocking();
+    complete(context, &mut descriptor, is_nonblocking, result)
+}
+
+/// Emulated files have no position to read or write at.
+pub(super) fn positional(context: &mut SyscallContext) -> SyscallResult {
+    match context.descriptor(context.args[0]) {
+        Some(_) => errno(libc::ESPIPE),
+        None => SyscallResult::Native,
+    }
+}
+
+/// Drops a native descriptor that is closed from the `epoll` instances watching it.
+fn forget_native(context: &mut SyscallContext, fd: i32) {
+    for descriptor in context.process.descriptors().values() {
+        let mut descriptor = descriptor
+            .lock()
+            .expect("tried to acquire poisoned descriptor lock");
+        if let File::Epoll(epoll) = descriptor.file_mut() {
+            epoll.forget_native(fd);
+        }
+    }
+}
+
+/// Lets the kernel fill in the status of the placeholder and corrects its file type to the one
+/// of the emulated file.
+pub(super) fn stat(context: &mut SyscallContext) -> SyscallResult {
+    let (fd, address, mode_offset) = match context.number {
+        libc::SYS_fstat => (context.args[0], context.args[1], STAT_MODE_OFFSET),
+        libc::SYS_newfstatat if is_empty_path(context, context.args[3]) => {
+            (context.args[0], context.args[2], STAT_MODE_OFFSET)
+        }
+        libc::SYS_statx if is_empty_path(context, context.args[2]) => {
+            (context.args[0], context.args[4], STATX_MODE_OFFSET)
+        }
+        _ => return SyscallResult::Native,
+    };
+
+    let descriptor = match context.descriptor(fd) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let file_type = match descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock")
+        .file()
+    {
+        File::PipeReader(_) | File::PipeWriter(_) => libc::S_IFIFO,
+        File::Udp(..) | File::Tcp(..) => libc::S_IFSOCK,
+        File::Timer(_) | File::EventFd(_) | File::Epoll(_) => return SyscallResult::Native,
+    };
+
+    let pid = context.pid;
+    SyscallResult::Replace {
+        number: context.number,
+        args: context.args,
+        on_exit: Box::new(move |_, result| {
+            let memory = Memory::new(pid);
+            let address = address + mode_offset;
+            if let (0, Ok(mode)) = (result, memory.read_value::<u16>(address)) {
+                let mode = (u32::from(mode) & !libc::S_IFMT) | file_type;
+                let _ = memory.write_value(address, &(mode as u16));
+            }
+            result
+        }),
+    }
+}
+
+/// Whether a call taking a path relative to a descriptor refers to the descriptor itself.
+fn is_empty_path(context: &SyscallContext, flags: u64) -> bool {
+    flags & libc::AT_EMPTY_PATH as u64 != 0
+        && matches!(context.memory.read(context.args[1], 1), Ok(bytes) if bytes[0] == 0)
+}
+
+pub(super) fn close(context: &mut SyscallContext) -> SyscallResult {
+    let fd = context.args[0] as i32;
+
+    match context.process.descriptors().remove(fd) {
+        Some(descriptor) => descriptor::release(descriptor, context.host),
+        None => forget_native(context, fd),
+    }
+
+    SyscallResult::Native
+}
+
+/// Lets the kernel create a pipe, whose ends then serve as placeholders for the emulated one.
+pub(super) fn pipe(context: &mut SyscallContext, flags: u64) -> SyscallResult {
+    let address = context.args[0];
+    let pid = context.pid;
+    let is_nonblocking = flags & libc::O_NONBLOCK as u64 != 0;
+
+    SyscallResult::Replace {
+        number: libc::SYS_pipe2,
+        args: [address, flags, 0, 0, 0, 0],
+        on_exit: Box::new(move |process, result| {
+            if result < 0 {
+                return result;
+            }
+
+            if let Ok(fds) = Memory::new(pid).read_value::<[libc::c_int; 2]>(address) {
+                let pipe = Pipe::new();
+                let reader = Descriptor::new(File::PipeReader(pipe.clone()), is_nonblocking);
+                let writer = Descriptor::new(File::PipeWriter(pipe), is_nonblocking);
+                process
+                    .descriptors()
+                    .insert(fds[0], Arc::new(Mutex::new(reader)));
+                process
+                    .descriptors()
+                    .insert(fds[1], Arc::new(Mutex::new(writer)));
+            }
+
+            result
+        }),
+    }
+}
+
+pub(super) fn eventfd(context: &mut SyscallContext, flags: u64) -> SyscallResult {
+    let is_semaphore = flags & libc::EFD_SEMAPHORE as u64 != 0;
+    let is_nonblocking = flags & libc::EFD_NONBLOCK as u64 != 0;
+    let eventfd = EventFd::new(context.args[0] as u32 as u64, is_semaphore);
+
+    placeholder(
+        flags,
+        Descriptor::new(File::EventFd(eventfd), is_nonblocking),
+    )
+}
+
+/// Registers the descriptor under the number the kernel picks for the duplicated placeholder.
+fn duplicate(context: &SyscallContext, descriptor: Arc<Mutex<Descriptor>>) -> SyscallResult {
+    SyscallResult::Replace {
+        number: context.number,
+        args: context.args,
+        on_exit: Box::new(move |process, fd| {
+            if fd >= 0 {
+                process.descriptors().insert(fd as i32, descriptor);
+            }
+            fd
+        }),
+    }
+}
+
+pub(super) fn fcntl(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+
+    match context.args[1] as libc::c_int {
+        libc::F_GETFL => {
+            let descriptor = descriptor
+                .lock()
+                .expect("tried to acquire poisoned descriptor lock");
+            let mode = match descriptor.file() {
+                File::PipeReader(_) => libc::O_RDONLY,
+                File::PipeWriter(_) => libc::O_WRONLY,
+                _ => libc::O_RDWR,
+            };
+            let flags = match descriptor.is_nonblocking() {
+                true => mode | libc::O_NONBLOCK,
+                false => mode,
+            };
+            SyscallResult::Done(i64::from(flags))
+        }
+        libc::F_SETFL => {
+            descriptor
+                .lock()
+                .expect("tried to acquire poisoned descriptor lock")
+                .set_nonblocking(context.args[2] & libc::O_NONBLOCK as u64 != 0);
+            SyscallResult::Native
+        }
+        libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => duplicate(context, descriptor),
+        // The descriptor flags only hold close-on-exec, which the placeholder keeps.
+        libc::F_GETFD | libc::F_SETFD => SyscallResult::Native,
+        _ => errno(libc::EINVAL),
+    }
+}
+
+pub(super) fn ioctl(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    match context.args[1] {
+        libc::FIONBIO => match context.memory.read_value::<libc::c_int>(context.args[2]) {
+            Ok(value) => {
+                descriptor.set_nonblocking(value != 0);
+                SyscallResult::Done(0)
+            }
+            Err(_) => errno(libc::EFAULT),
+        },
+        libc::FIONREAD => {
+            let available = match descriptor.file() {
+                File::PipeReader(pipe) => pipe.available(),
+                File::Udp(socket, _) => socket.next_len().unwrap_or(0),
+                File::Tcp(socket, _) => socket.available(),
+                _ => return errno(libc::ENOTTY),
+            };
+            match context
+                .memory
+                .write_value(context.args[2], &(available as libc::c_int))
+            {
+                Ok(()) => SyscallResult::Done(0),
+                Err(_) => errno(libc::EFAULT),
+            }
+        }
+        libc::FIOCLEX | libc::FIONCLEX => SyscallResult::Native,
+        _ => errno(libc::ENOTTY),
+    }
+}
+
+pub(super) fn dup(context: &mut SyscallContext) -> SyscallResult {
+    match context.descriptor(context.args[0]) {
+        Some(descriptor) => duplicate(context, descriptor),
+        None => SyscallResult::Native,
+    }
+}
+
+/// Duplicates onto a given number, which closes whatever was open there.
+pub(super) fn dup2(context: &mut SyscallContext) -> SyscallResult {
+    let (old, new) = (context.args[0] as i32, context.args[1] as i32);
+
+    if old == new {
+        return SyscallResult::Native;
+    }
+
+    match context.process.descriptors().remove(new) {
+        Some(replaced) => descriptor::release(replaced, context.host),
+        None => forget_native(context, new),
+    }
+
+    match context.descriptor(context.args[0]) {
+        Some(descriptor) => duplicate(context, descriptor),
+        None => SyscallResult::Native,
+    }
+}
+
+pub(super) fn epoll_create(context: &mut SyscallContext) -> SyscallResult {
+    let flags = match context.number {
+        libc::SYS_epoll_create if context.args[0] as i32 <= 0 => return errno(libc::EINVAL),
+        libc::SYS_epoll_create => 0,
+        _ => context.args[0],
+    };
+
+    placeholder(flags, Descriptor::new(File::Epoll(Epoll::default()), false))
+}
+
+pub(super) fn epoll_ctl(context: &mut SyscallContext) -> SyscallResult {
+    let epoll = match context.descriptor(context.args[0]) {
+        Some(epoll) => epoll,
+        None => return SyscallResult::Native,
+    };
+
+    let fd = context.args[2] as i32;
+    let target = context.descriptor(context.args[2]);
+
+    if let Some(target) = &target {
+        let is_epoll = Arc::ptr_eq(target, &epoll)
+            || matches!(
+                target
+                    .lock()
+                    .expect("tried to acquire poisoned descriptor lock")
+                    .file(),
+                File::Epoll(_)
+            );
+        if is_epoll {
+            return errno(libc::EINVAL);
+        }
+    }
+
+    let op = context.args[1] as libc::c_int;
+    let event = match op {
+        libc::EPOLL_CTL_DEL => None,
+        _ => match context
+            .memory
+            .read(context.args[3], EPOLL_EVENT_SIZE as usize)
+        {
+            Ok(bytes) => Some((
+                u32::from_ne_bytes(bytes[..4].try_into().unwrap_or_default()),
+                u64::from_ne_bytes(bytes[4..].try_into().unwrap_or_default()),
+            )),
+            Err(_) => return errno(libc::EFAULT),
+        },
+    };
+
+    let mut epoll = epoll
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+    let epoll = match epoll.file_mut() {
+        File::Epoll(epoll) => epoll,
+        _ => return errno(libc::EINVAL),
+    };
+
+    let result = match (op, event) {
+        (libc::EPOLL_CTL_ADD, Some((events, data))) => {
+            epoll.add(fd, target.as_ref().map(Arc::downgrade), events, data)
+        }
+        (libc::EPOLL_CTL_MOD, Some((events, data))) => epoll.modify(fd, events, data),
+        (libc::EPOLL_CTL_DEL, _) => epoll.remove(fd),
+        _ => Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    match result {
+        Ok(()) => SyscallResult::Done(0),
+        Err(err) => errno(errno_of(&err)),
+    }
+}
+
+/// Whether a waiting call without emulated descriptors has to wait in simulated time, which
+/// holds for a finite timeout. A zero timeout returns at once and an infinite one can only end
+/// by a native descriptor, so both are left to the kernel.
+fn is_timed(context: &mut SyscallContext, timeout: Timeout) -> bool {
+    if context.process.wakeup().is_some() {
+        return true;
+    }
+
+    match timeout.read(&context.memory) {
+        Ok(Some(timeout)) => timeout > SimulationTime::zero(),
+        _ => false,
+    }
+}
+
+/// The limit of descriptors of the process, which bounds the number of descriptors it may
+/// poll.
+fn descriptor_limit(pid: libc::pid_t) -> usize {
+    let mut limit = mem::MaybeUninit::<libc::rlimit>::uninit();
+
+    match unsafe {
+        libc::prlimit(
+            pid,
+            libc::RLIMIT_NOFILE,
+            std::ptr::null(),
+            limit.as_mut_ptr(),
+        )
+    } {
+        0 => unsafe { limit.assume_init() }.rlim_cur as usize,
+        _ => usize::MAX,
+    }
+}
+
+/// Reads the timeout of a waiting call, or the deadline computed when it first blocked.
+fn deadline(
+    context: &mut SyscallContext,
+    timeout: Timeout,
+) -> Result<Option<SimulationTime>, libc::c_int> {
+    if let Some(deadline) = context.process.wakeup() {
+        return Ok(Some(deadline));
+    }
+
+    Ok(timeout
+        .read(&context.memory)?
+        .map(|timeout| now() + timeout))
+}
+
+/// Blocks a waiting call without ready descriptors until the deadline, or returns `None` once
+/// it has passed.
+fn wait_until(
+    context: &mut SyscallContext,
+    deadline: Option<SimulationTime>,
+) -> Option<SyscallResult> {
+    match deadline {
+        Some(deadline) if sleep_until(context, deadline) => None,
+        _ => Some(SyscallResult::Block),
+    }
+}
+
+pub(super) fn epoll_wait(context: &mut SyscallContext) -> SyscallResult {
+    let epoll = match context.descriptor(context.args[0]) {
+        Some(epoll) => epoll,
+        None => return SyscallResult::Native,
+    };
+
+    let max = context.args[2] as i32;
+    if max <= 0 {
+        return errno(libc::EINVAL);
+    }
+
+    let deadline = match deadline(context, Timeout::Millis(context.args[3] as i32)) {
+        Ok(deadline) => deadline,
+        Err(code) => return errno(code),
+    };
+
+    let mut epoll = epoll
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+    let epoll = match epoll.file_mut() {
+        File::Epoll(epoll) => epoll,
+        _ => return errno(libc::EINVAL),
+    };
+
+    let pid = context.process.pid();
+    let ready = epoll.collect(now(), max as usize, |fd, events| {
+        native_events(pid, fd, events)
+    });
+
+    if !ready.is_empty() {
+        for (index, (events, data)) in ready.iter().enumerate() {
+            let mut bytes = events.to_ne_bytes().to_vec();
+            bytes.extend(data.to_ne_bytes());
+            let address = context.args[1] + index as u64 * EPOLL_EVENT_SIZE;
+            if context.memory.write(address, &bytes).is_err() {
+                return errno(libc::EFAULT);
+            }
+        }
+        return SyscallResult::Done(ready.len() as i64);
+    }
+
+    match wait_until(context, deadline) {
+        Some(result) => {
+            if let Some(process) = Worker::with_active_process(|process| process.clone()) {
+                if let Some(expiration) = epoll.wait(&process) {
+                    resume_at(context, expiration);
+                }
+            }
+            result
+        }
+        None => SyscallResult::Done(0),
+    }
+}
+
+/// Readiness of a native descriptor of the process in `poll` events, which the kernel reports
+/// for a copy of it polled with a zero timeout. The events of `epoll` have the same values.
+fn native_events(pid: Option<libc::pid_t>, fd: i32, requested: u32) -> u32 {
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return libc::POLLNVAL as u32,
+    };
+
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as libc::c_int;
+    if pidfd < 0 {
+        return libc::POLLNVAL as u32;
+    }
+    let copy = unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd, fd, 0) } as libc::c_int;
+    unsafe { libc::close(pidfd) };
+    if copy < 0 {
+        return libc::POLLNVAL as u32;
+    }
+
+    let mut pollfd = libc::pollfd {
+        fd: copy,
+        events: requested as u16 as libc::c_short,
+        revents: 0,
+    };
+    unsafe {
+        libc::poll(&mut pollfd, 1, 0);
+        libc::close(copy);
+    }
+
+    u32::from(pollfd.revents as u16)
+}
+
+/// Readiness of a descriptor in `poll` events.
+fn poll_events(
+    context: &mut SyscallContext,
+    fd: i32,
+    requested: u32,
+) -> (Option<Arc<Mutex<Descriptor>>>, u32) {
+    match context.process.descriptors().get(fd) {
+        Some(descriptor) => {
+            let events = descriptor
+                .lock()
+                .expect("tried to acquire poisoned descriptor lock")
+                .events(now());
+            (Some(descriptor), events)
+        }
+        None => (None, native_events(context.process.pid(), fd, requested)),
+    }
+}
+
+/// Registers the process on all descriptors it polls.
+fn wait_on(context: &SyscallContext, descriptors: &[Arc<Mutex<Descriptor>>]) {
+    let process = match Worker::with_active_process(|process| process.clone()) {
+        Some(process) => process,
+        None => return,
+    };
+
+    let expiration = descriptors
+        .iter()
+        .filter_map(|descriptor| {
+            descriptor
+                .lock()
+                .expect("tried to acquire poisoned descriptor lock")
+                .wait(&process)
+        })
+        .min();
+
+    if let Some(expiration) = expiration {
+        resume_at(context, expiration);
+    }
+}
+
+pub(super) fn poll(context: &mut SyscallContext, timeout: Timeout) -> SyscallResult {
+    let (address, count) = (context.args[0], context.args[1] as usize);
+
+    if count > descriptor_limit(context.pid) {
+        return errno(libc::EINVAL);
+    }
+
+    let mut fds = match context.memory.read(address, count * POLLFD_SIZE as usize) {
+        Ok(fds) => fds,
+        Err(_) => return errno(libc::EFAULT),
+    };
+
+    let is_emulated = fds.chunks_exact(POLLFD_SIZE as usize).any(|pollfd| {
+        let fd = i32::from_ne_bytes(pollfd[..4].try_into().unwrap_or_default());
+        context.process.descriptors().contains(fd)
+    });
+    if !is_emulated && !is_timed(context, timeout) {
+        return SyscallResult::Native;
+    }
+
+    let deadline = match deadline(context, timeout) {
+        Ok(deadline) => deadline,
+        Err(code) => return errno(code),
+    };
+
+    let always = (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) as u32;
+    let mut descriptors = Vec::new();
+    let mut ready = 0;
+
+    for pollfd in fds.chunks_exact_mut(POLLFD_SIZE as usize) {
+        let fd = i32::from_ne_bytes(pollfd[..4].try_into().unwrap_or_default());
+        let requested = u32::from(u16::from_ne_bytes([pollfd[4], pollfd[5]]));
+
+        let revents = match fd {
+            fd if fd < 0 => 0,
+            fd => {
+                let (descriptor, events) = poll_events(context, fd, requested);
+                descriptors.extend(descriptor);
+                events & (requested | always)
+            }
+        };
+
+        if revents != 0 {
+            ready += 1;
+        }
+        pollfd[6..8].copy_from_slice(&(revents as u16).to_ne_bytes());
+    }
+
+    if ready > 0 || matches!(deadline, Some(deadline) if deadline <= now()) {
+        return match context.memory.write(address, &fds) {
+            Ok(()) => SyscallResult::Done(ready),
+            Err(_) => errno(libc::EFAULT),
+        };
+    }
+
+    match wait_until(context, deadline) {
+        Some(result) => {
+            wait_on(context, &descriptors);
+            result
+        }
+        None => SyscallResult::Done(0),
+    }
+}
+
+fn read_fd_set(memory: &Memory, address: u64, count: usize) -> Result<Vec<u64>, libc::c_int> {
+    let words = count.div_ceil(64);
+
+    if address == 0 {
+        return Ok(vec![0; words]);
+    }
+
+    (0..words)
+        .map(|word| {
+            memory
+                .read_value::<u64>(address + word as u64 * 8)
+                .map_err(|_| libc::EFAULT)
+        })
+        .collect()
+}
+
+fn is_set(set: &[u64], fd: usize) -> bool {
+    set[fd / 64] & (1 << (fd % 64)) != 0
+}
+
+pub(super) fn select(context: &mut SyscallContext, timeout: Timeout) -> SyscallResult {
+    let count = context.args[0] as usize;
+
+    if count > FD_SETSIZE {
+        return errno(libc::EINVAL);
+    }
+
+    let addresses = [context.args[1], context.args[2], context.args[3]];
+    let mut sets = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        match read_fd_set(&context.memory, address, count) {
+            Ok(set) => sets.push(set),
+            Err(code) => return errno(code),
+        }
+    }
+
+    let is_emulated = (0..count).any(|fd| {
+        sets.iter().any(|set| is_set(set, fd)) && context.process.descriptors().contains(fd as i32)
+    });
+    if !is_emulated && !is_timed(context, timeout) {
+        return SyscallResult::Native;
+    }
+
+    let deadline = match deadline(context, timeout) {
+        Ok(deadline) => deadline,
+        Err(code) => return errno(code),
+    };
+
+    let readable = (libc::POLLIN | libc::POLLHUP | libc::POLLERR) as u32;
+    let writable = (libc::POLLOUT | libc::POLLERR) as u32;
+    let mut results = vec![vec![0u64; sets[0].len()]; 3];
+    let mut descriptors = Vec::new();
+    let mut ready = 0;
+
+    for fd in 0..count {
+        if !sets.iter().any(|set| is_set(set, fd)) {
+            continue;
+        }
+
+        let requested = [(0, libc::POLLIN), (1, libc::POLLOUT)]
+            .iter()
+            .filter(|(index, _)| is_set(&sets[*index], fd))
+            .fold(0, |requested, (_, events)| requested | *events as u32);
+        let (descriptor, events) = poll_events(context, fd as i32, requested);
+        if events & libc::POLLNVAL as u32 != 0 {
+            return errno(libc::EBADF);
+        }
+        descriptors.extend(descriptor);
+
+        for (index, mask) in [(0, readable), (1, writable)] {
+            if is_set(&sets[index], fd) && events & mask != 0 {
+                results[index][fd / 64] |= 1 << (fd % 64);
+                ready += 1;
+            }
+        }
+    }
+
+    if ready > 0 || matches!(deadline, Some(deadline) if deadline <= now()) {
+        for (address, set) in addresses.iter().zip(&results) {
+            if *address == 0 {
+                continue;
+            }
+            for (word, value) in set.iter().enumerate() {
+                if context
+                    .memory
+                    .write_value(address + word as u64 * 8, value)
+                    .is_err()
+                {
+                    return errno(libc::EFAULT);
+                }
+            }
+        }
+        return SyscallResult::Done(ready);
+    }
+
+    match wait_until(context, deadline) {
+        Some(result) => {
+            wait_on(context, &descriptors);
+            result
+        }
+        None => SyscallResult::Done(0),
+    }
+}
@@ -0,0 +1,355 @@
+mod clock;
+mod file;
+mod socket;
+mod thread;
+
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crate::descriptor::Descriptor;
+use crate::host::Host;
+use crate::process::{Memory, ProcessState};
+use crate::task::Task;
+use crate::time::SimulationTime;
+use crate::worker::Worker;
+
+const MAX_RANDOM_SIZE: usize = 33_554_431;
+
+pub type ExitHook = Box<dyn FnOnce(&mut ProcessState, i64) -> i64 + Send>;
+
+pub enum SyscallResult {
+    /// Let the kernel execute the syscall unchanged.
+    Native,
+    /// Let the kernel execute another syscall instead and map its result on exit.
+    Replace {
+        number: i64,
+        args: [u64; 6],
+        on_exit: ExitHook,
+    },
+    /// Skip the syscall and return the value, negative values being `-errno`.
+    Done(i64),
+    /// Keep the caller stopped at the syscall until the process is resumed.
+    Block,
+}
+
+pub struct SyscallContext<'a> {
+    pub host: &'a Arc<Host>,
+    pub process: &'a mut ProcessState,
+    pub pid: libc::pid_t,
+    pub memory: Memory,
+    pub number: i64,
+    pub args: [u64; 6],
+}
+
+impl<'a> SyscallContext<'a> {
+    pub fn new(
+        host: &'a Arc<Host>,
+        process: &'a mut ProcessState,
+        pid: libc::pid_t,
+        regs: &libc::user_regs_struct,
+    ) -> Self {
+        Self {
+            host,
+            process,
+            pid,
+            memory: Memory::new(pid),
+            number: regs.orig_rax as i64,
+            args: [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9],
+        }
+    }
+
+    fn descriptor(&mut self, fd: u64) -> Option<Arc<Mutex<Descriptor>>> {
+        self.process.descriptors().get(fd as i32)
+    }
+}
+
+pub fn handle(context: &mut SyscallContext) -> SyscallResult {
+    match context.number {
+        libc::SYS_uname => uname(context),
+        libc::SYS_getrandom => getrandom(context),
+        libc::SYS_clock_gettime => clock::clock_gettime(context),
+        libc::SYS_gettimeofday => clock::gettimeofday(context),
+        libc::SYS_time => clock::time(context),
+        libc::SYS_nanosleep => clock::nanosleep(context),
+        libc::SYS_clock_nanosleep => clock::clock_nanosleep(context),
+        libc::SYS_pause => clock::pause(context),
+        libc::SYS_timerfd_create => clock::timerfd_create(context),
+        libc::SYS_timerfd_settime => clock::timerfd_settime(context),
+        libc::SYS_timerfd_gettime => clock::timerfd_gettime(context),
+        libc::SYS_read => file::read(context),
+        libc::SYS_readv => file::readv(context),
+        libc::SYS_write => file::write(context),
+        libc::SYS_writev => file::writev(context),
+        libc::SYS_lseek
+        | libc::SYS_pread64
+        | libc::SYS_pwrite64
+        | libc::SYS_preadv
+        | libc::SYS_pwritev => file::positional(context),
+        libc::SYS_fstat | libc::SYS_newfstatat | libc::SYS_statx => file::stat(context),
+        libc::SYS_close => file::close(context),
+        libc::SYS_pipe => file::pipe(context, 0),
+        libc::SYS_pipe2 => file::pipe(context, context.args[1]),
+        libc::SYS_eventfd => file::eventfd(context, 0),
+        libc::SYS_eventfd2 => file::eventfd(context, context.args[1]),
+        libc::SYS_fcntl => file::fcntl(context),
+        libc::SYS_ioctl => file::ioctl(context),
+        libc::SYS_dup => file::dup(context),
+        libc::SYS_dup2 | libc::SYS_dup3 => file::dup2(context),
+        libc::SYS_epoll_create | libc::SYS_epoll_create1 => file::epoll_create(context),
+        libc::SYS_epoll_ctl => file::epoll_ctl(context),
+        libc::SYS_epoll_wait | libc::SYS_epoll_pwait => file::epoll_wait(context),
+        libc::SYS_poll => file::poll(context, Timeout::Millis(context.args[2] as i32)),
+        libc::SYS_ppoll => file::poll(context, Timeout::Timespec(context.args[2])),
+        libc::SYS_select => file::select(context, Timeout::Timeval(context.args[4])),
+        libc::SYS_pselect6 => file::select(context, Timeout::Timespec(context.args[4])),
+        libc::SYS_socket => socket::socket(context),
+        libc::SYS_socketpair => errno(libc::ENOSYS),
+        libc::SYS_bind => socket::bind(context),
+        libc::SYS_listen => socket::listen(context),
+        libc::SYS_accept => socket::accept(context, 0),
+        libc::SYS_accept4 => socket::accept(context, context.args[3]),
+        libc::SYS_connect => socket::connect(context),
+        libc::SYS_shutdown => socket::shutdown(context),
+        libc::SYS_getsockname => socket::getsockname(context),
+        libc::SYS_getpeername => socket::getpeername(context),
+        libc::SYS_getsockopt => socket::getsockopt(context),
+        libc::SYS_setsockopt => socket::setsockopt(context),
+        libc::SYS_sendto => socket::sendto(context),
+        libc::SYS_recvfrom => socket::recvfrom(context),
+        libc::SYS_sendmsg => socket::sendmsg(context),
+        libc::SYS_recvmsg => socket::recvmsg(context),
+        libc::SYS_sendmmsg => socket::sendmmsg(context),
+        libc::SYS_sendfile => socket::sendfile(context),
+        libc::SYS_clone => thread::clone(context),
+        libc::SYS_clone3 | libc::SYS_fork | libc::SYS_vfork => errno(libc::ENOSYS),
+        libc::SYS_set_tid_address => thread::set_tid_address(context),
+        libc::SYS_futex => thread::futex(context),
+        libc::SYS_sched_yield => thread::sched_yield(context),
+        libc::SYS_exit => thread::exit(context),
+        libc::SYS_exit_group => thread::exit_group(context),
+        _ => fallback(context),
+    }
+}
+
+/// Leaves the calls not emulated to the kernel, except the ones acting on the data of an
+/// emulated descriptor in ways emulated files do not support, which would act on the
+/// placeholder instead.
+fn fallback(context: &mut SyscallContext) -> SyscallResult {
+    let descriptors: &[usize] = match context.number {
+        libc::SYS_ftruncate
+        | libc::SYS_fallocate
+        | libc::SYS_fsync
+        | libc::SYS_fdatasync
+        | libc::SYS_syncfs
+        | libc::SYS_sync_file_range
+        | libc::SYS_fadvise64
+        | libc::SYS_readahead
+        | libc::SYS_getdents
+        | libc::SYS_getdents64
+        | libc::SYS_fchdir
+        | libc::SYS_fstatfs
+        | libc::SYS_vmsplice => &[0],
+        libc::SYS_splice | libc::SYS_copy_file_range => &[0, 2],
+        libc::SYS_tee => &[0, 1],
+        libc::SYS_mmap => &[4],
+        _ => return SyscallResult::Native,
+    };
+
+    let is_emulated = descriptors.iter().any(|index| {
+        context
+            .process
+            .descriptors()
+            .contains(context.args[*index] as i32)
+    });
+
+    match is_emulated {
+        true => errno(libc::EINVAL),
+        false => SyscallResult::Native,
+    }
+}
+
+fn errno(code: libc::c_int) -> SyscallResult {
+    SyscallResult::Done(-i64::from(code))
+}
+
+/// Maps an error of the simulated network or file to the number the kernel would report.
+fn errno_of(err: &io::Error) -> libc::c_int {
+    if let Some(code) = err.raw_os_error() {
+        return code;
+    }
+
+    match err.kind() {
+        io::ErrorKind::WouldBlock => libc::EAGAIN,
+        io::ErrorKind::InvalidInput => libc::EINVAL,
+        io::ErrorKind::NotConnected => libc::ENOTCONN,
+        io::ErrorKind::ConnectionRefused => libc::ECONNREFUSED,
+        io::ErrorKind::ConnectionReset => libc::ECONNRESET,
+        io::ErrorKind::ConnectionAborted => libc::ECONNABORTED,
+        io::ErrorKind::AddrInUse => libc::EADDRINUSE,
+        io::ErrorKind::AddrNotAvailable => libc::EADDRNOTAVAIL,
+        io::ErrorKind::BrokenPipe => libc::EPIPE,
+        io::ErrorKind::AlreadyExists => libc::EEXIST,
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+        _ => libc::EIO,
+    }
+}
+
+/// Reports the real system, but with the simulated host name as node name.
+fn uname(context: &mut SyscallContext) -> SyscallResult {
+    let mut name: libc::utsname = unsafe { mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } < 0 {
+        return errno(libc::EFAULT);
+    }
+
+    name.nodename = [0; 65];
+    for (dst, src) in name
+        .nodename
+        .iter_mut()
+        .zip(context.host.name().bytes().take(64))
+    {
+        *dst = src as libc::c_char;
+    }
+
+    match context.memory.write_value(context.args[0], &name) {
+        Ok(()) => SyscallResult::Done(0),
+        Err(_) => errno(libc::EFAULT),
+    }
+}
+
+/// Serves randomness from the seeded generator of the host, so runs stay reproducible.
+fn getrandom(context: &mut SyscallContext) -> SyscallResult {
+    let mut bytes = vec![0; (context.args[1] as usize).min(MAX_RANDOM_SIZE)];
+    context.host.fill_random(&mut bytes);
+
+    match context.memory.write(context.args[0], &bytes) {
+        Ok(()) => SyscallResult::Done(bytes.len() as i64),
+        Err(_) => errno(libc::EFAULT),
+    }
+}
+
+fn now() -> SimulationTime {
+    Worker::current_time()
+        .map(SimulationTime::from)
+        .unwrap_or_else(SimulationTime::zero)
+}
+
+/// The timeout argument of the calls waiting for descriptors, in the format of each call.
+#[derive(Clone, Copy)]
+enum Timeout {
+    Millis(i32),
+    Timeval(u64),
+    Timespec(u64),
+}
+
+impl Timeout {
+    /// Reads the timeout, `None` meaning to wait indefinitely.
+    fn read(self, memory: &Memory) -> Result<Option<SimulationTime>, libc::c_int> {
+        match self {
+            Timeout::Millis(millis) if millis < 0 => Ok(None),
+            Timeout::Millis(millis) => Ok(Some(SimulationTime::from_millis(i64::from(millis)))),
+            Timeout::Timeval(0) | Timeout::Timespec(0) => Ok(None),
+            Timeout::Timeval(address) => {
+                let value = memory
+                    .read_value::<libc::timeval>(address)
+                    .map_err(|_| libc::EFAULT)?;
+                let spec = libc::timespec {
+                    tv_sec: value.tv_sec,
+                    tv_nsec: value.tv_usec.saturating_mul(1000),
+                };
+                clock::from_timespec(&spec).map(Some).ok_or(libc::EINVAL)
+            }
+            Timeout::Timespec(address) => {
+                let value = memory
+                    .read_value::<libc::timespec>(address)
+                    .map_err(|_| libc::EFAULT)?;
+                clock::from_timespec(&value).map(Some).ok_or(libc::EINVAL)
+            }
+        }
+    }
+}
+
+/// Keeps the caller blocked until `deadline` and reports whether it has passed. The process is
+/// resumed by a scheduled task, which retries the syscall.
+fn sleep_until(context: &mut SyscallContext, deadline: SimulationTime) -> bool {
+    let now = now();
+
+    if deadline <= now {
+        return true;
+    }
+
+    if context.process.wakeup() != Some(deadline) {
+        context.process.set_wakeup(deadline);
+        schedule_resume(context, deadline - now);
+    }
+
+    false
+}
+
+/// Schedules the thread handled to retry its syscall after `delay`.
+fn schedule_resume(context: &SyscallContext, delay: SimulationTime) {
+    if let Some(process) = Worker::with_active_process(|process| process.clone()) {
+        let task = match Worker::active_thread() {
+            Some(thread) => Task::StartThread(process, thread),
+            None => Task::ResumeProcess(process),
+        };
+        Worker::schedule_task(task, context.host, delay);
+    }
+}
+
+/// Blocks the caller until the file changes, or until a timer it waits for expires.
+fn block_on(context: &SyscallContext, descriptor: &mut Descriptor) -> SyscallResult {
+    if let Some(process) = Worker::with_active_process(|process| process.clone()) {
+        if let Some(deadline) = descriptor.wait(&process) {
+            resume_at(context, deadline);
+        }
+    }
+
+    SyscallResult::Block
+}
+
+fn resume_at(context: &SyscallContext, deadline: SimulationTime) {
+    let now = now();
+    let delay = match deadline > now {
+        true => deadline - now,
+        false => SimulationTime::zero(),
+    };
+    schedule_resume(context, delay);
+}
+
+/// Finishes an operation on an emulated file, blocking the caller if it would block on a
+/// blocking descriptor.
+fn complete(
+    context: &SyscallContext,
+    descriptor: &mut Descriptor,
+    is_nonblocking: bool,
+    result: io::Result<i64>,
+) -> SyscallResult {
+    match result {
+        Ok(value) => SyscallResult::Done(value),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock && !is_nonblocking => {
+            block_on(context, descriptor)
+        }
+        Err(err) => errno(errno_of(&err)),
+    }
+}
+
+/// Creates a native eventfd in place of the descriptor, to hold the number of the emulated
+/// descriptor in the process' table.
+fn placeholder(flags: u64, descriptor: Descriptor) -> SyscallResult {
+    let flags = flags & (libc::O_CLOEXEC | libc::O_NONBLOCK) as u64;
+
+    SyscallResult::Replace {
+        number: libc::SYS_eventfd2,
+        args: [0, flags, 0, 0, 0, 0],
+        on_exit: Box::new(move |process, fd| {
+            if fd >= 0 {
+                process
+                    .descriptors()
+                    .insert(fd as i32, Arc::new(Mutex::new(descriptor)));
+            }
+            fd
+        }),
+    }
+}
@@ -0,0 +1,710 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::os::unix::fs::FileExt;
+
+use crate::descriptor::{Descriptor, File};
+use crate::net::AddressFamily;
+use crate::tcp::TcpSocket;
+use crate::udp::UdpSocket;
+
+use super::file::{gather, read_iovecs, receive, scatter, transmit, MAX_TRANSFER_SIZE};
+use super::{block_on, complete, errno, errno_of, placeholder, SyscallContext, SyscallResult};
+
+const SOCKADDR_IN_SIZE: usize = 16;
+const SOCKADDR_IN6_SIZE: usize = 28;
+const MMSGHDR_SIZE: u64 = 64;
+const MMSGHDR_LEN_OFFSET: u64 = 56;
+const MAX_MMSG_COUNT: usize = 1024;
+
+/// Size the kernel reports for the send buffer of datagram sockets.
+const UDP_SEND_BUFFER_SIZE: usize = 212_992;
+
+pub(super) fn socket(context: &mut SyscallContext) -> SyscallResult {
+    let family = match context.args[0] as libc::c_int {
+        libc::AF_INET => AddressFamily::Inet,
+        libc::AF_INET6 => AddressFamily::Inet6,
+        _ => return SyscallResult::Native,
+    };
+
+    let flags = context.args[1] as libc::c_int;
+    let is_nonblocking = flags & libc::SOCK_NONBLOCK != 0;
+    let protocol = context.args[2] as libc::c_int;
+
+    let file = match flags & !(libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC) {
+        libc::SOCK_STREAM if protocol == 0 || protocol == libc::IPPROTO_TCP => {
+            File::Tcp(TcpSocket::new(), family)
+        }
+        libc::SOCK_DGRAM if protocol == 0 || protocol == libc::IPPROTO_UDP => {
+            File::Udp(UdpSocket::new(), family)
+        }
+        _ => return errno(libc::EPROTONOSUPPORT),
+    };
+
+    placeholder(context.args[1], Descriptor::new(file, is_nonblocking))
+}
+
+/// Runs the operation on the socket behind the first argument. Native descriptors are left to
+/// the kernel.
+fn with_socket<F>(context: &mut SyscallContext, func: F) -> SyscallResult
+where
+    F: FnOnce(&mut SyscallContext, &mut Descriptor) -> SyscallResult,
+{
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+
+    match descriptor.is_socket() {
+        true => func(context, &mut descriptor),
+        false => errno(libc::ENOTSOCK),
+    }
+}
+
+fn family_of(descriptor: &Descriptor) -> AddressFamily {
+    match descriptor.file() {
+        File::Udp(_, family) | File::Tcp(_, family) => *family,
+        _ => AddressFamily::Inet,
+    }
+}
+
+/// Reads a socket address, `None` standing for `AF_UNSPEC`. IPv4-mapped IPv6 addresses are
+/// read as the IPv4 address they map.
+fn read_sockaddr(
+    context: &SyscallContext,
+    address: u64,
+    len: usize,
+) -> Result<Option<SocketAddr>, libc::c_int> {
+    if len < mem::size_of::<libc::sa_family_t>() {
+        return Err(libc::EINVAL);
+    }
+
+    let bytes = context
+        .memory
+        .read(address, len.min(SOCKADDR_IN6_SIZE))
+        .map_err(|_| libc::EFAULT)?;
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[2], bytes[3]]);
+
+    match i32::from(u16::from_ne_bytes([bytes[0], bytes[1]])) {
+        libc::AF_UNSPEC => Ok(None),
+        libc::AF_INET if bytes.len() >= SOCKADDR_IN_SIZE => {
+            let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port(&bytes))))
+        }
+        libc::AF_INET6 if bytes.len() >= SOCKADDR_IN6_SIZE => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&bytes[8..24]);
+            let ip = Ipv6Addr::from(octets);
+            let ip = match ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => IpAddr::V6(ip),
+            };
+            Ok(Some(SocketAddr::new(ip, port(&bytes))))
+        }
+        libc::AF_INET | libc::AF_INET6 => Err(libc::EINVAL),
+        _ => Err(libc::EAFNOSUPPORT),
+    }
+}
+
+/// Encodes the address as the socket family sees it, IPv6 sockets seeing IPv4 peers as
+/// IPv4-mapped addresses.
+fn encode_sockaddr(addr: SocketAddr, family: AddressFamily) -> Vec<u8> {
+    match (family, addr) {
+        (AddressFamily::Inet, SocketAddr::V4(addr)) => {
+            let mut bytes = (libc::AF_INET as libc::sa_family_t).to_ne_bytes().to_vec();
+            bytes.extend(addr.port().to_be_bytes());
+            bytes.extend(addr.ip().octets());
+            bytes.resize(SOCKADDR_IN_SIZE, 0);
+            bytes
+        }
+        (_, addr) => {
+            let addr = match addr {
+                SocketAddr::V4(addr) => {
+                    SocketAddrV6::new(addr.ip().to_ipv6_mapped(), addr.port(), 0, 0)
+                }
+                SocketAddr::V6(addr) => addr,
+            };
+            let mut bytes = (libc::AF_INET6 as libc::sa_family_t).to_ne_bytes().to_vec();
+            bytes.extend(addr.port().to_be_bytes());
+            bytes.extend(addr.flowinfo().to_ne_bytes());
+            bytes.extend(addr.ip().octets());
+            bytes.extend(addr.scope_id().to_ne_bytes());
+            bytes
+        }
+    }
+}
+
+/// Writes the address truncated to the buffer length the caller passed and reports the full
+/// length, like the kernel does.
+fn write_sockaddr(
+    context: &SyscallContext,
+    address: u64,
+    len_address: u64,
+    addr: SocketAddr,
+    family: AddressFamily,
+) -> Result<(), libc::c_int> {
+    if address == 0 || len_address == 0 {
+        return Ok(());
+    }
+
+    let len = context
+        .memory
+        .read_value::<libc::socklen_t>(len_address)
+        .map_err(|_| libc::EFAULT)? as usize;
+    let bytes = encode_sockaddr(addr, family);
+
+    context
+        .memory
+        .write(address, &bytes[..len.min(bytes.len())])
+        .and_then(|()| {
+            context
+                .memory
+                .write_value(len_address, &(bytes.len() as libc::socklen_t))
+        })
+        .map_err(|_| libc::EFAULT)
+}
+
+fn unspecified(family: AddressFamily) -> SocketAddr {
+    match family {
+        AddressFamily::Inet => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        AddressFamily::Inet6 => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    }
+}
+
+fn done(result: io::Result<()>) -> SyscallResult {
+    match result {
+        Ok(()) => SyscallResult::Done(0),
+        Err(err) => errno(errno_of(&err)),
+    }
+}
+
+pub(super) fn bind(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let family = family_of(descriptor);
+        let addr = match read_sockaddr(context, context.args[1], context.args[2] as usize) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => return errno(libc::EAFNOSUPPORT),
+            Err(code) => return errno(code),
+        };
+
+        if family == AddressFamily::Inet && addr.is_ipv6() {
+            return errno(libc::EAFNOSUPPORT);
+        }
+
+        match descriptor.file() {
+            File::Udp(socket, _) => done(socket.bind(context.host, addr)),
+            File::Tcp(socket, _) => done(socket.bind(context.host, addr)),
+            _ => errno(libc::ENOTSOCK),
+        }
+    })
+}
+
+pub(super) fn listen(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| match descriptor.file() {
+        File::Tcp(socket, family) => {
+            let backlog = (context.args[1] as i32).max(0) as usize;
+            done(socket.listen(context.host, backlog, *family))
+        }
+        _ => errno(libc::EOPNOTSUPP),
+    })
+}
+
+pub(super) fn accept(context: &mut SyscallContext, flags: u64) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let (socket, family) = match descriptor.file() {
+            File::Tcp(socket, family) => (socket.clone(), *family),
+            _ => return errno(libc::EOPNOTSUPP),
+        };
+
+        let child = match socket.accept() {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock && !descriptor.is_nonblocking() => {
+                return block_on(context, descriptor)
+            }
+            Err(err) => return errno(errno_of(&err)),
+        };
+
+        if let Ok(peer) = child.peer_addr() {
+            if let Err(code) =
+                write_sockaddr(context, context.args[1], context.args[2], peer, family)
+            {
+                child.close(context.host);
+                return errno(code);
+            }
+        }
+
+        let is_nonblocking = flags & libc::SOCK_NONBLOCK as u64 != 0;
+        placeholder(
+            flags,
+            Descriptor::new(File::Tcp(child, family), is_nonblocking),
+        )
+    })
+}
+
+pub(super) fn connect(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let addr = match read_sockaddr(context, context.args[1], context.args[2] as usize) {
+            Ok(addr) => addr,
+            Err(code) => return errno(code),
+        };
+
+        match (descriptor.file(), addr) {
+            // Connecting a datagram socket to AF_UNSPEC dissolves the association, which the
+            // simulated sockets have no use for.
+            (File::Udp(..), None) => SyscallResult::Done(0),
+            (File::Udp(socket, _), Some(addr)) => done(socket.connect(context.host, addr)),
+            (File::Tcp(..), None) => errno(libc::EAFNOSUPPORT),
+            (File::Tcp(socket, _), Some(_)) if context.process.is_retrying() => {
+                match socket.is_connected() {
+                    Ok(true) => SyscallResult::Done(0),
+                    Ok(false) => block_on(context, descriptor),
+                    Err(err) => errno(errno_of(&err)),
+                }
+            }
+            (File::Tcp(socket, _), Some(addr)) => match socket.connect(context.host, addr) {
+                Ok(()) => SyscallResult::Done(0),
+                Err(err)
+                    if err.raw_os_error() == Some(libc::EINPROGRESS)
+                        && !descriptor.is_nonblocking() =>
+                {
+                    block_on(context, descriptor)
+                }
+                Err(err) => errno(errno_of(&err)),
+            },
+            _ => errno(libc::ENOTSOCK),
+        }
+    })
+}
+
+pub(super) fn shutdown(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let (read, write) = match context.args[1] as libc::c_int {
+            libc::SHUT_RD => (true, false),
+            libc::SHUT_WR => (false, true),
+            libc::SHUT_RDWR => (true, true),
+            _ => return errno(libc::EINVAL),
+        };
+
+        match descriptor.file() {
+            File::Tcp(socket, _) => done(socket.shutdown(context.host, read, write)),
+            File::Udp(socket, _) => match socket.peer_addr() {
+                Ok(_) => SyscallResult::Done(0),
+                Err(_) => errno(libc::ENOTCONN),
+            },
+            _ => errno(libc::ENOTSOCK),
+        }
+    })
+}
+
+pub(super) fn getsockname(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let family = family_of(descriptor);
+        let addr = match descriptor.file() {
+            File::Udp(socket, _) => socket.local_addr(),
+            File::Tcp(socket, _) => socket.local_addr(),
+            _ => return errno(libc::ENOTSOCK),
+        };
+
+        let addr = addr.unwrap_or_else(|_| unspecified(family));
+        match write_sockaddr(context, context.args[1], context.args[2], addr, family) {
+            Ok(()) => SyscallResult::Done(0),
+            Err(code) => errno(code),
+        }
+    })
+}
+
+pub(super) fn getpeername(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let family = family_of(descriptor);
+        let addr = match descriptor.file() {
+            File::Udp(socket, _) => socket.peer_addr(),
+            File::Tcp(socket, _) => socket.peer_addr(),
+            _ => return errno(libc::ENOTSOCK),
+        };
+
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(_) => return errno(libc::ENOTCONN),
+        };
+
+        match write_sockaddr(context, context.args[1], context.args[2], addr, family) {
+            Ok(()) => SyscallResult::Done(0),
+            Err(code) => errno(code),
+        }
+    })
+}
+
+pub(super) fn getsockopt(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let family = family_of(descriptor);
+        let file = descriptor.file();
+        let is_stream = matches!(file, File::Tcp(..));
+
+        let value = match (
+            context.args[1] as libc::c_int,
+            context.args[2] as libc::c_int,
+        ) {
+            (libc::SOL_SOCKET, libc::SO_ERROR) => match file {
+                File::Udp(socket, _) => socket.take_error().unwrap_or(0),
+                File::Tcp(socket, _) => socket.take_error().unwrap_or(0),
+                _ => 0,
+            },
+            (libc::SOL_SOCKET, libc::SO_TYPE) => match is_stream {
+                true => libc::SOCK_STREAM,
+                false => libc::SOCK_DGRAM,
+            },
+            (libc::SOL_SOCKET, libc::SO_DOMAIN) => match family {
+                AddressFamily::Inet => libc::AF_INET,
+                AddressFamily::Inet6 => libc::AF_INET6,
+            },
+            (libc::SOL_SOCKET, libc::SO_PROTOCOL) => match is_stream {
+                true => libc::IPPROTO_TCP,
+                false => libc::IPPROTO_UDP,
+            },
+            (libc::SOL_SOCKET, libc::SO_RCVBUF) => match file {
+                File::Udp(socket, _) => socket.recv_buffer_size() as libc::c_int,
+                File::Tcp(socket, _) => socket.recv_buffer_size() as libc::c_int,
+                _ => 0,
+            },
+            (libc::SOL_SOCKET, libc::SO_SNDBUF) => match file {
+                File::Tcp(socket, _) => socket.send_buffer_size() as libc::c_int,
+                _ => UDP_SEND_BUFFER_SIZE as libc::c_int,
+            },
+            (libc::SOL_SOCKET, libc::SO_ACCEPTCONN) => match file {
+                File::Tcp(socket, _) => socket.is_listening() as libc::c_int,
+                _ => 0,
+            },
+            (libc::SOL_SOCKET, _) => 0,
+            (libc::IPPROTO_TCP, libc::TCP_NODELAY) if is_stream => 1,
+            (libc::IPPROTO_TCP, libc::TCP_MAXSEG) if is_stream => match family {
+                AddressFamily::Inet => 1460,
+                AddressFamily::Inet6 => 1440,
+            },
+            (libc::IPPROTO_IP, _) | (libc::IPPROTO_IPV6, _) => 0,
+            _ => return errno(libc::ENOPROTOOPT),
+        };
+
+        let (address, len_address) = (context.args[3], context.args[4]);
+        let len = match context.memory.read_value::<libc::socklen_t>(len_address) {
+            Ok(len) => len as usize,
+            Err(_) => return errno(libc::EFAULT),
+        };
+        let bytes = value.to_ne_bytes();
+        let len = len.min(bytes.len());
+
+        match context.memory.write(address, &bytes[..len]).and_then(|()| {
+            context
+                .memory
+                .write_value(len_address, &(len as libc::socklen_t))
+        }) {
+            Ok(()) => SyscallResult::Done(0),
+            Err(_) => errno(libc::EFAULT),
+        }
+    })
+}
+
+/// Applies the buffer sizes, which the kernel doubles for bookkeeping overhead, and accepts
+/// all other options without effect.
+pub(super) fn setsockopt(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let option = (
+            context.args[1] as libc::c_int,
+            context.args[2] as libc::c_int,
+        );
+
+        if !matches!(
+            option,
+            (libc::SOL_SOCKET, libc::SO_RCVBUF | libc::SO_SNDBUF)
+        ) {
+            return SyscallResult::Done(0);
+        }
+
+        if (context.args[4] as usize) < mem::size_of::<libc::c_int>() {
+            return errno(libc::EINVAL);
+        }
+
+        let size = match context.memory.read_value::<libc::c_int>(context.args[3]) {
+            Ok(size) => (size.max(0) as usize).saturating_mul(2),
+            Err(_) => return errno(libc::EFAULT),
+        };
+
+        match (descriptor.file(), option.1) {
+            (File::Udp(socket, _), libc::SO_RCVBUF) => socket.set_recv_buffer_size(size),
+            (File::Tcp(socket, _), libc::SO_RCVBUF) => socket.set_recv_buffer_size(size),
+            (File::Tcp(socket, _), libc::SO_SNDBUF) => socket.set_send_buffer_size(size),
+            _ => {}
+        }
+
+        SyscallResult::Done(0)
+    })
+}
+
+fn is_nonblocking(descriptor: &Descriptor, flags: libc::c_int) -> bool {
+    descriptor.is_nonblocking() || flags & libc::MSG_DONTWAIT != 0
+}
+
+/// Reads the destination of a datagram, which connected streams ignore.
+fn read_destination(
+    context: &SyscallContext,
+    descriptor: &Descriptor,
+    address: u64,
+    len: usize,
+) -> Result<Option<SocketAddr>, libc::c_int> {
+    if address == 0 || !matches!(descriptor.file(), File::Udp(..)) {
+        return Ok(None);
+    }
+
+    read_sockaddr(context, address, len)
+}
+
+pub(super) fn sendto(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let flags = context.args[3] as libc::c_int;
+        let addr = match read_destination(
+            context,
+            descriptor,
+            context.args[4],
+            context.args[5] as usize,
+        ) {
+            Ok(addr) => addr,
+            Err(code) => return errno(code),
+        };
+
+        let len = (context.args[2] as usize).min(MAX_TRANSFER_SIZE);
+        let result = context
+            .memory
+            .read(context.args[1], len)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))
+            .and_then(|data| transmit(context, descriptor, &data, addr, flags))
+            .map(|len| len as i64);
+
+        complete(
+            context,
+            descriptor,
+            is_nonblocking(descriptor, flags),
+            result,
+        )
+    })
+}
+
+pub(super) fn recvfrom(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let flags = context.args[3] as libc::c_int;
+        let family = family_of(descriptor);
+
+        let result = receive(context, descriptor, context.args[2] as usize, flags).and_then(
+            |(data, addr)| {
+                context
+                    .memory
+                    .write(context.args[1], &data)
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))?;
+                if let Some(addr) = addr {
+                    write_sockaddr(context, context.args[4], context.args[5], addr, family)
+                        .map_err(io::Error::from_raw_os_error)?;
+                }
+                Ok(data.len() as i64)
+            },
+        );
+
+        complete(
+            context,
+            descriptor,
+            is_nonblocking(descriptor, flags),
+            result,
+        )
+    })
+}
+
+/// Sends the message described by the `msghdr` at `address`, ignoring ancillary data.
+fn send_message(
+    context: &SyscallContext,
+    descriptor: &mut Descriptor,
+    address: u64,
+    flags: libc::c_int,
+) -> io::Result<usize> {
+    let header = context
+        .memory
+        .read_value::<libc::msghdr>(address)
+        .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))?;
+
+    let addr = read_destination(
+        context,
+        descriptor,
+        header.msg_name as u64,
+        header.msg_namelen as usize,
+    )
+    .map_err(io::Error::from_raw_os_error)?;
+
+    let iovecs = read_iovecs(&context.memory, header.msg_iov as u64, header.msg_iovlen)?;
+    let data = gather(&context.memory, &iovecs)?;
+    transmit(context, descriptor, &data, addr, flags)
+}
+
+pub(super) fn sendmsg(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let flags = context.args[2] as libc::c_int;
+        let result =
+            send_message(context, descriptor, context.args[1], flags).map(|len| len as i64);
+        complete(
+            context,
+            descriptor,
+            is_nonblocking(descriptor, flags),
+            result,
+        )
+    })
+}
+
+pub(super) fn sendmmsg(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let flags = context.args[3] as libc::c_int;
+        let count = (context.args[2] as usize).min(MAX_MMSG_COUNT);
+        let mut sent = 0;
+
+        while sent < count {
+            let address = context.args[1] + sent as u64 * MMSGHDR_SIZE;
+            let len = match send_message(context, descriptor, address, flags) {
+                Ok(len) => len,
+                Err(err) if sent == 0 => {
+                    let nonblocking = is_nonblocking(descriptor, flags);
+                    return complete(context, descriptor, nonblocking, Err(err));
+                }
+                Err(_) => break,
+            };
+
+            if context
+                .memory
+                .write_value(address + MMSGHDR_LEN_OFFSET, &(len as libc::c_uint))
+                .is_err()
+            {
+                return errno(libc::EFAULT);
+            }
+            sent += 1;
+        }
+
+        SyscallResult::Done(sent as i64)
+    })
+}
+
+pub(super) fn recvmsg(context: &mut SyscallContext) -> SyscallResult {
+    with_socket(context, |context, descriptor| {
+        let flags = context.args[2] as libc::c_int;
+        let family = family_of(descriptor);
+
+        let result = context
+            .memory
+            .read_value::<libc::msghdr>(context.args[1])
+            .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))
+            .and_then(|mut header| {
+                let iovecs =
+                    read_iovecs(&context.memory, header.msg_iov as u64, header.msg_iovlen)?;
+                let len = iovecs.iter().map(|(_, len)| len).sum();
+                let (data, addr) = receive(context, descriptor, len, flags)?;
+                scatter(&context.memory, &iovecs, &data)?;
+
+                match addr {
+                    Some(addr) if !header.msg_name.is_null() => {
+                        let bytes = encode_sockaddr(addr, family);
+                        let len = (header.msg_namelen as usize).min(bytes.len());
+                        context
+                            .memory
+                            .write(header.msg_name as u64, &bytes[..len])
+                            .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))?;
+                        header.msg_namelen = bytes.len() as libc::socklen_t;
+                    }
+                    _ => header.msg_namelen = 0,
+                }
+                header.msg_controllen = 0;
+                header.msg_flags = 0;
+
+                context
+                    .memory
+                    .write_value(context.args[1], &header)
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT))?;
+                Ok(data.len() as i64)
+            });
+
+        complete(
+            context,
+            descriptor,
+            is_nonblocking(descriptor, flags),
+            result,
+        )
+    })
+}
+
+/// Position of a native descriptor of the process, as the kernel reports it.
+fn file_position(pid: libc::pid_t, fd: i32) -> io::Result<u64> {
+    let info = fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd))?;
+
+    info.lines()
+        .find_map(|line| line.strip_prefix("pos:"))
+        .and_then(|pos| pos.trim().parse().ok())
+        .ok_or_else(|| io::ErrorKind::InvalidData.into())
+}
+
+/// Copies from a native file to an emulated socket or pipe. The file is read through the
+/// process' descriptor, whose position advances by what was sent.
+pub(super) fn sendfile(context: &mut SyscallContext) -> SyscallResult {
+    let descriptor = match context.descriptor(context.args[0]) {
+        Some(descriptor) => descriptor,
+        None => return SyscallResult::Native,
+    };
+
+    let in_fd = context.args[1] as i32;
+    if context.process.descriptors().contains(in_fd) {
+        return errno(libc::EINVAL);
+    }
+
+    let offset_address = context.args[2];
+    let offset = match offset_address {
+        0 => file_position(context.pid, in_fd),
+        address => context
+            .memory
+            .read_value::<i64>(address)
+            .map(|offset| offset as u64)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EFAULT)),
+    };
+    let offset = match offset {
+        Ok(offset) => offset,
+        Err(err) => return errno(errno_of(&err)),
+    };
+
+    let mut buf = vec![0; (context.args[3] as usize).min(MAX_TRANSFER_SIZE)];
+    let len = match fs::File::open(format!("/proc/{}/fd/{}", context.pid, in_fd))
+        .and_then(|file| file.read_at(&mut buf, offset))
+    {
+        Ok(len) => len,
+        Err(_) => return errno(libc::EBADF),
+    };
+
+    let mut descriptor = descriptor
+        .lock()
+        .expect("tried to acquire poisoned descriptor lock");
+    let nonblocking = descriptor.is_nonblocking();
+
+    let sent = match transmit(context, &mut descriptor, &buf[..len], None, 0) {
+        Ok(sent) => sent,
+        Err(err) => return complete(context, &mut descriptor, nonblocking, Err(err)),
+    };
+
+    if offset_address != 0 {
+        return match context
+            .memory
+            .write_value(offset_address, &((offset + sent as u64) as i64))
+        {
+            Ok(()) => SyscallResult::Done(sent as i64),
+            Err(_) => errno(libc::EFAULT),
+        };
+    }
+
+    SyscallResult::Replace {
+        number: libc::SYS_lseek,
+        args: [in_fd as u64, sent as u64, libc::SEEK_CUR as u64, 0, 0, 0],
+        on_exit: Box::new(move |_, _| sent as i64),
+    }
+}
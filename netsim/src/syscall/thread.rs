@@ -0,0 +1,247 @@
+use crate::time::SimulationTime;
+
+use super::clock::{clock_offset, from_timespec};
+use super::{errno, now, schedule_resume, sleep_until, SyscallContext, SyscallResult};
+
+const FUTEX_CMD_MASK: libc::c_int = !(libc::FUTEX_PRIVATE_FLAG | libc::FUTEX_CLOCK_REALTIME);
+const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// Notes the address cleared on exit of a new thread, whose exit wakes the threads joining it.
+/// Child processes are refused, as they would run outside of the host.
+pub(super) fn clone(context: &mut SyscallContext) -> SyscallResult {
+    let flags = context.args[0] as libc::c_int;
+
+    if flags & libc::CLONE_THREAD == 0 {
+        return errno(libc::ENOSYS);
+    }
+
+    if flags & libc::CLONE_CHILD_CLEARTID == 0 {
+        return SyscallResult::Native;
+    }
+
+    let address = context.args[3];
+
+    SyscallResult::Replace {
+        number: context.number,
+        args: context.args,
+        on_exit: Box::new(move |process, tid| {
+            if tid > 0 {
+                process.set_clear_child_tid(tid as libc::pid_t, address);
+            }
+            tid
+        }),
+    }
+}
+
+pub(super) fn set_tid_address(context: &mut SyscallContext) -> SyscallResult {
+    let tid = context.pid;
+    let address = context.args[0];
+
+    match address {
+        0 => SyscallResult::Native,
+        _ => {
+            context.process.set_clear_child_tid(tid, address);
+            SyscallResult::Native
+        }
+    }
+}
+
+/// Lets the calling thread exit, except for the leader of a process with other threads left.
+/// The kernel keeps such a leader around until the last thread exits, so it waits blocked
+/// until then instead.
+pub(super) fn exit(context: &mut SyscallContext) -> SyscallResult {
+    match context.process.is_leader() && context.process.thread_count() > 1 {
+        true => SyscallResult::Block,
+        false => SyscallResult::Native,
+    }
+}
+
+pub(super) fn exit_group(context: &mut SyscallContext) -> SyscallResult {
+    context.process.set_exiting();
+    SyscallResult::Native
+}
+
+/// Hands control to the other runnable threads of the process before continuing.
+pub(super) fn sched_yield(context: &mut SyscallContext) -> SyscallResult {
+    if context.process.is_retrying() {
+        return SyscallResult::Done(0);
+    }
+
+    schedule_resume(context, SimulationTime::zero());
+    SyscallResult::Block
+}
+
+fn read_word(context: &SyscallContext, address: u64) -> Result<u32, libc::c_int> {
+    context
+        .memory
+        .read_value::<u32>(address)
+        .map_err(|_| libc::EFAULT)
+}
+
+/// Emulates the futex operations threads synchronize with. A thread waiting natively would
+/// block the simulator, so waiters stay stopped until another thread wakes them.
+pub(super) fn futex(context: &mut SyscallContext) -> SyscallResult {
+    let address = context.args[0];
+    let op = context.args[1] as libc::c_int;
+    let value = context.args[2] as u32;
+
+    let result = match op & FUTEX_CMD_MASK {
+        libc::FUTEX_WAIT => return futex_wait(context, op, FUTEX_BITSET_MATCH_ANY),
+        libc::FUTEX_WAIT_BITSET => return futex_wait(context, op, context.args[5] as u32),
+        libc::FUTEX_WAKE => wake(context, address, value, FUTEX_BITSET_MATCH_ANY),
+        libc::FUTEX_WAKE_BITSET => wake(context, address, value, context.args[5] as u32),
+        libc::FUTEX_REQUEUE => requeue(context, None),
+        libc::FUTEX_CMP_REQUEUE => requeue(context, Some(context.args[5] as u32)),
+        libc::FUTEX_WAKE_OP => wake_op(context),
+        _ => Err(libc::ENOSYS),
+    };
+
+    match result {
+        Ok(count) => SyscallResult::Done(count as i64),
+        Err(code) => errno(code),
+    }
+}
+
+fn futex_wait(context: &mut SyscallContext, op: libc::c_int, bitset: u32) -> SyscallResult {
+    // A waiter is only retried to check its timeout, wakes complete the syscall directly.
+    if context.process.is_waiting_futex() {
+        return match context.process.wakeup() {
+            Some(deadline) if deadline <= now() => {
+                context.process.cancel_futex_wait();
+                errno(libc::ETIMEDOUT)
+            }
+            _ => SyscallResult::Block,
+        };
+    }
+
+    if bitset == 0 {
+        return errno(libc::EINVAL);
+    }
+
+    match read_word(context, context.args[0]) {
+        Ok(word) if word != context.args[2] as u32 => return errno(libc::EAGAIN),
+        Ok(_) => {}
+        Err(code) => return errno(code),
+    }
+
+    let deadline = match context.args[3] {
+        0 => None,
+        address => {
+            let timeout = match context.memory.read_value::<libc::timespec>(address) {
+                Ok(timeout) => timeout,
+                Err(_) => return errno(libc::EFAULT),
+            };
+            let timeout = match from_timespec(&timeout) {
+                Some(timeout) => timeout,
+                None => return errno(libc::EINVAL),
+            };
+
+            // Plain waits time out relatively, bitset waits at an absolute time of the
+            // selected clock.
+            match op & FUTEX_CMD_MASK {
+                libc::FUTEX_WAIT => Some(now() + timeout),
+                _ => {
+                    let clock = match op & libc::FUTEX_CLOCK_REALTIME {
+                        0 => libc::CLOCK_MONOTONIC,
+                        _ => libc::CLOCK_REALTIME,
+                    };
+                    Some(timeout - clock_offset(clock).unwrap_or_else(SimulationTime::zero))
+                }
+            }
+        }
+    };
+
+    if let Some(deadline) = deadline {
+        if sleep_until(context, deadline) {
+            return errno(libc::ETIMEDOUT);
+        }
+    }
+
+    context.process.wait_futex(context.args[0], bitset);
+    SyscallResult::Block
+}
+
+fn wake(
+    context: &mut SyscallContext,
+    address: u64,
+    count: u32,
+    bitset: u32,
+) -> Result<usize, libc::c_int> {
+    if bitset == 0 {
+        return Err(libc::EINVAL);
+    }
+
+    Ok(context
+        .process
+        .wake_futex(context.host, address, count as usize, bitset))
+}
+
+fn requeue(context: &mut SyscallContext, expected: Option<u32>) -> Result<usize, libc::c_int> {
+    let (from, to) = (context.args[0], context.args[4]);
+    let (wake_count, requeue_count) = (context.args[2] as u32, context.args[3] as u32);
+
+    if let Some(expected) = expected {
+        if read_word(context, from)? != expected {
+            return Err(libc::EAGAIN);
+        }
+    }
+
+    let woken = wake(context, from, wake_count, FUTEX_BITSET_MATCH_ANY)?;
+    let moved = context
+        .process
+        .requeue_futex(from, to, requeue_count as usize);
+
+    Ok(match expected {
+        Some(_) => woken + moved,
+        None => woken,
+    })
+}
+
+/// Applies the encoded operation to the second word and wakes its waiters if the old value
+/// passes the encoded comparison. The other threads are stopped, so the update is atomic.
+fn wake_op(context: &mut SyscallContext) -> Result<usize, libc::c_int> {
+    let (address, other) = (context.args[0], context.args[4]);
+    let (count, other_count) = (context.args[2] as u32, context.args[3] as u32);
+    let encoded = context.args[5] as u32;
+
+    let sign_extend = |value: u32| ((value << 20) as i32 >> 20) as u32;
+    let op = (encoded >> 28) & 7;
+    let oparg = match encoded >> 28 & (libc::FUTEX_OP_OPARG_SHIFT as u32) {
+        0 => sign_extend((encoded >> 12) & 0xfff),
+        _ => 1u32.wrapping_shl(sign_extend((encoded >> 12) & 0xfff)),
+    };
+    let cmp = (encoded >> 24) & 15;
+    let cmparg = sign_extend(encoded & 0xfff);
+
+    let old = read_word(context, other)?;
+    let new = match op as libc::c_int {
+        libc::FUTEX_OP_SET => oparg,
+        libc::FUTEX_OP_ADD => old.wrapping_add(oparg),
+        libc::FUTEX_OP_OR => old | oparg,
+        libc::FUTEX_OP_ANDN => old & !oparg,
+        libc::FUTEX_OP_XOR => old ^ oparg,
+        _ => return Err(libc::ENOSYS),
+    };
+    context
+        .memory
+        .write_value(other, &new)
+        .map_err(|_| libc::EFAULT)?;
+
+    let (old, cmparg) = (old as i32, cmparg as i32);
+    let is_match = match cmp as libc::c_int {
+        libc::FUTEX_OP_CMP_EQ => old == cmparg,
+        libc::FUTEX_OP_CMP_NE => old != cmparg,
+        libc::FUTEX_OP_CMP_LT => old < cmparg,
+        libc::FUTEX_OP_CMP_LE => old <= cmparg,
+        libc::FUTEX_OP_CMP_GT => old > cmparg,
+        libc::FUTEX_OP_CMP_GE => old >= cmparg,
+        _ => return Err(libc::ENOSYS),
+    };
+
+    let mut woken = wake(context, address, count, FUTEX_BITSET_MATCH_ANY)?;
+    if is_match {
+        woken += wake(context, other, other_count, FUTEX_BITSET_MATCH_ANY)?;
+    }
+
+    Ok(woken)
+}
@@ -1,50 +1,81 @@
 use std::sync::{Arc, Mutex};
 
 use crate::host::Host;
-use crate::net::Interface;
+use crate::net::{Interface, Packet};
+use crate::process::Process;
+use crate::simapp::{HostedApp, TimerId};
+use crate::tcp::TcpSocket;
 use crate::time::SimulationTime;
-use crate::worker::Worker;
+use crate::worker::{Thread, Worker};
 
 pub enum Task {
     // Close(Box<dyn Fn(&Host)>),
     // Expire(Box<dyn Fn(&Host)>),
-    // HeartBeat(Box<dyn Fn(&Host)>),
+    HeartBeat(SimulationTime),
     RefillBuckets(Arc<Mutex<Interface>>),
-    // Retransmit(Box<dyn Fn(&Host)>),
-    // StartProcess(Arc<Process>),
-    // StopProcess(Arc<Process>),
-    // StartThread(Arc<Process>, Arc<Thread>),
-    // ReceivePacket(Arc<Interface>, Arc<Packet>),
+    Retransmit(TcpSocket),
+    StartProcess(Process),
+    StopProcess(Process),
+    KillProcess(Process),
+    ResumeProcess(Process),
+    StartThread(Process, Thread),
+    ReceivePacket(Arc<Mutex<Interface>>, Arc<Packet>),
+    StartApp(HostedApp),
+    StopApp(HostedApp),
+    WakeApp(HostedApp),
+    AppTimer(HostedApp, TimerId),
 }
 
 impl Task {
-    pub fn execute(&mut self, host: Arc<Host>) {
+    /// Names the kind of the task, like `receive_packet`.
+    pub fn kind(&self) -> &'static str {
+        use Task::*;
+
+        match self {
+            HeartBeat(_) => "heartbeat",
+            RefillBuckets(_) => "refill_buckets",
+            Retransmit(_) => "retransmit",
+            StartProcess(_) => "start_process",
+            StopProcess(_) => "stop_process",
+            KillProcess(_) => "kill_process",
+            ResumeProcess(_) => "resume_process",
+            StartThread(..) => "start_thread",
+            ReceivePacket(..) => "receive_packet",
+            StartApp(_) => "start_app",
+            StopApp(_) => "stop_app",
+            WakeApp(_) => "wake_app",
+            AppTimer(..) => "app_timer",
+        }
+    }
+
+    pub fn execute(&self, host: Arc<Host>) {
         use Task::*;
 
         match self {
             // Close(func) => func(host),
             // Expire(func) => func(host),
-            // HeartBeat(func) => func(host),
-            // Retransmit(func) => func(host),
-            RefillBuckets(ref mut interface) => {
+            HeartBeat(interval) => {
+                host.heartbeat();
+                Worker::schedule_task(HeartBeat(*interval), &host, *interval);
+            }
+            RefillBuckets(interface) => {
                 let mut this = interface.lock().unwrap();
-                //this.refill_buckets(host);
-
-                if (this.is_refill_needed()) {
-                    let now: SimulationTime = Worker::current_time()
-                        .expect("Current time not set for worker")
-                        .into();
-                    let interval = Interface::refill_interval();
-                    let last_refill = now - this.refill_started();
-                    let next_refill = interval - last_refill % interval;
-                    let task = Task::RefillBuckets(interface.clone());
-                    //Worker::schedule_task(task, host, next_refill);
-                }
+                this.refill_buckets(&host);
+            }
+            Retransmit(socket) => socket.on_timer(&host),
+            StartProcess(process) => process.start(&host),
+            StopProcess(process) => process.stop(&host),
+            KillProcess(process) => process.kill(&host),
+            ResumeProcess(process) => process.resume(&host),
+            StartThread(process, thread) => process.start_thread(&host, *thread),
+            ReceivePacket(interface, packet) => {
+                let mut this = interface.lock().unwrap();
+                this.receive(&host, packet.clone());
             }
-            // StartProcess(process) => todo!(),
-            // StopProcess(process) => todo!(),
-            // StartThread(process, thread) => todo!(),
-            // ReceivePacket(interface, packet) => todo!(),
+            StartApp(app) => app.start(&host),
+            StopApp(app) => app.stop(&host),
+            WakeApp(app) => app.wake(&host),
+            AppTimer(app, timer) => app.fire_timer(&host, *timer),
         }
     }
 }
@@ -0,0 +1,1492 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::descriptor::Notifier;
+use crate::host::Host;
+use crate::icmp::Unreachable;
+use crate::net::{checksum, AddressFamily, Packet, Protocol};
+use crate::process::Process;
+use crate::simapp::HostedApp;
+use crate::task::Task;
+use crate::time::SimulationTime;
+use crate::worker::Worker;
+
+pub const SEND_BUFFER_SIZE: usize = 4_194_304;
+pub const RECV_BUFFER_SIZE: usize = 6_291_456;
+
+const HEADER_SIZE: usize = 20;
+const MSS_V4: usize = 1460;
+const MSS_V6: usize = 1440;
+const DEFAULT_MSS: usize = 536;
+const WINDOW_SCALE: u8 = 7;
+const INITIAL_WINDOW_SEGMENTS: usize = 10;
+const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+const MAX_SYN_RETRIES: u32 = 6;
+const MAX_RETRIES: u32 = 15;
+
+const INITIAL_RTO_NANOS: i64 = 1_000_000_000;
+const MIN_RTO_NANOS: i64 = 200_000_000;
+const MAX_RTO_NANOS: i64 = 120_000_000_000;
+const TIME_WAIT_NANOS: i64 = 60_000_000_000;
+
+mod flags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+fn now() -> SimulationTime {
+    Worker::current_time()
+        .map(SimulationTime::from)
+        .unwrap_or_else(SimulationTime::zero)
+}
+
+fn max_segment_size(addr: SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => MSS_V4,
+        SocketAddr::V6(_) => MSS_V6,
+    }
+}
+
+pub(crate) fn unspecified(family: AddressFamily) -> IpAddr {
+    match family {
+        AddressFamily::Inet => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        AddressFamily::Inet6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// A TCP segment in wire format, carried as the payload of a packet together with its header.
+#[derive(Clone, Debug, Default)]
+pub struct Segment {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Sequence space taken by the segment, where `SYN` and `FIN` count as one byte each.
+    fn len(&self) -> u32 {
+        self.data.len() as u32 + u32::from(self.has(flags::SYN)) + u32::from(self.has(flags::FIN))
+    }
+
+    pub fn encode(&self, src: IpAddr, dst: IpAddr) -> Vec<u8> {
+        let mut options = Vec::new();
+        if let Some(mss) = self.mss {
+            options.extend([2, 4]);
+            options.extend(mss.to_be_bytes());
+        }
+        if let Some(shift) = self.window_scale {
+            options.extend([1, 3, 3, shift]);
+        }
+
+        let header_size = HEADER_SIZE + options.len();
+        let mut bytes = Vec::with_capacity(header_size + self.data.len());
+        bytes.extend(self.src_port.to_be_bytes());
+        bytes.extend(self.dst_port.to_be_bytes());
+        bytes.extend(self.seq.to_be_bytes());
+        bytes.extend(self.ack.to_be_bytes());
+        bytes.extend([(header_size as u8 / 4) << 4, self.flags]);
+        bytes.extend(self.window.to_be_bytes());
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(options);
+        bytes.extend(&self.data);
+
+        let mut pseudo = Vec::with_capacity(40 + bytes.len());
+        match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                pseudo.extend(src.octets());
+                pseudo.extend(dst.octets());
+                pseudo.extend([0, 6]);
+                pseudo.extend((bytes.len() as u16).to_be_bytes());
+            }
+            (src, dst) => {
+                let octets = |ip: IpAddr| match ip {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+                    IpAddr::V6(ip) => ip.octets(),
+                };
+                pseudo.extend(octets(src));
+                pseudo.extend(octets(dst));
+                pseudo.extend((bytes.len() as u32).to_be_bytes());
+                pseudo.extend([0, 0, 0, 6]);
+            }
+        }
+        pseudo.extend(&bytes);
+
+        let sum = checksum(&pseudo);
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let header_size = usize::from(bytes[12] >> 4) * 4;
+        if header_size < HEADER_SIZE || bytes.len() < header_size {
+            return None;
+        }
+
+        let mut segment = Self {
+            src_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            dst_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            seq: u32::from_be_bytes(bytes[4..8].try_into().ok()?),
+            ack: u32::from_be_bytes(bytes[8..12].try_into().ok()?),
+            flags: bytes[13],
+            window: u16::from_be_bytes([bytes[14], bytes[15]]),
+            mss: None,
+            window_scale: None,
+            data: bytes[header_size..].to_vec(),
+        };
+
+        let mut options = &bytes[HEADER_SIZE..header_size];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = usize::from(*options.get(1)?);
+                    let option = options.get(..len.max(2))?;
+                    match (kind, option) {
+                        (2, [_, _, hi, lo]) => segment.mss = Some(u16::from_be_bytes([*hi, *lo])),
+                        (3, [_, _, shift]) => segment.window_scale = Some(*shift),
+                        _ => {}
+                    }
+                    options = &options[len.max(2)..];
+                }
+            }
+        }
+
+        Some(segment)
+    }
+}
+
+/// Answers a segment that reached no connection with a reset, as long as it is not one itself.
+pub(crate) fn reset_for(packet: &Packet) -> Option<Packet> {
+    let segment = Segment::decode(packet.payload())?;
+
+    if segment.has(flags::RST) {
+        return None;
+    }
+
+    let reply = match segment.has(flags::ACK) {
+        true => Segment {
+            src_port: segment.dst_port,
+            dst_port: segment.src_port,
+            seq: segment.ack,
+            flags: flags::RST,
+            ..Segment::default()
+        },
+        false => Segment {
+            src_port: segment.dst_port,
+            dst_port: segment.src_port,
+            ack: segment.seq.wrapping_add(segment.len()),
+            flags: flags::RST | flags::ACK,
+            ..Segment::default()
+        },
+    };
+
+    Some(Packet::tcp(packet.dst(), packet.src(), &reply))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// How the socket is reachable from the interface: listeners and connecting sockets own their
+/// port, accepted connections share the port of the listener and are told apart by the peer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    None,
+    Port,
+    Connection,
+}
+
+struct TcpSocketState {
+    state: State,
+    binding: Binding,
+    local_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    listener: Option<TcpSocket>,
+    backlog: usize,
+    accept_queue: VecDeque<TcpSocket>,
+    // Sending side, the buffer starts at `snd_una` and holds sent and unsent data.
+    send_buffer: VecDeque<u8>,
+    send_buffer_size: usize,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_max: u32,
+    snd_wnd: u32,
+    snd_wscale: u8,
+    mss: usize,
+    fin_pending: bool,
+    fin_seq: Option<u32>,
+    is_probing: bool,
+    cwnd: usize,
+    ssthresh: usize,
+    dup_acks: u32,
+    recover: u32,
+    srtt: Option<SimulationTime>,
+    rttvar: SimulationTime,
+    rto: SimulationTime,
+    rtt_sample: Option<(u32, SimulationTime)>,
+    retries: u32,
+    timer: Option<SimulationTime>,
+    is_timer_scheduled: bool,
+    // Receiving side.
+    rcv_nxt: u32,
+    rcv_wscale: u8,
+    rcv_adv: u32,
+    recv_buffer: VecDeque<u8>,
+    recv_buffer_size: usize,
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    peer_fin: Option<u32>,
+    fin_received: bool,
+    is_read_shutdown: bool,
+    error: Option<i32>,
+    outbox: Vec<Segment>,
+    notifier: Notifier,
+}
+
+/// A simplified TCP with the handshake, cumulative acknowledgements, retransmission with an
+/// adaptive timeout and Reno congestion control, enough for bulk transfers and request-response
+/// traffic to react to the latency, loss and bandwidth of the simulated network.
+#[derive(Clone)]
+pub struct TcpSocket(Arc<Mutex<TcpSocketState>>);
+
+impl TcpSocket {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(TcpSocketState {
+            state: State::Closed,
+            binding: Binding::None,
+            local_addr: None,
+            peer_addr: None,
+            listener: None,
+            backlog: 0,
+            accept_queue: VecDeque::new(),
+            send_buffer: VecDeque::new(),
+            send_buffer_size: SEND_BUFFER_SIZE,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            snd_wscale: 0,
+            mss: DEFAULT_MSS,
+            fin_pending: false,
+            fin_seq: None,
+            is_probing: false,
+            cwnd: 0,
+            ssthresh: usize::MAX,
+            dup_acks: 0,
+            recover: 0,
+            srtt: None,
+            rttvar: SimulationTime::zero(),
+            rto: SimulationTime::from_nanos(INITIAL_RTO_NANOS),
+            rtt_sample: None,
+            retries: 0,
+            timer: None,
+            is_timer_scheduled: false,
+            rcv_nxt: 0,
+            rcv_wscale: 0,
+            rcv_adv: 0,
+            recv_buffer: VecDeque::new(),
+            recv_buffer_size: RECV_BUFFER_SIZE,
+            out_of_order: Vec::new(),
+            peer_fin: None,
+            fin_received: false,
+            is_read_shutdown: false,
+            error: None,
+            outbox: Vec::new(),
+            notifier: Notifier::default(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, TcpSocketState> {
+        self.0.lock().expect("accessed poisoned tcp socket")
+    }
+
+    pub fn bind(&self, host: &Host, addr: SocketAddr) -> io::Result<()> {
+        let mut state = self.state();
+
+        if state.local_addr.is_some() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let ip = addr.ip();
+
+        if !ip.is_unspecified() && !ip.is_loopback() && !host.has_ip(ip) {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+
+        let interface = host.interface();
+        let mut interface = interface.lock().expect("accessed poisoned interface");
+
+        let port = match addr.port() {
+            0 => interface
+                .ephemeral_port(Protocol::Tcp)
+                .ok_or(io::ErrorKind::AddrInUse)?,
+            port if interface.is_associated(Protocol::Tcp, port) => {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            port => port,
+        };
+
+        interface.associate(Protocol::Tcp, port, self.clone().into());
+        state.local_addr = Some(SocketAddr::new(ip, port));
+        state.binding = Binding::Port;
+        Ok(())
+    }
+
+    pub fn listen(&self, host: &Host, backlog: usize, family: AddressFamily) -> io::Result<()> {
+        if self.state().local_addr.is_none() {
+            self.bind(host, SocketAddr::new(unspecified(family), 0))?;
+        }
+
+        let mut state = self.state();
+
+        match state.state {
+            State::Closed | State::Listen => {
+                state.state = State::Listen;
+                state.backlog = backlog.max(1);
+                Ok(())
+            }
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    /// Starts the handshake, which completes once the socket reports it is connected.
+    pub fn connect(&self, host: &Arc<Host>, addr: SocketAddr) -> io::Result<()> {
+        match self.state().state {
+            State::Closed => {}
+            State::SynSent => return Err(io::Error::from_raw_os_error(libc::EALREADY)),
+            State::Listen => return Err(io::ErrorKind::InvalidInput.into()),
+            _ => return Err(io::Error::from_raw_os_error(libc::EISCONN)),
+        }
+
+        if let Some(error) = self.state().error.take() {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+
+        if self.state().local_addr.is_none() {
+            self.bind(host, SocketAddr::new(unspecified(addr.ip().into()), 0))?;
+        }
+
+        let local_addr = self.local_addr()?;
+        let src_ip = match local_addr.ip() {
+            ip if !ip.is_unspecified() => ip,
+            _ if addr.ip().is_loopback() => addr.ip(),
+            _ => host
+                .ip_for(addr.ip().into())
+                .ok_or(io::ErrorKind::AddrNotAvailable)?,
+        };
+
+        let mut iss = [0; 4];
+        host.fill_random(&mut iss);
+
+        let packets = {
+            let mut state = self.state();
+            state.local_addr = Some(SocketAddr::new(src_ip, local_addr.port()));
+            state.peer_addr = Some(addr);
+            state.mss = max_segment_size(addr);
+            state.open(u32::from_ne_bytes(iss), State::SynSent);
+            state.output(now());
+            self.take_packets(host, &mut state)
+        };
+
+        self.transmit(host, packets);
+        Err(io::Error::from_raw_os_error(libc::EINPROGRESS))
+    }
+
+    /// Reports whether the handshake started by `connect` has completed.
+    pub fn is_connected(&self) -> io::Result<bool> {
+        let mut state = self.state();
+
+        if let Some(error) = state.error.take() {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+
+        match state.state {
+            State::SynSent | State::SynReceived => Ok(false),
+            State::Closed | State::Listen => Err(io::ErrorKind::NotConnected.into()),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<TcpSocket> {
+        let mut state = self.state();
+
+        if state.state != State::Listen {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        state
+            .accept_queue
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::WouldBlock.into())
+    }
+
+    pub fn send(&self, host: &Arc<Host>, buf: &[u8]) -> io::Result<usize> {
+        let (len, packets) = {
+            let mut state = self.state();
+
+            if let Some(error) = state.error.take() {
+                return Err(io::Error::from_raw_os_error(error));
+            }
+
+            match state.state {
+                State::Established | State::CloseWait if !state.fin_pending => {}
+                State::SynSent | State::SynReceived => return Err(io::ErrorKind::WouldBlock.into()),
+                State::Listen => return Err(io::ErrorKind::NotConnected.into()),
+                State::Closed if state.peer_addr.is_none() => {
+                    return Err(io::ErrorKind::NotConnected.into())
+                }
+                _ => return Err(io::ErrorKind::BrokenPipe.into()),
+            }
+
+            let space = state
+                .send_buffer_size
+                .saturating_sub(state.send_buffer.len());
+            if space == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            let len = space.min(buf.len());
+            state.send_buffer.extend(&buf[..len]);
+            state.output(now());
+            (len, self.take_packets(host, &mut state))
+        };
+
+        self.transmit(host, packets);
+        Ok(len)
+    }
+
+    pub fn recv(&self, host: &Arc<Host>, buf: &mut [u8], peek: bool) -> io::Result<usize> {
+        let (len, packets) = {
+            let mut state = self.state();
+
+            if state.recv_buffer.is_empty() {
+                if let Some(error) = state.error.take() {
+                    return Err(io::Error::from_raw_os_error(error));
+                }
+
+                return match state.state {
+                    _ if state.fin_received || state.is_read_shutdown => Ok(0),
+                    State::Closed if state.peer_addr.is_some() => Ok(0),
+                    State::Closed | State::Listen => Err(io::ErrorKind::NotConnected.into()),
+                    _ => Err(io::ErrorKind::WouldBlock.into()),
+                };
+            }
+
+            let len = buf.len().min(state.recv_buffer.len());
+            for (dst, src) in buf.iter_mut().zip(state.recv_buffer.iter()) {
+                *dst = *src;
+            }
+
+            if !peek {
+                state.recv_buffer.drain(..len);
+
+                // Tell the peer about a window that opened up, it may have stopped sending.
+                let window = state.receive_window();
+                let threshold = (state.recv_buffer_size / 2).min(2 * state.mss) as u32;
+                if window.saturating_sub(state.rcv_adv) >= threshold {
+                    state.send_ack();
+                }
+            }
+
+            (len, self.take_packets(host, &mut state))
+        };
+
+        self.transmit(host, packets);
+        Ok(len)
+    }
+
+    pub fn available(&self) -> usize {
+        self.state().recv_buffer.len()
+    }
+
+    pub fn shutdown(&self, host: &Arc<Host>, read: bool, write: bool) -> io::Result<()> {
+        let packets = {
+            let mut state = self.state();
+
+            match state.state {
+                State::Closed | State::Listen | State::SynSent => {
+                    return Err(io::ErrorKind::NotConnected.into())
+                }
+                _ => {}
+            }
+
+            if read {
+                state.is_read_shutdown = true;
+            }
+            if write {
+                state.fin_pending = true;
+                state.output(now());
+            }
+            state.notifier.notify(host);
+            self.take_packets(host, &mut state)
+        };
+
+        self.transmit(host, packets);
+        Ok(())
+    }
+
+    /// Releases the socket once the process closed its last descriptor. Connections finish
+    /// sending their data before closing, unless unread data is left, which resets them.
+    pub fn close(&self, host: &Arc<Host>) {
+        let (packets, children) = {
+            let mut state = self.state();
+
+            let children: Vec<_> = state.accept_queue.drain(..).collect();
+
+            match state.state {
+                State::Closed | State::Listen | State::SynSent => state.state = State::Closed,
+                State::TimeWait | State::LastAck | State::Closing | State::FinWait2 => {}
+                _ if !state.recv_buffer.is_empty() => state.reset(),
+                _ => {
+                    state.fin_pending = true;
+                    state.is_read_shutdown = true;
+                    state.output(now());
+                }
+            }
+
+            (self.take_packets(host, &mut state), children)
+        };
+
+        self.transmit(host, packets);
+
+        for child in children {
+            let packets = {
+                let mut state = child.state();
+                state.reset();
+                child.take_packets(host, &mut state)
+            };
+            child.transmit(host, packets);
+            child.release(host);
+        }
+
+        if self.state().state == State::Closed {
+            self.release(host);
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.state()
+            .local_addr
+            .ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        let state = self.state();
+
+        match state.state {
+            State::Closed | State::Listen | State::SynSent => {
+                Err(io::ErrorKind::NotConnected.into())
+            }
+            _ => state
+                .peer_addr
+                .ok_or_else(|| io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.state().state == State::Listen
+    }
+
+    pub fn take_error(&self) -> Option<i32> {
+        self.state().error.take()
+    }
+
+    pub fn recv_buffer_size(&self) -> usize {
+        self.state().recv_buffer_size
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) {
+        self.state().recv_buffer_size = size;
+    }
+
+    pub fn send_buffer_size(&self) -> usize {
+        self.state().send_buffer_size
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) {
+        self.state().send_buffer_size = size;
+    }
+
+    /// Readiness in terms of `epoll` events.
+    pub fn events(&self) -> u32 {
+        let state = self.state();
+        let mut events = 0;
+
+        if state.state == State::Listen {
+            if !state.accept_queue.is_empty() {
+                events |= libc::EPOLLIN;
+            }
+            return events as u32;
+        }
+
+        if !state.recv_buffer.is_empty() || state.is_read_shutdown {
+            events |= libc::EPOLLIN;
+        }
+        if state.fin_received {
+            events |= libc::EPOLLIN | libc::EPOLLRDHUP;
+        }
+        if state.error.is_some() {
+            events |= libc::EPOLLERR;
+        }
+
+        match state.state {
+            State::Established | State::CloseWait if !state.fin_pending => {
+                if state.send_buffer.len() < state.send_buffer_size {
+                    events |= libc::EPOLLOUT;
+                }
+            }
+            State::Closed => events |= libc::EPOLLOUT | libc::EPOLLHUP,
+            State::SynSent | State::SynReceived => {}
+            _ if state.fin_received => events |= libc::EPOLLHUP,
+            _ => {}
+        }
+
+        events as u32
+    }
+
+    pub fn notifier_generation(&self) -> u64 {
+        self.state().notifier.generation()
+    }
+
+    pub fn wait(&self, process: &Process) {
+        self.state().notifier.register(process);
+    }
+
+    pub(crate) fn wait_app(&self, app: &HostedApp) {
+        self.state().notifier.register_app(app);
+    }
+
+    /// Handles a segment of the connection and returns the replies.
+    pub(crate) fn push_in_packet(&self, host: &Arc<Host>, packet: &Packet) -> Vec<Packet> {
+        let segment = match Segment::decode(packet.payload()) {
+            Some(segment) => segment,
+            None => return Vec::new(),
+        };
+
+        let (packets, listener) = {
+            let mut state = self.state();
+
+            if state.peer_addr != Some(packet.src()) || state.state == State::Listen {
+                return reset_for(packet).into_iter().collect();
+            }
+
+            let was_half_open = state.state == State::SynReceived;
+            state.input(host, now(), segment);
+            let is_established = was_half_open && state.state == State::Established;
+
+            let listener = is_established.then(|| state.listener.take()).flatten();
+            (self.take_packets(host, &mut state), listener)
+        };
+
+        if let Some(listener) = listener {
+            let mut state = listener.state();
+            if state.state == State::Listen {
+                state.accept_queue.push_back(self.clone());
+                state.notifier.notify(host);
+            }
+        }
+
+        packets
+    }
+
+    /// Handles a segment sent to a listener, which opens a new connection for a `SYN`. The
+    /// connection joins the accept queue once the handshake completes.
+    pub(crate) fn push_in_syn(
+        &self,
+        host: &Arc<Host>,
+        packet: &Packet,
+    ) -> (Option<TcpSocket>, Vec<Packet>) {
+        let segment = match Segment::decode(packet.payload()) {
+            Some(segment) => segment,
+            None => return (None, Vec::new()),
+        };
+
+        if segment.has(flags::RST) {
+            return (None, Vec::new());
+        }
+
+        if !segment.has(flags::SYN) || segment.has(flags::ACK) {
+            return (None, reset_for(packet).into_iter().collect());
+        }
+
+        let (send_buffer_size, recv_buffer_size) = {
+            let state = self.state();
+            if state.accept_queue.len() >= state.backlog {
+                log::trace!("dropped syn from `{}` on full backlog", packet.src());
+                return (None, Vec::new());
+            }
+            (state.send_buffer_size, state.recv_buffer_size)
+        };
+
+        let mut iss = [0; 4];
+        host.fill_random(&mut iss);
+
+        let child = TcpSocket::new();
+        let packets = {
+            let mut state = child.state();
+            state.local_addr = Some(packet.dst());
+            state.peer_addr = Some(packet.src());
+            state.binding = Binding::Connection;
+            state.listener = Some(self.clone());
+            state.send_buffer_size = send_buffer_size;
+            state.recv_buffer_size = recv_buffer_size;
+            state.mss = max_segment_size(packet.src());
+            state.open(u32::from_ne_bytes(iss), State::SynReceived);
+            state.input(host, now(), segment);
+            state.output(now());
+            child.take_packets(host, &mut state)
+        };
+
+        (Some(child), packets)
+    }
+
+    pub(crate) fn push_in_unreachable(
+        &self,
+        host: &Arc<Host>,
+        dst: SocketAddr,
+        reason: Unreachable,
+    ) {
+        let mut state = self.state();
+
+        if state.peer_addr == Some(dst) && state.state == State::SynSent {
+            state.error = Some(match reason {
+                Unreachable::Port => libc::ECONNREFUSED,
+                Unreachable::Host => libc::EHOSTUNREACH,
+            });
+            state.state = State::Closed;
+            state.timer = None;
+            state.notifier.notify(host);
+        }
+    }
+
+    /// Reports whether the socket closed and gives up its place on the interface, which the
+    /// caller then removes it from.
+    pub(crate) fn unbind_if_closed(&self) -> bool {
+        let mut state = self.state();
+
+        match state.state {
+            State::Closed => std::mem::replace(&mut state.binding, Binding::None) != Binding::None,
+            _ => false,
+        }
+    }
+
+    /// Runs the retransmission timer, which also times out `TIME-WAIT`.
+    pub fn on_timer(&self, host: &Arc<Host>) {
+        let (packets, is_closed) = {
+            let mut state = self.state();
+            state.is_timer_scheduled = false;
+
+            let now = now();
+            match state.timer {
+                Some(deadline) if deadline <= now => {
+                    state.timer = None;
+                    state.expire(host, now);
+                }
+                _ => {}
+            }
+
+            (
+                self.take_packets(host, &mut state),
+                state.state == State::Closed,
+            )
+        };
+
+        self.transmit(host, packets);
+
+        if is_closed {
+            self.release(host);
+        }
+    }
+
+    fn take_packets(&self, host: &Arc<Host>, state: &mut TcpSocketState) -> Vec<Packet> {
+        self.schedule_timer(host, state);
+
+        let (local_addr, peer_addr) = match (state.local_addr, state.peer_addr) {
+            (Some(local_addr), Some(peer_addr)) => (local_addr, peer_addr),
+            _ => {
+                state.outbox.clear();
+                return Vec::new();
+            }
+        };
+
+        state
+            .outbox
+            .drain(..)
+            .map(|mut segment| {
+                segment.src_port = local_addr.port();
+                segment.dst_port = peer_addr.port();
+                Packet::tcp(local_addr, peer_addr, &segment)
+            })
+            .collect()
+    }
+
+    fn schedule_timer(&self, host: &Arc<Host>, state: &mut TcpSocketState) {
+        let deadline = match state.timer {
+            Some(deadline) if !state.is_timer_scheduled => deadline,
+            _ => return,
+        };
+
+        let delay = match deadline - now() {
+            delay if delay > SimulationTime::zero() => delay,
+            _ => SimulationTime::zero(),
+        };
+
+        state.is_timer_scheduled =
+            Worker::schedule_task(Task::Retransmit(self.clone()), host, delay);
+    }
+
+    fn transmit(&self, host: &Arc<Host>, packets: Vec<Packet>) {
+        if packets.is_empty() {
+            return;
+        }
+
+        let interface = host.interface();
+        let mut interface = interface.lock().expect("accessed poisoned interface");
+
+        for packet in packets {
+            interface.send(host, packet);
+        }
+    }
+
+    /// Gives up the port or connection slot of a closed socket on the interface.
+    fn release(&self, host: &Arc<Host>) {
+        let (binding, local_addr, peer_addr) = {
+            let mut state = self.state();
+            let binding = std::mem::replace(&mut state.binding, Binding::None);
+            (binding, state.local_addr, state.peer_addr)
+        };
+
+        let interface = host.interface();
+        let mut interface = interface.lock().expect("accessed poisoned interface");
+
+        match (binding, local_addr, peer_addr) {
+            (Binding::Port, Some(local_addr), _) => {
+                interface.disassociate(Protocol::Tcp, local_addr.port())
+            }
+            (Binding::Connection, Some(local_addr), Some(peer_addr)) => {
+                interface.remove_connection(local_addr.port(), peer_addr, self)
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports whether the segment opens a new incarnation of the connection, which is only
+    /// accepted in `TIME-WAIT` and beyond the sequence space of the old one.
+    pub(crate) fn is_reusable_by(&self, packet: &Packet) -> bool {
+        let state = self.state();
+
+        if state.state != State::TimeWait {
+            return false;
+        }
+
+        match Segment::decode(packet.payload()) {
+            Some(segment) => {
+                segment.has(flags::SYN)
+                    && !segment.has(flags::ACK)
+                    && seq_lt(state.rcv_nxt, segment.seq)
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_same(&self, other: &TcpSocket) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl TcpSocketState {
+    fn open(&mut self, iss: u32, state: State) {
+        self.state = state;
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+        self.snd_max = iss;
+        self.cwnd = INITIAL_WINDOW_SEGMENTS * self.mss;
+        self.rcv_wscale = WINDOW_SCALE;
+    }
+
+    fn receive_window(&self) -> u32 {
+        self.recv_buffer_size.saturating_sub(self.recv_buffer.len()) as u32
+    }
+
+    fn advertised_window(&mut self, is_syn: bool) -> u16 {
+        let window = self.receive_window();
+        self.rcv_adv = window;
+
+        match is_syn {
+            true => window.min(u32::from(u16::MAX)) as u16,
+            false => (window >> self.rcv_wscale).min(u32::from(u16::MAX)) as u16,
+        }
+    }
+
+    fn flight_size(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    fn push_segment(&mut self, seq: u32, flags: u8, data: Vec<u8>) {
+        let is_syn = flags & flags::SYN != 0;
+        let window = self.advertised_window(is_syn);
+        let ack = match self.state {
+            State::SynSent => 0,
+            _ => self.rcv_nxt,
+        };
+
+        self.outbox.push(Segment {
+            seq,
+            ack,
+            flags,
+            window,
+            mss: is_syn.then_some(self.mss as u16),
+            window_scale: is_syn.then_some(self.rcv_wscale),
+            data,
+            ..Segment::default()
+        });
+    }
+
+    fn send_ack(&mut self) {
+        self.push_segment(self.snd_nxt, flags::ACK, Vec::new());
+    }
+
+    fn reset(&mut self) {
+        if !matches!(self.state, State::Closed | State::Listen | State::SynSent) {
+            self.push_segment(self.snd_nxt, flags::RST | flags::ACK, Vec::new());
+        }
+        self.state = State::Closed;
+        self.timer = None;
+    }
+
+    fn arm_timer(&mut self, now: SimulationTime, delay: SimulationTime) {
+        if self.timer.is_none() {
+            self.timer = Some(now + delay);
+        }
+    }
+
+    /// Sends whatever the windows allow, starting at `snd_nxt`.
+    fn output(&mut self, now: SimulationTime) {
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = match self.state {
+                        State::SynSent => flags::SYN,
+                        _ => flags::SYN | flags::ACK,
+                    };
+                    self.push_segment(self.iss, flags, Vec::new());
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.snd_max = self.snd_nxt;
+                    if self.retries == 0 {
+                        self.rtt_sample = Some((self.snd_nxt, now));
+                    }
+                    self.arm_timer(now, self.rto);
+                }
+                return;
+            }
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck => {}
+            _ => return,
+        }
+
+        let window = match self.is_probing {
+            true => 1,
+            false => self.cwnd.min(self.snd_wnd as usize),
+        };
+        self.is_probing = false;
+
+        loop {
+            let offset = self.flight_size();
+            let unsent = self.send_buffer.len().saturating_sub(offset);
+
+            if unsent == 0 {
+                break;
+            }
+
+            let len = unsent
+                .min(self.mss)
+                .min(window.saturating_sub(self.flight_size()));
+            if len == 0 {
+                break;
+            }
+
+            let data: Vec<u8> = self
+                .send_buffer
+                .range(offset..offset + len)
+                .copied()
+                .collect();
+            let seq = self.snd_nxt;
+            let flags = match len == unsent {
+                true => flags::ACK | flags::PSH,
+                false => flags::ACK,
+            };
+
+            self.push_segment(seq, flags, data);
+            self.snd_nxt = seq.wrapping_add(len as u32);
+
+            if seq_lt(self.snd_max, self.snd_nxt) {
+                if self.rtt_sample.is_none() && seq == self.snd_max {
+                    self.rtt_sample = Some((self.snd_nxt, now));
+                }
+                self.snd_max = self.snd_nxt;
+            }
+            self.arm_timer(now, self.rto);
+        }
+
+        let is_all_sent = self.flight_size() >= self.send_buffer.len();
+        let is_fin_due = self.fin_pending
+            && is_all_sent
+            && !matches!(self.fin_seq, Some(seq) if seq != self.snd_nxt);
+
+        if is_fin_due {
+            let seq = self.snd_nxt;
+            self.push_segment(seq, flags::FIN | flags::ACK, Vec::new());
+            self.fin_seq = Some(seq);
+            self.snd_nxt = seq.wrapping_add(1);
+            if seq_lt(self.snd_max, self.snd_nxt) {
+                self.snd_max = self.snd_nxt;
+            }
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+            self.arm_timer(now, self.rto);
+        }
+
+        // A zero window stalls the sender until the persist timer probes it.
+        if self.flight_size() == 0 && !self.send_buffer.is_empty() && self.snd_wnd == 0 {
+            self.arm_timer(now, self.rto);
+        }
+    }
+
+    fn input(&mut self, host: &Arc<Host>, now: SimulationTime, segment: Segment) {
+        if segment.has(flags::RST) {
+            self.input_reset(host, &segment);
+            return;
+        }
+
+        match self.state {
+            State::SynSent => {
+                self.input_syn_ack(host, now, &segment);
+                return;
+            }
+            State::SynReceived if segment.has(flags::SYN) && !segment.has(flags::ACK) => {
+                self.irs(&segment);
+                if self.snd_nxt != self.iss {
+                    // Our SYN-ACK got lost, answer the retransmitted SYN again.
+                    self.push_segment(self.iss, flags::SYN | flags::ACK, Vec::new());
+                }
+                return;
+            }
+            State::Closed | State::Listen => return,
+            _ => {}
+        }
+
+        if segment.has(flags::SYN) {
+            self.send_ack();
+            return;
+        }
+
+        if segment.has(flags::ACK) {
+            self.input_ack(host, now, &segment);
+        }
+
+        if self.state == State::Closed {
+            return;
+        }
+
+        let len = segment.data.len() as u32;
+        let is_receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+
+        let is_fin = segment.has(flags::FIN);
+        if is_fin {
+            self.peer_fin = Some(segment.seq.wrapping_add(len));
+        }
+
+        if len > 0 && is_receiving {
+            self.input_data(host, segment.seq, segment.data);
+        } else if len > 0 {
+            self.send_ack();
+        }
+
+        if self.peer_fin == Some(self.rcv_nxt) && !self.fin_received {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            self.send_ack();
+            self.state = match self.state {
+                State::Established | State::SynReceived => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                State::FinWait2 => {
+                    self.timer = Some(now + SimulationTime::from_nanos(TIME_WAIT_NANOS));
+                    State::TimeWait
+                }
+                state => state,
+            };
+            self.notifier.notify(host);
+        } else if is_fin && self.state == State::TimeWait {
+            self.send_ack();
+            self.timer = Some(now + SimulationTime::from_nanos(TIME_WAIT_NANOS));
+        }
+
+        self.output(now);
+    }
+
+    fn irs(&mut self, segment: &Segment) {
+        self.rcv_nxt = segment.seq.wrapping_add(1);
+        self.snd_wnd = u32::from(segment.window);
+        self.snd_wscale = match segment.window_scale {
+            Some(shift) => shift.min(14),
+            None => {
+                self.rcv_wscale = 0;
+                0
+            }
+        };
+        if let Some(mss) = segment.mss {
+            self.mss = self.mss.min(usize::from(mss)).max(64);
+        }
+        self.cwnd = INITIAL_WINDOW_SEGMENTS * self.mss;
+    }
+
+    fn input_reset(&mut self, host: &Arc<Host>, segment: &Segment) {
+        let is_acceptable = match self.state {
+            State::SynSent => segment.has(flags::ACK) && segment.ack == self.snd_nxt,
+            State::Closed | State::Listen => false,
+            _ => {
+                let window = self.receive_window().max(1);
+                seq_le(self.rcv_nxt, segment.seq)
+                    && seq_lt(segment.seq, self.rcv_nxt.wrapping_add(window))
+            }
+        };
+
+        if !is_acceptable {
+            return;
+        }
+
+        self.error = Some(match self.state {
+            State::SynSent => libc::ECONNREFUSED,
+            State::CloseWait | State::LastAck | State::Closing | State::TimeWait => libc::EPIPE,
+            _ => libc::ECONNRESET,
+        });
+        self.state = State::Closed;
+        self.timer = None;
+        self.notifier.notify(host);
+    }
+
+    fn input_syn_ack(&mut self, host: &Arc<Host>, now: SimulationTime, segment: &Segment) {
+        if !segment.has(flags::ACK) || !segment.has(flags::SYN) {
+            return;
+        }
+
+        if segment.ack != self.snd_nxt {
+            self.outbox.push(Segment {
+                seq: segment.ack,
+                flags: flags::RST,
+                ..Segment::default()
+            });
+            return;
+        }
+
+        self.irs(segment);
+        self.snd_una = segment.ack;
+        self.update_rtt(now, segment.ack);
+        self.state = State::Established;
+        self.retries = 0;
+        self.timer = None;
+        self.send_ack();
+        self.notifier.notify(host);
+        self.output(now);
+    }
+
+    fn input_ack(&mut self, host: &Arc<Host>, now: SimulationTime, segment: &Segment) {
+        let ack = segment.ack;
+
+        if seq_lt(self.snd_max, ack) {
+            self.send_ack();
+            return;
+        }
+
+        if self.state == State::SynReceived {
+            if ack != self.snd_nxt {
+                return;
+            }
+            self.snd_una = ack;
+            self.state = State::Established;
+            self.retries = 0;
+            self.timer = None;
+            self.update_rtt(now, ack);
+            self.snd_wnd = u32::from(segment.window) << self.snd_wscale;
+            self.notifier.notify(host);
+            return;
+        }
+
+        let window = u32::from(segment.window) << self.snd_wscale;
+
+        if seq_lt(self.snd_una, ack) {
+            let acked = ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data);
+            self.snd_una = ack;
+            if seq_lt(self.snd_nxt, self.snd_una) {
+                self.snd_nxt = self.snd_una;
+            }
+
+            self.update_rtt(now, ack);
+            self.retries = 0;
+
+            if self.dup_acks >= DUPLICATE_ACK_THRESHOLD {
+                if seq_le(self.recover, ack) {
+                    self.cwnd = self.ssthresh;
+                    self.dup_acks = 0;
+                } else {
+                    // A partial acknowledgement during recovery means the next hole was lost.
+                    self.retransmit(host);
+                }
+            } else {
+                self.dup_acks = 0;
+                if self.cwnd < self.ssthresh {
+                    self.cwnd += acked.min(self.mss);
+                } else {
+                    self.cwnd += (self.mss * self.mss / self.cwnd).max(1);
+                }
+            }
+
+            self.timer = match self.snd_una == self.snd_max {
+                true => None,
+                false => Some(now + self.rto),
+            };
+
+            if let Some(fin_seq) = self.fin_seq {
+                if seq_lt(fin_seq, ack) {
+                    self.state = match self.state {
+                        State::FinWait1 => State::FinWait2,
+                        State::Closing => {
+                            self.timer = Some(now + SimulationTime::from_nanos(TIME_WAIT_NANOS));
+                            State::TimeWait
+                        }
+                        State::LastAck => State::Closed,
+                        state => state,
+                    };
+                }
+            }
+
+            self.snd_wnd = window;
+            self.notifier.notify(host);
+        } else if ack == self.snd_una {
+            let is_duplicate =
+                segment.data.is_empty() && window == self.snd_wnd && self.flight_size() > 0;
+
+            if is_duplicate {
+                self.dup_acks += 1;
+
+                if self.dup_acks == DUPLICATE_ACK_THRESHOLD {
+                    self.ssthresh = (self.flight_size() / 2).max(2 * self.mss);
+                    self.recover = self.snd_max;
+                    self.cwnd = self.ssthresh + 3 * self.mss;
+                    self.retransmit(host);
+                } else if self.dup_acks > DUPLICATE_ACK_THRESHOLD {
+                    self.cwnd += self.mss;
+                }
+            }
+
+            if window > self.snd_wnd {
+                self.notifier.notify(host);
+            }
+            self.snd_wnd = window;
+        }
+    }
+
+    /// Resends the first unacknowledged segment.
+    fn retransmit(&mut self, host: &Host) {
+        let len = self.send_buffer.len().min(self.mss);
+
+        if len > 0 {
+            let data = self.send_buffer.range(..len).copied().collect();
+            self.push_segment(self.snd_una, flags::ACK, data);
+        } else if let Some(seq) = self.fin_seq {
+            self.push_segment(seq, flags::FIN | flags::ACK, Vec::new());
+        }
+
+        host.count_retransmission();
+        self.rtt_sample = None;
+    }
+
+    fn input_data(&mut self, host: &Arc<Host>, seq: u32, data: Vec<u8>) {
+        let end = seq.wrapping_add(data.len() as u32);
+
+        if seq_le(end, self.rcv_nxt) {
+            self.send_ack();
+            return;
+        }
+
+        if seq_lt(self.rcv_nxt, seq) {
+            let window = self.receive_window() as usize;
+            let buffered: usize = self.out_of_order.iter().map(|(_, data)| data.len()).sum();
+            if buffered + data.len() <= window {
+                self.out_of_order.push((seq, data));
+            }
+            self.send_ack();
+            return;
+        }
+
+        self.append(seq, &data);
+
+        loop {
+            let rcv_nxt = self.rcv_nxt;
+            self.out_of_order
+                .retain(|(seq, data)| seq_lt(rcv_nxt, seq.wrapping_add(data.len() as u32)));
+
+            let next = self
+                .out_of_order
+                .iter()
+                .position(|(seq, _)| seq_le(*seq, rcv_nxt));
+
+            match next {
+                Some(index) => {
+                    let (seq, data) = self.out_of_order.swap_remove(index);
+                    self.append(seq, &data);
+                }
+                None => break,
+            }
+        }
+
+        self.send_ack();
+        self.notifier.notify(host);
+    }
+
+    /// Appends the part of the data at `seq` that starts at `rcv_nxt` and fits the buffer.
+    fn append(&mut self, seq: u32, data: &[u8]) {
+        let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+        let space = self.receive_window() as usize;
+        let data = &data[skip.min(data.len())..];
+        let len = data.len().min(space);
+
+        if self.is_read_shutdown {
+            // Data nobody is going to read is acknowledged and dropped.
+        } else {
+            self.recv_buffer.extend(&data[..len]);
+        }
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+    }
+
+    fn update_rtt(&mut self, now: SimulationTime, ack: u32) {
+        let sent = match self.rtt_sample {
+            Some((seq, sent)) if seq_le(seq, ack) => sent,
+            _ => return,
+        };
+        self.rtt_sample = None;
+
+        let rtt = now - sent;
+        let (srtt, rttvar) = match self.srtt {
+            None => (rtt, SimulationTime::from_nanos(rtt.as_nanos() / 2)),
+            Some(srtt) => {
+                let delta = (srtt.as_nanos() - rtt.as_nanos()).abs();
+                let rttvar = (3 * self.rttvar.as_nanos() + delta) / 4;
+                let srtt = (7 * srtt.as_nanos() + rtt.as_nanos()) / 8;
+                (
+                    SimulationTime::from_nanos(srtt),
+                    SimulationTime::from_nanos(rttvar),
+                )
+            }
+        };
+
+        self.srtt = Some(srtt);
+        self.rttvar = rttvar;
+        self.rto = SimulationTime::from_nanos(
+            (srtt.as_nanos() + 4 * rttvar.as_nanos()).clamp(MIN_RTO_NANOS, MAX_RTO_NANOS),
+        );
+    }
+
+    /// Handles an expired timer: times out `TIME-WAIT`, probes a zero window or retransmits.
+    fn expire(&mut self, host: &Arc<Host>, now: SimulationTime) {
+        match self.state {
+            State::TimeWait => {
+                self.state = State::Closed;
+                return;
+            }
+            State::Closed | State::Listen | State::FinWait2 => return,
+            _ => {}
+        }
+
+        let limit = match self.state {
+            State::SynSent | State::SynReceived => MAX_SYN_RETRIES,
+            _ => MAX_RETRIES,
+        };
+
+        if self.flight_size() == 0 {
+            if !self.send_buffer.is_empty() && self.snd_wnd == 0 {
+                self.is_probing = true;
+                self.output(now);
+            }
+            return;
+        }
+
+        self.retries += 1;
+        if self.retries > limit {
+            self.error = Some(libc::ETIMEDOUT);
+            self.reset();
+            self.notifier.notify(host);
+            return;
+        }
+
+        self.ssthresh = (self.flight_size() / 2).max(2 * self.mss);
+        self.cwnd = self.mss;
+        self.dup_acks = 0;
+        self.rtt_sample = None;
+        self.rto = SimulationTime::from_nanos((self.rto.as_nanos() * 2).min(MAX_RTO_NANOS));
+        self.snd_nxt = self.snd_una;
+        host.count_retransmission();
+
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                self.snd_nxt = self.iss;
+            }
+            _ => {}
+        }
+
+        self.output(now);
+        self.arm_timer(now, self.rto);
+    }
+}
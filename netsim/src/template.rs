@@ -0,0 +1,190 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the files whose placeholders are substituted when copied. The copy is named
+/// without the suffix.
+const TEMPLATE_SUFFIX: &str = ".template";
+
+/// Directory of overrides for single hosts within the template.
+const HOSTS_DIRECTORY: &str = "hosts";
+
+/// A directory tree copied into the directory of every host before its processes start.
+///
+/// Files in `hosts/<name>/` of the template are copied only for the host called `<name>`,
+/// after the shared files, which they replace. Hosts with a `quantity` above one take the
+/// overrides of the configured name first and then those of their own name. Placeholders
+/// like `{{hostname}}` in files ending with `.template` are replaced by values of the host.
+pub struct Template {
+    root: PathBuf,
+}
+
+impl Template {
+    pub fn new(root: PathBuf) -> Result<Self, TemplateError> {
+        match fs::metadata(&root) {
+            Ok(metadata) if metadata.is_dir() => Ok(Self { root }),
+            Ok(_) => Err(TemplateError::new(
+                root,
+                io::Error::new(io::ErrorKind::InvalidInput, "not a directory"),
+            )),
+            Err(err) => Err(TemplateError::new(root, err)),
+        }
+    }
+
+    /// Copies the template into `directory`, with the overrides of the hosts `names` in order.
+    pub fn expand(
+        &self,
+        directory: &Path,
+        names: &[&str],
+        placeholders: &Placeholders,
+    ) -> Result<(), TemplateError> {
+        let hosts = self.root.join(HOSTS_DIRECTORY);
+        copy_tree(&self.root, directory, Some(&hosts), placeholders)?;
+
+        for name in names {
+            let overrides = hosts.join(name);
+            if overrides.is_dir() {
+                copy_tree(&overrides, directory, None, placeholders)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Values substituted for the `{{name}}` placeholders of a template, surrounding whitespace
+/// within the braces is ignored. Unknown placeholders are left as they are.
+#[derive(Default)]
+pub struct Placeholders(Vec<(&'static str, String)>);
+
+impl Placeholders {
+    pub fn insert(&mut self, name: &'static str, value: impl ToString) {
+        self.0.push((name, value.to_string()));
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn substitute(&self, contents: &str) -> String {
+        let mut result = String::with_capacity(contents.len());
+        let mut rest = contents;
+
+        while let Some(start) = rest.find("{{") {
+            let (before, after) = rest.split_at(start);
+            result.push_str(before);
+
+            let end = match after.find("}}") {
+                Some(end) => end,
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+
+            match self.get(after[2..end].trim()) {
+                Some(value) => result.push_str(value),
+                None => {
+                    log::warn!("unknown template placeholder `{}`", &after[..end + 2]);
+                    result.push_str(&after[..end + 2]);
+                }
+            }
+            rest = &after[end + 2..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+fn copy_tree(
+    src: &Path,
+    dst: &Path,
+    skip: Option<&Path>,
+    placeholders: &Placeholders,
+) -> Result<(), TemplateError> {
+    let wrap = |path: &Path| {
+        let path = path.to_owned();
+        move |err| TemplateError::new(path, err)
+    };
+
+    fs::create_dir_all(dst).map_err(wrap(dst))?;
+
+    let mut entries = fs::read_dir(src)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(wrap(src))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if Some(path.as_path()) == skip {
+            continue;
+        }
+
+        let name = entry.file_name();
+        let file_type = entry.file_type().map_err(wrap(&path))?;
+
+        if file_type.is_dir() {
+            copy_tree(&path, &dst.join(&name), None, placeholders)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&path).map_err(wrap(&path))?;
+            let link = dst.join(&name);
+            let _ = fs::remove_file(&link);
+            symlink(target, &link).map_err(wrap(&link))?;
+        } else {
+            match name
+                .to_str()
+                .and_then(|name| name.strip_suffix(TEMPLATE_SUFFIX))
+            {
+                Some(rendered) => render(&path, &dst.join(rendered), placeholders)?,
+                None => {
+                    fs::copy(&path, dst.join(&name)).map_err(wrap(&path))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render(src: &Path, dst: &Path, placeholders: &Placeholders) -> Result<(), TemplateError> {
+    let contents = fs::read_to_string(src).map_err(|err| TemplateError::new(src.into(), err))?;
+    let permissions = fs::metadata(src)
+        .map_err(|err| TemplateError::new(src.into(), err))?
+        .permissions();
+
+    fs::write(dst, placeholders.substitute(&contents))
+        .and_then(|_| fs::set_permissions(dst, permissions))
+        .map_err(|err| TemplateError::new(dst.into(), err))
+}
+
+#[derive(Debug)]
+pub struct TemplateError {
+    path: PathBuf,
+    source: io::Error,
+}
+
+impl TemplateError {
+    fn new(path: PathBuf, source: io::Error) -> Self {
+        Self { path, source }
+    }
+}
+
+impl error::Error for TemplateError {}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "cannot expand template `{}`: {}",
+            self.path.display(),
+            self.source
+        )
+    }
+}
@@ -2,10 +2,16 @@ use std::time;
 
 use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+use crate::units::TimeInterval;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SimulationTime(Duration);
 
 impl SimulationTime {
+    pub fn zero() -> Self {
+        Self(Duration::zero())
+    }
+
     pub fn from_nanos(nanos: i64) -> Self {
         Self(Duration::nanoseconds(nanos))
     }
@@ -17,6 +23,10 @@ impl SimulationTime {
     pub fn from_millis(millis: i64) -> Self {
         Self(Duration::milliseconds(millis))
     }
+
+    pub fn as_nanos(&self) -> i64 {
+        self.0.num_nanoseconds().unwrap_or(i64::MAX)
+    }
 }
 
 impl From<Duration> for SimulationTime {
@@ -25,6 +35,14 @@ impl From<Duration> for SimulationTime {
     }
 }
 
+impl From<TimeInterval> for SimulationTime {
+    fn from(interval: TimeInterval) -> Self {
+        Duration::from_std(time::Duration::from(interval))
+            .unwrap_or_else(|_| Duration::max_value())
+            .into()
+    }
+}
+
 impl std::ops::Add<Self> for SimulationTime {
     type Output = Self;
 
@@ -45,14 +63,7 @@ impl std::ops::Rem<Self> for SimulationTime {
     type Output = Self;
 
     fn rem(self, other: Self) -> Self::Output {
-        let mut lhs = self.0;
-        let rhs = other.0;
-
-        while lhs > rhs {
-            lhs = lhs - rhs;
-        }
-
-        rhs.into()
+        Self::from_nanos(self.as_nanos() % other.as_nanos())
     }
 }
 
@@ -70,6 +81,11 @@ impl EmulatedTime {
     pub fn duration_since(&self, time: &EmulatedTime) -> Duration {
         self.0.signed_duration_since(time.0)
     }
+
+    /// Formats the time with the `strftime` specifiers of chrono.
+    pub fn format(&self, format: &str) -> String {
+        self.0.format(format).to_string()
+    }
 }
 
 impl From<DateTime<Utc>> for EmulatedTime {
@@ -105,10 +121,6 @@ pub enum PerfTimer {
 }
 
 impl PerfTimer {
-    pub fn new() -> Self {
-        Self::Paused(time::Duration::from_nanos(0))
-    }
-
     pub fn start() -> Self {
         Self::Running {
             start: time::Instant::now(),
@@ -144,12 +156,4 @@ impl PerfTimer {
             Self::Paused(lapsed) => lapsed,
         }
     }
-
-    pub fn reset(&mut self, start_paused: bool) {
-        *self = if start_paused {
-            Self::new()
-        } else {
-            Self::start()
-        }
-    }
 }
@@ -0,0 +1,444 @@
+//! Records of every event the scheduler queued or executed and of every state a packet went
+//! through, for following a flow through the simulation after the fact.
+//!
+//! The newline-delimited JSON trace holds one object per record:
+//!
+//! ```text
+//! {"time":1000000000,"type":"schedule","event":7,"src":"client","dst":"server","task":"receive_packet"}
+//! {"time":1000000000,"type":"packet","host":"client","packet":3,"state":"sent","protocol":"tcp","src":"11.0.0.1:49152","dst":"11.0.0.2:80","size":60}
+//! ```
+//!
+//! Each host numbers the events it schedules on its own, so that the numbering does not depend
+//! on how the hosts are spread over the workers. An event is identified by its `src` together
+//! with its `event` id, which then match between its schedule and execute records.
+//!
+//! The binary trace holds the same records in little-endian fields behind a tag byte, with
+//! every string replaced by the id of a name record written before its first use:
+//!
+//! - `0` name: id u32, length u16, UTF-8 bytes
+//! - `1` schedule and `2` execute: time u64, event u64, src u32, dst u32, task u32
+//! - `3` packet: time u64, host u32, packet u64, state u32, reason u32, protocol u32,
+//!   src u32, dst u32, size u32
+//!
+//! Packets without a reason refer to the name id `u32::MAX`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::event::Event;
+use crate::host::Host;
+use crate::net::{Packet, Protocol};
+use crate::time::SimulationTime;
+
+const NAME: u8 = 0;
+const SCHEDULE: u8 = 1;
+const EXECUTE: u8 = 2;
+const PACKET: u8 = 3;
+const NO_NAME: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceFormat {
+    Ndjson,
+    Binary,
+}
+
+impl TraceFormat {
+    fn file_name(&self) -> &'static str {
+        match self {
+            TraceFormat::Ndjson => "trace.ndjson",
+            TraceFormat::Binary => "trace.bin",
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ndjson" => Ok(TraceFormat::Ndjson),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!(
+                "invalid trace format `{}`, expected `ndjson` or `binary`",
+                value
+            )),
+        }
+    }
+}
+
+/// What happened to an event.
+#[derive(Clone, Copy, Debug)]
+pub enum EventState {
+    Scheduled,
+    Executed,
+}
+
+/// A step of a packet from the interface of its source to the socket of its destination.
+#[derive(Clone, Copy, Debug)]
+pub enum PacketState {
+    /// Waiting in the send queue of the interface for upstream bandwidth.
+    Queued,
+    /// Left the interface into the network.
+    Sent,
+    /// Reached the router in front of the destination interface.
+    Arrived,
+    /// Handed to the transport of the destination host.
+    Delivered,
+    Dropped(DropReason),
+}
+
+impl PacketState {
+    fn name(&self) -> &'static str {
+        match self {
+            PacketState::Queued => "queued",
+            PacketState::Sent => "sent",
+            PacketState::Arrived => "arrived",
+            PacketState::Delivered => "delivered",
+            PacketState::Dropped(_) => "dropped",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DropReason {
+    /// Lost on the path by the loss model of the graph.
+    PathLoss,
+    /// The router queue in front of the destination was full.
+    QueueFull,
+    /// No host has the destination address.
+    UnknownHost,
+    /// The graph has no path to the destination.
+    NoPath,
+    /// The receive buffer of the destination socket was full.
+    ReceiveBuffer,
+}
+
+impl DropReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropReason::PathLoss => "path_loss",
+            DropReason::QueueFull => "queue_full",
+            DropReason::UnknownHost => "unknown_host",
+            DropReason::NoPath => "no_path",
+            DropReason::ReceiveBuffer => "receive_buffer",
+        }
+    }
+}
+
+/// Writes the trace into the data directory. Failing writes are dropped, so that tracing
+/// never changes the course of the simulation.
+pub struct Tracer {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    names: HashMap<String, u32>,
+}
+
+impl Tracer {
+    pub fn create(directory: &Path, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(directory.join(format.file_name()))?),
+            format,
+            names: HashMap::new(),
+        })
+    }
+
+    pub fn event(&mut self, state: EventState, event: &Event) {
+        let _ = match self.format {
+            TraceFormat::Ndjson => {
+                let kind = match state {
+                    EventState::Scheduled => "schedule",
+                    EventState::Executed => "execute",
+                };
+                let mut line = format!(
+                    "{{\"time\":{},\"type\":\"{}\",\"event\":{}",
+                    event.time().as_nanos(),
+                    kind,
+                    i64::from(event.event_id())
+                );
+                push_field(&mut line, "src", event.src().name());
+                push_field(&mut line, "dst", event.host().name());
+                push_field(&mut line, "task", event.task().kind());
+                line.push_str("}\n");
+                self.writer.write_all(line.as_bytes())
+            }
+            TraceFormat::Binary => {
+                let tag = match state {
+                    EventState::Scheduled => SCHEDULE,
+                    EventState::Executed => EXECUTE,
+                };
+                let src = self.name(event.src().name());
+                let dst = self.name(event.host().name());
+                let task = self.name(event.task().kind());
+
+                let mut record = vec![tag];
+                record.extend(event.time().as_nanos().to_le_bytes());
+                record.extend(i64::from(event.event_id()).to_le_bytes());
+                record.extend(src.to_le_bytes());
+                record.extend(dst.to_le_bytes());
+                record.extend(task.to_le_bytes());
+                self.writer.write_all(&record)
+            }
+        };
+    }
+
+    pub fn packet(
+        &mut self,
+        time: SimulationTime,
+        host: &Host,
+        packet: &Packet,
+        state: PacketState,
+    ) {
+        let reason = match state {
+            PacketState::Dropped(reason) => Some(reason.name()),
+            _ => None,
+        };
+
+        let _ = match self.format {
+            TraceFormat::Ndjson => {
+                let mut line = format!("{{\"time\":{},\"type\":\"packet\"", time.as_nanos());
+                push_field(&mut line, "host", host.name());
+                let _ = write!(line, ",\"packet\":{}", packet.id());
+                push_field(&mut line, "state", state.name());
+                if let Some(reason) = reason {
+                    push_field(&mut line, "reason", reason);
+                }
+                push_field(&mut line, "protocol", protocol_name(packet.protocol()));
+                push_field(&mut line, "src", &packet.src().to_string());
+                push_field(&mut line, "dst", &packet.dst().to_string());
+                let _ = write!(line, ",\"size\":{}}}", packet.size());
+                line.push('\n');
+                self.writer.write_all(line.as_bytes())
+            }
+            TraceFormat::Binary => {
+                let host = self.name(host.name());
+                let state = self.name(state.name());
+                let reason = reason.map_or(NO_NAME, |reason| self.name(reason));
+                let protocol = self.name(protocol_name(packet.protocol()));
+                let src = self.name(&packet.src().to_string());
+                let dst = self.name(&packet.dst().to_string());
+
+                let mut record = vec![PACKET];
+                record.extend(time.as_nanos().to_le_bytes());
+                record.extend(host.to_le_bytes());
+                record.extend(packet.id().to_le_bytes());
+                record.extend(state.to_le_bytes());
+                record.extend(reason.to_le_bytes());
+                record.extend(protocol.to_le_bytes());
+                record.extend(src.to_le_bytes());
+                record.extend(dst.to_le_bytes());
+                record.extend((packet.size().min(u32::MAX as u64) as u32).to_le_bytes());
+                self.writer.write_all(&record)
+            }
+        };
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+
+    /// The id of a name in the binary trace, writing its name record on first use.
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.names.get(name) {
+            return *id;
+        }
+
+        let id = self.names.len() as u32;
+        let bytes = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+
+        let mut record = vec![NAME];
+        record.extend(id.to_le_bytes());
+        record.extend((bytes.len() as u16).to_le_bytes());
+        record.extend(bytes);
+        let _ = self.writer.write_all(&record);
+
+        self.names.insert(name.to_owned(), id);
+        id
+    }
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+        Protocol::Icmp => "icmp",
+    }
+}
+
+/// Appends `,"key":"value"` with the value escaped as a JSON string.
+fn push_field(line: &mut String, key: &str, value: &str) {
+    let _ = write!(line, ",\"{}\":", key);
+    push_json_string(line, value);
+}
+
+/// Appends the value as a quoted and escaped JSON string.
+pub(crate) fn push_json_string(line: &mut String, value: &str) {
+    line.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+
+    line.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::sim::testing::TestNetwork;
+    use crate::task::Task;
+
+    /// Reads the little-endian fields of a binary trace.
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn take(&mut self, len: usize) -> &[u8] {
+            let (field, rest) = self.0.split_at(len);
+            self.0 = rest;
+            field
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.take(1)[0]
+        }
+
+        fn u16(&mut self) -> u16 {
+            u16::from_le_bytes(self.take(2).try_into().unwrap())
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn u64(&mut self) -> u64 {
+            u64::from_le_bytes(self.take(8).try_into().unwrap())
+        }
+    }
+
+    /// Decodes a binary trace into its records, with the names resolved.
+    fn decode(bytes: &[u8]) -> Vec<String> {
+        let mut reader = Reader(bytes);
+        let mut names = Vec::new();
+        let mut records = Vec::new();
+
+        while !reader.0.is_empty() {
+            match reader.u8() {
+                NAME => {
+                    assert_eq!(reader.u32() as usize, names.len());
+                    let len = reader.u16() as usize;
+                    names.push(String::from_utf8(reader.take(len).to_vec()).unwrap());
+                }
+                tag @ (SCHEDULE | EXECUTE) => {
+                    let (time, event) = (reader.u64(), reader.u64());
+                    let (src, dst, task) = (reader.u32(), reader.u32(), reader.u32());
+                    records.push(format!(
+                        "{} {} {} {} {} {}",
+                        tag,
+                        time,
+                        event,
+                        names[src as usize],
+                        names[dst as usize],
+                        names[task as usize]
+                    ));
+                }
+                PACKET => {
+                    let (time, host, packet) = (reader.u64(), reader.u32(), reader.u64());
+                    let mut fields = vec![
+                        time.to_string(),
+                        names[host as usize].clone(),
+                        packet.to_string(),
+                    ];
+                    for _ in 0..5 {
+                        fields.push(match reader.u32() {
+                            NO_NAME => "-".to_owned(),
+                            name => names[name as usize].clone(),
+                        });
+                    }
+                    fields.push(reader.u32().to_string());
+                    records.push(format!("{} {}", PACKET, fields.join(" ")));
+                }
+                tag => panic!("unknown tag {}", tag),
+            }
+        }
+
+        records
+    }
+
+    #[test]
+    fn write_binary_records() {
+        let network = TestNetwork::new(&["11.0.0.1", "11.0.0.2"]);
+        let (client, server) = (network.host(0), network.host(1));
+
+        let directory = env::temp_dir().join(format!("netsim-trace-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let mut tracer = Tracer::create(&directory, TraceFormat::Binary).unwrap();
+
+        let task = Arc::new(Task::HeartBeat(SimulationTime::from_millis(10)));
+        let event = Event::new(
+            task,
+            SimulationTime::from_millis(5),
+            client.clone(),
+            server.clone(),
+        );
+        tracer.event(EventState::Scheduled, &event);
+        tracer.event(EventState::Executed, &event);
+
+        let src = "11.0.0.1:5000".parse().unwrap();
+        let dst = "11.0.0.2:53".parse().unwrap();
+        let packet = Packet::udp(src, dst, vec![0; 100]);
+        tracer.packet(
+            SimulationTime::from_millis(5),
+            server,
+            &packet,
+            PacketState::Delivered,
+        );
+        tracer.packet(
+            SimulationTime::from_millis(6),
+            client,
+            &packet,
+            PacketState::Dropped(DropReason::QueueFull),
+        );
+        tracer.flush();
+
+        let bytes = fs::read(directory.join("trace.bin")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let event_id = i64::from(event.event_id());
+        let id = packet.id();
+        assert_eq!(
+            decode(&bytes),
+            [
+                format!("1 5000000 {} host1 host2 heartbeat", event_id),
+                format!("2 5000000 {} host1 host2 heartbeat", event_id),
+                format!(
+                    "3 5000000 host2 {} delivered - udp 11.0.0.1:5000 11.0.0.2:53 128",
+                    id
+                ),
+                format!(
+                    "3 6000000 host1 {} dropped queue_full udp 11.0.0.1:5000 11.0.0.2:53 128",
+                    id
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::descriptor::Notifier;
+use crate::host::Host;
+use crate::icmp::Unreachable;
+use crate::net::{Packet, Protocol};
+use crate::process::Process;
+use crate::simapp::HostedApp;
+
+pub const RECV_BUFFER_SIZE: usize = 212_992;
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+struct UdpSocketState {
+    local_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    recv_queue: VecDeque<Arc<Packet>>,
+    recv_buffered: usize,
+    recv_buffer_size: usize,
+    error: Option<Unreachable>,
+    notifier: Notifier,
+}
+
+#[derive(Clone)]
+pub struct UdpSocket(Arc<Mutex<UdpSocketState>>);
+
+impl UdpSocket {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(UdpSocketState {
+            local_addr: None,
+            peer_addr: None,
+            recv_queue: VecDeque::new(),
+            recv_buffered: 0,
+            recv_buffer_size: RECV_BUFFER_SIZE,
+            error: None,
+            notifier: Notifier::default(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, UdpSocketState> {
+        self.0.lock().expect("accessed poisoned udp socket")
+    }
+
+    pub fn bind(&self, host: &Host, addr: SocketAddr) -> io::Result<()> {
+        if self.state().local_addr.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket is already bound",
+            ));
+        }
+
+        let ip = addr.ip();
+
+        if !ip.is_unspecified() && !ip.is_loopback() && !host.has_ip(ip) {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+
+        let interface = host.interface();
+        let mut interface = interface.lock().expect("accessed poisoned interface");
+
+        let port = match addr.port() {
+            0 => interface
+                .ephemeral_port(Protocol::Udp)
+                .ok_or(io::ErrorKind::AddrInUse)?,
+            port if interface.is_associated(Protocol::Udp, port) => {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            port => port,
+        };
+
+        interface.associate(Protocol::Udp, port, self.clone().into());
+        self.state().local_addr = Some(SocketAddr::new(ip, port));
+        Ok(())
+    }
+
+    fn bind_implicitly(&self, host: &Host, peer: SocketAddr) -> io::Result<()> {
+        if self.state().local_addr.is_some() {
+            return Ok(());
+        }
+
+        let unspecified = match peer.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        self.bind(host, SocketAddr::new(unspecified, 0))
+    }
+
+    pub fn connect(&self, host: &Host, addr: SocketAddr) -> io::Result<()> {
+        self.bind_implicitly(host, addr)?;
+
+        let mut state = self.state();
+
+        // Like the kernel, connecting settles the source address of an unspecified binding.
+        if let Some(local_addr) = state.local_addr.filter(|local| local.ip().is_unspecified()) {
+            let ip = match addr.ip() {
+                ip if ip.is_loopback() => Some(ip),
+                ip => host.ip_for(ip.into()),
+            };
+            if let Some(ip) = ip {
+                state.local_addr = Some(SocketAddr::new(ip, local_addr.port()));
+            }
+        }
+
+        state.peer_addr = Some(addr);
+        Ok(())
+    }
+
+    pub fn send_to(&self, host: &Arc<Host>, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if buf.len() > MAX_DATAGRAM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message too long",
+            ));
+        }
+
+        self.bind_implicitly(host, addr)?;
+
+        let local_addr = self.local_addr()?;
+
+        if !local_addr.ip().is_unspecified() && local_addr.is_ipv4() != addr.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "address family mismatch",
+            ));
+        }
+
+        let src_ip = match local_addr.ip() {
+            ip if !ip.is_unspecified() => ip,
+            _ if addr.ip().is_loopback() => addr.ip(),
+            _ => host
+                .ip_for(addr.ip().into())
+                .ok_or(io::ErrorKind::AddrNotAvailable)?,
+        };
+        let packet = Packet::udp(
+            SocketAddr::new(src_ip, local_addr.port()),
+            addr,
+            buf.to_vec(),
+        );
+
+        host.interface()
+            .lock()
+            .expect("accessed poisoned interface")
+            .send(host, packet);
+
+        Ok(buf.len())
+    }
+
+    pub fn send(&self, host: &Arc<Host>, buf: &[u8]) -> io::Result<usize> {
+        let peer = self.peer_addr()?;
+        self.send_to(host, buf, peer)
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut state = self.state();
+
+        if state.recv_queue.is_empty() {
+            return Err(match state.error.take() {
+                Some(Unreachable::Port) => io::ErrorKind::ConnectionRefused.into(),
+                Some(Unreachable::Host) => io::Error::from_raw_os_error(libc::EHOSTUNREACH),
+                None => io::ErrorKind::WouldBlock.into(),
+            });
+        }
+
+        let packet = state
+            .recv_queue
+            .pop_front()
+            .ok_or(io::ErrorKind::WouldBlock)?;
+        state.recv_buffered -= packet.payload().len();
+
+        let len = buf.len().min(packet.payload().len());
+        buf[..len].copy_from_slice(&packet.payload()[..len]);
+        Ok((len, packet.src()))
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    /// Copies the next datagram without removing it from the queue.
+    pub fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let state = self.state();
+
+        let packet = state.recv_queue.front().ok_or(io::ErrorKind::WouldBlock)?;
+
+        let len = buf.len().min(packet.payload().len());
+        buf[..len].copy_from_slice(&packet.payload()[..len]);
+        Ok((len, packet.src()))
+    }
+
+    /// Size of the next datagram, if any.
+    pub fn next_len(&self) -> Option<usize> {
+        self.state()
+            .recv_queue
+            .front()
+            .map(|packet| packet.payload().len())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.state()
+            .local_addr
+            .ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.state()
+            .peer_addr
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+
+    /// Readiness in terms of `epoll` events.
+    pub fn events(&self) -> u32 {
+        let state = self.state();
+        let mut events = libc::EPOLLOUT;
+
+        if !state.recv_queue.is_empty() {
+            events |= libc::EPOLLIN;
+        }
+        if state.error.is_some() {
+            events |= libc::EPOLLERR;
+        }
+
+        events as u32
+    }
+
+    pub fn notifier_generation(&self) -> u64 {
+        self.state().notifier.generation()
+    }
+
+    pub fn wait(&self, process: &Process) {
+        self.state().notifier.register(process);
+    }
+
+    pub(crate) fn wait_app(&self, app: &HostedApp) {
+        self.state().notifier.register_app(app);
+    }
+
+    pub fn take_error(&self) -> Option<i32> {
+        self.state().error.take().map(|reason| match reason {
+            Unreachable::Port => libc::ECONNREFUSED,
+            Unreachable::Host => libc::EHOSTUNREACH,
+        })
+    }
+
+    pub fn recv_buffer_size(&self) -> usize {
+        self.state().recv_buffer_size
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) {
+        self.state().recv_buffer_size = size;
+    }
+
+    pub fn close(&self, host: &Host) {
+        let local_addr = {
+            let mut state = self.state();
+            state.peer_addr = None;
+            state.error = None;
+            state.recv_queue.clear();
+            state.recv_buffered = 0;
+            state.local_addr.take()
+        };
+
+        if let Some(addr) = local_addr {
+            host.interface()
+                .lock()
+                .expect("accessed poisoned interface")
+                .disassociate(Protocol::Udp, addr.port());
+        }
+    }
+
+    /// Queues a datagram for receiving, or returns false if it did not fit into the receive
+    /// buffer and was dropped.
+    pub(crate) fn push_in_packet(&self, host: &Arc<Host>, packet: Arc<Packet>) -> bool {
+        let mut state = self.state();
+
+        if matches!(state.peer_addr, Some(peer) if peer != packet.src()) {
+            return true;
+        }
+
+        let len = packet.payload().len();
+
+        if state.recv_buffered + len > state.recv_buffer_size {
+            log::trace!("dropped datagram from `{}` on full buffer", packet.src());
+            return false;
+        }
+
+        state.recv_buffered += len;
+        state.recv_queue.push_back(packet);
+        state.notifier.notify(host);
+        true
+    }
+
+    pub(crate) fn push_in_unreachable(
+        &self,
+        host: &Arc<Host>,
+        dst: SocketAddr,
+        reason: Unreachable,
+    ) {
+        let mut state = self.state();
+
+        if state.peer_addr == Some(dst) {
+            state.error = Some(reason);
+            state.notifier.notify(host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::TestNetwork;
+    use crate::time::SimulationTime;
+
+    /// Latency between the hosts of the test network.
+    fn latency() -> SimulationTime {
+        SimulationTime::from_millis(10)
+    }
+
+    fn is_ephemeral(port: u16) -> bool {
+        (49152..=65535).contains(&port)
+    }
+
+    fn addr(value: &str) -> SocketAddr {
+        value.parse().unwrap()
+    }
+
+    fn network() -> TestNetwork {
+        TestNetwork::new(&["11.0.0.1", "11.0.0.2"])
+    }
+
+    fn bound(host: &Host, value: &str) -> UdpSocket {
+        let socket = UdpSocket::new();
+        socket.bind(host, addr(value)).unwrap();
+        socket
+    }
+
+    fn would_block(socket: &UdpSocket) -> bool {
+        let err = socket.recv_from(&mut [0; 16]).unwrap_err();
+        err.kind() == io::ErrorKind::WouldBlock
+    }
+
+    #[test]
+    fn bind_addresses() {
+        let network = network();
+        let host = network.host(0);
+
+        let socket = bound(host, "11.0.0.1:53");
+        assert_eq!(socket.local_addr().unwrap(), addr("11.0.0.1:53"));
+        assert!(socket.bind(host, addr("0.0.0.0:54")).is_err());
+
+        let err = UdpSocket::new().bind(host, addr("0.0.0.0:53")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let err = UdpSocket::new()
+            .bind(host, addr("11.0.0.2:54"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+
+        bound(host, "127.0.0.1:54");
+
+        // Closing releases the port.
+        socket.close(host);
+        assert!(socket.local_addr().is_err());
+        bound(host, "0.0.0.0:53");
+    }
+
+    #[test]
+    fn allocate_ephemeral_ports() {
+        let network = network();
+        let host = network.host(0);
+
+        let first = bound(host, "0.0.0.0:0").local_addr().unwrap().port();
+        let second = bound(host, "0.0.0.0:0").local_addr().unwrap().port();
+        assert!(is_ephemeral(first) && is_ephemeral(second));
+        assert_ne!(first, second);
+
+        // Sending binds an unbound socket implicitly, like connecting does.
+        let socket = UdpSocket::new();
+        socket.send_to(host, b"hello", addr("11.0.0.2:9")).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        assert!(is_ephemeral(port));
+        assert!(![first, second].contains(&port));
+    }
+
+    #[test]
+    fn send_and_receive_datagrams() {
+        let mut network = network();
+        let (client, server) = (network.host(0).clone(), network.host(1).clone());
+
+        let receiver = bound(&server, "0.0.0.0:9");
+        let sender = bound(&client, "0.0.0.0:4000");
+        assert_eq!(
+            sender.send_to(&client, b"one", addr("11.0.0.2:9")).unwrap(),
+            3
+        );
+        assert_eq!(
+            sender
+                .send_to(&client, b"two!", addr("11.0.0.2:9"))
+                .unwrap(),
+            4
+        );
+
+        network.run_for(latency() - SimulationTime::from_nanos(1));
+        assert!(would_block(&receiver));
+        assert_eq!(receiver.events() & libc::EPOLLIN as u32, 0);
+
+        network.run_for(SimulationTime::from_millis(1));
+        assert_ne!(receiver.events() & libc::EPOLLIN as u32, 0);
+        assert_eq!(receiver.next_len(), Some(3));
+
+        let mut buf = [0; 16];
+        let (len, src) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..len], src), (&b"one"[..], addr("11.0.0.1:4000")));
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"two!");
+        assert!(would_block(&receiver));
+
+        let err = sender
+            .send_to(&client, &[0; MAX_DATAGRAM_SIZE + 1], addr("11.0.0.2:9"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = sender
+            .send_to(&client, b"x", addr("[2000::2]:9"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[test]
+    fn truncate_datagrams() {
+        let mut network = network();
+        let (client, server) = (network.host(0).clone(), network.host(1).clone());
+
+        let receiver = bound(&server, "0.0.0.0:9");
+        let sender = UdpSocket::new();
+        sender
+            .send_to(&client, b"0123456789", addr("11.0.0.2:9"))
+            .unwrap();
+        sender
+            .send_to(&client, b"next", addr("11.0.0.2:9"))
+            .unwrap();
+        network.run_for(latency());
+
+        let mut buf = [0; 4];
+        let (len, _) = receiver.peek_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"0123");
+        assert_eq!(receiver.next_len(), Some(10));
+
+        // The rest of a datagram that does not fit is lost.
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"0123");
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"next");
+    }
+
+    #[test]
+    fn connect_to_peer() {
+        let mut network = network();
+        let (client, server) = (network.host(0).clone(), network.host(1).clone());
+
+        let socket = bound(&client, "0.0.0.0:4000");
+        assert_eq!(
+            socket.send(&client, b"x").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        socket.connect(&client, addr("11.0.0.2:9")).unwrap();
+        assert_eq!(socket.local_addr().unwrap(), addr("11.0.0.1:4000"));
+        assert_eq!(socket.peer_addr().unwrap(), addr("11.0.0.2:9"));
+
+        let peer = bound(&server, "0.0.0.0:9");
+        let stranger = bound(&server, "0.0.0.0:10");
+        peer.send_to(&server, b"from peer", addr("11.0.0.1:4000"))
+            .unwrap();
+        stranger
+            .send_to(&server, b"from stranger", addr("11.0.0.1:4000"))
+            .unwrap();
+        network.run_for(latency());
+
+        // Datagrams from other addresses than the peer are filtered.
+        let mut buf = [0; 16];
+        assert_eq!(socket.recv(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], b"from peer");
+        assert!(would_block(&socket));
+
+        assert_eq!(socket.send(&client, b"reply").unwrap(), 5);
+        network.run_for(latency());
+        let (len, src) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..len], src), (&b"reply"[..], addr("11.0.0.1:4000")));
+    }
+
+    #[test]
+    fn refuse_unreachable_port() {
+        let mut network = network();
+        let client = network.host(0).clone();
+
+        let socket = UdpSocket::new();
+        socket.connect(&client, addr("11.0.0.2:9")).unwrap();
+        socket.send(&client, b"anyone?").unwrap();
+
+        // The request takes one latency there and the port unreachable message one back.
+        network.run_for(latency() + latency());
+        assert_ne!(socket.events() & libc::EPOLLERR as u32, 0);
+        let err = socket.recv(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(would_block(&socket));
+    }
+
+    #[test]
+    fn drop_on_full_receive_buffer() {
+        let mut network = network();
+        let (client, server) = (network.host(0).clone(), network.host(1).clone());
+
+        let receiver = bound(&server, "0.0.0.0:9");
+        receiver.set_recv_buffer_size(1_500);
+        assert_eq!(receiver.recv_buffer_size(), 1_500);
+
+        let sender = UdpSocket::new();
+        for _ in 0..3 {
+            sender
+                .send_to(&client, &[7; 700], addr("11.0.0.2:9"))
+                .unwrap();
+        }
+        network.run_for(latency());
+
+        let stats = server.stats();
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.packets_overflowed, 1);
+        assert_eq!(stats.bytes_overflowed, 700);
+
+        let mut buf = [0; 1_000];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 700);
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 700);
+        assert!(would_block(&receiver));
+
+        // Reading made room again.
+        sender
+            .send_to(&client, &[7; 700], addr("11.0.0.2:9"))
+            .unwrap();
+        network.run_for(latency());
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 700);
+        assert_eq!(server.stats().packets_overflowed, 1);
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
 
-use serde::de::{self, Error, Visitor};
+use serde::de::{self, Error, MapAccess, Visitor};
 use serde::Deserialize;
 
 use crate::OutOfBoundsError;
@@ -35,7 +35,7 @@ pub const MIBYTE: &'static str = "mibyte";
 pub const GIBYTE: &'static str = "gibyte";
 pub const TIBYTE: &'static str = "tibyte";
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TimeInterval(Duration);
 
 impl FromStr for TimeInterval {
@@ -91,7 +91,7 @@ impl<'de> Deserialize<'de> for TimeInterval {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_str(TimeIntervalVisitor)
+        deserializer.deserialize_any(TimeIntervalVisitor)
     }
 }
 
@@ -137,6 +137,30 @@ impl<'de> Visitor<'de> for TimeIntervalVisitor {
         formatter.write_str("`uint` (ns | us | ms | s | min | h)")
     }
 
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        u64::try_from(value)
+            .map_err(|_| E::custom(format!("negative time interval `{}` not allowed", value)))
+            .and_then(|value| self.visit_u64(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Duration::from_secs(value).into())
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (unit, value) = single_entry(map)?;
+        self.visit_str(&format!("{} {}", value, unit))
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: Error,
@@ -156,17 +180,17 @@ impl<'de> Visitor<'de> for TimeIntervalVisitor {
 pub struct Bits(u64);
 
 impl Bits {
-    const BITS_PER_KBIT: u64 = 10u64.pow(1);
-    const BITS_PER_MBIT: u64 = 10u64.pow(2);
-    const BITS_PER_GBIT: u64 = 10u64.pow(3);
-    const BITS_PER_TBIT: u64 = 10u64.pow(4);
+    const BITS_PER_KBIT: u64 = 10u64.pow(3);
+    const BITS_PER_MBIT: u64 = 10u64.pow(6);
+    const BITS_PER_GBIT: u64 = 10u64.pow(9);
+    const BITS_PER_TBIT: u64 = 10u64.pow(12);
 
     const BITS_PER_KIBIT: u64 = 2u64.pow(10);
     const BITS_PER_MIBIT: u64 = 2u64.pow(20);
     const BITS_PER_GIBIT: u64 = 2u64.pow(30);
     const BITS_PER_TIBIT: u64 = 2u64.pow(40);
 
-    const fn bit(&self) -> u64 {
+    pub const fn bit(&self) -> u64 {
         self.0
     }
 
@@ -208,7 +232,7 @@ impl<'de> Deserialize<'de> for Bits {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_str(BitsVisitor)
+        deserializer.deserialize_any(BitsVisitor)
     }
 }
 
@@ -242,6 +266,14 @@ impl<'de> Visitor<'de> for BitsVisitor {
         formatter.write_str("`uint` (kbit | mbit | gbit | tbit | kibit | mibit | gibit | tibit)")
     }
 
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (unit, value) = single_entry(map)?;
+        self.visit_str(&format!("{} {}", value, unit))
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
@@ -305,17 +337,17 @@ impl<'de> Visitor<'de> for BitsVisitor {
 pub struct Bytes(u64);
 
 impl Bytes {
-    const BYTES_PER_KBYTE: u64 = 10u64.pow(1);
-    const BYTES_PER_MBYTE: u64 = 10u64.pow(2);
-    const BYTES_PER_GBYTE: u64 = 10u64.pow(3);
-    const BYTES_PER_TBYTE: u64 = 10u64.pow(4);
+    const BYTES_PER_KBYTE: u64 = 10u64.pow(3);
+    const BYTES_PER_MBYTE: u64 = 10u64.pow(6);
+    const BYTES_PER_GBYTE: u64 = 10u64.pow(9);
+    const BYTES_PER_TBYTE: u64 = 10u64.pow(12);
 
     const BYTES_PER_KIBYTE: u64 = 2u64.pow(10);
     const BYTES_PER_MIBYTE: u64 = 2u64.pow(20);
     const BYTES_PER_GIBYTE: u64 = 2u64.pow(30);
     const BYTES_PER_TIBYTE: u64 = 2u64.pow(40);
 
-    const fn bytes(&self) -> u64 {
+    pub const fn bytes(&self) -> u64 {
         self.0
     }
 
@@ -357,7 +389,7 @@ impl<'de> Deserialize<'de> for Bytes {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_str(BytesVisitor)
+        deserializer.deserialize_any(BytesVisitor)
     }
 }
 
@@ -392,6 +424,30 @@ impl<'de> Visitor<'de> for BytesVisitor {
             .write_str("`uint` (kbyte | mbyte | gbyte | tbyte | kibyte | mibyte | gibyte | tibyte)")
     }
 
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        u64::try_from(value)
+            .map_err(|_| E::custom(format!("negative size `{}` not allowed", value)))
+            .and_then(|value| self.visit_u64(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Bytes(value))
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (unit, value) = single_entry(map)?;
+        self.visit_str(&format!("{} {}", value, unit))
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
@@ -451,7 +507,7 @@ impl<'de> Visitor<'de> for BytesVisitor {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Percentage<const L: u64, const U: u64>(u64);
 
 pub type Fraction = Percentage<0, 100>;
@@ -468,6 +524,15 @@ impl<const L: u64, const U: u64> TryFrom<u64> for Percentage<L, U> {
     }
 }
 
+impl<'de, const L: u64, const U: u64> Deserialize<'de> for Percentage<L, U> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(PercentageVisitor)
+    }
+}
+
 impl<const L: u64, const U: u64> From<Percentage<L, U>> for f64 {
     fn from(value: Percentage<L, U>) -> Self {
         value.0 as f64 * 0.01
@@ -533,3 +598,20 @@ impl<'de, const L: u64, const U: u64> Visitor<'de> for PercentageVisitor<L, U> {
         Self::Value::try_from(value).map_err(|err| E::custom(err))
     }
 }
+
+fn single_entry<'de, A>(mut map: A) -> Result<(String, u64), A::Error>
+where
+    A: MapAccess<'de>,
+{
+    let entry = map
+        .next_entry::<String, u64>()?
+        .ok_or_else(|| A::Error::custom("expected exactly one `unit = value` entry"))?;
+
+    if map.next_key::<String>()?.is_some() {
+        return Err(A::Error::custom(
+            "expected exactly one `unit = value` entry",
+        ));
+    }
+
+    Ok(entry)
+}
@@ -1,21 +1,51 @@
 use core::cell;
 use std::lazy;
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 
+use crate::event::Event;
+use crate::graph::{NodeId, Path, Topology};
 use crate::host::Host;
-use crate::time::EmulatedTime;
+use crate::net::{NameServer, Packet};
+use crate::process::Process;
+use crate::sim::Scheduler;
+use crate::task::Task;
+use crate::time::{EmulatedTime, SimulationTime};
+use crate::trace::{PacketState, Tracer};
 
 #[derive(Clone, Copy)]
 pub struct WorkerId(u32);
 
-pub struct Process {
-    id: u64,
+impl From<u32> for WorkerId {
+    fn from(value: u32) -> Self {
+        WorkerId(value)
+    }
+}
+
+impl From<WorkerId> for u32 {
+    fn from(value: WorkerId) -> Self {
+        value.0
+    }
 }
 
+/// A native thread of a managed process, identified by its kernel thread id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Thread {
     id: u64,
 }
 
+impl Thread {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl From<u64> for Thread {
+    fn from(value: u64) -> Self {
+        Thread { id: value }
+    }
+}
+
 struct Clock {
     now: Option<EmulatedTime>,
     last: Option<EmulatedTime>,
@@ -28,6 +58,7 @@ pub struct Worker {
     active_process: Option<Process>,
     active_thread: Option<Thread>,
     pool: Arc<Mutex<WorkerPool>>,
+    tracer: Option<Arc<Mutex<Tracer>>>,
     clock: Clock,
     bootstrap_end_time: EmulatedTime,
 }
@@ -36,6 +67,11 @@ std::thread_local! { static WORKER: lazy::OnceCell<cell::RefCell<Worker>> = lazy
 
 impl Worker {
     pub fn spawn(pool: Arc<Mutex<WorkerPool>>, id: WorkerId, bootstrap_end_time: EmulatedTime) {
+        let tracer = pool
+            .lock()
+            .expect("accessed tracer through poisoned worker pool lock")
+            .tracer();
+
         WORKER.with(|worker| {
             let _ = worker.set(cell::RefCell::new(Self {
                 id,
@@ -43,6 +79,7 @@ impl Worker {
                 active_process: None,
                 active_thread: None,
                 pool,
+                tracer,
                 clock: Clock {
                     now: None,
                     last: None,
@@ -95,6 +132,13 @@ impl Worker {
             .expect("tried to set active process on uninitialized worker");
     }
 
+    pub fn with_active_process<F, R>(func: F) -> Option<R>
+    where
+        F: FnOnce(&Process) -> R,
+    {
+        Self::with(|worker| worker.active_process.as_ref().map(func)).flatten()
+    }
+
     pub fn clear_active_process() {
         let _ = Self::with_mut(|worker| worker.active_process.take())
             .expect("tried to clear active process on uninitialized worker");
@@ -105,6 +149,10 @@ impl Worker {
             .expect("tried to set active thread on uninitialized worker");
     }
 
+    pub fn active_thread() -> Option<Thread> {
+        Self::with(|worker| worker.active_thread).flatten()
+    }
+
     pub fn clear_active_thread() {
         let _ = Self::with_mut(|worker| worker.active_thread.take())
             .expect("tried to clear active thread on unitialized worker");
@@ -143,22 +191,18 @@ impl Worker {
     }
 
     pub fn is_bootstrap_active() -> bool {
-        Worker::with(|worker| {
-            worker
-                .clock
-                .now
-                .as_ref()
-                .map_or(false, |now| *now < worker.bootstrap_end_time)
-        })
+        Worker::with(
+            |worker| matches!(&worker.clock.now, Some(now) if *now < worker.bootstrap_end_time),
+        )
         .unwrap_or_else(|| unreachable!())
     }
 
-    // pub fn is_scheduler_running() -> bool {
-    //     Worker::scheduler()
-    //         .lock()
-    //         .expect("tried to acquire poisoned manager lock")
-    //         .is_running()
-    // }
+    pub fn is_scheduler_running() -> bool {
+        Worker::scheduler()
+            .lock()
+            .expect("tried to acquire poisoned manager lock")
+            .is_running()
+    }
 
     pub fn worker_id() -> Option<WorkerId> {
         Worker::with(|worker| worker.id)
@@ -169,34 +213,116 @@ impl Worker {
             .expect("cannot access worker pool from unitialized worker")
     }
 
-    // pub fn scheduler() -> Arc<Mutex<Scheduler>> {
-    //     Worker::worker_pool()
-    //         .lock()
-    //         .expect("accessed scheduler through poisoned worker pool lock")
-    //         .scheduler()
-    // }
-
-    // pub fn schedule_task(task: Task, host: &Host, delay: SimulationTime) -> bool {
-    //     if !Self::is_scheduler_running() {
-    //         return false;
-    //     }
-
-    //     if let Some(now) = Self::current_time().map(SimulationTime::from) {
-    //         let event = Event::new(task, now + delay, host);
-    //         Self::scheduler()
-    //             .lock()
-    //             .expect("tried to acquired poisoned scheduler lock")
-    //             .push(event, host, delay)
-    //     } else {
-    //         false
-    //     }
-    // }
+    pub fn scheduler() -> Arc<Mutex<Scheduler>> {
+        Worker::worker_pool()
+            .lock()
+            .expect("accessed scheduler through poisoned worker pool lock")
+            .scheduler()
+    }
+
+    pub fn topology() -> Arc<Topology> {
+        Worker::worker_pool()
+            .lock()
+            .expect("accessed topology through poisoned worker pool lock")
+            .topology()
+    }
+
+    pub fn name_server() -> Arc<Mutex<NameServer>> {
+        Worker::worker_pool()
+            .lock()
+            .expect("accessed name server through poisoned worker pool lock")
+            .name_server()
+    }
+
+    pub fn is_registered(ip: IpAddr) -> bool {
+        !Self::name_server()
+            .lock()
+            .expect("tried to acquire poisoned name server lock")
+            .is_unique(ip)
+    }
+
+    pub fn resolve_host(ip: IpAddr) -> Option<Arc<Host>> {
+        Self::scheduler()
+            .lock()
+            .expect("tried to acquire poisoned scheduler lock")
+            .host_by_ip(ip)
+    }
+
+    /// Records a step of a packet in the trace, if the simulation writes one. The tracer is
+    /// taken from the pool once when the worker spawns, so packets are not slowed down by
+    /// locking the pool when there is none.
+    pub fn trace_packet(host: &Host, packet: &Packet, state: PacketState) {
+        let tracer = Self::with(|worker| worker.tracer.clone()).flatten();
+
+        if let (Some(tracer), Some(now)) = (tracer, Self::current_time()) {
+            tracer
+                .lock()
+                .expect("tried to acquire poisoned tracer lock")
+                .packet(now.into(), host, packet, state);
+        }
+    }
+
+    pub fn path_between(src: NodeId, dst: NodeId) -> Option<Path> {
+        Self::topology().path(src, dst)
+    }
+
+    pub fn schedule_task(task: Task, host: &Arc<Host>, delay: SimulationTime) -> bool {
+        if !Self::is_scheduler_running() {
+            return false;
+        }
+
+        let src = Self::with_active_host(|src| src.clone()).unwrap_or_else(|| host.clone());
+
+        if let Some(now) = Self::current_time().map(SimulationTime::from) {
+            let event = Event::new(Arc::new(task), now + delay, src, host.clone());
+            Self::scheduler()
+                .lock()
+                .expect("tried to acquired poisoned scheduler lock")
+                .push(event)
+        } else {
+            false
+        }
+    }
 }
 
-pub struct WorkerPool {}
+pub struct WorkerPool {
+    scheduler: Arc<Mutex<Scheduler>>,
+    topology: Arc<Topology>,
+    name_server: Arc<Mutex<NameServer>>,
+    tracer: Option<Arc<Mutex<Tracer>>>,
+}
 
 impl WorkerPool {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(
+        scheduler: Arc<Mutex<Scheduler>>,
+        topology: Arc<Topology>,
+        name_server: Arc<Mutex<NameServer>>,
+    ) -> Self {
+        Self {
+            scheduler,
+            topology,
+            name_server,
+            tracer: None,
+        }
+    }
+
+    pub fn scheduler(&self) -> Arc<Mutex<Scheduler>> {
+        self.scheduler.clone()
+    }
+
+    pub fn topology(&self) -> Arc<Topology> {
+        self.topology.clone()
+    }
+
+    pub fn name_server(&self) -> Arc<Mutex<NameServer>> {
+        self.name_server.clone()
+    }
+
+    pub fn tracer(&self) -> Option<Arc<Mutex<Tracer>>> {
+        self.tracer.clone()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Arc<Mutex<Tracer>>) {
+        self.tracer = Some(tracer);
     }
 }
@@ -0,0 +1,152 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const SIMULATOR: &str = env!("CARGO_BIN_EXE_netsim");
+
+/// Runs the simulator on `config` in a directory of its own, and hands the output directory
+/// to `read` before removing it.
+fn simulate<F, T>(name: &str, config: &str, read: F) -> T
+where
+    F: FnOnce(&Path) -> T,
+{
+    let directory = env::temp_dir().join(format!("netsim-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("failed to create test directory");
+
+    let config_path = directory.join("config.toml");
+    fs::write(&config_path, config).expect("failed to write config");
+
+    let output = directory.join("output");
+    let status = Command::new(SIMULATOR)
+        .arg(&config_path)
+        .arg("--output-directory")
+        .arg(&output)
+        .status()
+        .expect("failed to run simulator");
+    assert!(status.success());
+
+    let result = read(&output);
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+fn read(path: PathBuf) -> String {
+    fs::read_to_string(&path).unwrap_or_else(|_| panic!("no {}", path.display()))
+}
+
+/// Runs the example with its config, pointed at the example binary cargo builds next to the
+/// simulator, and returns the summary and the output of its process.
+fn run(name: &str, host: &str) -> (String, String) {
+    let example = Path::new(SIMULATOR)
+        .parent()
+        .expect("simulator outside of a directory")
+        .join("examples")
+        .join(name);
+
+    let config_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join(format!("{}.toml", name));
+    let config = fs::read_to_string(config_path)
+        .expect("failed to read example config")
+        .replace(
+            &format!("\"target/debug/examples/{}\"", name),
+            &format!("{:?}", example),
+        );
+
+    simulate(name, &config, |output| {
+        (
+            read(output.join("summary.json")),
+            read(output.join(format!("hosts/{}/{}.1.stdout", host, name))),
+        )
+    })
+}
+
+/// Reads a number from the flat JSON of the summary.
+fn number(summary: &str, key: &str) -> f64 {
+    let pattern = format!("\"{}\": ", key);
+    let start = summary
+        .find(&pattern)
+        .unwrap_or_else(|| panic!("no `{}` in summary", key))
+        + pattern.len();
+    let end = summary[start..]
+        .find(|c: char| c != '.' && !c.is_ascii_digit())
+        .map_or(summary.len(), |end| start + end);
+
+    summary[start..end].parse().expect("malformed number")
+}
+
+#[test]
+fn sleep_in_simulated_time() {
+    let (summary, stdout) = run("sleep", "sleeper");
+
+    assert_eq!(number(&summary, "simulated_time"), 60.0);
+    assert!(number(&summary, "total") < 10.0);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "started 946684801 s after the unix epoch",
+            "slept for 10 s",
+            "timer expired after 15 s",
+            "polled until 17 s",
+            "selected until 20 s",
+        ]
+    );
+}
+
+#[test]
+fn wait_on_descriptors() {
+    let (summary, stdout) = run("descriptors", "waiter");
+
+    assert!(number(&summary, "total") < 10.0);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "pipe is a fifo: true",
+            "ready at first: []",
+            "ready after writes: [1, 2]",
+            "ready after waiting: [3]",
+            "timer expired after 2 s",
+            "polled 0 ready until 3 s",
+            "pipe hung up: true",
+            "socketpair Function not implemented (os error 38)",
+            "fork Function not implemented (os error 38)",
+        ]
+    );
+}
+
+#[test]
+fn transfer_with_builtin_apps() {
+    let config = r#"
+        [general]
+        stop_time = 30
+
+        [network.graph]
+        node = { id = 0, host_bandwidth_down = "10 mbit", host_bandwidth_up = "10 mbit" }
+        edge = { source = 0, target = 0, latency = "10 ms" }
+
+        [[hosts]]
+        name = "sink"
+        network_node_id = 0
+        processes = [ { app = "bulk_sink", args = "interval=60s", stop_time = 20 } ]
+
+        [[hosts]]
+        name = "sender"
+        network_node_id = 0
+        processes = [ { app = "bulk_sender", args = "server=sink size=1mibyte", start_time = 1 } ]
+    "#;
+
+    let (sender, sink) = simulate("apps", config, |output| {
+        (
+            read(output.join("hosts/sender/bulk_sender.1.stdout")),
+            read(output.join("hosts/sink/bulk_sink.1.stdout")),
+        )
+    });
+
+    let last = |output: &str| output.lines().last().unwrap_or_default().to_owned();
+    assert!(sender.starts_with("time=1.020000 event=connected peer=11.0.0.1:5001\n"));
+    assert!(last(&sender).contains(" event=done bytes=1048576 "));
+    assert!(last(&sink).contains(" event=done peer=11.0.0.2:"));
+    assert!(last(&sink).contains(" bytes=1048576 "));
+}